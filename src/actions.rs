@@ -0,0 +1,1636 @@
+//! actions.rs
+//!
+//! Actions which are applied to the files found by a Finder, such as
+//! deleting them. Actions are applied by the Apply sink, which performs a
+//! dry run unless told otherwise, reporting what would be done to each
+//! match without doing it.
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::{self, File, FileTimes, Metadata};
+use std::io::{self, BufRead, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use walkdir::WalkDir;
+
+#[cfg(unix)]
+use crate::{owner::Ownership, perms::Mode};
+use crate::{
+    constants::EXEC_BATCH_BYTES, errors::AmbleError, fields::Availability, filematch::FileMatch, manifest::sha256,
+    size::Size, traits::Sink, undo::Journal,
+};
+
+/// An operation applied to each match. Besides the actions provided here,
+/// consumers of the library may implement their own, and hand them to an
+/// Apply sink.
+pub trait Action: Send {
+    /// The name of the action, as reported to the user (eg "delete").
+    fn verb(&self) -> &'static str;
+
+    /// Describe what applying the action to the match does.
+    fn describe(&self, found: &FileMatch) -> String {
+        format!("{} {}", self.verb(), found.path.display())
+    }
+
+    /// Apply the action to the match.
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError>;
+
+    /// The commands which would apply the action to the match, as argument
+    /// lists, for actions which may be emitted as a shell script. Each
+    /// command is only run if the one before it succeeds.
+    fn script(&self, _found: &FileMatch) -> Option<Vec<Vec<OsString>>> {
+        None
+    }
+
+    /// Called once every match has been handed to the action.
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        Ok(())
+    }
+
+    /// Whether the action is applied to matches some time after they are
+    /// handed to it, such as in batches, rather than straight away. Such
+    /// an action reports the outcome for each match once it is known, via
+    /// settled, so that matches are only recorded as done once they are.
+    fn deferred(&self) -> bool {
+        false
+    }
+
+    /// Take the outcome for each match which a deferred action has been
+    /// applied to, or has failed to be, since last asked.
+    fn settled(&mut self) -> Vec<Settled> {
+        Vec::new()
+    }
+}
+
+/// A match handed to a deferred action, and the outcome of applying the
+/// action to it.
+pub type Settled = (FileMatch, Result<(), AmbleError>);
+
+impl<A: Action + ?Sized> Action for Box<A> {
+    fn verb(&self) -> &'static str {
+        (**self).verb()
+    }
+
+    fn describe(&self, found: &FileMatch) -> String {
+        (**self).describe(found)
+    }
+
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError> {
+        (**self).apply(found)
+    }
+
+    fn script(&self, found: &FileMatch) -> Option<Vec<Vec<OsString>>> {
+        (**self).script(found)
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        (**self).finish()
+    }
+
+    fn deferred(&self) -> bool {
+        (**self).deferred()
+    }
+
+    fn settled(&mut self) -> Vec<Settled> {
+        (**self).settled()
+    }
+}
+
+/// Deletes each match. Directories, which are only matched when rolled
+/// up, are deleted along with their contents.
+#[derive(Debug, Default)]
+pub struct Delete;
+
+impl Action for Delete {
+    fn verb(&self) -> &'static str {
+        "delete"
+    }
+
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError> {
+        if found.contents.is_some() {
+            fs::remove_dir_all(&found.path)?;
+        } else {
+            fs::remove_file(&found.path)?;
+        }
+        Ok(())
+    }
+
+    fn script(&self, found: &FileMatch) -> Option<Vec<Vec<OsString>>> {
+        let flags = if found.contents.is_some() { "-rf" } else { "-f" };
+        Some(vec![command(&["rm", flags, "--"], &[found.path.as_os_str()])])
+    }
+}
+
+/// Truncates each match to zero bytes, rather than deleting it, keeping
+/// its ownership and permissions, as suits log files which must continue
+/// to exist. The files beneath directories, which are only matched when
+/// rolled up, are truncated in their place.
+#[derive(Debug, Default)]
+pub struct Truncate;
+
+impl Action for Truncate {
+    fn verb(&self) -> &'static str {
+        "truncate"
+    }
+
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError> {
+        for entry in WalkDir::new(&found.path) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                fs::OpenOptions::new().write(true).open(entry.path())?.set_len(0)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn script(&self, found: &FileMatch) -> Option<Vec<Vec<OsString>>> {
+        if found.contents.is_some() {
+            let mut find = command(&["find"], &[found.path.as_os_str()]);
+            find.extend(command(&["-type", "f", "-exec", "truncate", "-s", "0", "--", "{}", "+"], &[]));
+            Some(vec![find])
+        } else {
+            Some(vec![command(&["truncate", "-s", "0", "--"], &[found.path.as_os_str()])])
+        }
+    }
+}
+
+/// Moves each match to the trash (the XDG trash, or the macOS Trash),
+/// from which it may be restored. Directories, which are only matched when
+/// rolled up, are trashed along with their contents. Requires the trash
+/// feature.
+#[cfg(feature = "trash")]
+#[derive(Debug, Default)]
+pub struct Trash {
+    journal: Option<Journal>,
+}
+
+#[cfg(feature = "trash")]
+impl Trash {
+    /// Record each match trashed in the undo journal, so that it may be
+    /// restored by `undo`.
+    pub fn journal(&mut self, journal: Journal) -> &mut Self {
+        self.journal = Some(journal);
+        self
+    }
+}
+
+#[cfg(feature = "trash")]
+impl Action for Trash {
+    fn verb(&self) -> &'static str {
+        "trash"
+    }
+
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError> {
+        trash::delete(&found.path).map_err(|e| AmbleError::IoError(e.to_string()))?;
+        if let Some(journal) = &mut self.journal {
+            journal.record("trash", &found.path, None)?;
+        }
+        Ok(())
+    }
+}
+
+/// Changes the permissions of each match. The contents of directories,
+/// which are only matched when rolled up, are changed along with them.
+/// Symbolic links are left alone.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct Chmod {
+    mode: Mode,
+}
+
+#[cfg(unix)]
+impl Chmod {
+    /// New up a Chmod applying the supplied mode.
+    pub fn new(mode: Mode) -> Self {
+        Self { mode }
+    }
+}
+
+#[cfg(unix)]
+impl Action for Chmod {
+    fn verb(&self) -> &'static str {
+        "chmod"
+    }
+
+    fn describe(&self, found: &FileMatch) -> String {
+        format!("chmod {} {}", self.mode, found.path.display())
+    }
+
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        // contents first, so that removing search permission from a
+        // directory does not prevent changing what is inside it
+        for entry in WalkDir::new(&found.path).contents_first(true) {
+            let entry = entry?;
+            if entry.file_type().is_symlink() {
+                continue;
+            }
+            let mode = entry.metadata()?.permissions().mode();
+            let changed = self.mode.apply(mode, entry.file_type().is_dir());
+            if changed != mode & 0o7777 {
+                fs::set_permissions(entry.path(), fs::Permissions::from_mode(changed))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn script(&self, found: &FileMatch) -> Option<Vec<Vec<OsString>>> {
+        let mode = self.mode.to_string();
+        let program: &[&str] = if found.contents.is_some() { &["chmod", "-R"] } else { &["chmod"] };
+        Some(vec![command(&[program, &[mode.as_str(), "--"]].concat(), &[found.path.as_os_str()])])
+    }
+}
+
+/// Changes the owner and/or group of each match. The contents of
+/// directories, which are only matched when rolled up, are changed along
+/// with them. Symbolic links are changed themselves, rather than their
+/// targets.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct Chown {
+    ownership: Ownership,
+}
+
+#[cfg(unix)]
+impl Chown {
+    /// New up a Chown applying the supplied ownership.
+    pub fn new(ownership: Ownership) -> Self {
+        Self { ownership }
+    }
+}
+
+#[cfg(unix)]
+impl Action for Chown {
+    fn verb(&self) -> &'static str {
+        "chown"
+    }
+
+    fn describe(&self, found: &FileMatch) -> String {
+        format!("chown {} {}", self.ownership, found.path.display())
+    }
+
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError> {
+        for entry in WalkDir::new(&found.path) {
+            std::os::unix::fs::lchown(entry?.path(), self.ownership.user, self.ownership.group)?;
+        }
+        Ok(())
+    }
+
+    fn script(&self, found: &FileMatch) -> Option<Vec<Vec<OsString>>> {
+        let ownership = self.ownership.to_string();
+        let program: &[&str] = if found.contents.is_some() { &["chown", "-hR"] } else { &["chown", "-h"] };
+        Some(vec![command(&[program, &[ownership.as_str(), "--"]].concat(), &[found.path.as_os_str()])])
+    }
+}
+
+/// Replaces duplicate matches with hard links to a single copy, reclaiming
+/// their space without deleting anything. Matches are duplicates when
+/// their contents are identical by SHA-256, and they share a filesystem,
+/// owner, group, and permissions, so that linking them changes nobody's
+/// access.
+/// The first of each group to be found is kept, and files beneath rolled
+/// up directories are considered individually, those which cannot be read
+/// being skipped and counted as errors. Each duplicate is replaced
+/// atomically, by renaming a new link over it, unless either file has
+/// changed in size or modification time since it was hashed. Only the
+/// space of duplicates without other links is counted as reclaimed.
+#[cfg(unix)]
+#[derive(Debug, Default)]
+pub struct Dedupe {
+    // candidates by (device, size, owner, group, mode)
+    candidates: HashMap<(u64, u64, u32, u32, u32), Vec<Candidate>>,
+    linked: u64,
+    reclaimed: u64,
+}
+
+// A file which others may be linked to
+#[cfg(unix)]
+#[derive(Debug)]
+struct Candidate {
+    path: PathBuf,
+    ino: u64,
+    // when the candidate was found, so that changes since may be noticed
+    modified: Option<SystemTime>,
+    // computed once a second candidate turns up
+    checksum: Option<String>,
+}
+
+// Whether the file at the path has changed, in size or modification time,
+// since its metadata was read. A file which cannot be read has changed.
+#[cfg(unix)]
+fn changed_since(path: &Path, len: u64, modified: Option<SystemTime>) -> bool {
+    fs::symlink_metadata(path).map_or(true, |now| now.len() != len || now.modified().ok() != modified)
+}
+
+#[cfg(unix)]
+impl Dedupe {
+    // Link the file to an identical candidate, if there is one, or else
+    // record it as a candidate.
+    fn dedupe(&mut self, path: &Path, metadata: &Metadata) -> Result<(), AmbleError> {
+        use std::os::unix::fs::MetadataExt;
+
+        let key = (metadata.dev(), metadata.len(), metadata.uid(), metadata.gid(), metadata.mode() & 0o7777);
+        let modified = metadata.modified().ok();
+        let candidates = self.candidates.entry(key).or_default();
+        if candidates.is_empty() {
+            candidates.push(Candidate { path: path.to_path_buf(), ino: metadata.ino(), modified, checksum: None });
+            return Ok(());
+        }
+        if candidates.iter().any(|candidate| candidate.ino == metadata.ino()) {
+            return Ok(());
+        }
+        let checksum = sha256(path)?;
+        for candidate in candidates.iter_mut() {
+            if candidate.checksum.is_none() {
+                candidate.checksum = Some(sha256(&candidate.path)?);
+            }
+            if candidate.checksum.as_deref() == Some(checksum.as_str()) {
+                // neither file may have changed since it was hashed
+                if changed_since(path, metadata.len(), modified)
+                   || changed_since(&candidate.path, metadata.len(), candidate.modified) {
+                    return Err(AmbleError::UnexpectedResult("changed while being compared".to_string()));
+                }
+                let temp = temp_beside(path);
+                fs::hard_link(&candidate.path, &temp)?;
+                if let Err(e) = fs::rename(&temp, path) {
+                    let _ = fs::remove_file(&temp);
+                    return Err(e.into());
+                }
+                self.linked += 1;
+                // the space of a file with other links is not freed
+                if metadata.nlink() == 1 {
+                    self.reclaimed += metadata.len();
+                }
+                return Ok(());
+            }
+        }
+        candidates.push(Candidate { path: path.to_path_buf(), ino: metadata.ino(), modified,
+                                    checksum: Some(checksum) });
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl Action for Dedupe {
+    fn verb(&self) -> &'static str {
+        "dedupe"
+    }
+
+    fn describe(&self, found: &FileMatch) -> String {
+        // the first of each group found is kept as it is
+        match found.contents {
+            Some(_) => format!("dedupe the files beneath {}, linking each to an identical file found before it, if any",
+                               found.path.display()),
+            None => format!("dedupe {}, linking it to an identical file found before it, if any", found.path.display()),
+        }
+    }
+
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError> {
+        let metadata = fs::symlink_metadata(&found.path)?;
+        if !metadata.is_dir() {
+            if !metadata.is_file() {
+                return Err(AmbleError::UnexpectedResult("not a regular file".to_string()));
+            }
+            return self.dedupe(&found.path, &metadata);
+        }
+        // an entry which cannot be read, or linked, is skipped, rather than
+        // giving up on the rest of the directory
+        let mut failed = 0;
+        for entry in WalkDir::new(&found.path) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    eprintln!("Warning: unable to read beneath '{}': {}", found.path.display(), e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let result = entry.metadata().map_err(AmbleError::from)
+                              .and_then(|metadata| self.dedupe(entry.path(), &metadata));
+            if let Err(e) = result {
+                eprintln!("Warning: unable to dedupe '{}': {}", entry.path().display(), e);
+                failed += 1;
+            }
+        }
+        match failed {
+            0 => Ok(()),
+            failed => Err(AmbleError::UnexpectedResult(format!("{} entries beneath it could not be deduped", failed))),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        println!("# linked {} duplicates, reclaiming {} bytes", self.linked, self.reclaimed);
+        Ok(())
+    }
+}
+
+/// Moves each match beneath a destination directory, recreating its path
+/// relative to the root it was found under (like `rsync -R`). Existing
+/// files at the destination are never overwritten. Moves between
+/// filesystems fall back to copying, preserving access and modification
+/// times, before removing the original. Optionally, a symlink to the
+/// moved file is left in its place.
+#[derive(Debug)]
+pub struct MoveTo {
+    dest: PathBuf,
+    roots: Vec<PathBuf>,
+    leave_symlink: bool,
+    journal: Option<Journal>,
+}
+
+impl MoveTo {
+    /// New up a MoveTo for the supplied destination, and the roots under
+    /// which matches are found. Matches which are not under any of the
+    /// roots keep their full path beneath the destination.
+    pub fn new(dest: impl Into<PathBuf>, roots: Vec<PathBuf>) -> Self {
+        Self { dest: dest.into(), roots, leave_symlink: false, journal: None }
+    }
+
+    /// Leave a symlink at the original path of each match, pointing to
+    /// where it was moved, so that references to the old path still work.
+    pub fn leave_symlink(&mut self, leave_symlink: bool) -> &mut Self {
+        self.leave_symlink = leave_symlink;
+        self
+    }
+
+    /// Record each match moved in the undo journal, so that it may be
+    /// restored by `undo`.
+    pub fn journal(&mut self, journal: Journal) -> &mut Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// The destination for the supplied path. When leaving symlinks, this
+    /// is absolute, so the symlink resolves wherever it is left.
+    pub fn destination(&self, path: &Path) -> Result<PathBuf, AmbleError> {
+        let dest = self.dest.join(relative_path(path, &self.roots)?);
+        if self.leave_symlink {
+            Ok(std::path::absolute(dest)?)
+        } else {
+            Ok(dest)
+        }
+    }
+}
+
+impl Action for MoveTo {
+    fn verb(&self) -> &'static str {
+        "move"
+    }
+
+    fn describe(&self, found: &FileMatch) -> String {
+        match self.destination(&found.path) {
+            Ok(dest) => format!("move {} -> {}", found.path.display(), dest.display()),
+            Err(_) => format!("move {}", found.path.display()),
+        }
+    }
+
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError> {
+        let dest = self.destination(&found.path)?;
+        if fs::symlink_metadata(&dest).is_ok() {
+            return Err(AmbleError::IoError(format!("'{}' already exists", dest.display())));
+        }
+        if self.leave_symlink && cfg!(not(unix)) {
+            return Err(AmbleError::IoError("leaving symlinks is only supported on unix".to_string()));
+        }
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        relocate(&found.path, &dest)?;
+        #[cfg(unix)]
+        {
+            if self.leave_symlink {
+                std::os::unix::fs::symlink(&dest, &found.path)?;
+            }
+        }
+        if let Some(journal) = &mut self.journal {
+            journal.record("move", &found.path, Some(&dest))?;
+        }
+        Ok(())
+    }
+
+    fn script(&self, found: &FileMatch) -> Option<Vec<Vec<OsString>>> {
+        let dest = self.destination(&found.path).ok()?;
+        let parent = dest.parent()?;
+        let mut commands = vec![command(&["mkdir", "-p", "--"], &[parent.as_os_str()]),
+                                command(&["mv", "-n", "--"], &[found.path.as_os_str(), dest.as_os_str()])];
+        if self.leave_symlink {
+            commands.push(command(&["ln", "-s", "--"], &[dest.as_os_str(), found.path.as_os_str()]));
+        }
+        Some(commands)
+    }
+}
+
+/// Runs a command for each match, like `find -exec`. Every occurrence of
+/// `{}` in the arguments is replaced by the path of the match. The command
+/// is run directly rather than by a shell, so paths need no quoting. A
+/// command which exits unsuccessfully is reported as an error.
+#[derive(Debug)]
+pub struct Exec {
+    program: OsString,
+    args: Vec<String>,
+}
+
+impl Exec {
+    /// New up an Exec from a command line, the first element of which is
+    /// the program to run. The command line may not be empty.
+    pub fn new(command: Vec<String>) -> Result<Self, AmbleError> {
+        let mut command = command.into_iter();
+        let program = command.next()
+                             .ok_or_else(|| AmbleError::UnexpectedResult("no command supplied".to_string()))?;
+        Ok(Self { program: program.into(), args: command.collect() })
+    }
+
+    /// The arguments for the supplied path, with `{}` substituted.
+    pub fn args(&self, path: &Path) -> Vec<OsString> {
+        self.args.iter().map(|arg| substitute(arg, path)).collect()
+    }
+}
+
+impl Action for Exec {
+    fn verb(&self) -> &'static str {
+        "exec"
+    }
+
+    fn describe(&self, found: &FileMatch) -> String {
+        format!("exec {}", command_line(&self.program, &self.args(&found.path)))
+    }
+
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError> {
+        let status = Command::new(&self.program).args(self.args(&found.path)).status()?;
+        if !status.success() {
+            return Err(AmbleError::UnexpectedResult(format!("'{}' {}", self.program.to_string_lossy(), status)));
+        }
+        Ok(())
+    }
+
+    fn script(&self, found: &FileMatch) -> Option<Vec<Vec<OsString>>> {
+        let mut command = vec![self.program.clone()];
+        command.extend(self.args(&found.path));
+        Some(vec![command])
+    }
+}
+
+/// Runs a command for batches of matches, like `xargs` or `find -exec +`.
+/// An argument of `{}` is replaced by as many paths as fit within the
+/// argument limit; if there is no such argument, the paths are appended to
+/// the command. Each match is only settled once the command for its batch
+/// has run, and a batch whose command exits unsuccessfully fails each of
+/// its matches.
+#[derive(Debug)]
+pub struct ExecBatch {
+    exec: Exec,
+    // the number of bytes available for paths in each batch
+    capacity: usize,
+    batch: Vec<FileMatch>,
+    used: usize,
+    settled: Vec<Settled>,
+}
+
+impl ExecBatch {
+    /// New up an ExecBatch from a command line, the first element of which
+    /// is the program to run. The command line may not be empty.
+    pub fn new(command: Vec<String>) -> Result<Self, AmbleError> {
+        let exec = Exec::new(command)?;
+        let fixed = arg_bytes(&exec.program)
+            + exec.args.iter().map(|arg| arg_bytes(OsStr::new(arg))).sum::<usize>()
+            + std::env::vars_os().map(|(key, value)| arg_bytes(&key) + arg_bytes(&value)).sum::<usize>();
+        let capacity = EXEC_BATCH_BYTES.checked_sub(fixed).filter(|capacity| *capacity > 0).ok_or_else(|| {
+            AmbleError::UnexpectedResult("the command and environment leave no room for paths".to_string())
+        })?;
+        Ok(Self { exec, capacity, batch: Vec::new(), used: 0, settled: Vec::new() })
+    }
+
+    /// The arguments for the supplied batch of paths.
+    pub fn args(&self, paths: &[PathBuf]) -> Vec<OsString> {
+        let mut args = Vec::new();
+        let mut substituted = false;
+        for arg in &self.exec.args {
+            if arg == "{}" {
+                args.extend(paths.iter().map(OsString::from));
+                substituted = true;
+            } else {
+                args.push(OsString::from(arg));
+            }
+        }
+        if !substituted {
+            args.extend(paths.iter().map(OsString::from));
+        }
+        args
+    }
+
+    // Run the command for the matches batched so far, settling each of
+    // them.
+    fn flush(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let batch = std::mem::take(&mut self.batch);
+        self.used = 0;
+        let paths = batch.iter().map(|found| found.path.clone()).collect::<Vec<_>>();
+        let failure = match Command::new(&self.exec.program).args(self.args(&paths)).status() {
+            Ok(status) if status.success() => None,
+            Ok(status) => Some(format!("'{}' {} for a batch of {} files",
+                                       self.exec.program.to_string_lossy(), status, batch.len())),
+            Err(e) => Some(format!("unable to run '{}': {}", self.exec.program.to_string_lossy(), e)),
+        };
+        self.settled.extend(batch.into_iter().map(|found| {
+            let outcome = match &failure {
+                Some(failure) => Err(AmbleError::UnexpectedResult(failure.clone())),
+                None => Ok(()),
+            };
+            (found, outcome)
+        }));
+    }
+}
+
+impl Action for ExecBatch {
+    fn verb(&self) -> &'static str {
+        "exec"
+    }
+
+    fn describe(&self, found: &FileMatch) -> String {
+        let args = self.args(std::slice::from_ref(&found.path));
+        format!("exec {} (batched)", command_line(&self.exec.program, &args))
+    }
+
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError> {
+        let bytes = arg_bytes(found.path.as_os_str());
+        if self.used + bytes > self.capacity {
+            self.flush();
+        }
+        self.batch.push(found.clone());
+        self.used += bytes;
+        Ok(())
+    }
+
+    fn script(&self, found: &FileMatch) -> Option<Vec<Vec<OsString>>> {
+        let mut command = vec![self.exec.program.clone()];
+        command.extend(self.args(std::slice::from_ref(&found.path)));
+        Some(vec![command])
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        self.flush();
+        Ok(())
+    }
+
+    fn deferred(&self) -> bool {
+        true
+    }
+
+    fn settled(&mut self) -> Vec<Settled> {
+        std::mem::take(&mut self.settled)
+    }
+}
+
+// The space taken by an argument: its bytes, its terminating NUL, and the
+// pointer to it.
+fn arg_bytes(arg: &OsStr) -> usize {
+    arg.len() + 1 + std::mem::size_of::<usize>()
+}
+
+// Replace each occurrence of {} in arg with the path.
+fn substitute(arg: &str, path: &Path) -> OsString {
+    let mut pieces = arg.split("{}");
+    let mut result = OsString::from(pieces.next().unwrap_or_default());
+    for piece in pieces {
+        result.push(path);
+        result.push(piece);
+    }
+    result
+}
+
+// A command, from its leading arguments and the paths which follow them.
+fn command(args: &[&str], paths: &[&OsStr]) -> Vec<OsString> {
+    args.iter().map(OsString::from).chain(paths.iter().map(OsString::from)).collect()
+}
+
+// The command line, quoted for display.
+fn command_line(program: &OsStr, args: &[OsString]) -> String {
+    let mut line = shell_quote(program);
+    for arg in args {
+        line.push(' ');
+        line.push_str(&shell_quote(arg));
+    }
+    line
+}
+
+// Quote an argument for display, so that the command line may be pasted
+// into a POSIX shell.
+fn shell_quote(arg: &OsStr) -> String {
+    let arg = arg.to_string_lossy();
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.into_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Copies each match beneath a destination directory, recreating its path
+/// relative to the root it was found under, before handing it to another
+/// action, if any. Each copied file is verified against its original by
+/// size and SHA-256, and recorded in a mapping file of JSON lines,
+/// `amble-backup.jsonl`, in the destination, of absolute original path,
+/// absolute backup path, and checksum. A match is only handed to the other action once it has
+/// been backed up. Existing files at the destination are never
+/// overwritten.
+pub struct BackupTo {
+    dest: PathBuf,
+    roots: Vec<PathBuf>,
+    then: Option<Box<dyn Action>>,
+    mapping: Option<File>,
+}
+
+// A line of the mapping file of BackupTo
+#[derive(Debug, Serialize)]
+struct Backup {
+    #[serde(with = "crate::spill::path")]
+    original: PathBuf,
+    #[serde(with = "crate::spill::path")]
+    backup: PathBuf,
+    sha256: String,
+}
+
+impl BackupTo {
+    /// New up a BackupTo for the supplied destination, and the roots under
+    /// which matches are found.
+    pub fn new(dest: impl Into<PathBuf>, roots: Vec<PathBuf>) -> Self {
+        Self { dest: dest.into(), roots, then: None, mapping: None }
+    }
+
+    /// Set the action to apply to each match once it has been backed up.
+    pub fn then(&mut self, action: Box<dyn Action>) -> &mut Self {
+        self.then = Some(action);
+        self
+    }
+
+    /// The backup for the supplied path.
+    pub fn destination(&self, path: &Path) -> Result<PathBuf, AmbleError> {
+        Ok(self.dest.join(relative_path(path, &self.roots)?))
+    }
+
+    // Verify each file copied from src to dest, recording it in the
+    // mapping file.
+    fn verify(&mut self, src: &Path, dest: &Path) -> Result<(), AmbleError> {
+        for entry in WalkDir::new(src) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let copy = match entry.path().strip_prefix(src) {
+                Ok(relative) if !relative.as_os_str().is_empty() => dest.join(relative),
+                _ => dest.to_path_buf(),
+            };
+            let checksum = sha256(entry.path())?;
+            if fs::metadata(&copy)?.len() != entry.metadata()?.len() || sha256(&copy)? != checksum {
+                return Err(AmbleError::UnexpectedResult(format!("backup '{}' does not match", copy.display())));
+            }
+            let mapping = match &mut self.mapping {
+                Some(mapping) => mapping,
+                None => {
+                    let path = self.dest.join("amble-backup.jsonl");
+                    self.mapping.insert(File::options().append(true).create(true).open(path)?)
+                },
+            };
+            let backup = Backup { original: std::path::absolute(entry.path())?, backup: std::path::absolute(&copy)?,
+                                  sha256: checksum };
+            let mut line = serde_json::to_vec(&backup)?;
+            line.push(b'\n');
+            mapping.write_all(&line)?;
+        }
+        Ok(())
+    }
+}
+
+impl Action for BackupTo {
+    fn verb(&self) -> &'static str {
+        self.then.as_ref().map_or("backup", |then| then.verb())
+    }
+
+    fn describe(&self, found: &FileMatch) -> String {
+        let backup = match self.destination(&found.path) {
+            Ok(dest) => format!("backup {} -> {}", found.path.display(), dest.display()),
+            Err(_) => format!("backup {}", found.path.display()),
+        };
+        match &self.then {
+            Some(then) => format!("{}, then {}", backup, then.describe(found)),
+            None => backup,
+        }
+    }
+
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError> {
+        let dest = self.destination(&found.path)?;
+        if fs::symlink_metadata(&dest).is_ok() {
+            return Err(AmbleError::IoError(format!("'{}' already exists", dest.display())));
+        }
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        copy_all(&found.path, &dest)?;
+        self.verify(&found.path, &dest)?;
+        match &mut self.then {
+            Some(then) => then.apply(found),
+            None => Ok(()),
+        }
+    }
+
+    fn script(&self, found: &FileMatch) -> Option<Vec<Vec<OsString>>> {
+        let dest = self.destination(&found.path).ok()?;
+        let parent = dest.parent()?;
+        let mut commands = vec![command(&["mkdir", "-p", "--"], &[parent.as_os_str()]),
+                                command(&["cp", "-pR", "--"], &[found.path.as_os_str(), dest.as_os_str()])];
+        if let Some(then) = &self.then {
+            commands.extend(then.script(found)?);
+        }
+        Some(commands)
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        if let Some(mapping) = &self.mapping {
+            mapping.sync_all()?;
+        }
+        match &mut self.then {
+            Some(then) => then.finish(),
+            None => Ok(()),
+        }
+    }
+
+    fn deferred(&self) -> bool {
+        self.then.as_ref().is_some_and(|then| then.deferred())
+    }
+
+    fn settled(&mut self) -> Vec<Settled> {
+        self.then.as_mut().map(|then| then.settled()).unwrap_or_default()
+    }
+}
+
+/// Wraps an action, recording each file it is applied to in a log of JSON
+/// lines, of time (in seconds since the epoch), verb, absolute path, size,
+/// and optionally SHA-256, computed before the action is applied. Paths
+/// are kept intact whether or not they are valid unicode. This leaves a
+/// forensic record of exactly what content was removed. The files beneath
+/// directories, which are only matched when rolled up, are recorded
+/// individually. Files are only recorded once the action has succeeded,
+/// which, for deferred actions, is once they are settled.
+pub struct ActionLog {
+    action: Box<dyn Action>,
+    path: PathBuf,
+    checksums: bool,
+    log: Option<File>,
+    // the entries of the matches handed to a deferred action, until settled
+    pending: HashMap<PathBuf, Vec<(PathBuf, u64, Option<String>)>>,
+}
+
+// A line of the action log
+#[derive(Debug, Serialize)]
+struct Logged<'a> {
+    time: u64,
+    verb: &'a str,
+    #[serde(with = "crate::spill::path")]
+    path: PathBuf,
+    size: u64,
+    sha256: Option<String>,
+}
+
+impl ActionLog {
+    /// New up an ActionLog wrapping the supplied action, and appending to
+    /// the log at the supplied path.
+    pub fn new(action: Box<dyn Action>, path: impl Into<PathBuf>) -> Self {
+        Self { action, path: path.into(), checksums: false, log: None, pending: HashMap::new() }
+    }
+
+    /// Record the SHA-256 of each file. Otherwise null is recorded.
+    pub fn checksums(&mut self, checksums: bool) -> &mut Self {
+        self.checksums = checksums;
+        self
+    }
+
+    // The size and checksum of each file beneath the match, ahead of the
+    // action being applied.
+    fn entries(&self, found: &FileMatch) -> Result<Vec<(PathBuf, u64, Option<String>)>, AmbleError> {
+        let mut entries = Vec::new();
+        for entry in WalkDir::new(&found.path) {
+            let entry = entry?;
+            if entry.file_type().is_dir() {
+                continue;
+            }
+            let checksum = if self.checksums && entry.file_type().is_file() {
+                Some(sha256(entry.path())?)
+            } else {
+                None
+            };
+            // absolute, so that the log may be read from anywhere
+            entries.push((std::path::absolute(entry.path())?, entry.metadata()?.len(), checksum));
+        }
+        Ok(entries)
+    }
+
+    // Record the entries of a match the action has been applied to.
+    fn record(&mut self, entries: Vec<(PathBuf, u64, Option<String>)>) -> Result<(), AmbleError> {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let log = match &mut self.log {
+            Some(log) => log,
+            None => self.log.insert(File::options().append(true).create(true).open(&self.path)?),
+        };
+        for (path, size, sha256) in entries {
+            let mut line = serde_json::to_vec(&Logged { time, verb: self.action.verb(), path, size, sha256 })?;
+            line.push(b'\n');
+            log.write_all(&line)?;
+        }
+        Ok(())
+    }
+}
+
+impl Action for ActionLog {
+    fn verb(&self) -> &'static str {
+        self.action.verb()
+    }
+
+    fn describe(&self, found: &FileMatch) -> String {
+        self.action.describe(found)
+    }
+
+    fn script(&self, found: &FileMatch) -> Option<Vec<Vec<OsString>>> {
+        self.action.script(found)
+    }
+
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError> {
+        let entries = self.entries(found)?;
+        self.action.apply(found)?;
+        if self.action.deferred() {
+            self.pending.insert(found.path.clone(), entries);
+            return Ok(());
+        }
+        self.record(entries)
+    }
+
+    // the matches of a deferred action are recorded, and synced, as they
+    // are settled
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        if let Some(log) = &self.log {
+            log.sync_all()?;
+        }
+        self.action.finish()
+    }
+
+    fn deferred(&self) -> bool {
+        self.action.deferred()
+    }
+
+    fn settled(&mut self) -> Vec<Settled> {
+        let mut settled = self.action.settled();
+        for (found, outcome) in &mut settled {
+            let entries = self.pending.remove(&found.path).unwrap_or_default();
+            if outcome.is_ok() {
+                if let Err(e) = self.record(entries) {
+                    *outcome = Err(e);
+                }
+            }
+        }
+        if let Some(log) = &self.log {
+            if let Err(e) = log.sync_all() {
+                eprintln!("Warning: unable to sync the action log '{}': {}", self.path.display(), e);
+            }
+        }
+        settled
+    }
+}
+
+/// Wraps an action which removes matches, such as Delete or MoveTo,
+/// removing the directories left empty once it has finished. Only the
+/// parents of matches are candidates, and the search never climbs above,
+/// or removes, the root the match was found under.
+pub struct PruneEmptyDirs {
+    action: Box<dyn Action>,
+    roots: Vec<PathBuf>,
+    parents: BTreeSet<PathBuf>,
+    // the matches a deferred action settled as it finished
+    settled: Vec<Settled>,
+}
+
+impl PruneEmptyDirs {
+    /// New up a PruneEmptyDirs wrapping the supplied action, for matches
+    /// found under the supplied roots.
+    pub fn new(action: Box<dyn Action>, roots: Vec<PathBuf>) -> Self {
+        Self { action, roots, parents: BTreeSet::new(), settled: Vec::new() }
+    }
+
+    // Remove the directory, and then each of its ancestors in turn, while
+    // they are empty and beneath the root.
+    fn prune(&self, dir: &Path) -> u64 {
+        let root = match self.roots.iter().filter(|root| dir.starts_with(root)).max_by_key(|root| root.as_os_str().len()) {
+            Some(root) => root,
+            None => return 0,
+        };
+        let mut pruned = 0;
+        for dir in dir.ancestors().take_while(|dir| *dir != root.as_path() && dir.starts_with(root)) {
+            // remove_dir fails on directories which are not empty
+            if fs::remove_dir(dir).is_err() {
+                break;
+            }
+            pruned += 1;
+        }
+        pruned
+    }
+
+    // Note the parents of the matches the action succeeded for.
+    fn note(&mut self, settled: &[Settled]) {
+        let parents = settled.iter().filter(|(_, outcome)| outcome.is_ok()).filter_map(|(found, _)| found.path.parent());
+        self.parents.extend(parents.map(Path::to_path_buf));
+    }
+}
+
+impl Action for PruneEmptyDirs {
+    fn verb(&self) -> &'static str {
+        self.action.verb()
+    }
+
+    fn describe(&self, found: &FileMatch) -> String {
+        self.action.describe(found)
+    }
+
+    fn script(&self, found: &FileMatch) -> Option<Vec<Vec<OsString>>> {
+        self.action.script(found)
+    }
+
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError> {
+        self.action.apply(found)?;
+        if let (false, Some(parent)) = (self.action.deferred(), found.path.parent()) {
+            self.parents.insert(parent.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        self.action.finish()?;
+        let settled = self.action.settled();
+        self.note(&settled);
+        self.settled.extend(settled);
+        // deepest first, so that a parent is tried after its children
+        let mut parents: Vec<PathBuf> = std::mem::take(&mut self.parents).into_iter().collect();
+        parents.sort_by_key(|parent| std::cmp::Reverse(parent.components().count()));
+        let pruned: u64 = parents.iter().map(|parent| self.prune(parent)).sum();
+        println!("# pruned {} empty directories", pruned);
+        Ok(())
+    }
+
+    fn deferred(&self) -> bool {
+        self.action.deferred()
+    }
+
+    fn settled(&mut self) -> Vec<Settled> {
+        let settled = self.action.settled();
+        self.note(&settled);
+        let mut all = std::mem::take(&mut self.settled);
+        all.extend(settled);
+        all
+    }
+}
+
+/// The path relative to the longest of the roots which contains it, for
+/// actions which recreate paths elsewhere. If no root contains it, this is
+/// the path without its root or prefix. A path which is itself a root is
+/// placed by name. Paths which would escape their new location are an error.
+pub(crate) fn relative_path(path: &Path, roots: &[PathBuf]) -> Result<PathBuf, AmbleError> {
+    let relative = relative_to_roots(path, roots);
+    if relative.components().any(|component| component == Component::ParentDir) {
+        return Err(AmbleError::UnexpectedResult(
+            format!("'{}' cannot be placed beneath the destination", path.display())));
+    }
+    Ok(relative)
+}
+
+fn relative_to_roots(path: &Path, roots: &[PathBuf]) -> PathBuf {
+    let relative = roots.iter()
+                        .filter_map(|root| path.strip_prefix(root).ok())
+                        .min_by_key(|relative| relative.components().count());
+    match relative {
+        Some(relative) if relative.as_os_str().is_empty() => {
+            path.file_name().map(PathBuf::from).unwrap_or_default()
+        },
+        Some(relative) => relative.to_path_buf(),
+        None => path.components()
+                    .filter(|component| !matches!(component, Component::Prefix(_) | Component::RootDir | Component::CurDir))
+                    .collect(),
+    }
+}
+
+// Move src to dest, copying and then removing src if they are on
+// different filesystems.
+pub(crate) fn relocate(src: &Path, dest: &Path) -> Result<(), AmbleError> {
+    match fs::rename(src, dest) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_all(src, dest)?;
+            if fs::symlink_metadata(src)?.is_dir() {
+                fs::remove_dir_all(src)?;
+            } else {
+                fs::remove_file(src)?;
+            }
+            Ok(())
+        },
+        result => Ok(result?),
+    }
+}
+
+// A path beside the supplied path for a temporary file, unique to this
+// run, so that it does not name a file left behind by an interrupted one.
+// It may still name an unrelated file, so it is only ever created anew.
+#[cfg(any(unix, feature = "compress"))]
+pub(crate) fn temp_beside(path: &Path) -> PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+    static TEMPS: AtomicUsize = AtomicUsize::new(0);
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".amble-tmp-{}-{}", std::process::id(), TEMPS.fetch_add(1, AtomicOrdering::Relaxed)));
+    PathBuf::from(temp)
+}
+
+// Copy src to dest, recursively if src is a directory, preserving times.
+fn copy_all(src: &Path, dest: &Path) -> Result<(), AmbleError> {
+    for entry in WalkDir::new(src) {
+        let entry = entry?;
+        let target = match entry.path().strip_prefix(src) {
+            Ok(relative) if !relative.as_os_str().is_empty() => dest.join(relative),
+            _ => dest.to_path_buf(),
+        };
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            fs::create_dir_all(&target)?;
+        } else if file_type.is_symlink() {
+            copy_link(entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+            let metadata = entry.metadata()?;
+            let times = FileTimes::new().set_accessed(metadata.accessed()?)
+                                        .set_modified(metadata.modified()?);
+            File::options().write(true).open(&target)?.set_times(times)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_link(src: &Path, dest: &Path) -> Result<(), AmbleError> {
+    std::os::unix::fs::symlink(fs::read_link(src)?, dest)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_link(src: &Path, dest: &Path) -> Result<(), AmbleError> {
+    fs::copy(src, dest)?;
+    Ok(())
+}
+
+/// Tally of the matches handed to an action.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Number of files the action was applied to. A rolled up directory
+    /// counts the files beneath it
+    pub applied: u64,
+    /// Total apparent size, in bytes, of those matches
+    pub bytes: u64,
+    /// Number of matches for which the action failed
+    pub errors: u64,
+    /// Number of matches declined when confirming interactively
+    pub skipped: u64,
+}
+
+/// A limit on the rate at which an action is applied, parsed from a number
+/// of operations per second (eg "50/s"), or a number of bytes per second,
+/// with a unit as accepted for sizes (eg "20MB/s"). The "/s" is optional.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Throttle {
+    /// Matches per second
+    Ops(f64),
+    /// Bytes of matches per second
+    Bytes(f64),
+}
+
+impl Throttle {
+    // The cost of a match against the limit.
+    fn cost(self, found: &FileMatch) -> f64 {
+        match self {
+            Throttle::Ops(_) => 1.0,
+            Throttle::Bytes(_) => found.size as f64,
+        }
+    }
+
+    // The limit, in units of cost per second.
+    fn rate(self) -> f64 {
+        match self {
+            Throttle::Ops(rate) | Throttle::Bytes(rate) => rate,
+        }
+    }
+}
+
+impl FromStr for Throttle {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AmbleError::ParseError(format!("invalid throttle '{}'. Expected eg 50/s or 20MB/s", s));
+        let rate = s.trim();
+        let rate = rate.strip_suffix("/s").unwrap_or(rate).trim();
+        let throttle = match rate.parse::<f64>() {
+            Ok(ops) => Throttle::Ops(ops),
+            Err(_) => Throttle::Bytes(rate.parse::<Size>().map_err(|_| invalid())?.0 as f64),
+        };
+        if !(throttle.rate().is_finite() && throttle.rate() > 0.0) {
+            return Err(invalid());
+        }
+        Ok(throttle)
+    }
+}
+
+// Paces matches so that, on average, the limit is not exceeded.
+#[derive(Debug)]
+struct Pacer {
+    throttle: Throttle,
+    started: Option<Instant>,
+    spent: f64,
+}
+
+impl Pacer {
+    // Wait until the match may be applied.
+    fn wait(&mut self, found: &FileMatch) {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let due = started + Duration::from_secs_f64(self.spent / self.throttle.rate());
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+        self.spent += self.throttle.cost(found);
+    }
+}
+
+// The answer to an interactive prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    Yes,
+    No,
+    All,
+    Quit,
+}
+
+/// Sink which applies an action to each match. In a dry run, the default,
+/// each match is reported along with what the action would do, but the
+/// action is not applied. Failures are reported per file, and do not stop
+/// the search. A summary is printed once the search finishes.
+pub struct Apply<A: Action> {
+    action: A,
+    dry_run: bool,
+    // whether the dry run was forced, so that it cannot be confirmed
+    forced: bool,
+    summary: Summary,
+    // when confirming interactively, where answers are read from
+    prompt: Option<Box<dyn BufRead + Send>>,
+    // the answer which applies to every remaining match, once given
+    answer: Option<Answer>,
+    pacer: Option<Pacer>,
+}
+
+impl<A: Action> Apply<A> {
+    /// New up an Apply for the supplied action, performing a dry run.
+    pub fn new(action: A) -> Self {
+        Self { action, dry_run: true, forced: false, summary: Summary::default(), prompt: None, answer: None,
+               pacer: None }
+    }
+
+    /// Set whether or not to perform a dry run.
+    pub fn dry_run(&mut self, dry_run: bool) -> &mut Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Set whether the dry run was forced, as by --dry-run, in which case
+    /// the summary does not suggest confirming it.
+    pub fn forced(&mut self, forced: bool) -> &mut Self {
+        self.forced = forced;
+        self
+    }
+
+    /// Confirm each match before applying the action to it, like `rm -i`,
+    /// reading answers from the supplied reader. Each match may be
+    /// accepted (y), declined (n), accepted along with every remaining
+    /// match (a), or declined along with every remaining match (q). When
+    /// directories are rolled up, each directory is confirmed as a whole.
+    /// This only has an effect when not performing a dry run.
+    pub fn interactive(&mut self, prompt: Box<dyn BufRead + Send>) -> &mut Self {
+        self.prompt = Some(prompt);
+        self
+    }
+
+    /// Limit the rate at which the action is applied, so that large
+    /// actions may run without swamping shared storage.
+    pub fn throttle(&mut self, throttle: Throttle) -> &mut Self {
+        self.pacer = Some(Pacer { throttle, started: None, spent: 0.0 });
+        self
+    }
+
+    // Ask whether to apply the action to the match. Reaching the end of
+    // the answers is taken as quitting.
+    fn confirm(&mut self, found: &FileMatch) -> Result<bool, AmbleError> {
+        let prompt = match (&mut self.prompt, self.answer) {
+            (None, _) | (_, Some(Answer::All)) => return Ok(true),
+            (_, Some(Answer::Quit)) => return Ok(false),
+            (Some(prompt), _) => prompt,
+        };
+        loop {
+            eprint!("{}? [y/n/a/q] ", self.action.describe(found));
+            io::stderr().flush()?;
+            let mut line = String::new();
+            let answer = if prompt.read_line(&mut line)? == 0 {
+                eprintln!();
+                Answer::Quit
+            } else {
+                match line.trim().to_lowercase().as_str() {
+                    "y" | "yes" => Answer::Yes,
+                    "n" | "no" => Answer::No,
+                    "a" | "all" => Answer::All,
+                    "q" | "quit" => Answer::Quit,
+                    _ => continue,
+                }
+            };
+            if answer == Answer::All || answer == Answer::Quit {
+                self.answer = Some(answer);
+            }
+            return Ok(answer == Answer::Yes || answer == Answer::All);
+        }
+    }
+
+    /// The tally of the matches handed to the action so far.
+    pub fn summary(&self) -> Summary {
+        self.summary
+    }
+
+    // Report the matches a deferred action has settled.
+    fn settle(&mut self) {
+        for (found, outcome) in self.action.settled() {
+            match outcome {
+                Ok(()) => {
+                    println!("{}", self.action.describe(&found));
+                    self.summary.applied += found.contents.unwrap_or(1);
+                    self.summary.bytes += found.size;
+                },
+                Err(e) => {
+                    eprintln!("Error: unable to {} '{}': {}", self.action.verb(), found.path.display(), e);
+                    self.summary.errors += 1;
+                },
+            }
+        }
+    }
+}
+
+impl<A: Action> Sink for Apply<A> {
+    fn begin(&mut self, availability: &Availability) -> Result<(), AmbleError> {
+        if availability.is_degraded() {
+            println!("# fields: {}", availability);
+        }
+        Ok(())
+    }
+
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        if self.dry_run {
+            println!("[dry run] would {}", self.action.describe(&found));
+        } else {
+            if !self.confirm(&found)? {
+                self.summary.skipped += 1;
+                return Ok(());
+            }
+            if let Some(pacer) = &mut self.pacer {
+                pacer.wait(&found);
+            }
+            if let Err(e) = self.action.apply(&found) {
+                eprintln!("Error: unable to {} '{}': {}", self.action.verb(), found.path.display(), e);
+                self.summary.errors += 1;
+                return Ok(());
+            }
+            // deferred matches are reported once settled
+            if self.action.deferred() {
+                self.settle();
+                return Ok(());
+            }
+            println!("{}", self.action.describe(&found));
+        }
+        self.summary.applied += found.contents.unwrap_or(1);
+        self.summary.bytes += found.size;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        if !self.dry_run {
+            self.action.finish()?;
+            self.settle();
+        }
+        let summary = SummaryLine { verb: self.action.verb(), dry_run: self.dry_run, forced: self.forced,
+                                    summary: self.summary };
+        println!("{}", summary);
+        Ok(())
+    }
+
+    fn errors(&self) -> u64 {
+        self.summary.errors
+    }
+}
+
+// The summary, as printed once the search finishes
+struct SummaryLine {
+    verb: &'static str,
+    dry_run: bool,
+    forced: bool,
+    summary: Summary,
+}
+
+impl fmt::Display for SummaryLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.dry_run {
+            write!(f, "# [dry run] would {} {} files ({} bytes)",
+                   self.verb, self.summary.applied, self.summary.bytes)?;
+            if !self.forced {
+                write!(f, ". Use --yes to {} them", self.verb)?;
+            }
+            Ok(())
+        } else {
+            write!(f, "# {}: {} files ({} bytes), {} errors",
+                   self.verb, self.summary.applied, self.summary.bytes, self.summary.errors)?;
+            if self.summary.skipped > 0 {
+                write!(f, ", {} skipped", self.summary.skipped)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Sink which writes a shell script of the commands which would apply an
+/// action to each match, rather than applying it, so that the script may
+/// be reviewed and run by hand. Arguments are quoted byte for byte, so
+/// paths which are not valid UTF-8 survive. Actions which cannot be
+/// expressed as commands are an error.
+pub struct Script {
+    action: Box<dyn Action>,
+    path: PathBuf,
+    writer: io::BufWriter<File>,
+    summary: Summary,
+}
+
+impl Script {
+    /// New up a Script for the supplied action, writing to the file at path.
+    pub fn new(action: Box<dyn Action>, path: impl Into<PathBuf>) -> Result<Self, AmbleError> {
+        let path = path.into();
+        let file = File::create(&path).map_err(|e| {
+            AmbleError::IoError(format!("unable to write script '{}': {}", path.display(), e))
+        })?;
+        Ok(Self { action, path, writer: io::BufWriter::new(file), summary: Summary::default() })
+    }
+}
+
+impl Sink for Script {
+    fn begin(&mut self, _availability: &Availability) -> Result<(), AmbleError> {
+        writeln!(self.writer, "#!/bin/sh")?;
+        writeln!(self.writer, "# {} the matches found by amble. Review before running.", self.action.verb())?;
+        Ok(())
+    }
+
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        let commands = self.action.script(&found).ok_or_else(|| {
+            AmbleError::UnexpectedResult(format!("unable to {} '{}' from a script", self.action.verb(), found.path.display()))
+        })?;
+        for (index, command) in commands.iter().enumerate() {
+            if index > 0 {
+                self.writer.write_all(b" && ")?;
+            }
+            for (index, arg) in command.iter().enumerate() {
+                if index > 0 {
+                    self.writer.write_all(b" ")?;
+                }
+                self.writer.write_all(&quote_bytes(arg))?;
+            }
+        }
+        self.writer.write_all(b"\n")?;
+        self.summary.applied += found.contents.unwrap_or(1);
+        self.summary.bytes += found.size;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        self.writer.flush()?;
+        println!("# script to {} {} files ({} bytes) written to {}",
+                 self.action.verb(), self.summary.applied, self.summary.bytes, self.path.display());
+        Ok(())
+    }
+}
+
+// Quote an argument for a POSIX shell, byte for byte.
+fn quote_bytes(arg: &OsStr) -> Vec<u8> {
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(arg).to_vec();
+    #[cfg(not(unix))]
+    let bytes = arg.to_string_lossy().into_owned().into_bytes();
+
+    let safe = |byte: &u8| byte.is_ascii_alphanumeric() || b"-_./=:,+@%".contains(byte);
+    if !bytes.is_empty() && bytes.iter().all(safe) {
+        return bytes;
+    }
+    let mut quoted = vec![b'\''];
+    for byte in bytes {
+        if byte == b'\'' {
+            quoted.extend_from_slice(b"'\\''");
+        } else {
+            quoted.push(byte);
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{syncwalk::SyncSearch, testing::{age, TempTree}, traits::Finder};
+
+    #[test]
+    fn dry_run_changes_nothing() {
+        let tree = TempTree::new("dry-run");
+        let path = tree.file("old.log", b"stale");
+        let mut apply = Apply::new(Delete);
+        apply.accept(FileMatch::new(&path, 5)).unwrap();
+        apply.finish().unwrap();
+        assert!(path.exists());
+        assert_eq!(apply.summary().applied, 1);
+    }
+
+    #[test]
+    fn delete_removes_matches() {
+        let tree = TempTree::new("delete");
+        let path = tree.file("old.log", b"stale");
+        let mut apply = Apply::new(Delete);
+        apply.dry_run(false);
+        apply.accept(FileMatch::new(&path, 5)).unwrap();
+        apply.finish().unwrap();
+        assert!(!path.exists());
+        assert_eq!(apply.summary(), Summary { applied: 1, bytes: 5, errors: 0, skipped: 0 });
+    }
+
+    #[test]
+    fn rollup_refuses_incomplete_directories() {
+        let tree = TempTree::new("rollup");
+        let full = [tree.file("full/a", b"a"), tree.file("full/b", b"b")];
+        let matched = tree.file("part/c", b"c");
+        let kept = tree.file("part/d", b"d");
+        age(&kept, 30);
+        let mut apply = Apply::new(Delete);
+        apply.dry_run(false);
+        SyncSearch::new(tree.path()).access(false).create(false).modify(true).days(1.0).rollup(true)
+                                     .find_into(&mut apply).unwrap();
+        // the complete directory is deleted whole, and the incomplete one
+        // only loses its matches
+        assert!(!full[0].parent().unwrap().exists());
+        assert!(!matched.exists());
+        assert!(kept.exists());
+    }
+
+    #[cfg(unix)]
+    fn inode(path: &Path) -> u64 {
+        std::os::unix::fs::MetadataExt::ino(&fs::metadata(path).unwrap())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dedupe_links_identical_files() {
+        let tree = TempTree::new("dedupe");
+        let first = tree.file("a", b"same");
+        let second = tree.file("b", b"same");
+        let other = tree.file("c", b"diff");
+        let mut dedupe = Dedupe::default();
+        for path in [&first, &second, &other] {
+            dedupe.apply(&FileMatch::new(path, 4)).unwrap();
+        }
+        assert_eq!(inode(&first), inode(&second));
+        assert_ne!(inode(&first), inode(&other));
+        assert_eq!((dedupe.linked, dedupe.reclaimed), (1, 4));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dedupe_leaves_files_changed_since_found() {
+        let tree = TempTree::new("dedupe-changed");
+        let first = tree.file("a", b"same");
+        let second = tree.file("b", b"same");
+        let mut dedupe = Dedupe::default();
+        dedupe.apply(&FileMatch::new(&first, 4)).unwrap();
+        age(&first, 1);
+        assert!(dedupe.apply(&FileMatch::new(&second, 4)).is_err());
+        assert_ne!(inode(&first), inode(&second));
+        assert_eq!(fs::read(&second).unwrap(), b"same");
+        assert_eq!(fs::read_dir(tree.path()).unwrap().count(), 2);
+    }
+}
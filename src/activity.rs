@@ -0,0 +1,178 @@
+//! activity.rs
+//!
+//! The last activity of directories. Rather than asking which files are
+//! old, we often want to know which directories are dead: those in which
+//! nothing has been accessed or modified for some time. LastActivity
+//! receives every file beneath the roots, tracks the newest access and
+//! modification time beneath each directory, and once the search completes,
+//! reports the directories whose last activity is older than the
+//! threshold. A dead directory within a dead directory is implied by its
+//! parent, so only the outermost are reported. The activity of each
+//! directory is spilled to temporary files beyond a limit on the memory it
+//! takes (see spill.rs), so that trees of many millions of directories are
+//! reported in bounded memory.
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{constants::BUFFER_BYTES, errors::AmbleError, fields::{Availability, Field}, filematch::FileMatch,
+            spill::{self, optional_time, Combine, Spill, SpillMap}, traits::Sink};
+
+// A directory, ordered by its path, so that each comes before those
+// beneath it.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+struct Dir(#[serde(with = "spill::path")] PathBuf);
+
+impl Spill for Dir {
+    fn footprint(&self) -> usize {
+        mem::size_of::<Self>() + self.0.as_os_str().len()
+    }
+}
+
+// The activity beneath a directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Activity {
+    size: u64,
+    allocated: u64,
+    files: u64,
+    #[serde(with = "optional_time")]
+    accessed: Option<SystemTime>,
+    #[serde(with = "optional_time")]
+    modified: Option<SystemTime>,
+}
+
+impl Activity {
+    // The activity of a single match.
+    fn of(found: &FileMatch) -> Self {
+        Self { size: found.size, allocated: found.allocated, files: found.contents.unwrap_or(1),
+               accessed: found.accessed, modified: found.modified }
+    }
+}
+
+impl Combine for Activity {
+    fn combine(&mut self, other: Self) {
+        self.size += other.size;
+        self.allocated += other.allocated;
+        self.files += other.files;
+        self.accessed = self.accessed.max(other.accessed);
+        self.modified = self.modified.max(other.modified);
+    }
+}
+
+impl Spill for Activity {
+    fn footprint(&self) -> usize {
+        mem::size_of::<Self>()
+    }
+}
+
+/// Sink which reports the directories beneath the roots whose newest
+/// access or modification time is older than a duration, handing a
+/// match for each on to another sink. The search feeding it should match
+/// every file (see `every` on the searches).
+#[derive(Debug)]
+pub struct LastActivity<S: Sink> {
+    sink: S,
+    roots: Vec<PathBuf>,
+    idle: Duration,
+    fields: Vec<Field>,
+    now: SystemTime,
+    dirs: SpillMap<Dir, Activity>,
+    buffer_limit: u64,
+}
+
+impl<S: Sink> LastActivity<S> {
+    /// New up a LastActivity reporting the directories beneath the
+    /// supplied roots which have been idle for at least `idle`.
+    pub fn new(sink: S, roots: Vec<PathBuf>, idle: Duration) -> Self {
+        Self {
+            sink,
+            roots,
+            idle,
+            fields: vec![Field::Access, Field::Modify],
+            now: SystemTime::now(),
+            dirs: SpillMap::new(BUFFER_BYTES),
+            buffer_limit: BUFFER_BYTES,
+        }
+    }
+
+    /// The fields which count as activity, of Access and Modify. Both
+    /// count by default.
+    pub fn fields(&mut self, fields: Vec<Field>) -> &mut Self {
+        self.fields = fields;
+        self
+    }
+
+    /// Compute idle times relative to the supplied instant, rather than
+    /// when the LastActivity was created.
+    pub fn as_of(&mut self, as_of: SystemTime) -> &mut Self {
+        self.now = as_of;
+        self
+    }
+
+    /// Set the number of bytes of directories which may be held in
+    /// memory, beyond which they are spilled to temporary files. The
+    /// default is BUFFER_BYTES.
+    pub fn buffer_limit(&mut self, limit: u64) -> &mut Self {
+        self.dirs = SpillMap::new(limit);
+        self.buffer_limit = limit;
+        self
+    }
+
+    // The directories containing the supplied path, up to and including
+    // the root it was found beneath.
+    fn dirs_of<'a>(&self, path: &'a Path) -> impl Iterator<Item = &'a Path> {
+        let root = self.roots.iter().filter(|root| path.starts_with(root)).max_by_key(|root| root.as_os_str().len());
+        let depth = root.map_or(1, |root| path.components().count() - root.components().count());
+        path.ancestors().skip(1).take(depth)
+    }
+
+    // The newest activity beneath the directory, if any of it is known.
+    fn last_activity(&self, activity: &Activity) -> Option<SystemTime> {
+        let accessed = activity.accessed.filter(|_| self.fields.contains(&Field::Access));
+        let modified = activity.modified.filter(|_| self.fields.contains(&Field::Modify));
+        accessed.max(modified)
+    }
+}
+
+impl<S: Sink> Sink for LastActivity<S> {
+    fn begin(&mut self, availability: &Availability) -> Result<(), AmbleError> {
+        self.sink.begin(availability)
+    }
+
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        let dirs: Vec<PathBuf> = self.dirs_of(&found.path).map(Path::to_path_buf).collect();
+        for dir in dirs {
+            self.dirs.add(Dir(dir), Activity::of(&found))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        // directories come in order, each before those beneath it
+        let dirs = mem::replace(&mut self.dirs, SpillMap::new(self.buffer_limit)).aggregated()?;
+        let mut reported: Option<PathBuf> = None;
+        for dir in dirs {
+            let (Dir(dir), activity) = dir?;
+            let dead = self.last_activity(&activity).is_some_and(|last| {
+                self.now.duration_since(last).unwrap_or_default() >= self.idle
+            });
+            if !dead || reported.as_ref().is_some_and(|parent| dir.starts_with(parent)) {
+                continue;
+            }
+            let mut found = FileMatch::new(dir.clone(), activity.size);
+            found.allocated = activity.allocated;
+            found.contents = Some(activity.files);
+            found.accessed = activity.accessed;
+            found.modified = activity.modified;
+            self.sink.accept(found)?;
+            reported = Some(dir);
+        }
+        self.sink.finish()
+    }
+
+    fn errors(&self) -> u64 {
+        self.sink.errors()
+    }
+}
@@ -0,0 +1,152 @@
+//! adaptive.rs
+//!
+//! Adaptive concurrency for the async search. The search starts with as
+//! many threads as cores, each taking a permit before it processes an
+//! entry, and the time taken to read each entry's metadata is measured.
+//! Reads this slow are waiting on a device or server rather than the
+//! cache, so fewer permits are tried. By Little's law, the rate at which
+//! entries are read is the number of permits over the latency of a read.
+//! When threads are piling requests onto an NFS server, the latency falls
+//! with the number of permits, so the rate holds, and fewer are kept. When
+//! the rate falls instead, the threads were being put to use, so the
+//! permit is handed back, and held for a while before trying fewer again.
+//! Once reads are fast again, permits are handed back up to the number of
+//! threads.
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// Number of ops whose latency is averaged before the limit is adjusted
+const SAMPLES: u32 = 256;
+// Average latency beyond which ops are taken to be waiting on I/O
+const SLOW_OP: Duration = Duration::from_micros(500);
+// Fraction of the rate of reads which may be lost by working with fewer
+// threads before they are kept
+const TOLERANCE: f64 = 0.05;
+// Number of adjustments to hold the limit for, once it has been raised back
+const HOLD: u32 = 16;
+
+#[derive(Debug)]
+struct State {
+    // number of permits which may be held at once
+    limit: usize,
+    // the lowest the limit has been
+    lowest: usize,
+    // number of permits held
+    active: usize,
+    // the latency of the ops since the limit was last adjusted
+    total: Duration,
+    samples: u32,
+    // the limit, and the rate of reads, before the limit was last lowered
+    previous: Option<(usize, f64)>,
+    // number of adjustments left to hold the limit for
+    hold: u32,
+}
+
+/// Limits the number of threads working at once, adapting the limit to the
+/// latency of their ops. When disabled, every thread works at once.
+#[derive(Debug)]
+pub(crate) struct Governor {
+    enabled: bool,
+    threads: usize,
+    state: Mutex<State>,
+    freed: Condvar,
+}
+
+/// A permit to work, which is returned to the Governor when dropped.
+pub(crate) struct Permit<'a> {
+    governor: Option<&'a Governor>,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if let Some(governor) = self.governor {
+            governor.lock().active -= 1;
+            governor.freed.notify_one();
+        }
+    }
+}
+
+impl Governor {
+    /// New up a Governor for the supplied number of threads, all of which
+    /// may work at once to begin with.
+    pub(crate) fn new(enabled: bool, threads: usize) -> Self {
+        let state = State { limit: threads, lowest: threads, active: 0, total: Duration::ZERO, samples: 0,
+                            previous: None, hold: 0 };
+        Self { enabled, threads, state: Mutex::new(state), freed: Condvar::new() }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Wait for a permit to work.
+    pub(crate) fn acquire(&self) -> Permit<'_> {
+        if !self.enabled {
+            return Permit { governor: None };
+        }
+        let mut state = self.lock();
+        while state.active >= state.limit {
+            state = self.freed.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        state.active += 1;
+        Permit { governor: Some(self) }
+    }
+
+    /// Perform an op, such as reading metadata, recording its latency.
+    pub(crate) fn time<T>(&self, op: impl FnOnce() -> T) -> T {
+        if !self.enabled {
+            return op();
+        }
+        let started = Instant::now();
+        let result = op();
+        self.record(started.elapsed());
+        result
+    }
+
+    // Record the latency of an op, adjusting the limit once enough have
+    // been recorded.
+    fn record(&self, latency: Duration) {
+        let mut state = self.lock();
+        state.total += latency;
+        state.samples += 1;
+        if state.samples < SAMPLES {
+            return;
+        }
+        let average = state.total / state.samples;
+        let rate = state.limit as f64 / average.as_secs_f64().max(f64::EPSILON);
+        state.total = Duration::ZERO;
+        state.samples = 0;
+        let raise = match state.previous.take() {
+            // reads are served from the cache, and need no restraint
+            _ if average <= SLOW_OP => true,
+            // working with fewer threads lost too much
+            Some((previous, previous_rate)) if previous > state.limit && rate < previous_rate * (1.0 - TOLERANCE) => {
+                state.hold = HOLD;
+                true
+            },
+            _ if state.hold > 0 => {
+                state.hold -= 1;
+                false
+            },
+            _ => {
+                if state.limit > 1 {
+                    state.previous = Some((state.limit, rate));
+                    state.limit -= 1;
+                    state.lowest = state.lowest.min(state.limit);
+                }
+                false
+            },
+        };
+        if raise && state.limit < self.threads {
+            state.limit += 1;
+            drop(state);
+            self.freed.notify_one();
+        }
+    }
+
+    /// The fewest threads which have been allowed to work at once, if
+    /// adaptive.
+    pub(crate) fn lowest(&self) -> Option<usize> {
+        if self.enabled { Some(self.lock().lowest) } else { None }
+    }
+}
@@ -6,16 +6,62 @@
 //! Specifically, we are looking for files whose create, modify, and/or
 //! update dates fall within a certain number of days, supplied by the
 //! user.
+#[cfg(feature = "archive")]
+use dir_ageism::archive::{Archive, ArchiveFormat};
+#[cfg(feature = "compress")]
+use dir_ageism::compress::{Compress, CompressFormat};
+#[cfg(feature = "trash")]
+use dir_ageism::actions::Trash;
+#[cfg(unix)]
+use dir_ageism::actions::{Chmod, Chown, Dedupe};
+#[cfg(all(feature = "profile", unix))]
+use dir_ageism::profile::{CountingAlloc, Profiler};
+#[cfg(feature = "watch")]
+use dir_ageism::watch::{Changes, Watch};
 use dir_ageism::{
+    activity::LastActivity,
+    backend::{Backend, WalkOrder},
+    actions::{Action, ActionLog, Apply, BackupTo, Delete, Exec, ExecBatch, MoveTo, PruneEmptyDirs, Script, Throttle, Truncate},
     asyncwalk::AsyncSearch,
-    constants::MIN_DAYS,
+    classify::{ClassReport, Classifier, Threshold},
+    config::{Config, Settings},
+    daemon::Daemon,
+    indicator::Indicator,
+    constants::{BUFFER_BYTES, CHANNEL_CAPACITY, SECS_PER_DAY, WATCH_INTERVAL_SECS},
+    duration::{parse_time, project_cutoff, Days},
     errors::AmbleError,
+    fields::{atime_mode, AtimeMode, Field},
+    hardlinks::{inode, DedupLinks, LinkCount},
+    manifest::{Manifest, ManifestSearch, Plan},
+    sinks::{JsonSink, PrintSink, SortBy, Sorted, Tally},
+    owner::{Owner, Ownership},
+    groups::{GroupBy, GroupReport},
+    policy::{read_policy, Policy, Rule},
+    schedule::{Schedule, ScheduleCheck},
+    space::{FreeTarget, SpaceTarget},
+    stats::ScanStats,
+    state::Incremental,
+    undo::{default_journal, new_scan_id, undo, Journal},
+    timefmt::{TimeFormat, Zone},
+    perms::Mode,
+    priority::nice_io,
+    roots::read_path_list,
+    size::{Size, SizeMode},
+    skip::read_skip_list,
     syncwalk::SyncSearch,
-    traits::Finder,
+    traits::{Finder, Sink},
 };
 
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::env;
+use std::process::{Command, ExitCode};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use structopt::StructOpt;
+use tracing::Level;
 
 /// Traverse a directory recursively, reporting on files
 /// whose access, modification, and/or creation time falls within a
@@ -24,47 +70,462 @@ use structopt::StructOpt;
 /// If the user does not specify the metadata
 /// properties of interest, amble will use access, modify, and create
 /// times
+///
+/// Like grep, amble exits with 0 when matches were found, 1 when none
+/// were, 2 when the scan could not be run, and 3 when it completed but
+/// some entries could not be read or some actions failed
 #[derive(StructOpt, Debug)]
-#[structopt(name = "amble")]
+#[structopt(name = "amble", raw(setting = "structopt::clap::AppSettings::SubcommandsNegateReqs"))]
 struct Opt {
+    #[structopt(flatten)]
+    scan: ScanOpts,
+
+    #[structopt(flatten)]
+    actions: ActionOpts,
+
+    #[structopt(flatten)]
+    reports: ReportOpts,
+
+    /// Simulate every action, reporting what it would do without doing
+    /// it, whatever --yes, --interactive, or the config file say. Also
+    /// simulates undo, and the scans run by daemon
+    #[structopt(long = "dry-run", raw(global = "true"))]
+    dry_run: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Subcommand>,
+}
+
+/// The options shared by every mode: the criteria, filters, traversal,
+/// and output of the search.
+#[derive(StructOpt, Debug, Default)]
+struct ScanOpts {
     /// Use access time to determine whether a candidate is
     /// of interest to Amble
     #[structopt(short = "a", long = "access")]
     access: bool,
 
+    /// Use access time, within DAYS rather than --days. Implies --access
+    #[structopt(long = "access-days", value_name = "DAYS")]
+    access_days: Option<Days>,
+
     /// Use modification time to determine whether a candidate is
     /// of interest to Amble
     #[structopt(short = "m", long = "modify")]
     modify: bool,
 
-    /// Use creation time to determine whether a candidate is
-    /// of interest to Amble. (NOT AVAILABLE ON LINUX)
+    /// Use modification time, within DAYS rather than --days. Implies
+    /// --modify
+    #[structopt(long = "modify-days", value_name = "DAYS")]
+    modify_days: Option<Days>,
+
+    /// Use inode change time (ctime) to determine whether a candidate is
+    /// of interest to Amble. Reported with an 'h'. (UNIX ONLY)
+    #[structopt(short = "C", long = "change")]
+    change: bool,
+
+    /// Use change time, within DAYS rather than --days. Implies --change
+    #[structopt(long = "change-days", value_name = "DAYS")]
+    change_days: Option<Days>,
+
+    /// Use creation (birth) time to determine whether a candidate is
+    /// of interest to Amble. Reported with a 'b'. (ON LINUX, REQUIRES A
+    /// FILESYSTEM WHICH RECORDS BIRTH TIME)
     #[structopt(short = "c", long = "create")]
     create: bool,
 
-    /// Ignore Hidden Files (that start with ".")
+    /// Use creation time, within DAYS rather than --days. Implies --create
+    #[structopt(long = "create-days", value_name = "DAYS")]
+    create_days: Option<Days>,
+
+    /// Use the effective time, the latest of access, modification, and
+    /// creation times, where available, in place of the other metadata
+    /// criteria, so that a match is a file touched in any way within the
+    /// number of days. Reported with an 'e'
+    #[structopt(short = "e", long = "effective", raw(conflicts_with_all = r#"&["access", "modify", "change", "create", "access_days",
+                                                                              "modify_days", "change_days", "create_days"]"#))]
+    effective: bool,
+
+    /// Ignore Hidden Files and Directories (that start with ".")
     #[structopt(short = "i", long = "ignore-hidden")]
     ignore: bool,
 
+    /// Do not search hidden directories (that start with "."), while still
+    /// reporting hidden files elsewhere
+    #[structopt(long = "ignore-hidden-dirs")]
+    ignore_hidden_dirs: bool,
+
+    /// Do not report hidden files (that start with "."), while still
+    /// searching hidden directories
+    #[structopt(long = "ignore-hidden-files")]
+    ignore_hidden_files: bool,
+
     /// The time period in days in which to consider entities, based
-    /// on the metadata criteria
+    /// on the metadata criteria. Required unless each criterion supplies
+    /// its own number of days. Anywhere a number of days is accepted, a
+    /// duration such as 36h, 2w, 90d, 1y6m, or 1.5s may be given instead,
+    /// in ms, s, min, h, d, w, m (30 day months), or y (365 day years)
     #[structopt(short = "d", long = "days")]
-    days: f32,
+    days: Option<Days>,
+
+    /// Count --days in calendar terms, so that 3 days means since local
+    /// midnight three calendar days ago, rather than the last 72 hours
+    #[structopt(long = "calendar-days", raw(conflicts_with_all = r#"&["hours", "minutes"]"#))]
+    calendar_days: bool,
+
+    /// The time period in business days in which to consider entities, in
+    /// place of --days, skipping weekends, so that 5 means since this time
+    /// of day five weekdays ago
+    #[structopt(long = "business-days", raw(conflicts_with_all = r#"&["days", "hours", "minutes", "calendar_days"]"#))]
+    business_days: Option<u32>,
+
+    /// The time period in hours in which to consider entities, in place
+    /// of --days
+    #[structopt(long = "hours", raw(conflicts_with_all = r#"&["days", "minutes"]"#))]
+    hours: Option<f64>,
+
+    /// The time period in minutes in which to consider entities, in place
+    /// of --days
+    #[structopt(long = "minutes", raw(conflicts_with_all = r#"&["days"]"#))]
+    minutes: Option<f64>,
+
+    /// Match files whose timestamps, per the metadata criteria, are later
+    /// than the modification time of FILE, in place of --days (like `find
+    /// -newer`)
+    #[structopt(long = "newer-than", parse(from_os_str),
+                raw(conflicts_with_all = r#"&["days", "hours", "minutes", "business_days"]"#))]
+    newer_than: Option<PathBuf>,
+
+    /// Match files whose timestamps, per the metadata criteria, are earlier
+    /// than the modification time of FILE, in place of --days
+    #[structopt(long = "older-than-file", parse(from_os_str),
+                raw(conflicts_with_all = r#"&["days", "hours", "minutes", "business_days"]"#))]
+    older_than_file: Option<PathBuf>,
+
+    /// Match files whose timestamps, per the metadata criteria, are older
+    /// than PERCENT of the project's lifetime so far, as measured from
+    /// --project-start, in place of --days, so that 50 means older than
+    /// half the lifetime of the project
+    #[structopt(long = "age-pct", raw(requires = r#""project_start""#),
+                raw(conflicts_with_all = r#"&["days", "hours", "minutes", "business_days", "older_than_file"]"#))]
+    age_pct: Option<f64>,
+
+    /// The start of the project, for --age-pct. DATE is given as for --as-of
+    #[structopt(long = "project-start", parse(try_from_str = "parse_time"), raw(requires = r#""age_pct""#))]
+    project_start: Option<SystemTime>,
+
+    /// Compute ages as of TIME, rather than now, so that a search may be
+    /// reproduced, or re-run as of some past time. TIME is a local date
+    /// (meaning its midnight), a local date and time (eg 2024-03-10 18:30),
+    /// an RFC 3339 timestamp, or @SECONDS since the epoch
+    #[structopt(long = "as-of", parse(try_from_str = "parse_time"))]
+    as_of: Option<SystemTime>,
+
+    /// Widen windows by TOLERANCE, a duration such as 30s, to absorb
+    /// clock drift between hosts, such as NFS clients and servers, so that
+    /// files written slightly in the future by another host still match
+    #[structopt(long = "skew")]
+    skew: Option<Days>,
+
+    /// Record the matches of this run in FILE, and report only those which
+    /// were not recorded by the previous run, or whose modification time
+    /// or size has changed since, so that a nightly scan reports what is
+    /// new
+    #[structopt(long = "state", parse(from_os_str),
+                raw(conflicts_with_all = r#"&["classify", "plan", "policy", "last_activity"]"#))]
+    state: Option<PathBuf>,
+
+    /// Report each match as a JSON object, one per line, in place of text
+    #[structopt(long = "json", raw(conflicts_with_all = r#"&["classify", "plan"]"#))]
+    json: bool,
+
+    /// How timestamps appear in JSON output: epoch (seconds), epoch-ms
+    /// (milliseconds), or rfc3339 (with the timezone offset, the default)
+    #[structopt(long = "time-format", raw(requires = r#""json""#))]
+    time_format: Option<TimeFormat>,
+
+    /// The timezone timestamps are shown in: local (the default), UTC, or
+    /// a named zone, such as America/Los_Angeles
+    #[structopt(long = "timezone")]
+    timezone: Option<Zone>,
+
+    /// Sort matches before reporting them, or applying an action to them,
+    /// by age (youngest first), size (smallest first), path, or name. The
+    /// matches are held back until the search completes
+    #[structopt(long = "sort", raw(conflicts_with_all = r#"&["group_by", "policy", "free_target"]"#))]
+    sort: Option<SortBy>,
+
+    /// Reverse the order of --sort, so that --sort age --reverse lists the
+    /// oldest matches first
+    #[structopt(long = "reverse", raw(requires = r#""sort""#))]
+    reverse: bool,
+
+    /// Only match empty (zero length) files
+    #[structopt(long = "empty", raw(conflicts_with = r#""non_empty""#))]
+    empty: bool,
+
+    /// Only match files which are not empty
+    #[structopt(long = "non-empty")]
+    non_empty: bool,
+
+    /// Only match files of at least SIZE (eg 512, 10K, 1.5G)
+    #[structopt(long = "min-size")]
+    min_size: Option<Size>,
+
+    /// Only match files of at most SIZE (eg 512, 10K, 1.5G)
+    #[structopt(long = "max-size")]
+    max_size: Option<Size>,
+
+    /// Only match files with any execute bit set (UNIX ONLY)
+    #[structopt(long = "executable")]
+    executable: bool,
+
+    /// Only match files with N hard links, more than N (+N), or fewer
+    /// than N (-N). Files with a single link are the last remaining
+    /// reference to their data
+    #[structopt(long = "links", raw(allow_hyphen_values = "true"))]
+    links: Option<LinkCount>,
+
+    /// Only match files owned by USER, given as a user name or numeric
+    /// user id (UNIX ONLY)
+    #[structopt(long = "owner")]
+    owner: Option<Owner>,
+
+    /// Only match files owned by you - the effective user running amble.
+    /// Equivalent to --owner $USER (UNIX ONLY)
+    #[structopt(long = "mine", raw(conflicts_with = r#""owner""#))]
+    mine: bool,
+
+    /// Flag matches which have security relevant mode bits set: setuid,
+    /// setgid, sticky, or world-writable (UNIX ONLY)
+    #[structopt(long = "mode-flags")]
+    mode_flags: bool,
+
+    /// Measure file sizes by the space allocated on disk rather than their
+    /// length, when filtering by size and tallying bytes. Sparse files
+    /// occupy far less space than their length suggests
+    #[structopt(long = "allocated")]
+    allocated: bool,
+
+    /// Report dangling symbolic links encountered during traversal, rather
+    /// than files
+    #[structopt(long = "broken-links")]
+    broken_links: bool,
+
+    /// Only report dangling symbolic links whose own change time falls
+    /// within the number of days. Implies --broken-links
+    #[structopt(long = "broken-links-aged")]
+    broken_links_aged: bool,
+
+    /// Do not follow symbolic links. Links to directories are not
+    /// traversed, and links to files are not reported
+    #[structopt(long = "no-follow")]
+    no_follow: bool,
 
     /// Optional list of directory names to skip
     #[structopt(short = "s", long = "skip")]
     skip: Vec<String>,
 
-    /// Optionally specify how many threads to spawn when using async
-    #[structopt(short = "t", long = "threads")]
+    /// Leave out anything whose absolute path starts with PREFIX. More
+    /// precise than --skip when a name appears in many places. May be
+    /// supplied more than once
+    #[structopt(long = "exclude-path", parse(from_os_str), raw(number_of_values = "1"))]
+    exclude_path: Vec<PathBuf>,
+
+    /// Only match files whose name matches the shell glob PATTERN (eg
+    /// "*.abc"). May be supplied more than once, in which case a file
+    /// matching any of the patterns is of interest
+    #[structopt(long = "name", raw(number_of_values = "1"))]
+    name: Vec<String>,
+
+    /// Only match files whose content type, determined by examining the
+    /// file, matches the MIME PATTERN (eg "image/*" or "video/mp4"). May
+    /// be supplied more than once. Requires the mime feature
+    #[structopt(long = "mime", raw(number_of_values = "1"))]
+    mime: Vec<String>,
+
+    /// Read additional names to skip from a file, one per line. Blank
+    /// lines and lines starting with '#' are ignored
+    #[structopt(long = "skip-from", parse(from_os_str))]
+    skip_from: Option<PathBuf>,
+
+    /// Match skip names and name patterns without regard to case, for
+    /// trees served to case-insensitive clients
+    #[structopt(long = "case-insensitive")]
+    case_insensitive: bool,
+
+    /// Report files with multiple hard links only once, rather than once
+    /// per path
+    #[structopt(long = "dedup-links")]
+    dedup_links: bool,
+
+    /// When deduplicating hard links, list all the paths found for each
+    /// file. Implies --dedup-links
+    #[structopt(long = "all-links")]
+    all_links: bool,
+
+    /// Report a directory as a single match, in place of its contents,
+    /// when every entry beneath it matches. Directories containing anything
+    /// which does not match, or which is skipped, are not rolled up
+    #[structopt(long = "rollup", raw(conflicts_with = r#""classify""#))]
+    rollup: bool,
+
+    /// Run CMD via the shell once the search completes, with the number of
+    /// matches, their total size in bytes, the number of errors, and the
+    /// instant ages were computed relative to (in seconds since the epoch)
+    /// in the MATCH_COUNT, TOTAL_BYTES, ERROR_COUNT, and REFERENCE_TIME
+    /// environment variables
+    #[structopt(long = "on-complete")]
+    on_complete: Option<String>,
+
+    /// Stop searching once N matches have been found
+    #[structopt(long = "limit")]
+    limit: Option<usize>,
+
+    /// Optionally specify how many threads to spawn when using async. With
+    /// --sync or --backend, how many roots are searched at once, each by a
+    /// single thread, when more than one is supplied, and otherwise, when
+    /// given, how many read metadata ahead of the search, as with
+    /// --prefetch. By default, one per core when using async, and with
+    /// --sync or --backend, the roots are searched one at a time
+    #[structopt(short = "t", long = "threads", raw(conflicts_with = r#""files_from""#))]
     threads: Option<u8>,
 
-    /// Root directory to process. Amble will recursively descend through
-    /// the supplied directory, identifying files which meet the provided
+    /// Start with as many threads as cores, and work with fewer while
+    /// reading metadata is slow and contended, as on a busy NFS server,
+    /// rather than piling requests onto it. Async only
+    #[structopt(long = "adaptive-threads", raw(conflicts_with_all = r#"&["threads", "sync", "backend"]"#))]
+    adaptive_threads: bool,
+
+    /// The most reads of metadata in flight at once on any one mount beneath
+    /// the roots, eg 4 for an NFS filer, while the scan works with as many
+    /// threads as ever across mounts. Applies to the async search, and to
+    /// the sync search when searching several roots at once or prefetching
+    #[structopt(long = "max-per-mount")]
+    max_per_mount: Option<usize>,
+
+    /// Read metadata ahead of the sync search on a pool of THREADS threads,
+    /// a few entries ahead of it in each directory, hiding the latency of
+    /// each stat on slow storage while keeping matches in order. Reads
+    /// directories as the fast backend does. Implies --sync
+    #[structopt(long = "prefetch", value_name = "THREADS",
+                raw(conflicts_with_all = r#"&["files_from", "adaptive_threads"]"#))]
+    prefetch: Option<usize>,
+
+    /// How directories are traversed: walkdir, or fast, which reads each
+    /// file's metadata relative to its open directory, rather than by its
+    /// full path, cutting the cost of path resolution on large trees, or
+    /// io_uring, which also prefetches each directory's metadata in batches
+    /// (EXPERIMENTAL, LINUX ONLY, AND REQUIRES THE io_uring FEATURE). The
+    /// fast backends search each root with a single thread, like --sync
+    #[structopt(long = "backend")]
+    backend: Option<Backend>,
+
+    /// The order in which directories are walked: depth (first), or
+    /// breadth (first), which searches every directory at one depth before
+    /// any beneath them, so that shallow matches are reported first rather
+    /// than after every deep subtree ahead of them. Breadth first holds
+    /// the directories left at the next depth in memory. Implies --sync
+    #[structopt(long = "walk-order", raw(conflicts_with_all = r#"&["rollup", "files_from", "adaptive_threads"]"#))]
+    walk_order: Option<WalkOrder>,
+
+    /// Report statistics of the scan once it completes - its duration, the
+    /// rates at which files and directories were processed, the number of
+    /// stat calls, and, for the async backend, the utilization of each
+    /// thread - to stderr, for tuning --threads and comparing backends
+    #[structopt(long = "stats")]
+    stats: bool,
+
+    /// Log the progress of the scan to stderr: entries which cannot be
+    /// read with -v, directories entered and entries skipped, and why, with
+    /// -vv, and each file evaluated against the criteria with -vvv
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: u8,
+
+    /// Do not show the progress of the scan - the directories traversed,
+    /// files evaluated, and matches found so far - on stderr. It is only
+    /// shown when stderr is a terminal, and not while logging with -v or
+    /// confirming actions with --interactive
+    #[structopt(long = "no-progress")]
+    no_progress: bool,
+
+    /// Profile the scan, writing a flamegraph of where its CPU time went to
+    /// FILE, an SVG, and reporting the allocations it made to stderr, to
+    /// diagnose slow scans. Requires the profile feature (unix only)
+    #[structopt(long = "flamegraph", value_name = "FILE", parse(from_os_str))]
+    flamegraph: Option<PathBuf>,
+
+    /// Skip directories, and everything beneath them, whose own
+    /// modification and change times are older than the window, on local
+    /// filesystems known to keep them up to date. Much faster on mostly
+    /// cold trees, but INACCURATE: files modified in place, and files added
+    /// beneath a subdirectory, do not update a directory's times, so are
+    /// missed. Only applies to scans of modification or creation times
+    #[structopt(long = "prune-cold-dirs")]
+    prune_cold_dirs: bool,
+
+    /// Scan at the lowest CPU priority, and the idle I/O priority (Linux
+    /// only), so that scheduled scans do not compete with interactive or
+    /// render workloads on the same host
+    #[structopt(long = "nice-io")]
+    nice_io: bool,
+
+    /// How many matches the threads of an async search may queue before
+    /// they wait for them to be reported, bounding memory when output is
+    /// consumed slowly
+    #[structopt(long = "channel-capacity", raw(conflicts_with = r#""sync""#))]
+    channel_capacity: Option<usize>,
+
+    /// How much memory matches held back for sorting may take - by --sort,
+    /// by --free-target, and by --rollup when searching in parallel - or
+    /// the groups of --group-by and directories of --last-activity may
+    /// take, before they are spilled to temporary files, eg 512MB
+    #[structopt(long = "buffer-limit")]
+    buffer_limit: Option<Size>,
+
+    /// Make the scan resumable, saving the directories it has yet to search
+    /// to FILE every few seconds. If the scan is interrupted, running it
+    /// again with the same FILE picks up where it left off, rather than
+    /// starting over from the roots. Matches found since the last save are
+    /// reported again. FILE is removed once the scan completes. Implies
+    /// --sync
+    #[structopt(long = "resume", parse(from_os_str),
+                raw(conflicts_with_all = r#"&["rollup", "group_by", "last_activity", "free_target", "files_from",
+                                               "adaptive_threads"]"#))]
+    resume: Option<PathBuf>,
+
+    /// Root directories to process. Amble will recursively descend through
+    /// the supplied directories, identifying files which meet the provided
     /// criteria, and report them to stdout, along with an indication
-    /// of the matching criteria
-    #[structopt(name = "DIR", parse(from_os_str))]
-    dir: PathBuf,
+    /// of the matching criteria. Overlapping directories are searched once
+    #[structopt(name = "DIR", parse(from_os_str), raw(required_unless_one = r#"&["files_from", "apply_manifest"]"#))]
+    dir: Vec<PathBuf>,
+
+    /// Rather than traversing directories, evaluate the paths listed in
+    /// FILE, or in stdin if FILE is "-". Paths are newline delimited, or
+    /// NUL delimited if the list contains a NUL (eg from `find -print0`).
+    /// Any DIR supplied is ignored
+    #[structopt(long = "files-from", parse(from_os_str))]
+    files_from: Option<PathBuf>,
+
+    /// Read defaults for the options from the TOML config FILE, rather than
+    /// from the nearest .amble.toml in or above the first DIR. Options
+    /// supplied on the command line override those of the file, though
+    /// flags which switch something on cannot be switched off again
+    #[structopt(long = "config", parse(from_os_str), raw(env = r#""AMBLE_CONFIG""#))]
+    config: Option<PathBuf>,
+
+    /// Do not read a config file
+    #[structopt(long = "no-config")]
+    no_config: bool,
+
+    /// Take the options of the named profile of the config file, such as
+    /// [profile.weekly-clean], over those at the top of the file. A profile
+    /// may also give an action, which is taken unless the command line
+    /// gives one, or the find or report subcommand is used. NAME is always
+    /// that of a config profile; see --flamegraph to profile the scan itself
+    #[structopt(long = "profile", value_name = "NAME", raw(conflicts_with = r#""no_config""#))]
+    profile: Option<String>,
 
     /// Use single threaded directory traversal. The default behavior is
     /// to process directories using as many threads as cores.
@@ -74,53 +535,1479 @@ struct Opt {
     sync: bool,
 }
 
-fn main() -> Result<(), AmbleError>{
-    let mut opt = Opt::from_args();
-    if !opt.dir.exists() {
-        println!("Warning: '{}' does not exist. Exiting.",
-                opt.dir
-                    .into_os_string()
-                    .into_string()
-                    .unwrap());
-        return Ok(());
+/// The options of the clean mode: the actions applied to the matches, and
+/// how they are confirmed and recorded.
+#[derive(StructOpt, Debug, Default)]
+struct ActionOpts {
+    /// Delete the matches. Unless --yes is supplied, this is a dry run,
+    /// reporting what would be deleted
+    #[structopt(long = "delete", raw(conflicts_with = r#""classify""#))]
+    delete: bool,
+
+    /// Move the matches beneath DEST, recreating their paths relative to
+    /// the root directory they were found under. Unless --yes is supplied,
+    /// this is a dry run, reporting what would be moved
+    #[structopt(long = "move-to", parse(from_os_str),
+                raw(conflicts_with_all = r#"&["classify", "delete"]"#))]
+    move_to: Option<PathBuf>,
+
+    /// Leave a symlink at the original path of each match moved by
+    /// --move-to, pointing to where it was moved, so that anything still
+    /// referring to the old path keeps working
+    #[structopt(long = "leave-symlink", raw(requires = r#""move_to""#))]
+    leave_symlink: bool,
+
+    /// Archive the matches to FILE, naming them by their paths relative to
+    /// the root directory they were found under. FILE is written as a zip
+    /// archive if it ends in ".zip", and a gzip compressed tar archive
+    /// otherwise. Unless --yes is supplied, this is a dry run, reporting
+    /// what would be archived. Requires the archive feature
+    #[structopt(long = "archive", parse(from_os_str),
+                raw(conflicts_with_all = r#"&["classify", "delete", "move_to"]"#))]
+    archive: Option<PathBuf>,
+
+    /// The format of the archive, overriding the extension of FILE:
+    /// tar.gz or zip
+    #[structopt(long = "archive-format", raw(requires = r#""archive""#))]
+    archive_format: Option<String>,
+
+    /// Remove the originals once they have been archived successfully
+    #[structopt(long = "remove-originals", raw(requires = r#""archive""#))]
+    remove_originals: bool,
+
+    /// Run a command for each match, as in `--exec rm -i {} ;`. Each {} in
+    /// the command is replaced by the path of the match, and the command
+    /// ends with an argument of ";". Unless --yes is supplied, this is a
+    /// dry run, reporting the commands which would be run
+    #[structopt(long = "exec", raw(allow_hyphen_values = "true", value_terminator = r#"";""#),
+                raw(conflicts_with_all = r#"&["classify", "delete", "move_to", "archive"]"#))]
+    exec: Vec<String>,
+
+    /// Run a command for batches of matches, as in `--exec-batch chmod 600
+    /// {} ;`. An argument of {} is replaced by as many paths as fit on the
+    /// command line, and the paths are appended if there is no such
+    /// argument. Unless --yes is supplied, this is a dry run
+    #[structopt(long = "exec-batch", raw(allow_hyphen_values = "true", value_terminator = r#"";""#),
+                raw(conflicts_with_all = r#"&["classify", "delete", "move_to", "archive", "exec"]"#))]
+    exec_batch: Vec<String>,
+
+    /// Move the matches to the trash, from which they may be restored,
+    /// rather than deleting them. Unless --yes is supplied, this is a dry
+    /// run. Requires the trash feature
+    #[structopt(long = "trash",
+                raw(conflicts_with_all = r#"&["classify", "delete", "move_to", "archive", "exec", "exec_batch"]"#))]
+    trash: bool,
+
+    /// Change the permissions of the matches to MODE, an octal mode or
+    /// symbolic clauses such as go-w or a-w. Unless --yes is supplied, this
+    /// is a dry run
+    #[structopt(long = "chmod",
+                raw(conflicts_with_all = r#"&["classify", "delete", "move_to", "archive", "exec", "exec_batch", "trash"]"#))]
+    chmod: Option<Mode>,
+
+    /// Change the ownership of the matches to USER[:GROUP], or :GROUP.
+    /// Unless --yes is supplied, this is a dry run
+    #[structopt(long = "chown",
+                raw(conflicts_with_all = r#"&["classify", "delete", "move_to", "archive", "exec", "exec_batch", "trash",
+                                              "chmod"]"#))]
+    chown: Option<Ownership>,
+
+    /// Compress each match in place, replacing it with a compressed copy
+    /// which keeps its permissions and times. The format is zstd, unless
+    /// --compress=gzip is supplied. Unless --yes is supplied, this is a
+    /// dry run. Requires the compress feature
+    #[structopt(long = "compress", raw(require_equals = "true"),
+                raw(conflicts_with_all = r#"&["classify", "delete", "move_to", "archive", "exec", "exec_batch", "trash",
+                                              "chmod", "chown", "plan"]"#))]
+    compress: Option<Option<String>>,
+
+    /// Copy the matches beneath DEST before applying any other action,
+    /// recreating their paths relative to the root directory they were
+    /// found under. Each copy is verified against its original by size and
+    /// checksum, and recorded in DEST/amble-backup.jsonl. Other actions, such
+    /// as --delete, are only applied to matches which were backed up.
+    /// Unless --yes is supplied, this is a dry run
+    #[structopt(long = "backup-to", parse(from_os_str), raw(conflicts_with_all = r#"&["classify", "plan"]"#))]
+    backup_to: Option<PathBuf>,
+
+    /// Replace matches which are identical to an earlier match, by content,
+    /// owner, group, and permissions, with hard links to it, reclaiming their
+    /// space. Unless --yes is supplied, this is a dry run (UNIX ONLY)
+    #[structopt(long = "hardlink-dupes",
+                raw(conflicts_with_all = r#"&["classify", "delete", "move_to", "archive", "exec", "exec_batch", "trash",
+                                              "chmod", "chown", "compress", "plan"]"#))]
+    hardlink_dupes: bool,
+
+    /// Truncate the matches to zero bytes, rather than deleting them,
+    /// keeping their ownership and permissions. Unless --yes is supplied,
+    /// this is a dry run
+    #[structopt(long = "truncate",
+                raw(conflicts_with_all = r#"&["classify", "delete", "move_to", "archive", "exec", "exec_batch", "trash",
+                                              "chmod", "chown", "compress", "hardlink_dupes", "plan"]"#))]
+    truncate: bool,
+
+    /// Apply an action, such as --delete or --move-to, to the oldest
+    /// matches first, stopping once the free space on their filesystem
+    /// would reach TARGET, a size (eg 500G) or a percentage of the
+    /// filesystem (eg 10%). Matches are oldest by their effective times
+    /// (see --effective) (UNIX ONLY)
+    #[structopt(long = "free-target", raw(conflicts_with_all = r#"&["classify", "plan", "policy"]"#))]
+    free_target: Option<SpaceTarget>,
+
+    /// Record where each match moved by --move-to or --trash went in the
+    /// undo journal FILE, rather than $XDG_DATA_HOME/amble/undo.jsonl, so
+    /// that they may be restored by `amble undo`
+    #[structopt(long = "undo-journal", parse(from_os_str))]
+    undo_journal: Option<PathBuf>,
+
+    /// Record each file an action, such as --delete or --move-to, is
+    /// applied to in the log FILE, of JSON lines giving the time, action,
+    /// absolute path, and size
+    #[structopt(long = "action-log", parse(from_os_str), raw(conflicts_with_all = r#"&["classify", "plan"]"#))]
+    action_log: Option<PathBuf>,
+
+    /// Record the SHA-256 of each file in the --action-log, computed
+    /// before the action is applied
+    #[structopt(long = "log-checksums", raw(requires = r#""action_log""#))]
+    log_checksums: bool,
+
+    /// Once an action such as --delete or --move-to has finished, remove
+    /// the directories it left empty, up to but not including the root
+    /// directory they were found under
+    #[structopt(long = "prune-empty-dirs")]
+    prune_empty_dirs: bool,
+
+    /// Write a shell script of the commands which would apply an action,
+    /// such as --delete or --move-to, to FILE for review, rather than
+    /// applying it
+    #[structopt(long = "emit-script", parse(from_os_str),
+                raw(conflicts_with_all = r#"&["yes", "interactive", "prune_empty_dirs"]"#))]
+    emit_script: Option<PathBuf>,
+
+    /// Limit the rate at which an action is applied, to RATE matches per
+    /// second (eg 50/s), or RATE bytes of matches per second (eg 20MB/s)
+    #[structopt(long = "throttle")]
+    throttle: Option<Throttle>,
+
+    /// Apply a tiered policy read from FILE in a single search, in which
+    /// each line gives the number of days a file must have been idle for a
+    /// tier, and the action for the tier, eg "90 compress" or "365 move-to
+    /// /archive". Files fall into the tier with the most days they have
+    /// been idle for, from their effective times (see --effective).
+    /// Actions are report, delete, truncate, trash, compress [FORMAT],
+    /// move-to DEST, stub-to DEST (moving, and leaving a symlink behind),
+    /// archive FILE, chmod MODE, and chown OWNER. Archived files are
+    /// removed once archived. Unless --yes is supplied, this is a dry run
+    #[structopt(long = "policy", parse(from_os_str),
+                raw(conflicts_with_all = r#"&["classify", "delete", "move_to", "archive", "exec", "exec_batch", "trash",
+                                              "chmod", "chown", "compress", "hardlink_dupes", "truncate", "plan",
+                                              "emit_script", "rollup", "apply_manifest", "last_activity"]"#))]
+    policy: Option<PathBuf>,
+
+    /// Apply actions such as --delete, --move-to, and --exec, rather
+    /// than performing a dry run
+    #[structopt(long = "yes")]
+    yes: bool,
+
+    /// Confirm each match before applying an action to it, like rm -i.
+    /// Answer y to apply the action, n to skip the match, a to apply the
+    /// action to every remaining match, or q to skip every remaining match
+    #[structopt(long = "interactive", raw(conflicts_with = r#""yes""#))]
+    interactive: bool,
+
+    /// Plan a cleanup, writing a manifest of the matches, with their sizes,
+    /// modification times, and hashes, to FILE for review. See also the
+    /// plan subcommand
+    #[structopt(long = "plan", parse(from_os_str),
+                raw(conflicts_with_all = r#"&["classify", "delete", "move_to", "archive", "exec", "exec_batch", "trash",
+                                              "chmod", "chown", "apply_manifest"]"#))]
+    plan: Option<PathBuf>,
+
+    /// Apply an action, such as --delete or --move-to, to exactly the
+    /// files listed in the manifest FILE written by --plan, rather than
+    /// searching. Files which have changed since they were planned are
+    /// refused. Search criteria and any DIR supplied are ignored. See also
+    /// the apply subcommand
+    #[structopt(long = "apply", parse(from_os_str))]
+    apply_manifest: Option<PathBuf>,
+}
+
+/// The options of the report mode: the aggregations reported in place of
+/// the matches.
+#[derive(StructOpt, Debug, Default)]
+struct ReportOpts {
+    /// Classify every file as hot, warm, cold, or frozen based on its
+    /// access and modification times, and report the number of files and
+    /// bytes in each class. The days and metadata criteria are ignored
+    #[structopt(long = "classify")]
+    classify: bool,
+
+    /// Report the number of matches and bytes in each directory, extension,
+    /// or owner (dir, ext, or owner), along with the oldest and newest
+    /// effective times of the matches in each, in place of the matches
+    #[structopt(long = "group-by", raw(conflicts_with_all = r#"&["classify", "plan", "policy", "last_activity"]"#))]
+    group_by: Option<GroupBy>,
+
+    /// Threshold for the hot class, as DAYS or ACCESS_DAYS:MODIFY_DAYS.
+    /// Defaults to 7 days
+    #[structopt(long = "hot")]
+    hot: Option<Threshold>,
+
+    /// Threshold for the warm class, as DAYS or ACCESS_DAYS:MODIFY_DAYS.
+    /// Defaults to 30 days
+    #[structopt(long = "warm")]
+    warm: Option<Threshold>,
+
+    /// Threshold for the cold class, as DAYS or ACCESS_DAYS:MODIFY_DAYS.
+    /// Files outside of the cold threshold are frozen. Defaults to 180 days
+    #[structopt(long = "cold")]
+    cold: Option<Threshold>,
+
+    /// Report the directories in which nothing has been accessed or
+    /// modified for at least DAYS (or a duration, such as 6m), in place of
+    /// files. Only the outermost such directories are reported. -a or -m
+    /// restrict activity to access or modification
+    #[structopt(long = "last-activity", raw(conflicts_with_all = r#"&["classify", "rollup", "broken_links"]"#))]
+    last_activity: Option<Days>,
+}
+
+impl ActionOpts {
+    // Was an action, or a plan or policy of actions, requested?
+    fn requested(&self) -> bool {
+        self.delete || self.move_to.is_some() || self.archive.is_some() || !self.exec.is_empty()
+            || !self.exec_batch.is_empty() || self.trash || self.chmod.is_some() || self.chown.is_some()
+            || self.compress.is_some() || self.hardlink_dupes || self.truncate || self.policy.is_some()
+            || self.plan.is_some() || self.apply_manifest.is_some()
+    }
+}
+
+impl ReportOpts {
+    // Was an aggregation requested?
+    fn requested(&self) -> bool {
+        self.classify || self.group_by.is_some() || self.last_activity.is_some()
+    }
+}
+
+// parsed once, so the size of the options is of no concern
+#[allow(clippy::large_enum_variant)]
+#[derive(StructOpt, Debug)]
+enum Subcommand {
+    /// Report the files matching the criteria, as amble does without a
+    /// subcommand, but without its actions or aggregations
+    #[structopt(name = "find")]
+    Find {
+        #[structopt(flatten)]
+        scan: ScanOpts,
+    },
+
+    /// Apply an action, such as --delete or --move-to, to the files
+    /// matching the criteria. Unless --yes is supplied, this is a dry run
+    #[structopt(name = "clean")]
+    Clean {
+        #[structopt(flatten)]
+        scan: ScanOpts,
+
+        #[structopt(flatten)]
+        actions: ActionOpts,
+    },
+
+    /// Report an aggregation of the files matching the criteria, such as
+    /// --group-by or --classify, in place of the files themselves
+    #[structopt(name = "report")]
+    Report {
+        #[structopt(flatten)]
+        scan: ScanOpts,
+
+        #[structopt(flatten)]
+        reports: ReportOpts,
+    },
+
+    /// Plan a cleanup, writing a manifest of the files matching the
+    /// criteria, with their sizes, modification times, and hashes, to FILE
+    /// for review, as --plan does. The manifest is applied with apply
+    #[structopt(name = "plan")]
+    Plan {
+        #[structopt(flatten)]
+        scan: ScanOpts,
+
+        /// The FILE the manifest is written to
+        #[structopt(long = "manifest", value_name = "FILE", parse(from_os_str))]
+        manifest: PathBuf,
+    },
+
+    /// Apply an action, such as --delete or --move-to, to exactly the files
+    /// listed in MANIFEST, written by plan, as --apply does. Files which
+    /// have changed since they were planned are refused. Unless --yes is
+    /// supplied, this is a dry run
+    #[structopt(name = "apply")]
+    Apply {
+        #[structopt(name = "MANIFEST", parse(from_os_str))]
+        manifest: PathBuf,
+
+        #[structopt(flatten)]
+        actions: ActionOpts,
+    },
+
+    /// Restore the matches moved or trashed by the scan SCAN_ID, as
+    /// reported once they were moved, to their original paths
+    #[structopt(name = "undo")]
+    Undo {
+        #[structopt(name = "SCAN_ID")]
+        scan_id: String,
+
+        /// The undo journal the scan was recorded in, rather than
+        /// $XDG_DATA_HOME/amble/undo.jsonl
+        #[structopt(long = "undo-journal", parse(from_os_str))]
+        undo_journal: Option<PathBuf>,
+    },
+
+    /// Report how POLICY, run on SCHEDULE, will treat the files beneath
+    /// each PATH: which files the next run will move into a new tier, or,
+    /// when each PATH is a file, when it will reach each tier
+    #[structopt(name = "schedule-check")]
+    ScheduleCheck {
+        /// The policy file, as given to --policy
+        #[structopt(long = "policy", parse(from_os_str))]
+        policy: PathBuf,
+
+        /// The cron schedule the policy is run on, such as '0 2 * * *'
+        #[structopt(long = "schedule")]
+        schedule: Schedule,
+
+        #[structopt(name = "PATH", parse(from_os_str), raw(required = "true"))]
+        paths: Vec<PathBuf>,
+    },
+
+    /// Report the files matching the criteria, then watch each DIR,
+    /// reporting files as they come to match, marked +, and as they stop
+    /// matching, marked -. Requires the watch feature
+    #[structopt(name = "watch")]
+    Watch {
+        #[structopt(flatten)]
+        scan: ScanOpts,
+
+        /// Check whether the files which match have aged out of the window
+        /// every SECS seconds (60 by default)
+        #[structopt(long = "interval", value_name = "SECS")]
+        interval: Option<u64>,
+
+        /// Rescan each DIR in full every SECS seconds, to find files which
+        /// come to match as time passes, such as those older than the
+        /// window. By default, DIRs are not rescanned
+        #[structopt(long = "rescan", value_name = "SECS")]
+        rescan: Option<u64>,
+    },
+
+    /// Run each profile of the config file which gives a schedule on that
+    /// schedule, writing the results of each run to the profile's outputs
+    #[structopt(name = "daemon")]
+    Daemon {
+        /// The config FILE, rather than the nearest .amble.toml in or above
+        /// the current directory
+        #[structopt(long = "config", parse(from_os_str), raw(env = r#""AMBLE_CONFIG""#))]
+        config: Option<PathBuf>,
+
+        /// Run each scheduled profile once, straight away, and exit
+        #[structopt(long = "once")]
+        once: bool,
+    },
+
+    /// Inspect the configuration amble runs with
+    #[structopt(name = "config")]
+    Config {
+        #[structopt(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(StructOpt, Debug)]
+enum ConfigCommand {
+    /// Print the options a scan would run with, once those of the command
+    /// line, the environment, and the config file are merged, as TOML, or
+    /// as JSON with --json
+    #[structopt(name = "show")]
+    Show {
+        #[structopt(flatten)]
+        scan: ScanOpts,
+        #[structopt(flatten)]
+        actions: ActionOpts,
+    },
+}
+
+// What amble does once its options are resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunMode {
+    Scan,
+    ShowConfig,
+    Watch { interval: Duration, rescan: Option<Duration> },
+}
+
+// counts the allocations reported when profiling
+#[cfg(all(feature = "profile", unix))]
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+// The exit status of amble, like grep's, so that scripts may branch on the
+// result of a scan without parsing its output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    // matches were found
+    Matched = 0,
+    // no matches were found
+    Unmatched = 1,
+    // the scan could not be run
+    Failed = 2,
+    // the scan completed, but some entries could not be read, or some
+    // actions failed
+    Incomplete = 3,
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
+
+fn main() -> ExitCode {
+    let opt = match Opt::from_iter_safe(env::args_os()) {
+        Ok(opt) => opt,
+        // help and version are not failures
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            eprintln!("{}", e.message);
+            return Status::Failed.into();
+        },
+    };
+    match run(opt) {
+        Ok(status) => status.into(),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Status::Failed.into()
+        },
+    }
+}
+
+fn run(opt: Opt) -> Result<Status, AmbleError> {
+    // each mode takes only the options which apply to it, and runs as amble
+    // does without a subcommand
+    let dry_run = opt.dry_run;
+    let (opt, mode) = match opt {
+        Opt { command: Some(Subcommand::Config { command: ConfigCommand::Show { scan, actions } }), .. } => {
+            (Opt { scan, actions, reports: ReportOpts::default(), dry_run, command: None }, RunMode::ShowConfig)
+        },
+        Opt { command: Some(Subcommand::Watch { scan, interval, rescan }), .. } => {
+            let interval = Duration::from_secs(interval.unwrap_or(WATCH_INTERVAL_SECS).max(1));
+            let rescan = rescan.map(|rescan| Duration::from_secs(rescan.max(1)));
+            (Opt { scan, actions: ActionOpts::default(), reports: ReportOpts::default(), dry_run, command: None },
+             RunMode::Watch { interval, rescan })
+        },
+        opt => (opt, RunMode::Scan),
+    };
+    let (mut opt, actionable) = match opt {
+        Opt { command: Some(Subcommand::Find { scan }), .. } => {
+            (Opt { scan, actions: ActionOpts::default(), reports: ReportOpts::default(), dry_run, command: None }, false)
+        },
+        Opt { command: Some(Subcommand::Clean { scan, actions }), .. } => {
+            if !actions.requested() {
+                return Err(AmbleError::UnexpectedResult(
+                    "clean requires an action, such as --delete, --move-to, --plan, or --policy".to_string()));
+            }
+            (Opt { scan, actions, reports: ReportOpts::default(), dry_run, command: None }, true)
+        },
+        Opt { command: Some(Subcommand::Plan { scan, manifest }), .. } => {
+            let actions = ActionOpts { plan: Some(manifest), ..ActionOpts::default() };
+            (Opt { scan, actions, reports: ReportOpts::default(), dry_run, command: None }, false)
+        },
+        Opt { command: Some(Subcommand::Apply { manifest, actions }), .. } => {
+            if actions.plan.is_some() || actions.apply_manifest.is_some() || actions.policy.is_some() {
+                return Err(AmbleError::UnexpectedResult(
+                    "apply does not support --plan, --apply, or --policy".to_string()));
+            }
+            if !actions.requested() {
+                return Err(AmbleError::UnexpectedResult(
+                    "apply requires an action, such as --delete or --move-to".to_string()));
+            }
+            let actions = ActionOpts { apply_manifest: Some(manifest), ..actions };
+            (Opt { scan: ScanOpts::default(), actions, reports: ReportOpts::default(), dry_run, command: None }, true)
+        },
+        Opt { command: Some(Subcommand::Report { scan, reports }), .. } => {
+            if !reports.requested() {
+                return Err(AmbleError::UnexpectedResult(
+                    "report requires an aggregation, such as --group-by, --classify, or --last-activity".to_string()));
+            }
+            (Opt { scan, actions: ActionOpts::default(), reports, dry_run, command: None }, false)
+        },
+        opt => (opt, true),
+    };
+    init_logging(opt.scan.verbose);
+    if let Some(Subcommand::Undo { scan_id, undo_journal }) = &opt.command {
+        let journal = undo_journal.clone().or_else(default_journal).ok_or_else(|| {
+            AmbleError::UnexpectedResult("unable to locate the undo journal. Use --undo-journal".to_string())
+        })?;
+        let restored = undo(&journal, scan_id, opt.dry_run)?;
+        if opt.dry_run {
+            println!("# [dry run] would restore {} matches", restored.restored);
+        } else {
+            println!("# restored {} matches, {} errors", restored.restored, restored.failed);
+        }
+        return Ok(if restored.failed > 0 { Status::Incomplete } else { Status::Matched });
+    }
+    if let Some(Subcommand::Daemon { config, once }) = &opt.command {
+        let config = config.clone().or_else(|| Config::discover(Path::new("."))).ok_or_else(|| {
+            AmbleError::UnexpectedResult("unable to find a config file. Use --config".to_string())
+        })?;
+        let mut daemon = Daemon::new(&config, &env::current_exe()?)?;
+        daemon.dry_run(opt.dry_run);
+        if *once {
+            return Ok(if daemon.run_once()? { Status::Matched } else { Status::Incomplete });
+        }
+        daemon.run()?;
+        return Ok(Status::Matched);
+    }
+    if let Some(Subcommand::ScheduleCheck { policy, schedule, paths }) = &opt.command {
+        schedule_check(policy, schedule, paths, &opt)?;
+        return Ok(Status::Matched);
+    }
+    // a number of days for a criterion implies it
+    opt.scan.access |= opt.scan.access_days.is_some();
+    opt.scan.modify |= opt.scan.modify_days.is_some();
+    opt.scan.change |= opt.scan.change_days.is_some();
+    opt.scan.create |= opt.scan.create_days.is_some();
+    // shown in the format requested on the command line, whatever the file
+    let show_json = opt.scan.json;
+    let mut source = None;
+    if !opt.scan.no_config {
+        let path = opt.scan.config.clone().or_else(|| {
+            Config::discover(opt.scan.dir.first().map_or(Path::new("."), PathBuf::as_path))
+        });
+        match (path, &opt.scan.profile) {
+            (Some(path), profile) => {
+                let config = Config::load(&path)?;
+                let settings = match profile {
+                    Some(name) => config.profile(name)?,
+                    None => config.settings,
+                };
+                apply_settings(&mut opt, &settings, actionable)?;
+                source = Some(path);
+            },
+            (None, Some(name)) => {
+                return Err(AmbleError::UnexpectedResult(format!(
+                    "unable to find a config file holding profile '{}'. Use --config", name)));
+            },
+            (None, None) => (),
+        }
+    }
+    if mode == RunMode::ShowConfig {
+        show_settings(&effective_settings(&opt), source.as_deref(), show_json)?;
+        return Ok(Status::Matched);
+    }
+    // lowered before any threads are spawned, so that they inherit it
+    if opt.scan.nice_io {
+        if let Err(e) = nice_io() {
+            eprintln!("Warning: unable to lower the priority of the scan: {}", e);
+        }
     }
+    let manifest = match &opt.actions.apply_manifest {
+        Some(path) => {
+            let file = File::open(path).map_err(|e| {
+                AmbleError::IoError(format!("unable to read manifest '{}': {}", path.display(), e))
+            })?;
+            Some(Manifest::read(BufReader::new(file))?)
+        },
+        None => None,
+    };
+    // actions recreating paths place them relative to the planned roots
+    let roots = manifest.as_ref().map_or_else(|| opt.scan.dir.clone(), |manifest| manifest.roots.clone());
 
-    if !(opt.days > MIN_DAYS) {
-        println!("Warning: days must be greater than 0: {}.", opt.days);
-        return Ok(());
+    let rules = match &opt.actions.policy {
+        Some(policy) => {
+            let file = File::open(policy).map_err(|e| {
+                AmbleError::IoError(format!("unable to read policy '{}': {}", policy.display(), e))
+            })?;
+            Some(read_policy(file)?)
+        },
+        None => None,
+    };
+
+    if let Some(dir) = opt.scan.dir.iter().find(|dir| !dir.exists()) {
+        return Err(AmbleError::IoError(format!("'{}' does not exist", dir.display())));
     }
 
     // If the user doesn't specify the metadata of interest, then
     // it is all of interest.
-    if !(opt.access || opt.create || opt.modify) {
-        opt.access = true;
-        #[cfg(target_os = "macos")]
+    let defaulted = !opt.scan.access && !opt.scan.create && !opt.scan.modify && !opt.scan.change;
+    if defaulted {
+        opt.scan.access = true;
+        #[cfg(any(target_os = "macos", target_os = "linux"))]
         {
-            opt.create = true;
+            opt.scan.create = true;
         }
-        #[cfg(target_os = "linux")]
-        {
-            opt.create = false;
+        opt.scan.modify = true;
+    }
+
+    // the number of days of each criterion tested, or None where --days is
+    let criteria = IntoIterator::into_iter([(opt.scan.access, opt.scan.access_days),
+                                            (opt.scan.create, opt.scan.create_days),
+                                            (opt.scan.modify, opt.scan.modify_days),
+                                            (opt.scan.change, opt.scan.change_days)])
+        .filter_map(|(tested, days)| tested.then_some(days))
+        .collect::<Vec<_>>();
+    let broken_links = opt.scan.broken_links || opt.scan.broken_links_aged;
+    // days are required by any criterion which does not supply its own
+    let newer_than = opt.scan.newer_than.as_deref().map(reference_time).transpose()?;
+    let mut older_than = opt.scan.older_than_file.as_deref().map(reference_time).transpose()?;
+    if let (Some(pct), Some(start)) = (opt.scan.age_pct, opt.scan.project_start) {
+        if !(pct > 0.0 && pct.is_finite()) {
+            return Err(AmbleError::ParseError(format!("--age-pct must be greater than 0: {}", pct)));
+        }
+        let now = opt.scan.as_of.unwrap_or_else(SystemTime::now);
+        older_than = match project_cutoff(start, now, pct) {
+            Some(cutoff) => Some(cutoff),
+            None => {
+                return Err(AmbleError::ParseError(
+                    "--project-start must be before the time ages are computed as of".to_string()));
+            },
+        };
+    }
+    let days_required = !opt.reports.classify && manifest.is_none() && rules.is_none()
+        && opt.reports.last_activity.is_none()
+        && newer_than.is_none() && older_than.is_none() && if broken_links {
+        opt.scan.broken_links_aged && opt.scan.change_days.is_none()
+    } else {
+        criteria.contains(&None)
+    };
+    let window = opt.scan.days.map(|days| Ok(days.0))
+        .or_else(|| opt.scan.hours.map(|hours| Duration::try_from_secs_f64(hours * 3600.0)))
+        .or_else(|| opt.scan.minutes.map(|minutes| Duration::try_from_secs_f64(minutes * 60.0)))
+        .or_else(|| opt.scan.business_days.map(|days| Ok(Duration::from_secs(u64::from(days) * SECS_PER_DAY))))
+        .transpose()
+        .map_err(|e| AmbleError::ParseError(format!("invalid number of hours or minutes: {}", e)))?;
+    let window = match window {
+        Some(window) => window,
+        None if days_required => {
+            return Err(AmbleError::UnexpectedResult(
+                "--days, --hours, --minutes, or --business-days is required unless each criterion supplies its own \
+                 number of days".to_string()));
+        },
+        None => Duration::ZERO,
+    };
+    if opt.reports.last_activity.is_some() && !opt.scan.access && !opt.scan.modify {
+        return Err(AmbleError::UnexpectedResult(
+            "--last-activity requires access or modification times, -a or -m".to_string()));
+    }
+    if days_required && window.is_zero() {
+        return Err(AmbleError::ParseError(format!("days must be greater than 0: {}", Days(window))));
+    }
+    if let Some(days) = criteria.iter().flatten().find(|days| days.0.is_zero()) {
+        return Err(AmbleError::ParseError(format!("days must be greater than 0: {}", days)));
+    }
+
+    // Access times are never updated on filesystems mounted noatime, so
+    // testing them would keep everything. Unless the user asked for them,
+    // they are not tested on those roots. Under relatime, they are only
+    // accurate to a day.
+    let mut noatime_devices = HashSet::new();
+    if opt.scan.access || opt.scan.effective {
+        let access_window = opt.scan.access_days.map_or(window, |days| days.0);
+        for dir in &roots {
+            match atime_mode(dir) {
+                AtimeMode::Never if defaulted && !opt.scan.effective => {
+                    eprintln!("Note: '{}' is mounted noatime, so access times are not tested there.", dir.display());
+                    if let Some((device, _)) = fs::metadata(dir).ok().as_ref().and_then(inode) {
+                        noatime_devices.insert(device);
+                    }
+                },
+                AtimeMode::Never => {
+                    eprintln!("Warning: '{}' is mounted noatime, so access times are not updated.", dir.display());
+                },
+                AtimeMode::Relative if !access_window.is_zero() && access_window < Duration::from_secs(SECS_PER_DAY) => {
+                    eprintln!("Warning: '{}' is mounted relatime, so access times are only accurate to a day.",
+                              dir.display());
+                },
+                _ => (),
+            }
+        }
+        // when every root is mounted noatime, access times are not tested at all
+        if defaulted && !opt.scan.effective && !roots.is_empty()
+            && roots.iter().all(|dir| atime_mode(dir) == AtimeMode::Never) {
+            opt.scan.access = false;
+            noatime_devices.clear();
+        }
+    }
+
+    // directories only record when their own entries change, so pruning
+    // by their times misses files modified in place
+    let prune_cold_dirs = opt.scan.prune_cold_dirs && !opt.scan.access && !opt.scan.change
+        && !opt.scan.effective
+        && !opt.reports.classify && rules.is_none() && opt.reports.last_activity.is_none() && !broken_links
+        && older_than.is_none() && opt.scan.as_of.is_none();
+    if prune_cold_dirs {
+        eprintln!("Warning: --prune-cold-dirs skips directories by their own times, so files modified in place, and \
+                   files added beneath their subdirectories, are missed.");
+    } else if opt.scan.prune_cold_dirs {
+        eprintln!("Warning: --prune-cold-dirs only applies to scans of recent modification or creation times (-m, \
+                   -c), so is ignored.");
+    }
+
+    // never search the destination of moved, archived, or backed up
+    // matches, or the manifest of a plan or emitted script
+    let actions = &opt.actions;
+    for dest in [&actions.move_to, &actions.archive, &actions.backup_to, &actions.plan, &actions.emit_script,
+                 &actions.action_log]
+        .iter().copied().flatten() {
+        opt.scan.exclude_path.push(dest.clone());
+    }
+    for rule in rules.iter().flatten().filter(|rule| matches!(rule.action.as_str(), "move-to" | "stub-to" | "archive")) {
+        opt.scan.exclude_path.extend(rule.argument.as_ref().map(PathBuf::from));
+    }
+
+    let candidates = match &opt.scan.files_from {
+        Some(files_from) if files_from.as_os_str() == "-" => Some(read_path_list(io::stdin().lock())?),
+        Some(files_from) => {
+            let file = File::open(files_from).map_err(|e| {
+                AmbleError::IoError(format!("unable to read file list '{}': {}", files_from.display(), e))
+            })?;
+            Some(read_path_list(file)?)
+        },
+        None => None,
+    };
+
+    if let Some(skip_from) = &opt.scan.skip_from {
+        let file = File::open(skip_from).map_err(|e| {
+            AmbleError::IoError(format!("unable to read skip list '{}': {}", skip_from.display(), e))
+        })?;
+        opt.scan.skip.extend(read_skip_list(file)?);
+    }
+
+    let classifier = if opt.reports.classify {
+        let mut classifier = Classifier::new();
+        if let Some(hot) = opt.reports.hot { classifier.hot(hot); }
+        if let Some(warm) = opt.reports.warm { classifier.warm(warm); }
+        if let Some(cold) = opt.reports.cold { classifier.cold(cold); }
+        Some(classifier)
+    } else {
+        None
+    };
+
+    let empty = match (opt.scan.empty, opt.scan.non_empty) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    };
+
+    let size_mode = if opt.scan.allocated { SizeMode::Allocated } else { SizeMode::Apparent };
+
+    let confirm = match (opt.actions.yes, opt.actions.interactive) {
+        _ if opt.dry_run => Confirm::Simulate,
+        (_, true) => Confirm::Interactive,
+        (true, _) => Confirm::Yes,
+        _ => Confirm::DryRun,
+    };
+    // moves and trashing are recorded in the undo journal under this scan
+    let scan_id = new_scan_id();
+    let journal_path = opt.actions.undo_journal.clone().or_else(default_journal);
+    let journal = || journal_path.as_ref().map(|path| Journal::new(path, scan_id.as_str()));
+    let journaled = journal_path.is_some() && matches!(confirm, Confirm::Yes | Confirm::Interactive)
+        && (opt.actions.move_to.is_some() || opt.actions.trash
+            || rules.iter().flatten().any(|rule| matches!(rule.action.as_str(), "move-to" | "stub-to" | "trash")));
+
+    let action: Option<Box<dyn Action>> = if opt.actions.delete {
+        Some(Box::new(Delete))
+    } else if let Some(dest) = &opt.actions.move_to {
+        let mut move_to = MoveTo::new(dest, roots.clone());
+        move_to.leave_symlink(opt.actions.leave_symlink);
+        if let Some(journal) = journal() {
+            move_to.journal(journal);
+        }
+        Some(Box::new(move_to))
+    } else if let Some(archive) = &opt.actions.archive {
+        Some(archive_action(archive, opt.actions.archive_format.as_deref(), roots.clone(),
+                            opt.actions.remove_originals)?)
+    } else if !opt.actions.exec.is_empty() {
+        Some(Box::new(Exec::new(opt.actions.exec.clone())?))
+    } else if !opt.actions.exec_batch.is_empty() {
+        Some(Box::new(ExecBatch::new(opt.actions.exec_batch.clone())?))
+    } else if opt.actions.trash {
+        Some(trash_action(journal())?)
+    } else if let Some(mode) = &opt.actions.chmod {
+        Some(chmod_action(mode.clone())?)
+    } else if let Some(ownership) = &opt.actions.chown {
+        Some(chown_action(ownership.clone())?)
+    } else if let Some(format) = &opt.actions.compress {
+        Some(compress_action(format.as_deref())?)
+    } else if opt.actions.hardlink_dupes {
+        Some(dedupe_action()?)
+    } else if opt.actions.truncate {
+        Some(Box::new(Truncate))
+    } else {
+        None
+    };
+    let action = wrap_action(action, &opt, &roots);
+    let buffer_limit = opt.scan.buffer_limit.map_or(BUFFER_BYTES, |size| size.0);
+
+    let mut sink: Box<dyn Sink> = if let Some(rules) = &rules {
+        let mut policy = Policy::new();
+        if let Some(as_of) = opt.scan.as_of {
+            policy.as_of(as_of);
+        }
+        for rule in rules {
+            let sink = match policy_action(rule, &roots, &journal)? {
+                Some(action) => {
+                    let action = wrap_action(Some(action), &opt, &roots);
+                    apply(action.unwrap(), confirm, opt.actions.throttle)
+                },
+                None => report(&opt, size_mode, buffer_limit),
+            };
+            policy.tier(rule.days, sink);
+        }
+        Box::new(policy)
+    } else if let Some(script) = &opt.actions.emit_script {
+        let action = action.ok_or_else(|| {
+            AmbleError::UnexpectedResult("--emit-script requires an action, such as --delete or --move-to".to_string())
+        })?;
+        free_target(Box::new(Script::new(action, script)?), opt.actions.free_target, buffer_limit)
+    } else if let Some(action) = action {
+        free_target(apply(action, confirm, opt.actions.throttle), opt.actions.free_target, buffer_limit)
+    } else if opt.actions.free_target.is_some() {
+        return Err(AmbleError::UnexpectedResult("--free-target requires an action, such as --delete or --move-to".to_string()));
+    } else if opt.reports.classify {
+        let mut report = ClassReport::new();
+        report.size_mode(size_mode);
+        Box::new(report)
+    } else if let Some(plan) = &opt.actions.plan {
+        Box::new(Plan::new(plan, roots.clone()))
+    } else {
+        report(&opt, size_mode, buffer_limit)
+    };
+    if let Some(sort_by) = opt.scan.sort {
+        let mut sorted = Sorted::new(sink, sort_by, opt.scan.reverse, size_mode);
+        sorted.buffer_limit(buffer_limit);
+        sink = Box::new(sorted);
+    }
+    if let Some(days) = opt.reports.last_activity {
+        let mut last_activity = LastActivity::new(sink, roots.clone(), days.0);
+        last_activity.buffer_limit(buffer_limit);
+        last_activity.fields([(Field::Access, opt.scan.access), (Field::Modify, opt.scan.modify)].iter()
+                                .filter(|(_, requested)| *requested)
+                                .map(|(field, _)| *field)
+                                .collect());
+        if let Some(as_of) = opt.scan.as_of {
+            last_activity.as_of(as_of);
+        }
+        sink = Box::new(last_activity);
+    }
+    if let Some(state) = &opt.scan.state {
+        sink = Box::new(Incremental::new(sink, state)?);
+    }
+    if opt.scan.dedup_links || opt.scan.all_links {
+        sink = Box::new(DedupLinks::new(sink, opt.scan.all_links));
+    }
+    let indicator = (!opt.scan.no_progress && opt.scan.verbose == 0 && confirm != Confirm::Interactive
+                     && manifest.is_none() && io::stderr().is_terminal()).then(Indicator::start);
+    if let Some(indicator) = &indicator {
+        sink = Box::new(indicator.around(sink));
+    }
+    let live_progress = indicator.as_ref().map(Indicator::progress);
+
+    // SyncSearch and AsyncSearch share the same builder methods, so
+    // whichever we are using is configured here.
+    macro_rules! configure {
+        ($search:expr) => {{
+            let mut search = $search;
+            search.window(window)
+                  .access(opt.scan.access)
+                  .noatime_devices(noatime_devices)
+                  .create(opt.scan.create)
+                  .modify(opt.scan.modify)
+                  .change(opt.scan.change)
+                  .skip(opt.scan.skip)
+                  .exclude_paths(opt.scan.exclude_path)
+                  .names(opt.scan.name)
+                  .mime(opt.scan.mime)
+                  .case_insensitive(opt.scan.case_insensitive)
+                  .hidden_dirs(opt.scan.ignore || opt.scan.ignore_hidden_dirs)
+                  .hidden_files(opt.scan.ignore || opt.scan.ignore_hidden_files)
+                  .follow_links(!opt.scan.no_follow)
+                  .empty(empty)
+                  .broken_links(broken_links, opt.scan.broken_links_aged)
+                  .size_mode(size_mode)
+                  .min_size(opt.scan.min_size.map(|size| size.0))
+                  .max_size(opt.scan.max_size.map(|size| size.0))
+                  .executable(opt.scan.executable)
+                  .links(opt.scan.links)
+                  .owner(opt.scan.owner.map(|owner| owner.0))
+                  .mine(opt.scan.mine)
+                  .mode_flags(opt.scan.mode_flags)
+                  .effective(opt.scan.effective)
+                  .every(rules.is_some() || opt.reports.last_activity.is_some())
+                  .max_results(opt.scan.limit)
+                  .rollup(opt.scan.rollup)
+                  .prune_cold_dirs(prune_cold_dirs)
+                  .classify(classifier);
+            if let Some(as_of) = opt.scan.as_of { search.as_of(as_of); }
+            if let Some(skew) = opt.scan.skew { search.skew(skew.0); }
+            search.newer_than(newer_than).older_than(older_than).calendar_days(opt.scan.calendar_days)
+                  .business_days(opt.scan.business_days.is_some());
+            for (field, days) in [(Field::Access, opt.scan.access_days), (Field::Create, opt.scan.create_days),
+                                  (Field::Modify, opt.scan.modify_days), (Field::Change, opt.scan.change_days)].iter() {
+                if let Some(days) = days { search.field_window(*field, days.0); }
+            }
+            search
+        }};
+    }
+
+    if let RunMode::Watch { interval, rescan } = mode {
+        let unsupported = [(opt.scan.rollup, "--rollup"), (opt.scan.sort.is_some(), "--sort"),
+                           (opt.scan.limit.is_some(), "--limit"), (opt.scan.resume.is_some(), "--resume"),
+                           (opt.scan.state.is_some(), "--state"), (candidates.is_some(), "--files-from")];
+        if let Some((_, flag)) = unsupported.iter().find(|(given, _)| *given) {
+            return Err(AmbleError::UnexpectedResult(format!("watch does not support {}", flag)));
         }
-        opt.modify = true;
+        let changes: Box<dyn Changes> = if opt.scan.json {
+            let mut sink = JsonSink::new(opt.scan.time_format.unwrap_or_default());
+            sink.zone(opt.scan.timezone.unwrap_or_default());
+            Box::new(sink)
+        } else {
+            Box::new(PrintSink::default())
+        };
+        let mut search = configure!(SyncSearch::new("."));
+        search.backend(opt.scan.backend.unwrap_or_default())
+              .threads(opt.scan.threads)
+              .max_per_mount(opt.scan.max_per_mount)
+              .prefetch(opt.scan.prefetch);
+        watch(search, opt.scan.dir, interval, rescan, changes)?;
+        return Ok(Status::Matched);
+    }
+
+    let mut sink = Tally::new(sink);
+    let profiler = start_profile(opt.scan.flamegraph.as_deref())?;
+    let stats = if let Some(manifest) = manifest {
+        ManifestSearch::new(manifest).find_into(&mut sink)?
+    } else if opt.scan.sync || candidates.is_some() || opt.scan.backend.is_some() || opt.scan.resume.is_some()
+              || opt.scan.walk_order.is_some() || opt.scan.prefetch.is_some() {
+        // the candidates are evaluated in place, so there is nothing to
+        // traverse in parallel
+        configure!(SyncSearch::new(".")).roots(opt.scan.dir)
+                                        .candidates(candidates)
+                                        .backend(opt.scan.backend.unwrap_or_default())
+                                        .threads(opt.scan.threads)
+                                        .resume(opt.scan.resume)
+                                        .order(opt.scan.walk_order.unwrap_or_default())
+                                        .max_per_mount(opt.scan.max_per_mount)
+                                        .prefetch(opt.scan.prefetch)
+                                        .live_progress(live_progress)
+                                        .find_into(&mut sink)?
+    } else {
+        configure!(AsyncSearch::new(".")).roots(opt.scan.dir)
+                                         .threads(opt.scan.threads)
+                                         .adaptive_threads(opt.scan.adaptive_threads)
+                                         .max_per_mount(opt.scan.max_per_mount)
+                                         .channel_capacity(opt.scan.channel_capacity.unwrap_or(CHANNEL_CAPACITY))
+                                         .buffer_limit(buffer_limit)
+                                         .live_progress(live_progress)
+                                         .find_into(&mut sink)?
+    };
+    if let Some(indicator) = indicator {
+        indicator.finish();
+    }
+    finish_profile(profiler)?;
+
+    // the summary goes to stderr, so as not to disturb the matches
+    if opt.scan.stats {
+        report_stats(&stats, opt.scan.timezone.unwrap_or_default());
+    }
+    if journaled && sink.matches() > 0 {
+        println!("# scan {}: restore with `amble undo {}`", scan_id, scan_id);
+    }
+    let errors = stats.errors + sink.errors();
+    if let Some(command) = &opt.scan.on_complete {
+        on_complete(command, sink.matches(), sink.bytes(), errors, stats.reference);
     }
+    Ok(match (errors, sink.matches()) {
+        (0, 0) => Status::Unmatched,
+        (0, _) => Status::Matched,
+        _ => Status::Incomplete,
+    })
+}
+
+// Report the statistics of a scan to stderr, with times in the zone.
+fn report_stats(stats: &ScanStats, zone: Zone) {
+    if let Some(reference) = stats.reference {
+        eprintln!("# ages as of {}", zone.rfc3339(reference));
+    }
+    let secs = stats.elapsed.as_secs_f64();
+    eprintln!("# scanned {} files and {} directories in {:.3}s: {:.0} files/s, {:.0} directories/s",
+              stats.files, stats.dirs, secs, stats.rate(stats.files), stats.rate(stats.dirs));
+    eprintln!("# {} stat calls, {} bytes of metadata", stats.stat_calls, stats.metadata_bytes());
+    for (thread, cpu) in stats.threads.iter().enumerate() {
+        let utilization = if secs > 0.0 { 100.0 * cpu.as_secs_f64() / secs } else { 0.0 };
+        eprintln!("# thread {}: {:.3}s of CPU, {:.0}% utilized", thread, cpu.as_secs_f64(), utilization);
+    }
+    if let Some(fewest) = stats.fewest_threads {
+        eprintln!("# adaptive threads: as few as {} working at once", fewest);
+    }
+}
 
-    if opt.sync {
-        SyncSearch::new(&opt.dir).days(opt.days)
-                                 .access(opt.access)
-                                 .create(opt.create)
-                                 .modify(opt.modify)
-                                 .skip(opt.skip)
-                                 .ignore_hidden(opt.ignore)
-                                 .find_matching()
+// Report how a policy, run on a schedule, will treat the files beneath the
+// paths.
+fn schedule_check(policy: &Path, schedule: &Schedule, paths: &[PathBuf], opt: &Opt) -> Result<(), AmbleError> {
+    let file = File::open(policy).map_err(|e| {
+        AmbleError::IoError(format!("unable to read policy '{}': {}", policy.display(), e))
+    })?;
+    let mut check = ScheduleCheck::new(read_policy(file)?, schedule.clone());
+    if let Some(as_of) = opt.scan.as_of {
+        check.as_of(as_of);
+    }
+    let timeline = paths.iter().all(|path| path.is_file());
+    check.timeline(timeline).zone(opt.scan.timezone.unwrap_or_default());
+    match check.next_run() {
+        Some(next_run) => println!("# next run: {}", opt.scan.timezone.unwrap_or_default().rfc3339(next_run)),
+        None => println!("# '{}' never runs", schedule),
+    }
+    let mut search = SyncSearch::new(".");
+    search.every(true).follow_links(!opt.scan.no_follow);
+    if timeline {
+        search.candidates(Some(paths.to_vec()));
     } else {
-        AsyncSearch::new(&opt.dir).days(opt.days)
-                                  .access(opt.access)
-                                  .create(opt.create)
-                                  .modify(opt.modify)
-                                  .skip(opt.skip)
-                                  .ignore_hidden(opt.ignore)
-                                  .threads(opt.threads)
-                                  .find_matching()
-    }
-}
\ No newline at end of file
+        search.roots(paths.to_vec());
+    }
+    search.find_into(&mut check)?;
+    Ok(())
+}
+
+// The modification time of a reference file, as given to --newer-than
+fn reference_time(path: &Path) -> Result<SystemTime, AmbleError> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).map_err(|e| {
+        AmbleError::IoError(format!("unable to read the modification time of '{}': {}", path.display(), e))
+    })
+}
+
+// Run the command supplied to --on-complete via the shell, passing the
+// summary of the search in its environment. A command which fails is
+// reported, but does not fail the search.
+fn on_complete(command: &str, matches: u64, bytes: u64, errors: u64, reference: Option<SystemTime>) {
+    let mut shell = Command::new("sh");
+    shell.arg("-c")
+         .arg(command)
+         .env("MATCH_COUNT", matches.to_string())
+         .env("TOTAL_BYTES", bytes.to_string())
+         .env("ERROR_COUNT", errors.to_string());
+    if let Some(reference) = reference.and_then(|reference| reference.duration_since(UNIX_EPOCH).ok()) {
+        shell.env("REFERENCE_TIME", reference.as_secs().to_string());
+    }
+    let status = shell.status();
+    match status {
+        Ok(status) if status.success() => (),
+        Ok(status) => eprintln!("Warning: --on-complete command {}", status),
+        Err(e) => eprintln!("Warning: unable to run --on-complete command: {}", e),
+    }
+}
+
+// How actions are confirmed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Confirm {
+    DryRun,
+    // a dry run, whatever the user said, with --dry-run
+    Simulate,
+    Yes,
+    Interactive,
+}
+
+// Wrap the action in those which apply to every action: a backup is taken
+// before it is applied, it is logged, and empty directories are pruned after.
+fn wrap_action(action: Option<Box<dyn Action>>, opt: &Opt, roots: &[PathBuf]) -> Option<Box<dyn Action>> {
+    let action = match &opt.actions.backup_to {
+        Some(dest) => {
+            let mut backup = BackupTo::new(dest, roots.to_vec());
+            if let Some(action) = action {
+                backup.then(action);
+            }
+            Some(Box::new(backup) as Box<dyn Action>)
+        },
+        None => action,
+    };
+    let action = match (action, &opt.actions.action_log) {
+        (Some(action), Some(path)) => {
+            let mut log = ActionLog::new(action, path);
+            log.checksums(opt.actions.log_checksums);
+            Some(Box::new(log) as Box<dyn Action>)
+        },
+        (action, _) => action,
+    };
+    match action {
+        Some(action) if opt.actions.prune_empty_dirs => Some(Box::new(PruneEmptyDirs::new(action, roots.to_vec()))),
+        action => action,
+    }
+}
+
+// The action for a tier of a policy, or None if its matches are reported.
+fn policy_action(rule: &Rule, roots: &[PathBuf], journal: &dyn Fn() -> Option<Journal>)
+-> Result<Option<Box<dyn Action>>, AmbleError> {
+    let argument = rule.argument.as_deref().unwrap_or_default();
+    let action: Box<dyn Action> = match rule.action.as_str() {
+        "report" => return Ok(None),
+        "delete" => Box::new(Delete),
+        "truncate" => Box::new(Truncate),
+        "trash" => trash_action(journal())?,
+        "compress" => compress_action(rule.argument.as_deref())?,
+        "move-to" | "stub-to" => {
+            let mut move_to = MoveTo::new(argument, roots.to_vec());
+            move_to.leave_symlink(rule.action == "stub-to");
+            if let Some(journal) = journal() {
+                move_to.journal(journal);
+            }
+            Box::new(move_to)
+        },
+        "archive" => archive_action(Path::new(argument), None, roots.to_vec(), true)?,
+        "chmod" => chmod_action(argument.parse()?)?,
+        "chown" => chown_action(argument.parse()?)?,
+        action => return Err(AmbleError::ParseError(format!("unknown policy action '{}'", action))),
+    };
+    Ok(Some(action))
+}
+
+// Log to stderr, at a level set by the number of -v flags.
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => Level::WARN,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    tracing_subscriber::fmt().with_max_level(level)
+                             .with_target(false)
+                             .with_ansi(io::stderr().is_terminal())
+                             .with_writer(io::stderr)
+                             .init();
+}
+
+// Fill in the options which were not supplied on the command line from the
+// settings of a config file, including its action, should it be actionable.
+fn apply_settings(opt: &mut Opt, settings: &Settings, actionable: bool) -> Result<(), AmbleError> {
+    let scan = &mut opt.scan;
+    let windowed = scan.days.is_some() || scan.hours.is_some() || scan.minutes.is_some()
+        || scan.business_days.is_some() || scan.newer_than.is_some() || scan.older_than_file.is_some()
+        || scan.age_pct.is_some();
+    if let (false, Some(days)) = (windowed, &settings.days) {
+        scan.days = Some(days.parse()?);
+    }
+    // criteria are taken as a set, from the command line or from the file
+    if !scan.access && !scan.modify && !scan.change && !scan.create && !scan.effective {
+        let enabled = |criterion: Option<bool>| criterion == Some(true);
+        scan.access = enabled(settings.access);
+        scan.modify = enabled(settings.modify);
+        scan.change = enabled(settings.change);
+        scan.create = enabled(settings.create);
+        scan.effective = settings.effective == Some(true);
+    }
+    if scan.skip.is_empty() {
+        scan.skip = settings.skip.clone();
+    }
+    if scan.exclude_path.is_empty() {
+        scan.exclude_path = settings.exclude_path.clone();
+    }
+    if scan.name.is_empty() {
+        scan.name = settings.name.clone();
+    }
+    scan.ignore |= settings.ignore_hidden == Some(true);
+    scan.no_follow |= settings.follow_links == Some(false);
+    match settings.format.as_deref().map(str::to_ascii_lowercase).as_deref() {
+        // the reports and plans have formats of their own
+        Some("json") => scan.json |= !opt.reports.classify && opt.actions.plan.is_none(),
+        Some("text") | None => (),
+        Some(format) => {
+            return Err(AmbleError::ParseError(format!("invalid format '{}'. Expected text or json", format)));
+        },
+    }
+    if let (None, Some(time_format)) = (scan.time_format, &settings.time_format) {
+        scan.time_format = Some(time_format.parse()?);
+    }
+    if let (None, Some(timezone)) = (scan.timezone, &settings.timezone) {
+        scan.timezone = Some(timezone.parse()?);
+    }
+    if !scan.adaptive_threads && scan.threads.is_none() {
+        scan.threads = settings.threads;
+    }
+    scan.sync |= settings.sync == Some(true);
+    if let (None, Some(backend)) = (scan.backend, &settings.backend) {
+        scan.backend = Some(backend.parse()?);
+    }
+    if let (None, Some(min_size)) = (scan.min_size, &settings.min_size) {
+        scan.min_size = Some(min_size.parse()?);
+    }
+    if let (None, Some(max_size)) = (scan.max_size, &settings.max_size) {
+        scan.max_size = Some(max_size.parse()?);
+    }
+    if scan.mime.is_empty() {
+        scan.mime = settings.mime.clone();
+    }
+    // the action is taken as a whole, from the command line or from the file
+    let actions = &mut opt.actions;
+    if actionable && !actions.requested() && !opt.reports.classify {
+        actions.delete = settings.delete == Some(true);
+        actions.move_to = settings.move_to.clone();
+        actions.archive = settings.archive.clone();
+        actions.trash = settings.trash == Some(true);
+        actions.exec = settings.exec.clone();
+        actions.plan = settings.plan.clone();
+        if actions.action_log.is_none() {
+            actions.action_log = settings.action_log.clone();
+        }
+        actions.prune_empty_dirs |= settings.prune_empty_dirs == Some(true);
+        actions.yes |= settings.yes == Some(true) && !actions.interactive;
+    }
+    Ok(())
+}
+
+// The settings a scan runs with, as they would be written in a config file.
+fn effective_settings(opt: &Opt) -> Settings {
+    let scan = &opt.scan;
+    let actions = &opt.actions;
+    let flag = |set: bool| Some(set);
+    Settings {
+        days: scan.days.map(|days| days.to_string()),
+        access: flag(scan.access),
+        modify: flag(scan.modify),
+        change: flag(scan.change),
+        create: flag(scan.create),
+        effective: flag(scan.effective),
+        skip: scan.skip.clone(),
+        exclude_path: scan.exclude_path.clone(),
+        name: scan.name.clone(),
+        ignore_hidden: flag(scan.ignore),
+        follow_links: flag(!scan.no_follow),
+        format: Some(if scan.json { "json" } else { "text" }.to_string()),
+        time_format: Some(scan.time_format.unwrap_or_default().to_string()),
+        timezone: Some(scan.timezone.unwrap_or_default().to_string()),
+        threads: scan.threads,
+        sync: flag(scan.sync),
+        backend: scan.backend.map(|backend| backend.to_string()),
+        min_size: scan.min_size.map(|size| size.0.to_string()),
+        max_size: scan.max_size.map(|size| size.0.to_string()),
+        mime: scan.mime.clone(),
+        delete: flag(actions.delete),
+        move_to: actions.move_to.clone(),
+        archive: actions.archive.clone(),
+        trash: flag(actions.trash),
+        exec: actions.exec.clone(),
+        plan: actions.plan.clone(),
+        action_log: actions.action_log.clone(),
+        prune_empty_dirs: flag(actions.prune_empty_dirs),
+        yes: flag(actions.yes),
+        dirs: scan.dir.clone(),
+        ..Settings::default()
+    }
+}
+
+// Print the supplied settings as TOML, or as JSON, noting the config file
+// they were read from to stderr.
+fn show_settings(settings: &Settings, source: Option<&Path>, json: bool) -> Result<(), AmbleError> {
+    match source {
+        Some(source) => eprintln!("# config: {}", source.display()),
+        None => eprintln!("# config: none"),
+    }
+    if json {
+        let text = serde_json::to_string_pretty(settings)
+            .map_err(|e| AmbleError::UnexpectedResult(format!("unable to serialize the config: {}", e)))?;
+        println!("{}", text);
+    } else {
+        let text = toml::to_string(settings)
+            .map_err(|e| AmbleError::UnexpectedResult(format!("unable to serialize the config: {}", e)))?;
+        print!("{}", text);
+    }
+    Ok(())
+}
+
+// A sink reporting matches, or their groups, as JSON or as text.
+fn report(opt: &Opt, size_mode: SizeMode, buffer_limit: u64) -> Box<dyn Sink> {
+    if let Some(group_by) = opt.reports.group_by {
+        let mut report = GroupReport::new(group_by);
+        report.size_mode(size_mode).zone(opt.scan.timezone.unwrap_or_default()).buffer_limit(buffer_limit);
+        if opt.scan.json {
+            report.json(opt.scan.time_format.unwrap_or_default());
+        }
+        Box::new(report)
+    } else if opt.scan.json {
+        let mut sink = JsonSink::new(opt.scan.time_format.unwrap_or_default());
+        sink.zone(opt.scan.timezone.unwrap_or_default());
+        Box::new(sink)
+    } else {
+        Box::new(PrintSink::default())
+    }
+}
+
+// Limit the supplied sink to the oldest matches needed to reach the free
+// space target, if there is one.
+fn free_target(sink: Box<dyn Sink>, target: Option<SpaceTarget>, buffer_limit: u64) -> Box<dyn Sink> {
+    match target {
+        Some(target) => {
+            let mut free_target = FreeTarget::new(sink, target);
+            free_target.buffer_limit(buffer_limit);
+            Box::new(free_target)
+        },
+        None => sink,
+    }
+}
+
+// A sink applying the supplied action, which is a dry run unless the
+// user has said yes, or is confirming each match, without --dry-run.
+fn apply(action: Box<dyn Action>, confirm: Confirm, throttle: Option<Throttle>) -> Box<dyn Sink> {
+    let mut apply = Apply::new(action);
+    apply.dry_run(matches!(confirm, Confirm::DryRun | Confirm::Simulate))
+         .forced(confirm == Confirm::Simulate);
+    if let Some(throttle) = throttle {
+        apply.throttle(throttle);
+    }
+    if confirm == Confirm::Interactive {
+        apply.interactive(prompt());
+    }
+    Box::new(apply)
+}
+
+// Where answers to interactive prompts are read from. The terminal is
+// preferred, as stdin may be supplying the paths to search.
+fn prompt() -> Box<dyn BufRead + Send> {
+    match File::open("/dev/tty") {
+        Ok(tty) => Box::new(BufReader::new(tty)),
+        Err(_) => Box::new(BufReader::new(io::stdin())),
+    }
+}
+
+#[cfg(feature = "archive")]
+fn archive_action(dest: &Path, format: Option<&str>, roots: Vec<PathBuf>, remove_originals: bool)
+-> Result<Box<dyn Action>, AmbleError> {
+    let mut archive = Archive::new(dest, roots);
+    if let Some(format) = format {
+        archive.format(format.parse::<ArchiveFormat>()?);
+    }
+    archive.remove_originals(remove_originals);
+    Ok(Box::new(archive))
+}
+
+#[cfg(not(feature = "archive"))]
+fn archive_action(_dest: &Path, _format: Option<&str>, _roots: Vec<PathBuf>, _remove_originals: bool)
+-> Result<Box<dyn Action>, AmbleError> {
+    Err(AmbleError::UnexpectedResult(
+        "archiving requires amble to be built with the archive feature".to_string()))
+}
+
+// Watch the roots, reporting the changes to their matches, until the watch
+// fails.
+#[cfg(feature = "watch")]
+fn watch(mut search: SyncSearch, roots: Vec<PathBuf>, interval: Duration, rescan: Option<Duration>,
+         mut changes: Box<dyn Changes>)
+-> Result<(), AmbleError> {
+    let mut watch = Watch::new(roots);
+    watch.interval(interval).rescan(rescan);
+    watch.run(&mut search, &mut *changes)
+}
+
+#[cfg(not(feature = "watch"))]
+fn watch(_search: SyncSearch, _roots: Vec<PathBuf>, _interval: Duration, _rescan: Option<Duration>,
+         _changes: Box<dyn Changes>)
+-> Result<(), AmbleError> {
+    Err(AmbleError::UnexpectedResult("watching requires amble to be built with the watch feature".to_string()))
+}
+
+// Stands in for the changes of a watch, without the watch feature.
+#[cfg(not(feature = "watch"))]
+trait Changes {}
+
+#[cfg(not(feature = "watch"))]
+impl Changes for PrintSink {}
+
+#[cfg(not(feature = "watch"))]
+impl Changes for JsonSink {}
+
+// Start profiling the scan, should a flamegraph be wanted.
+#[cfg(all(feature = "profile", unix))]
+fn start_profile(out: Option<&Path>) -> Result<Option<Profiler>, AmbleError> {
+    out.map(Profiler::start).transpose()
+}
+
+#[cfg(all(feature = "profile", unix))]
+fn finish_profile(profiler: Option<Profiler>) -> Result<(), AmbleError> {
+    profiler.map_or(Ok(()), Profiler::finish)
+}
+
+// Stands in for the profiler, without the profile feature.
+#[cfg(not(all(feature = "profile", unix)))]
+enum Profiler {}
+
+#[cfg(not(all(feature = "profile", unix)))]
+fn start_profile(out: Option<&Path>) -> Result<Option<Profiler>, AmbleError> {
+    match out {
+        Some(_) => Err(AmbleError::UnexpectedResult(
+            "profiling requires amble to be built with the profile feature".to_string())),
+        None => Ok(None),
+    }
+}
+
+#[cfg(not(all(feature = "profile", unix)))]
+fn finish_profile(profiler: Option<Profiler>) -> Result<(), AmbleError> {
+    match profiler {
+        Some(profiler) => match profiler {},
+        None => Ok(()),
+    }
+}
+
+#[cfg(feature = "trash")]
+fn trash_action(journal: Option<Journal>) -> Result<Box<dyn Action>, AmbleError> {
+    let mut trash = Trash::default();
+    if let Some(journal) = journal {
+        trash.journal(journal);
+    }
+    Ok(Box::new(trash))
+}
+
+#[cfg(not(feature = "trash"))]
+fn trash_action(_journal: Option<Journal>) -> Result<Box<dyn Action>, AmbleError> {
+    Err(AmbleError::UnexpectedResult(
+        "trashing requires amble to be built with the trash feature".to_string()))
+}
+
+#[cfg(feature = "compress")]
+fn compress_action(format: Option<&str>) -> Result<Box<dyn Action>, AmbleError> {
+    let format = match format {
+        Some(format) => format.parse::<CompressFormat>()?,
+        None => CompressFormat::default(),
+    };
+    Ok(Box::new(Compress::new(format)))
+}
+
+#[cfg(not(feature = "compress"))]
+fn compress_action(_format: Option<&str>) -> Result<Box<dyn Action>, AmbleError> {
+    Err(AmbleError::UnexpectedResult(
+        "compressing requires amble to be built with the compress feature".to_string()))
+}
+
+#[cfg(unix)]
+fn chmod_action(mode: Mode) -> Result<Box<dyn Action>, AmbleError> {
+    Ok(Box::new(Chmod::new(mode)))
+}
+
+#[cfg(not(unix))]
+fn chmod_action(_mode: Mode) -> Result<Box<dyn Action>, AmbleError> {
+    Err(AmbleError::UnexpectedResult("--chmod is only supported on unix".to_string()))
+}
+
+#[cfg(unix)]
+fn chown_action(ownership: Ownership) -> Result<Box<dyn Action>, AmbleError> {
+    Ok(Box::new(Chown::new(ownership)))
+}
+
+#[cfg(not(unix))]
+fn chown_action(_ownership: Ownership) -> Result<Box<dyn Action>, AmbleError> {
+    Err(AmbleError::UnexpectedResult("--chown is only supported on unix".to_string()))
+}
+
+#[cfg(unix)]
+fn dedupe_action() -> Result<Box<dyn Action>, AmbleError> {
+    Ok(Box::new(Dedupe::default()))
+}
+
+#[cfg(not(unix))]
+fn dedupe_action() -> Result<Box<dyn Action>, AmbleError> {
+    Err(AmbleError::UnexpectedResult("--hardlink-dupes is only supported on unix".to_string()))
+}
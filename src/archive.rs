@@ -0,0 +1,244 @@
+//! archive.rs
+//!
+//! Archiving of matches, so that cold data may be packed off to cheaper
+//! storage in one step. Archives are written as gzip compressed tar files,
+//! or as zip files, which are easier to open on Windows. Requires the
+//! archive feature.
+use std::convert::TryFrom;
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::path::{self, Component, Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use chrono::{Datelike, Timelike, Utc};
+use flate2::{write::GzEncoder, Compression};
+use tar::Builder;
+use walkdir::WalkDir;
+use zip::{write::SimpleFileOptions, DateTime, ZipWriter};
+
+use crate::{actions::{relative_path, Action}, errors::AmbleError, filematch::FileMatch, hardlinks::inode};
+
+/// The format in which an archive is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// A gzip compressed tar file
+    TarGz,
+    /// A zip file
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// The format implied by the extension of the supplied path: zip for
+    /// ".zip", and tar.gz otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("zip") => ArchiveFormat::Zip,
+            _ => ArchiveFormat::TarGz,
+        }
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "tar.gz" | "tgz" => Ok(ArchiveFormat::TarGz),
+            "zip" => Ok(ArchiveFormat::Zip),
+            _ => Err(AmbleError::ParseError(format!("invalid archive format '{}'. Expected tar.gz or zip", s))),
+        }
+    }
+}
+
+// an archive being written
+enum Writer {
+    TarGz(Box<Builder<GzEncoder<File>>>),
+    Zip(Box<ZipWriter<File>>),
+}
+
+impl Writer {
+    // Append a directory, link, or file to the archive under the supplied
+    // name. Links are archived as links.
+    fn append(&mut self, path: &Path, name: &Path, metadata: &Metadata) -> Result<(), AmbleError> {
+        match self {
+            Writer::TarGz(builder) => if metadata.is_dir() {
+                builder.append_dir(name, path)?;
+            } else {
+                builder.append_path_with_name(path, name)?;
+            },
+            Writer::Zip(zip) => append_zip(zip, path, &zip_name(name), metadata)?,
+        }
+        Ok(())
+    }
+}
+
+/// Streams each match into an archive, named by its path relative to the
+/// root it was found under. The archive is created when the first match
+/// arrives, and an existing file is never overwritten. Optionally, the
+/// originals are removed once the archive has been written successfully.
+pub struct Archive {
+    dest: PathBuf,
+    roots: Vec<PathBuf>,
+    format: ArchiveFormat,
+    remove_originals: bool,
+    writer: Option<Writer>,
+    // the (device, inode) pair of the archive, which is never archived
+    inode: Option<(u64, u64)>,
+    archived: Vec<FileMatch>,
+}
+
+impl Archive {
+    /// New up an Archive writing to the supplied file, for matches found
+    /// under the supplied roots. The format is implied by the extension
+    /// of the file.
+    pub fn new(dest: impl Into<PathBuf>, roots: Vec<PathBuf>) -> Self {
+        let dest = dest.into();
+        Self {
+            format: ArchiveFormat::from_path(&dest),
+            dest,
+            roots,
+            remove_originals: false,
+            writer: None,
+            inode: None,
+            archived: Vec::new(),
+        }
+    }
+
+    /// Set the format in which the archive is written.
+    pub fn format(&mut self, format: ArchiveFormat) -> &mut Self {
+        self.format = format;
+        self
+    }
+
+    /// Set whether or not to remove the originals once the archive has been
+    /// written successfully.
+    pub fn remove_originals(&mut self, remove_originals: bool) -> &mut Self {
+        self.remove_originals = remove_originals;
+        self
+    }
+
+    // the archive, which is created on first use
+    fn writer(&mut self) -> Result<&mut Writer, AmbleError> {
+        if self.writer.is_none() {
+            let file = File::options().write(true).create_new(true).open(&self.dest).map_err(|e| {
+                AmbleError::IoError(format!("unable to create archive '{}': {}", self.dest.display(), e))
+            })?;
+            self.inode = inode(&file.metadata()?);
+            self.writer = Some(match self.format {
+                ArchiveFormat::TarGz => {
+                    let mut builder = Builder::new(GzEncoder::new(file, Compression::default()));
+                    // broken links are archived as links
+                    builder.follow_symlinks(false);
+                    Writer::TarGz(Box::new(builder))
+                },
+                ArchiveFormat::Zip => Writer::Zip(Box::new(ZipWriter::new(file))),
+            });
+        }
+        Ok(self.writer.as_mut().unwrap())
+    }
+}
+
+impl Action for Archive {
+    fn verb(&self) -> &'static str {
+        "archive"
+    }
+
+    fn describe(&self, found: &FileMatch) -> String {
+        format!("archive {} -> {}", found.path.display(), self.dest.display())
+    }
+
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError> {
+        let name = relative_path(&found.path, &self.roots)?;
+        self.writer()?;
+        let archive_inode = self.inode;
+        let writer = self.writer.as_mut().unwrap();
+        // a rolled up directory is walked, so that the archive itself may
+        // be left out if it is beneath it
+        for entry in WalkDir::new(&found.path) {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if archive_inode.is_some() && inode(&metadata) == archive_inode {
+                continue;
+            }
+            let name = match entry.path().strip_prefix(&found.path) {
+                Ok(relative) if !relative.as_os_str().is_empty() => name.join(relative),
+                _ => name.clone(),
+            };
+            writer.append(entry.path(), &name, &metadata)?;
+        }
+        if self.remove_originals {
+            self.archived.push(found.clone());
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        match self.writer.take() {
+            Some(Writer::TarGz(builder)) => builder.into_inner()?.finish()?.sync_all()?,
+            Some(Writer::Zip(zip)) => zip.finish().map_err(io::Error::from)?.sync_all()?,
+            None => return Ok(()),
+        }
+
+        let archive = path::absolute(&self.dest)?;
+        for found in self.archived.drain(..) {
+            if path::absolute(&found.path).is_ok_and(|path| archive.starts_with(path)) {
+                eprintln!("Warning: not removing '{}', which contains the archive", found.path.display());
+                continue;
+            }
+            let removed = if found.contents.is_some() {
+                fs::remove_dir_all(&found.path)
+            } else {
+                fs::remove_file(&found.path)
+            };
+            if let Err(e) = removed {
+                eprintln!("Error: unable to remove '{}' after archiving: {}", found.path.display(), e);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Append a directory, link, or file to a zip archive, preserving its
+// modification time and permissions.
+fn append_zip(zip: &mut ZipWriter<File>, path: &Path, name: &str, metadata: &Metadata) -> Result<(), AmbleError> {
+    let mut options = SimpleFileOptions::default().large_file(metadata.len() >= u64::from(u32::MAX));
+    if let Some(modified) = metadata.modified().ok().and_then(zip_time) {
+        options = options.last_modified_time(modified);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        options = options.unix_permissions(metadata.permissions().mode());
+    }
+    let file_type = metadata.file_type();
+    if file_type.is_dir() {
+        zip.add_directory(name, options).map_err(io::Error::from)?;
+    } else if file_type.is_symlink() {
+        let target = fs::read_link(path)?;
+        zip.add_symlink(name, target.to_string_lossy(), options).map_err(io::Error::from)?;
+    } else {
+        zip.start_file(name, options).map_err(io::Error::from)?;
+        io::copy(&mut File::open(path)?, zip)?;
+    }
+    Ok(())
+}
+
+// Names within a zip archive are separated by '/', whatever the platform.
+fn zip_name(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Convert a timestamp to a zip DateTime (UTC). Zip cannot represent times
+// before 1980.
+fn zip_time(time: SystemTime) -> Option<DateTime> {
+    let time = chrono::DateTime::<Utc>::from(time);
+    DateTime::from_date_and_time(u16::try_from(time.year()).ok()?, time.month() as u8, time.day() as u8,
+                                 time.hour() as u8, time.minute() as u8, time.second() as u8).ok()
+}
@@ -12,80 +12,303 @@
 //! All results are printed to stdout.
 //!
 //! All errors are printed to stderr.
+//!
+//! The channels between the walker threads and the threads reporting
+//! results and errors are bounded, so that when the walkers outpace the
+//! sink, such as when piping into a slow consumer, they wait for it to
+//! catch up rather than queueing every match in memory. Memory is then
+//! bounded by the capacity of the channels, at the cost of walkers idling
+//! while the sink is blocked. Rolling up still gathers every entry, as
+//! entries must all arrive before any directory may be reported, though
+//! beyond a limit they are spilled to temporary files.
 
 // replacement channel that is more efficient
 use crossbeam_channel as channel;
+use crossbeam_channel::SendError;
 // embed color codes in strings
 use colored::*;
 // ignore crate written for ripgrep
-use ignore::{WalkBuilder,DirEntry, WalkState};
-use std::path::PathBuf;
-use std::thread;
+use ignore::{WalkBuilder, WalkState};
+use tracing::debug;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant, SystemTime};
 
 // internal imports
-use crate::{ constants::SECS_PER_DAY, errors::AmbleError, traits::Finder };
+use crate::{
+    adaptive::Governor,
+    classify::Classifier,
+    constants::{BUFFER_BYTES, CHANNEL_CAPACITY, SECS_PER_DAY},
+    criteria::Criteria,
+    duration::to_duration,
+    errors::AmbleError,
+    indicator::eprint_above,
+    fields::{Availability, Field},
+    hardlinks::{inode, LinkCount},
+    mounts::MountLimits,
+    owner::effective_uid,
+    roots::{dedup_roots, Attribute},
+    rollup::{Rollup, Visit},
+    size::SizeMode,
+    skip::{read_skip_list, Prune},
+    stats::{thread_cpu_time, Counters, ScanProgress, ScanStats},
+    symlinks::{dangling_link, ignore_error_path},
+    traits::{Finder, Sink},
+};
+
+// Records the CPU time of the walker thread which drops it. The walker
+// threads are spawned for the search, so their CPU time is all spent on
+// it. The calling thread, which visits the roots, is not recorded.
+struct ThreadCpu<'a> {
+    times: &'a Mutex<Vec<Duration>>,
+    caller: ThreadId,
+}
+
+impl Drop for ThreadCpu<'_> {
+    fn drop(&mut self) {
+        if thread::current().id() == self.caller {
+            return;
+        }
+        if let (Some(time), Ok(mut times)) = (thread_cpu_time(), self.times.lock()) {
+            times.push(time);
+        }
+    }
+}
 
 /// Provides implementation of Finder.
 pub struct AsyncSearch {
-    start_dir: PathBuf,
-    days: f32,
-    access: bool,
-    create: bool,
-    modify: bool,
-    ignore_hidden: bool,
+    roots: Vec<PathBuf>,
+    criteria: Criteria,
+    hidden_dirs: bool,
+    hidden_files: bool,
+    follow_links: bool,
     skip: Vec<String>,
-    threads: Option<u8>
+    case_insensitive: bool,
+    exclude_paths: Vec<PathBuf>,
+    max_results: Option<usize>,
+    rollup: bool,
+    threads: Option<u8>,
+    adaptive_threads: bool,
+    max_per_mount: Option<usize>,
+    channel_capacity: usize,
+    buffer_limit: u64,
+    live_progress: Option<Arc<ScanProgress>>,
 }
 
 impl AsyncSearch {
     /// New up an AsyncSearch instance, passing a PathBuf
     pub fn new(start_dir: impl Into<PathBuf>) -> Self {
         Self {
-            start_dir: start_dir.into(),
-            days: 8.0,
-            access: true,
-            create: true,
-            modify: true,
-            ignore_hidden: true,
+            roots: vec![start_dir.into()],
+            criteria: Criteria::default(),
+            hidden_dirs: true,
+            hidden_files: true,
+            follow_links: true,
             skip: Vec::new(),
+            case_insensitive: false,
+            exclude_paths: Vec::new(),
+            max_results: None,
+            rollup: false,
             threads: None,
+            adaptive_threads: false,
+            max_per_mount: None,
+            channel_capacity: CHANNEL_CAPACITY,
+            buffer_limit: BUFFER_BYTES,
+            live_progress: None,
         }
     }
 
-    /// Reset the start directory for a search.
+    /// Reset the start directory for a search, replacing any roots.
     pub fn start_dir(&mut self, start_dir: impl Into<PathBuf>) -> &mut Self {
-        self.start_dir = start_dir.into();
+        self.roots = vec![start_dir.into()];
+        self
+    }
+
+    /// Set the root directories to search. Roots which are nested within,
+    /// or the same as, another root are only searched once.
+    pub fn roots(&mut self, roots: Vec<PathBuf>) -> &mut Self {
+        self.roots = roots;
         self
     }
     /// Set the number of days to search for.
     pub fn days(&mut self, days: f32) -> &mut Self {
-        self.days = days;
+        self.criteria.window = to_duration(days, SECS_PER_DAY);
+        self
+    }
+
+    /// Compute ages relative to the supplied instant, rather than now, so
+    /// that a search may be reproduced, or re-run as of some past time.
+    pub fn as_of(&mut self, as_of: SystemTime) -> &mut Self {
+        self.criteria.as_of = Some(as_of);
+        self
+    }
+
+    /// Match timestamps later than the supplied time, such as the
+    /// modification time of a reference file, in place of days.
+    pub fn newer_than(&mut self, newer_than: Option<SystemTime>) -> &mut Self {
+        self.criteria.newer_than = newer_than;
+        self
+    }
+
+    /// Match timestamps earlier than the supplied time, in place of days.
+    pub fn older_than(&mut self, older_than: Option<SystemTime>) -> &mut Self {
+        self.criteria.older_than = older_than;
+        self
+    }
+
+    /// Widen windows by the supplied tolerance, to absorb clock drift
+    /// between hosts, such as NFS clients and servers. Timestamps are only
+    /// flagged as in the future when later than now by more than it.
+    pub fn skew(&mut self, skew: Duration) -> &mut Self {
+        self.criteria.skew = skew;
+        self
+    }
+
+    /// Test the effective time of each file, the latest of its access,
+    /// modification, and creation times, where available, in place of the
+    /// individual fields, so that a match has been touched in any way
+    /// within the number of days.
+    pub fn effective(&mut self, effective: bool) -> &mut Self {
+        self.criteria.effective = effective;
+        self
+    }
+
+    /// Count days in calendar terms, so that a window of N days begins at
+    /// local midnight N calendar days ago, rather than N * 24 hours ago.
+    /// Fractions of days are rounded up.
+    pub fn calendar_days(&mut self, calendar_days: bool) -> &mut Self {
+        self.criteria.calendar_days = calendar_days;
+        self
+    }
+
+    /// Set how far back to search, in place of days, precise to the
+    /// nanosecond.
+    pub fn window(&mut self, window: Duration) -> &mut Self {
+        self.criteria.window = window;
+        self
+    }
+
+    /// Use the supplied field, with its own window, overriding the window
+    /// set by `days` for this criterion.
+    pub fn field_window(&mut self, field: Field, window: Duration) -> &mut Self {
+        self.criteria.field_window(field, window);
+        self
+    }
+
+    /// Count days as business days, so that a window of N days begins N
+    /// weekdays ago, skipping weekends. Fractions of days are rounded up.
+    pub fn business_days(&mut self, business_days: bool) -> &mut Self {
+        self.criteria.business_days = business_days;
+        self
+    }
+
+    /// Set the number of hours to search for, in place of days.
+    pub fn hours(&mut self, hours: f32) -> &mut Self {
+        self.criteria.window = to_duration(hours, 60 * 60);
+        self
+    }
+
+    /// Set the number of minutes to search for, in place of days.
+    pub fn minutes(&mut self, minutes: f32) -> &mut Self {
+        self.criteria.window = to_duration(minutes, 60);
         self
     }
 
     /// Set whether or not we are interested in access time.
     pub fn access(&mut self, access: bool) -> &mut Self {
-        self.access = access;
+        self.criteria.access = access;
+        self
+    }
+
+    /// Do not test the access times of files on the supplied devices,
+    /// such as those mounted noatime, whose access times are never updated.
+    pub fn noatime_devices(&mut self, devices: HashSet<u64>) -> &mut Self {
+        self.criteria.noatime_devices = devices;
+        self
+    }
+
+    /// Use access time, with its own number of days, overriding the
+    /// number set by `days` for this criterion.
+    pub fn access_days(&mut self, days: f32) -> &mut Self {
+        self.criteria.field_window(Field::Access, to_duration(days, SECS_PER_DAY));
         self
     }
 
     /// Set whether or not we are interested in creation time.
     pub fn create(&mut self, create: bool) -> &mut Self {
-        self.create = create;
+        self.criteria.create = create;
+        self
+    }
+
+    /// Use creation time, with its own number of days, overriding the
+    /// number set by `days` for this criterion.
+    pub fn create_days(&mut self, days: f32) -> &mut Self {
+        self.criteria.field_window(Field::Create, to_duration(days, SECS_PER_DAY));
         self
     }
 
     /// Set whether or not we are interested in modification time.
     pub fn modify(&mut self, modify: bool) -> &mut Self {
-        self.modify = modify;
+        self.criteria.modify = modify;
+        self
+    }
+
+    /// Use modification time, with its own number of days, overriding the
+    /// number set by `days` for this criterion.
+    pub fn modify_days(&mut self, days: f32) -> &mut Self {
+        self.criteria.field_window(Field::Modify, to_duration(days, SECS_PER_DAY));
+        self
+    }
+
+    /// Set whether or not we are interested in inode change time (ctime).
+    /// Unlike modification time, change time cannot be set by the user.
+    pub fn change(&mut self, change: bool) -> &mut Self {
+        self.criteria.change = change;
+        self
+    }
+
+    /// Use inode change time, with its own number of days, overriding the
+    /// number set by `days` for this criterion.
+    pub fn change_days(&mut self, days: f32) -> &mut Self {
+        self.criteria.field_window(Field::Change, to_duration(days, SECS_PER_DAY));
         self
     }
 
 
-    /// Set whether or not we should ignore hidden directories by default. Hidden
-    /// directories start with a '.'.
+    /// Set whether or not we should ignore hidden directories and hidden
+    /// files. Hidden names start with a '.'. This is shorthand for setting
+    /// both `hidden_dirs` and `hidden_files`.
     pub fn ignore_hidden(&mut self, ignore_hidden: bool) -> &mut Self {
-        self.ignore_hidden = ignore_hidden;
+        self.hidden_dirs = ignore_hidden;
+        self.hidden_files = ignore_hidden;
+        self
+    }
+
+    /// Set whether or not hidden directories are skipped. When they are,
+    /// nothing beneath them is searched. The start directory is never
+    /// considered hidden.
+    pub fn hidden_dirs(&mut self, hidden_dirs: bool) -> &mut Self {
+        self.hidden_dirs = hidden_dirs;
+        self
+    }
+
+    /// Set whether or not hidden files are skipped. This does not affect
+    /// whether hidden directories are searched.
+    pub fn hidden_files(&mut self, hidden_files: bool) -> &mut Self {
+        self.hidden_files = hidden_files;
+        self
+    }
+
+    /// Set whether or not symbolic links are followed. When they are not,
+    /// links to directories are not traversed, and links to files are
+    /// not reported.
+    pub fn follow_links(&mut self, follow_links: bool) -> &mut Self {
+        self.follow_links = follow_links;
         self
     }
 
@@ -95,189 +318,502 @@ impl AsyncSearch {
         self
     }
 
+    /// Set the path prefixes to exclude. Any entry whose absolute path
+    /// starts with one of the prefixes is left out, along with anything
+    /// beneath it. Unlike the skip list, this singles out one instance of
+    /// a name which appears in many places.
+    pub fn exclude_paths(&mut self, exclude_paths: Vec<PathBuf>) -> &mut Self {
+        self.exclude_paths = exclude_paths;
+        self
+    }
+
+    /// Restrict matches to files whose names match one of the supplied
+    /// shell glob patterns (eg "*.abc"). Patterns are matched against the
+    /// file name only, not the full path.
+    pub fn names(&mut self, names: Vec<String>) -> &mut Self {
+        self.criteria.names = names;
+        self
+    }
+
+    /// Set whether or not names and name patterns are matched without
+    /// regard to case. This is useful for trees served to case-insensitive clients, where
+    /// "Cache" and "cache" name the same directory.
+    pub fn case_insensitive(&mut self, case_insensitive: bool) -> &mut Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    /// Extend the skip list with names read from the supplied reader, one
+    /// per line. Blank lines and lines starting with '#' are ignored.
+    pub fn skip_from_reader(&mut self, reader: impl Read) -> Result<&mut Self, AmbleError> {
+        self.skip.extend(read_skip_list(reader)?);
+        Ok(self)
+    }
+
     /// Set the number of threads
     pub fn threads(&mut self, threads: Option<u8>) -> &mut Self {
         self.threads = threads;
         self
     }
 
-    // Process a single entry to determine whether or not it matches criteria.
-    // If it matches, we return an Ok wrapping a tuple of WalkState, Some(path).
-    // If we want to skip an entry, we return Ok wrapping a tuple of WalkState, None.
-    // If there is an error, we return an Err wrrapping AmbleError.
-    fn process_entry(result: std::result::Result<ignore::DirEntry, ignore::Error>,
-                     days: f32, access: bool, create: bool, modify: bool,
-                     skip: &[String])
-    -> Result<(WalkState, Option<String>),AmbleError> {
-        let entry = result?;
-        let entry_type = entry.file_type().unwrap();
-
-        // Filter out directory if its name matches one of the provided
-        // names in the skip list.
-        if entry_type.is_dir() {
-            if  !skip.is_empty() && AsyncSearch::matches_list(&entry, &skip) {
-                return Ok((WalkState::Skip, None));
-            }
-        } else if entry_type.is_file() {
-            let f_name = entry.path().to_string_lossy();
+    /// Set whether or not to adapt the number of threads working at once
+    /// to the latency of reading metadata. The search starts with as many
+    /// threads as cores, and works with fewer while reads are slow and
+    /// contended, as on a busy network filesystem, rather than piling
+    /// requests onto the server. Overrides the number of threads.
+    pub fn adaptive_threads(&mut self, adaptive_threads: bool) -> &mut Self {
+        self.adaptive_threads = adaptive_threads;
+        self
+    }
 
-            // Test the various metadata statuses
-            let mut meta = "".to_string();
-            if access && AsyncSearch::report_accessed(&entry, days)? {
-                meta.push('a');
+    /// Cap the reads of metadata in flight at once on any one mount beneath
+    /// the roots, while working with as many threads as ever across mounts,
+    /// so that a scan does not overload a single filer. See mounts.rs.
+    pub fn max_per_mount(&mut self, max_per_mount: Option<usize>) -> &mut Self {
+        self.max_per_mount = max_per_mount;
+        self
+    }
 
-            }
+    /// Restrict matches to empty files (Some(true)), or to non-empty files
+    /// (Some(false)). None, the default, matches files of any size.
+    pub fn empty(&mut self, empty: Option<bool>) -> &mut Self {
+        self.criteria.empty = empty;
+        self
+    }
 
-            if create {
-                #[cfg(target_os = "macos")] {
-                if AsyncSearch::report_created(&entry, days)? {
-                    meta.push('c');
-                };
-                }
-            }
+    /// Set whether or not to report dangling symbolic links, rather than
+    /// files. When `aged` is set, only links whose own change time falls
+    /// within the number of days are reported.
+    pub fn broken_links(&mut self, broken_links: bool, aged: bool) -> &mut Self {
+        self.criteria.broken_links = broken_links;
+        self.criteria.broken_links_aged = aged;
+        self
+    }
 
-            if modify && AsyncSearch::report_modified(&entry, days)? {
-                meta.push('m');
+    /// Restrict matches to files whose sniffed content type matches one of
+    /// the supplied MIME patterns, such as "image/*" or "video/mp4". The
+    /// content is only examined for files which meet the other criteria.
+    /// Requires the mime feature; searching with patterns set otherwise
+    /// returns an error.
+    pub fn mime(&mut self, mime: Vec<String>) -> &mut Self {
+        self.criteria.mime = mime;
+        self
+    }
 
-            }
+    /// Set how file sizes are measured when filtering by size. Allocated
+    /// size is the better measure for sparse files.
+    pub fn size_mode(&mut self, size_mode: SizeMode) -> &mut Self {
+        self.criteria.size_mode = size_mode;
+        self
+    }
 
-            if !meta.is_empty() {
-                return Ok((WalkState::Continue, Some( format!("{} ({})", f_name, meta))));
-            }
-            return Ok((WalkState::Continue, None));
-        };
+    /// Restrict matches to files of at least `min_size` bytes.
+    pub fn min_size(&mut self, min_size: Option<u64>) -> &mut Self {
+        self.criteria.min_size = min_size;
+        self
+    }
 
-        Ok((WalkState::Continue, None))
+    /// Restrict matches to files of at most `max_size` bytes.
+    pub fn max_size(&mut self, max_size: Option<u64>) -> &mut Self {
+        self.criteria.max_size = max_size;
+        self
     }
 
-    // was the entry modified within the last `days` # of days
-    fn report_modified(entry: &DirEntry, days: f32) -> Result<bool, AmbleError> {
-        let modified = entry.metadata()?.modified()?;
-        Ok(modified.elapsed()?.as_secs() < ((SECS_PER_DAY as f64 * f64::from(days)).ceil() as u64))
+    /// Restrict matches to files with any execute bit set. Execute bits
+    /// are unix only, so elsewhere nothing matches.
+    pub fn executable(&mut self, executable: bool) -> &mut Self {
+        self.criteria.executable = executable;
+        self
     }
 
-    // was the entry accessed iwthint the last `days` # of days
-    fn report_accessed(entry: &DirEntry, days: f32) -> Result<bool, AmbleError> {
-        let accessed = entry.metadata().unwrap().accessed()?;
-        Ok(accessed.elapsed()?.as_secs() < ((SECS_PER_DAY as f64 * f64::from(days)).ceil() as u64))
+    /// Restrict matches to files whose number of hard links satisfies the
+    /// supplied constraint. A file with a single link is the last
+    /// remaining reference to its data.
+    pub fn links(&mut self, links: Option<LinkCount>) -> &mut Self {
+        self.criteria.links = links;
+        self
     }
 
-    // was the entry created in the last `days` number of days
-    fn report_created(entry: &DirEntry, days: f32) -> Result<bool, AmbleError> {
-        let created = entry.metadata()?.created()?;
-        Ok(created.elapsed()?.as_secs() < ((SECS_PER_DAY as f64 * f64::from(days)).ceil() as u64))
+    /// Restrict matches to files owned by the supplied user id (unix only).
+    pub fn owner(&mut self, owner: Option<u32>) -> &mut Self {
+        self.criteria.owner = owner;
+        self
     }
 
-    fn matches_list(entry: &DirEntry, list: &[String] ) -> bool {
-        if !list.is_empty() {
-            return false;
+    /// Restrict matches to files owned by the effective user running the
+    /// search (unix only). This is shorthand for `owner`, with the user id
+    /// from geteuid(2). Passing false leaves the owner unchanged.
+    pub fn mine(&mut self, mine: bool) -> &mut Self {
+        if mine {
+            self.criteria.owner = effective_uid();
         }
+        self
+    }
+
+    /// Set whether or not to flag security relevant mode bits (setuid,
+    /// setgid, sticky, and world-writable) on matches, so that an age
+    /// based audit can also pick out risky files.
+    pub fn mode_flags(&mut self, mode_flags: bool) -> &mut Self {
+        self.criteria.mode_flags = mode_flags;
+        self
+    }
+
+    /// Set whether or not every file which passes the filters is a match,
+    /// regardless of its times. This is how a Policy sees every file.
+    pub fn every(&mut self, every: bool) -> &mut Self {
+        self.criteria.every = every;
+        self
+    }
+
+    /// Set whether or not to skip directories, and everything beneath
+    /// them, whose own modification and change times are older than the
+    /// window. This is a heuristic, which only applies to scans of
+    /// modification or creation times, on local filesystems known to keep
+    /// the times of directories up to date. Files modified in place, and
+    /// entries added beneath subdirectories, do not update those times, so
+    /// such matches are missed.
+    pub fn prune_cold_dirs(&mut self, prune_cold_dirs: bool) -> &mut Self {
+        self.criteria.cold_dirs = prune_cold_dirs;
+        self
+    }
+
+    /// Set the maximum number of matches to report. Once it is reached, the
+    /// search stops. None, the default, reports every match.
+    pub fn max_results(&mut self, max_results: Option<usize>) -> &mut Self {
+        self.max_results = max_results;
+        self
+    }
+
+    /// Set the number of matches, and of errors, which may be queued for
+    /// reporting before the walker threads wait, so that a slow sink
+    /// applies backpressure to the walk. Larger capacities smooth out
+    /// bursts of matches, at the cost of memory, which is allocated up
+    /// front. Zero hands each match directly to the sink, which is
+    /// markedly slower. The default, CHANNEL_CAPACITY, is as fast as an
+    /// unbounded queue when the sink keeps up.
+    pub fn channel_capacity(&mut self, channel_capacity: usize) -> &mut Self {
+        self.channel_capacity = channel_capacity;
+        self
+    }
+
+    /// Set whether or not to report a directory as a single match, in place
+    /// of its contents, when every entry beneath it matches. Directories
+    /// containing anything which does not match, or which is skipped, are
+    /// not rolled up. The maximum number of matches counts files. As
+    /// entries arrive in no particular order, matches are only reported
+    /// once the search has finished.
+    pub fn rollup(&mut self, rollup: bool) -> &mut Self {
+        self.rollup = rollup;
+        self
+    }
 
-        for item in list {
-            if entry.file_name()
-                .to_str()
-                .map(|s| s == item)
-                .unwrap_or(false) {
-                    return true;
+    /// Set the number of bytes of entries which rolling up may hold in
+    /// memory while it orders them, beyond which they are spilled to
+    /// temporary files. The default is BUFFER_BYTES.
+    pub fn buffer_limit(&mut self, buffer_limit: u64) -> &mut Self {
+        self.buffer_limit = buffer_limit;
+        self
+    }
+
+    /// Count the files, directories, and matches of the search into the
+    /// supplied progress as it runs, so that another thread may report it.
+    pub fn live_progress(&mut self, live_progress: Option<Arc<ScanProgress>>) -> &mut Self {
+        self.live_progress = live_progress;
+        self
+    }
+
+    /// Classify every file using the supplied Classifier, rather than
+    /// testing files against the number of days. Pass None to turn
+    /// classification off.
+    pub fn classify(&mut self, classifier: Option<Classifier>) -> &mut Self {
+        self.criteria.classifier = classifier;
+        self
+    }
+
+    // Process a single entry to determine whether or not it matches criteria.
+    // We return an Ok wrapping a tuple of WalkState and the Visit - a
+    // Visit::Match if the entry matches.
+    // If there is an error, we return an Err wrapping AmbleError, along
+    // with the path of the entry, when known, so that a rollup may learn
+    // where it occurred.
+    fn process_entry(result: std::result::Result<ignore::DirEntry, ignore::Error>,
+                     criteria: &Criteria, prune: &Prune, counters: &Counters,
+                     visited: Option<&Mutex<HashSet<(u64, u64)>>>, governor: &Governor, mounts: &MountLimits)
+    -> Result<(WalkState, Visit), EntryError> {
+        let entry = match result {
+            Ok(entry) => entry,
+            Err(err) => {
+                // when following links, a dangling link arrives as an error
+                if let Some((path, md)) = ignore_error_path(&err).and_then(|p| {
+                    counters.stats(2);
+                    dangling_link(p).map(|md| (p, md))
+                }) {
+                    if criteria.broken_links {
+                        let evaluated = criteria.evaluate_link(path, &md).map_err(|e| failed_at(path, e))?;
+                        return Ok((WalkState::Continue, Visit::evaluated(path, evaluated)));
+                    }
                 }
+                let path = ignore_error_path(&err).map(Path::to_path_buf);
+                return Err((err.into(), path));
+            }
+        };
+        let entry_type = entry.file_type().unwrap();
+        let skipped = || Visit::Other(entry.path().to_path_buf());
+
+        // Filter out entries which are hidden, in the skip list, or beneath
+        // an excluded path. The contents of directories are skipped too.
+        if prune.skips(entry.path(), entry_type.is_dir(), entry.depth()) {
+            let state = if entry_type.is_dir() { WalkState::Skip } else { WalkState::Continue };
+            return Ok((state, skipped()));
         }
-        false
+
+        if entry_type.is_dir() {
+            let cold = criteria.prunes_cold_dirs() && entry.depth() > 0;
+            let metadata = if cold || visited.is_some() {
+                counters.stats(1);
+                let _permit = mounts.acquire(entry.path());
+                entry.metadata().ok()
+            } else {
+                None
+            };
+            if cold && metadata.as_ref().is_some_and(|md| criteria.skips_dir(entry.path(), md)) {
+                return Ok((WalkState::Skip, skipped()));
+            }
+            // when following links, a directory may be reached by several
+            // paths, and is only traversed by the first
+            if let Some(visited) = visited {
+                let id = metadata.as_ref().and_then(inode);
+                if id.is_some_and(|id| visited.lock().is_ok_and(|mut visited| !visited.insert(id))) {
+                    eprint_above(format_args!("Warning: skipping '{}', which has already been visited",
+                                              entry.path().display()));
+                    return Ok((WalkState::Skip, skipped()));
+                }
+            }
+            counters.dir();
+            debug!(path = %entry.path().display(), "entering directory");
+            return Ok((WalkState::Continue, Visit::Dir(entry.into_path())));
+        } else if entry_type.is_symlink() {
+            counters.stats(2);
+            if let Some(md) = dangling_link(entry.path()) {
+                let evaluated = criteria.evaluate_link(entry.path(), &md).map_err(|e| failed_at(entry.path(), e))?;
+                return Ok((WalkState::Continue, Visit::evaluated(entry.path(), evaluated)));
+            }
+        } else if entry_type.is_file() {
+            counters.file();
+            if criteria.excludes(entry.path()) {
+                return Ok((WalkState::Continue, skipped()));
+            }
+            counters.stats(1);
+            let _permit = mounts.acquire(entry.path());
+            let metadata = governor.time(|| entry.metadata()).map_err(|e| failed_at(entry.path(), e))?;
+            let evaluated = criteria.evaluate(entry.path(), &metadata).map_err(|e| failed_at(entry.path(), e))?;
+            return Ok((WalkState::Continue, Visit::evaluated(entry.path(), evaluated)));
+        };
+
+        Ok((WalkState::Continue, skipped()))
     }
+
+}
+
+// An error processing an entry, and the path of the entry, when known.
+type EntryError = (AmbleError, Option<PathBuf>);
+
+// The error processing the entry at the supplied path.
+fn failed_at(path: &Path, error: impl Into<AmbleError>) -> EntryError {
+    (error.into(), Some(path.to_path_buf()))
 }
 
 impl Finder for AsyncSearch {
-    type ReturnType = ();
-    fn find_matching(&self
+    type ReturnType = ScanStats;
+    fn find_into(&self, sink: &mut dyn Sink
     ) -> Result<Self::ReturnType, AmbleError> {
-        if !(self.access || self.create || self.modify) {
-            println!("No search criteria specified. Must use access, create, or modify");
-            return Ok(());
+        let started = Instant::now();
+        if self.criteria.is_empty() {
+            println!("No search criteria specified. Must use access, create, modify, change, or broken links");
+            return Ok(ScanStats::default());
         }
+        let criteria = self.criteria.compile(self.case_insensitive)?;
+        let roots = dedup_roots(&self.roots);
+        let first = match roots.first() {
+            Some(first) => first,
+            None => {
+                println!("No root directories specified");
+                return Ok(ScanStats::default());
+            }
+        };
+        let availability = Availability::probe(&fs::metadata(first)?,
+                                               &criteria.fields());
+        let mut sink = Attribute::new(&roots, sink);
+        let sink: &mut dyn Sink = &mut sink;
+        sink.begin(&availability)?;
+
         // for stdout
-        //let (tx, rx) = channel::bounded::<String>(100);
-        let (tx, rx) = channel::unbounded::<String>();
+        let (tx, rx) = channel::bounded::<Visit>(self.channel_capacity);
 
         // for errors
-        //let (tex, rex) = channel::bounded::<String>(100);
-        let (tex, rex) = channel::unbounded::<String>();
+        let (tex, rex) = channel::bounded::<String>(self.channel_capacity);
 
-        let stdout_thread = thread::spawn(move || {
-            for dent in rx {
-                println!("{}", dent)
-            }
-        });
-
-        // If we want to capture the errors and print them out after
-        // the thread has finished its thing, we could do this
-        // let stderr_thread = thread::spawn(move || -> Vec<String> {
-        //     let mut stderr_result = Vec:: new();
-        //     for dent in rex {
-        //         stderr_result.push(dent);
-        //     }
-        //     stderr_result
-        // });
-
-        let stderr_thread = thread::spawn(move || {
-            for dent in rex {
-                eprintln!("{}", dent.red());
+        let mut builder = WalkBuilder::new(first);
+        for root in &roots[1..] {
+            builder.add(root);
+        }
+        // hidden entries are filtered in process_entry, which distinguishes
+        // between hidden directories and hidden files
+        builder.hidden(false)
+               .follow_links(self.follow_links);
+        let governor = if self.adaptive_threads {
+            let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+            builder.threads(cores);
+            Governor::new(true, cores)
+        } else {
+            if let Some(th) = self.threads {
+                builder.threads(th as usize);
             }
-        });
-
-        let walker = match self.threads {
-            Some(th) => WalkBuilder::new(&self.start_dir)
-                                    .hidden(self.ignore_hidden)
-                                    .threads(th as usize)
-                                    .follow_links(true)
-                                    .build_parallel(),
-
-            None => WalkBuilder::new(&self.start_dir)
-                                .hidden(self.ignore_hidden)
-                                .follow_links(true)
-                                .build_parallel(),
+            Governor::new(false, 0)
         };
-
-        walker.run(|| {
-            let tx = tx.clone();
-            let tex = tex.clone();
-            let myskip = self.skip.clone();
-            let days = self.days;
-            let access = self.access;
-            let create = self.create;
-            let modify = self.modify;
-
-            Box::new(move |result| {
-                match AsyncSearch::process_entry(result, days, access, create,
-                                                 modify, &myskip ) {
-                    Ok((state,Some(meta))) => {
-                        tx.send(meta).unwrap();
-                        state
-                    },
-                    Err(e) => {
-                        tex.send(e.to_string()).unwrap();
-                        WalkState::Continue
-                    },
-                    Ok((state, None))=>{
-                        state
-                    }
+        let walker = builder.build_parallel();
+        let mounts = MountLimits::new(&roots, self.max_per_mount);
+
+        let prune = Prune::new(&self.skip, self.case_insensitive, self.hidden_dirs, self.hidden_files,
+                               &self.exclude_paths);
+
+        // number of matches sent to the sink so far, shared by all threads
+        let count = AtomicUsize::new(0);
+        // counts of the work done, and the CPU time of each walker thread
+        let counters = Counters::reporting(self.live_progress.clone());
+        let thread_times = Mutex::new(Vec::new());
+        let caller = thread::current().id();
+        // (device, inode) pairs of the directories traversed, when following
+        // links
+        let visited = Mutex::new(HashSet::new());
+        let visited = if self.follow_links { Some(&visited) } else { None };
+
+        // scoped threads, so that the stdout thread may borrow the sink
+        thread::scope(|scope| {
+            let rollup = self.rollup;
+            let max_results = self.max_results;
+            let buffer_limit = self.buffer_limit;
+            let count = &count;
+            let stdout_thread = scope.spawn(move || {
+                let mut rollup = Rollup::new(rollup);
+                // entries arrive in no particular order, so when rolling up,
+                // they are gathered before being replayed in order
+                let result = if rollup.is_enabled() {
+                    rollup.replay(rx.iter(), buffer_limit, sink).and_then(|_| {
+                        let cut_short = max_results.is_some_and(|max| count.load(AtomicOrdering::SeqCst) >= max);
+                        rollup.finish(cut_short, sink)
+                    })
+                } else {
+                    rx.iter().try_for_each(|visit| rollup.visit(visit, sink))
+                };
+                // the sink is finished either way, but a failure of the
+                // sink fails the scan, as it does for the sync search
+                let finished = sink.finish();
+                result.and(finished)
+            });
+
+            // If we want to capture the errors and print them out after
+            // the thread has finished its thing, we could do this
+            // let stderr_thread = scope.spawn(move || -> Vec<String> {
+            //     let mut stderr_result = Vec:: new();
+            //     for dent in rex {
+            //         stderr_result.push(dent);
+            //     }
+            //     stderr_result
+            // });
+
+            let stderr_thread = scope.spawn(move || {
+                let mut errors = 0;
+                for dent in rex {
+                    eprint_above(format_args!("{}", dent.red()));
+                    errors += 1;
                 }
-            })
-        });
-
-        drop(tx);
-        drop(tex);
-        stdout_thread.join().unwrap();
-        let _err_vals = stderr_thread.join().unwrap();
-
-        // if we wanted to print out errors after the fact, we could do this
-        // if err_vals.len() > 0  {
-        //     println!("{}","\nERRORS\n".red());
-        //     for err in err_vals {
-        //         eprintln!("{}", err.red());
-        //     }
-        // }
+                errors
+            });
+
+            walker.run(|| {
+                let tx = tx.clone();
+                let tex = tex.clone();
+                let criteria = &criteria;
+                let prune = &prune;
+                let counters = &counters;
+                let governor = &governor;
+                let mounts = &mounts;
+                // the visitor is dropped by its thread once the walk is done
+                let cpu = ThreadCpu { times: &thread_times, caller };
+
+                // should the sink fail, the reporting thread stops, and the
+                // walk with it. The error it returns fails the scan.
+                let forward = move |visit: Visit, state: WalkState| match tx.send(visit) {
+                    Ok(()) => state,
+                    Err(SendError(_)) => WalkState::Quit,
+                };
 
-        Ok(())
+                Box::new(move |result| {
+                    let _ = &cpu;
+                    let _permit = governor.acquire();
+                    match AsyncSearch::process_entry(result, criteria, prune, counters, visited, governor, mounts) {
+                        Ok((state, Visit::Match(found))) => {
+                            // Other threads may find matches before they
+                            // see the Quit, so only send those under the max.
+                            match max_results {
+                                Some(max) => {
+                                    let sent = count.fetch_add(1, AtomicOrdering::SeqCst);
+                                    let state = if sent + 1 >= max { WalkState::Quit } else { state };
+                                    if sent < max {
+                                        counters.matched();
+                                        forward(Visit::Match(found), state)
+                                    } else if rollup {
+                                        forward(Visit::Other(found.path), state)
+                                    } else {
+                                        state
+                                    }
+                                },
+                                None => {
+                                    counters.matched();
+                                    forward(Visit::Match(found), state)
+                                }
+                            }
+                        },
+                        Err((e, error_path)) => {
+                            // a closed error channel is no reason to stop
+                            let _ = tex.send(e.to_string());
+                            // a rollup needs to know where errors occurred
+                            match (rollup, error_path) {
+                                (true, Some(path)) => forward(Visit::Other(path), WalkState::Continue),
+                                _ => WalkState::Continue,
+                            }
+                        },
+                        Ok((state, visit))=>{
+                            if rollup {
+                                forward(visit, state)
+                            } else {
+                                state
+                            }
+                        }
+                    }
+                })
+            });
+
+            drop(tx);
+            drop(tex);
+            let errors = stderr_thread.join().unwrap();
+
+            // if we wanted to print out errors after the fact, we could do this
+            // if err_vals.len() > 0  {
+            //     println!("{}","\nERRORS\n".red());
+            //     for err in err_vals {
+            //         eprintln!("{}", err.red());
+            //     }
+            // }
+
+            stdout_thread.join().unwrap()?;
+            let mut stats = ScanStats { errors, reference: Some(criteria.now()), elapsed: started.elapsed(),
+                                        ..ScanStats::default() };
+            counters.record(&mut stats);
+            stats.threads = thread_times.into_inner().unwrap_or_default();
+            stats.fewest_threads = governor.lowest();
+            Ok(stats)
+        })
     }
-
-
 }
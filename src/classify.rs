@@ -0,0 +1,219 @@
+//! classify.rs
+//!
+//! Classify files into storage tiers - hot, warm, cold, and frozen -
+//! based upon how recently they were accessed and modified. The
+//! vocabulary matches the tiering terms used by storage vendors.
+//!
+//! Each tier but frozen carries a threshold of access days and
+//! modify days. A file belongs to the first (hottest) tier whose
+//! access or modify threshold it falls within. Files which fall
+//! within none of the thresholds are frozen.
+use std::fmt;
+use std::fs::Metadata;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::{COLD_DAYS, HOT_DAYS, WARM_DAYS},
+    criteria::within_days,
+    duration::parse_days,
+    errors::AmbleError,
+    filematch::FileMatch,
+    size::SizeMode,
+    traits::Sink,
+};
+
+/// Storage tier of a file, from most to least recently used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Class {
+    Hot,
+    Warm,
+    Cold,
+    Frozen,
+}
+
+impl Class {
+    /// All of the classes, from hottest to coldest.
+    pub const ALL: [Class; 4] = [Class::Hot, Class::Warm, Class::Cold, Class::Frozen];
+
+    /// Name of the class, as reported to the user.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Class::Hot => "hot",
+            Class::Warm => "warm",
+            Class::Cold => "cold",
+            Class::Frozen => "frozen",
+        }
+    }
+
+    // position of the class within Class::ALL
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Access and modify thresholds, in days, for a single tier. A file
+/// falls within the threshold if it was accessed within `access` days
+/// or modified within `modify` days.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Threshold {
+    pub access: f32,
+    pub modify: f32,
+}
+
+impl Threshold {
+    /// New up a Threshold using the same number of days for access and modify.
+    pub fn days(days: f32) -> Self {
+        Self { access: days, modify: days }
+    }
+
+    // Was the file accessed or modified within the threshold of now?
+    fn contains(&self, accessed: SystemTime, modified: SystemTime, now: SystemTime) -> bool {
+        within_days(accessed, self.access, now) || within_days(modified, self.modify, now)
+    }
+}
+
+/// Parse a threshold from either `DAYS`, or `ACCESS_DAYS:MODIFY_DAYS`.
+impl FromStr for Threshold {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |value: &str| {
+            parse_days(value).map_err(|_| {
+                AmbleError::ParseError(format!("invalid threshold '{}'. Expected DAYS or ACCESS_DAYS:MODIFY_DAYS", s))
+            })
+        };
+        match s.find(':') {
+            Some(idx) => Ok(Self { access: parse(&s[..idx])?, modify: parse(&s[idx + 1..])? }),
+            None => Ok(Self::days(parse(s)?)),
+        }
+    }
+}
+
+/// Assigns files to a Class, based on the thresholds of the hot, warm,
+/// and cold tiers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Classifier {
+    hot: Threshold,
+    warm: Threshold,
+    cold: Threshold,
+}
+
+impl Classifier {
+    /// New up a Classifier.
+    ///
+    /// We default to:
+    /// - hot: 7 days
+    /// - warm: 30 days
+    /// - cold: 180 days
+    pub fn new() -> Self {
+        Self {
+            hot: Threshold::days(HOT_DAYS),
+            warm: Threshold::days(WARM_DAYS),
+            cold: Threshold::days(COLD_DAYS),
+        }
+    }
+
+    /// Set the threshold for the hot tier.
+    pub fn hot(&mut self, hot: Threshold) -> &mut Self {
+        self.hot = hot;
+        self
+    }
+
+    /// Set the threshold for the warm tier.
+    pub fn warm(&mut self, warm: Threshold) -> &mut Self {
+        self.warm = warm;
+        self
+    }
+
+    /// Set the threshold for the cold tier.
+    pub fn cold(&mut self, cold: Threshold) -> &mut Self {
+        self.cold = cold;
+        self
+    }
+
+    /// Determine the Class of a file given its metadata.
+    pub fn classify(&self, metadata: &Metadata) -> Result<Class, AmbleError> {
+        self.classify_as_of(metadata, SystemTime::now())
+    }
+
+    /// Determine the Class of a file given its metadata, as of the
+    /// supplied instant rather than now. Times after the instant are taken
+    /// to be at it.
+    pub fn classify_as_of(&self, metadata: &Metadata, now: SystemTime) -> Result<Class, AmbleError> {
+        let accessed = metadata.accessed()?.min(now);
+        let modified = metadata.modified()?.min(now);
+        for (class, threshold) in [(Class::Hot, &self.hot), (Class::Warm, &self.warm), (Class::Cold, &self.cold)] {
+            if threshold.contains(accessed, modified, now) {
+                return Ok(class);
+            }
+        }
+        Ok(Class::Frozen)
+    }
+}
+
+impl Default for Classifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Sink which prints each classified file, and tallies the number of
+/// files and bytes per class, printing a summary when finished.
+#[derive(Debug, Default)]
+pub struct ClassReport {
+    counts: [u64; 4],
+    bytes: [u64; 4],
+    size_mode: SizeMode,
+}
+
+impl ClassReport {
+    /// New up an empty ClassReport, which tallies apparent sizes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how file sizes are measured when tallying bytes.
+    pub fn size_mode(&mut self, size_mode: SizeMode) -> &mut Self {
+        self.size_mode = size_mode;
+        self
+    }
+
+    /// Number of files tallied for the supplied class.
+    pub fn count(&self, class: Class) -> u64 {
+        self.counts[class.index()]
+    }
+
+    /// Number of bytes tallied for the supplied class.
+    pub fn bytes(&self, class: Class) -> u64 {
+        self.bytes[class.index()]
+    }
+}
+
+impl Sink for ClassReport {
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        if let Some(class) = found.class {
+            self.counts[class.index()] += 1;
+            self.bytes[class.index()] += found.size_for(self.size_mode);
+        }
+        println!("{}", found);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        println!();
+        println!("{:<8} {:>12} {:>16}", "class", "files", "bytes");
+        for class in Class::ALL.iter() {
+            println!("{:<8} {:>12} {:>16}", class.as_str(), self.count(*class), self.bytes(*class));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! compress.rs
+//!
+//! Compression of matches in place, for data which is kept online but is
+//! stale enough to be stored compressed. Each file is replaced by a
+//! compressed copy alongside it (eg `file.zst`), which keeps the
+//! permissions and times of the original. Requires the compress feature.
+use std::ffi::OsString;
+use std::fs::{self, File, FileTimes, Metadata};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use flate2::{write::GzEncoder, Compression};
+use walkdir::WalkDir;
+
+use crate::{actions::{temp_beside, Action}, errors::AmbleError, filematch::FileMatch};
+
+// The extensions of compressed formats, whose files are left alone
+const COMPRESSED_EXTENSIONS: &[&str] = &["gz", "tgz", "zst", "tzst", "xz", "txz", "lzma", "bz2", "tbz2", "lz4",
+                                         "lz", "br", "z", "zip", "7z", "rar"];
+
+// The magic numbers which compressed files start with: gzip, zstd, xz,
+// bzip2, lz4, zip, and 7z
+const COMPRESSED_MAGIC: &[&[u8]] = &[b"\x1f\x8b", b"\x28\xb5\x2f\xfd", b"\xfd7zXZ\x00", b"BZh",
+                                     b"\x04\x22\x4d\x18", b"PK\x03\x04", b"7z\xbc\xaf\x27\x1c"];
+
+/// The format in which files are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressFormat {
+    /// zstd, the default, which is fast to compress and decompress
+    #[default]
+    Zstd,
+    /// gzip, for compatibility
+    Gzip,
+}
+
+impl CompressFormat {
+    /// The extension appended to compressed files.
+    pub fn extension(self) -> &'static str {
+        match self {
+            CompressFormat::Zstd => "zst",
+            CompressFormat::Gzip => "gz",
+        }
+    }
+}
+
+impl FromStr for CompressFormat {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "zstd" | "zst" => Ok(CompressFormat::Zstd),
+            "gzip" | "gz" => Ok(CompressFormat::Gzip),
+            _ => Err(AmbleError::ParseError(format!("invalid compression format '{}'. Expected zstd or gzip", s))),
+        }
+    }
+}
+
+/// Compresses each match in place, writing the compressed copy to a
+/// temporary file which is linked into place once complete, and only then
+/// removing the original. Existing files are never overwritten, even one
+/// which takes the place of the compressed copy while it is written. The
+/// files beneath rolled up directories are compressed individually, and
+/// files which are already compressed, in any known format, by extension
+/// or by content, are left alone.
+#[derive(Debug, Default)]
+pub struct Compress {
+    format: CompressFormat,
+}
+
+impl Compress {
+    /// New up a Compress for the supplied format.
+    pub fn new(format: CompressFormat) -> Self {
+        Self { format }
+    }
+
+    /// The path of the compressed copy of the supplied path.
+    pub fn destination(&self, path: &Path) -> PathBuf {
+        let mut dest = OsString::from(path);
+        dest.push(".");
+        dest.push(self.format.extension());
+        PathBuf::from(dest)
+    }
+
+    // Compress a single regular file.
+    fn compress(&self, path: &Path, metadata: &Metadata) -> Result<(), AmbleError> {
+        let dest = self.destination(path);
+        if fs::symlink_metadata(&dest).is_ok() {
+            return Err(AmbleError::IoError(format!("'{}' already exists", dest.display())));
+        }
+        // the temporary file is only removed once it is known to be ours
+        let temp = temp_beside(&dest);
+        let file = File::options().write(true).create_new(true).open(&temp)?;
+        // linking fails, rather than replacing it, should a file have taken
+        // the place of the compressed copy since
+        let result = self.write(path, file, metadata).and_then(|_| Ok(fs::hard_link(&temp, &dest)?));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+            return result;
+        }
+        fs::remove_file(&temp)?;
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    // Write the compressed copy of path to the file, with the permissions
+    // and times of the original.
+    fn write(&self, path: &Path, file: File, metadata: &Metadata) -> Result<(), AmbleError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut file = match self.format {
+            CompressFormat::Zstd => {
+                let mut encoder = zstd::Encoder::new(file, 0)?;
+                io::copy(&mut reader, &mut encoder)?;
+                encoder.finish()?
+            },
+            CompressFormat::Gzip => {
+                let mut encoder = GzEncoder::new(file, Compression::default());
+                io::copy(&mut reader, &mut encoder)?;
+                encoder.finish()?
+            },
+        };
+        file.flush()?;
+        file.set_permissions(metadata.permissions())?;
+        file.set_times(FileTimes::new().set_accessed(metadata.accessed()?).set_modified(metadata.modified()?))?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+impl Action for Compress {
+    fn verb(&self) -> &'static str {
+        "compress"
+    }
+
+    fn describe(&self, found: &FileMatch) -> String {
+        if found.contents.is_some() {
+            format!("compress {} (each file)", found.path.display())
+        } else {
+            format!("compress {} -> {}", found.path.display(), self.destination(&found.path).display())
+        }
+    }
+
+    fn apply(&mut self, found: &FileMatch) -> Result<(), AmbleError> {
+        let metadata = fs::symlink_metadata(&found.path)?;
+        if !metadata.is_dir() {
+            if !metadata.is_file() {
+                return Err(AmbleError::UnexpectedResult("not a regular file".to_string()));
+            }
+            if is_compressed(&found.path) {
+                return Err(AmbleError::UnexpectedResult("already compressed".to_string()));
+            }
+            return self.compress(&found.path, &metadata);
+        }
+        // the walk is completed first, so that it does not see the files
+        // being written
+        let entries = WalkDir::new(&found.path).into_iter().collect::<Result<Vec<_>, _>>()?;
+        for entry in entries {
+            if entry.file_type().is_file() && !is_compressed(entry.path()) {
+                self.compress(entry.path(), &entry.metadata()?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Is the file already compressed, in any known format, by its extension or
+// by the magic number it starts with?
+fn is_compressed(path: &Path) -> bool {
+    let by_extension = path.extension()
+                           .and_then(|ext| ext.to_str())
+                           .is_some_and(|ext| COMPRESSED_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+    by_extension || {
+        let mut magic = Vec::new();
+        let read = File::open(path).and_then(|file| file.take(6).read_to_end(&mut magic));
+        read.is_ok() && COMPRESSED_MAGIC.iter().any(|prefix| magic.starts_with(prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempTree;
+
+    #[test]
+    fn compress_replaces_the_original() {
+        let tree = TempTree::new("compress");
+        let path = tree.file("a.log", b"stale stale stale");
+        Compress::default().apply(&FileMatch::new(&path, 17)).unwrap();
+        assert!(!path.exists());
+        let compressed = fs::read(tree.path().join("a.log.zst")).unwrap();
+        assert_eq!(zstd::decode_all(compressed.as_slice()).unwrap(), b"stale stale stale");
+    }
+
+    #[test]
+    fn compress_leaves_the_original_on_failure() {
+        let tree = TempTree::new("compress-exists");
+        let path = tree.file("a.log", b"stale");
+        let existing = tree.file("a.log.zst", b"unrelated");
+        assert!(Compress::default().apply(&FileMatch::new(&path, 5)).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"stale");
+        assert_eq!(fs::read(&existing).unwrap(), b"unrelated");
+    }
+
+    #[test]
+    fn compress_leaves_files_named_like_its_own_alone() {
+        let tree = TempTree::new("compress-temp");
+        let path = tree.file("a.log", b"stale");
+        let leftover = tree.file("a.log.zst.amble-tmp", b"unrelated");
+        Compress::default().apply(&FileMatch::new(&path, 5)).unwrap();
+        assert_eq!(fs::read(&leftover).unwrap(), b"unrelated");
+        assert!(tree.path().join("a.log.zst").exists());
+    }
+}
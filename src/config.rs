@@ -0,0 +1,294 @@
+//! config.rs
+//!
+//! Configuration files, so that a standard scan need not be spelled out
+//! with a dozen flags on every run. A config file is TOML, giving defaults
+//! for the options of amble, such as:
+//!
+//! ```toml
+//! days = "90d"
+//! modify = true
+//! skip = ["node_modules", ".git"]
+//! format = "json"
+//! threads = 8
+//! ```
+//!
+//! The file is either given explicitly, or discovered by walking up from
+//! the scan root to the first directory holding a `.amble.toml`. Options
+//! supplied on the command line override those of the file.
+//!
+//! A file may also hold named profiles, each a table of options overriding
+//! those at the top of the file, and selected with --profile:
+//!
+//! ```toml
+//! [profile.weekly-clean]
+//! days = "7"
+//! name = ["*.tmp"]
+//! trash = true
+//! yes = true
+//! ```
+//!
+//! Actions may only be given in a profile, so that a config file which is
+//! merely discovered never makes a scan destructive. So may the schedule on
+//! which amble daemon runs a profile, and where it writes the results:
+//!
+//! ```toml
+//! [profile.audit]
+//! dirs = ["/shows"]
+//! days = "30"
+//! schedule = "0 2 * * *"
+//! output = "/var/log/amble/audit.jsonl"
+//! ```
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{constants::CONFIG_FILE, errors::AmbleError};
+
+/// The options a config file may supply. Options which are not given are
+/// left to the command line, or to amble's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    /// The time period in which to consider entities, as for --days
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days: Option<String>,
+    /// Test access times, as for --access
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access: Option<bool>,
+    /// Test modification times, as for --modify
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modify: Option<bool>,
+    /// Test inode change times, as for --change
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change: Option<bool>,
+    /// Test creation times, as for --create
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub create: Option<bool>,
+    /// Test effective times, as for --effective
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effective: Option<bool>,
+    /// Directory names or globs to skip, as for --skip
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skip: Vec<String>,
+    /// Paths to exclude, as for --exclude-path
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude_path: Vec<PathBuf>,
+    /// File name globs to match, as for --name
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub name: Vec<String>,
+    /// Ignore hidden files and directories, as for --ignore-hidden
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ignore_hidden: Option<bool>,
+    /// Follow symbolic links, unlike --no-follow
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub follow_links: Option<bool>,
+    /// How matches are reported: text, or json
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// How timestamps appear in JSON, as for --time-format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_format: Option<String>,
+    /// The timezone timestamps are shown in, as for --timezone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// The number of threads, as for --threads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u8>,
+    /// Search with a single thread, as for --sync
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync: Option<bool>,
+    /// The traversal backend, as for --backend
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    /// The minimum size of matches, as for --min-size
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<String>,
+    /// The maximum size of matches, as for --max-size
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<String>,
+    /// The types of file to match, as for --mime
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mime: Vec<String>,
+    /// Delete matches, as for --delete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete: Option<bool>,
+    /// Move matches to a directory, as for --move-to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub move_to: Option<PathBuf>,
+    /// Archive matches, as for --archive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive: Option<PathBuf>,
+    /// Move matches to the trash, as for --trash
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trash: Option<bool>,
+    /// Run a command on each match, as for --exec
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exec: Vec<String>,
+    /// Write a plan of matches, rather than acting on them, as for --plan
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<PathBuf>,
+    /// Record the actions taken, as for --action-log
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action_log: Option<PathBuf>,
+    /// Remove directories emptied by the action, as for --prune-empty-dirs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prune_empty_dirs: Option<bool>,
+    /// Act without confirmation, as for --yes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub yes: Option<bool>,
+    /// The directories amble daemon scans
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dirs: Vec<PathBuf>,
+    /// The cron schedule amble daemon runs the profile on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+    /// A file amble daemon appends the results of each run to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    /// A SQLite database amble daemon records the results of each run in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sqlite: Option<PathBuf>,
+    /// A URL amble daemon posts the results of each run to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
+    /// Named profiles, selected with --profile
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub profile: BTreeMap<String, Settings>,
+}
+
+impl Settings {
+    /// The actions the settings request, by name.
+    pub fn actions(&self) -> Vec<&'static str> {
+        let mut actions = Vec::new();
+        if self.delete == Some(true) {
+            actions.push("delete");
+        }
+        if self.move_to.is_some() {
+            actions.push("move-to");
+        }
+        if self.archive.is_some() {
+            actions.push("archive");
+        }
+        if self.trash == Some(true) {
+            actions.push("trash");
+        }
+        if !self.exec.is_empty() {
+            actions.push("exec");
+        }
+        if self.plan.is_some() {
+            actions.push("plan");
+        }
+        actions
+    }
+
+    /// The keys the settings give which may only be given in a profile,
+    /// by name.
+    pub fn profile_only(&self) -> Vec<&'static str> {
+        let mut keys = self.actions();
+        let daemon = [(self.schedule.is_some(), "schedule"), (self.output.is_some(), "output"),
+                      (self.sqlite.is_some(), "sqlite"), (self.webhook.is_some(), "webhook")];
+        keys.extend(IntoIterator::into_iter(daemon).filter(|(given, _)| *given).map(|(_, key)| key));
+        keys
+    }
+
+    /// The settings, with those of the supplied profile in their place
+    /// wherever the profile gives them.
+    pub fn overridden_by(&self, profile: &Settings) -> Settings {
+        fn or<T: Clone>(profile: &Option<T>, base: &Option<T>) -> Option<T> {
+            profile.as_ref().or(base.as_ref()).cloned()
+        }
+        fn or_list<T: Clone>(profile: &[T], base: &[T]) -> Vec<T> {
+            if profile.is_empty() { base } else { profile }.to_vec()
+        }
+        Settings {
+            days: or(&profile.days, &self.days),
+            access: or(&profile.access, &self.access),
+            modify: or(&profile.modify, &self.modify),
+            change: or(&profile.change, &self.change),
+            create: or(&profile.create, &self.create),
+            effective: or(&profile.effective, &self.effective),
+            skip: or_list(&profile.skip, &self.skip),
+            exclude_path: or_list(&profile.exclude_path, &self.exclude_path),
+            name: or_list(&profile.name, &self.name),
+            ignore_hidden: or(&profile.ignore_hidden, &self.ignore_hidden),
+            follow_links: or(&profile.follow_links, &self.follow_links),
+            format: or(&profile.format, &self.format),
+            time_format: or(&profile.time_format, &self.time_format),
+            timezone: or(&profile.timezone, &self.timezone),
+            threads: or(&profile.threads, &self.threads),
+            sync: or(&profile.sync, &self.sync),
+            backend: or(&profile.backend, &self.backend),
+            min_size: or(&profile.min_size, &self.min_size),
+            max_size: or(&profile.max_size, &self.max_size),
+            mime: or_list(&profile.mime, &self.mime),
+            dirs: or_list(&profile.dirs, &self.dirs),
+            // actions, and the daemon's schedule and outputs, are only ever
+            // those of the profile
+            ..profile.clone()
+        }.without_profiles()
+    }
+
+    // The settings, without any profiles of their own.
+    fn without_profiles(mut self) -> Settings {
+        self.profile.clear();
+        self
+    }
+}
+
+/// A config file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    /// The file the config was read from
+    pub path: Option<PathBuf>,
+    /// The options the file supplies
+    pub settings: Settings,
+}
+
+impl Config {
+    /// Read the config file at the supplied path.
+    pub fn load(path: &Path) -> Result<Self, AmbleError> {
+        let text = fs::read_to_string(path).map_err(|e| {
+            AmbleError::IoError(format!("unable to read config '{}': {}", path.display(), e))
+        })?;
+        let invalid = |e: String| AmbleError::ParseError(format!("invalid config '{}': {}", path.display(), e));
+        let settings: Settings = toml::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+        if let Some(key) = settings.profile_only().first() {
+            return Err(invalid(format!("{} may only be given in a profile", key)));
+        }
+        for (name, profile) in &settings.profile {
+            if !profile.profile.is_empty() {
+                return Err(invalid(format!("profile '{}' holds profiles of its own", name)));
+            }
+            if let [first, second, ..] = profile.actions()[..] {
+                return Err(invalid(format!("profile '{}' gives both {} and {}", name, first, second)));
+            }
+        }
+        Ok(Self { path: Some(path.to_path_buf()), settings })
+    }
+
+    /// The settings of the named profile, falling back on those at the top
+    /// of the file.
+    pub fn profile(&self, name: &str) -> Result<Settings, AmbleError> {
+        match self.settings.profile.get(name) {
+            Some(profile) => Ok(self.settings.overridden_by(profile)),
+            None => {
+                let path = self.path.as_deref().unwrap_or_else(|| Path::new(CONFIG_FILE));
+                let mut message = format!("no profile '{}' in '{}'", name, path.display());
+                if !self.settings.profile.is_empty() {
+                    let names = self.settings.profile.keys().map(String::as_str).collect::<Vec<_>>();
+                    message.push_str(&format!(". Expected one of: {}", names.join(", ")));
+                }
+                Err(AmbleError::UnexpectedResult(message))
+            },
+        }
+    }
+
+    /// The nearest config file to the supplied directory: the first
+    /// `.amble.toml` in it or any directory above it.
+    pub fn discover(dir: &Path) -> Option<PathBuf> {
+        let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        dir.ancestors().map(|dir| dir.join(CONFIG_FILE)).find(|path| path.is_file())
+    }
+}
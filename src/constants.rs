@@ -6,4 +6,66 @@
 pub const MIN_DAYS: f32 = 0.000_000_1;
 
 /// Number of seconds in a day
-pub const SECS_PER_DAY: u64 = 86_400;
\ No newline at end of file
+pub const SECS_PER_DAY: u64 = 86_400;
+
+/// Default number of days within which a file is considered hot
+pub const HOT_DAYS: f32 = 7.0;
+
+/// Default number of days within which a file is considered warm
+pub const WARM_DAYS: f32 = 30.0;
+
+/// Default number of days within which a file is considered cold. Files
+/// older than this are frozen
+pub const COLD_DAYS: f32 = 180.0;
+
+/// Default number of matches, and of errors, which the walker threads of an
+/// asynchronous search may queue before they wait on the thread reporting
+/// them
+pub const CHANNEL_CAPACITY: usize = 1024;
+
+/// Default number of bytes of matches which may be held in memory while
+/// they are sorted, beyond which they are spilled to temporary files
+pub const BUFFER_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Number of bytes of matches buffered before they are written to stdout,
+/// when it is not a terminal
+pub const STDOUT_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Number of milliseconds after matches were last written to stdout that
+/// the next match is written straight away, rather than buffered, so that
+/// matches found slowly still appear promptly
+pub const STDOUT_FLUSH_MILLIS: u64 = 100;
+
+/// Number of entries whose metadata is read ahead of the sync search, for
+/// each thread prefetching it
+pub const PREFETCH_AHEAD: usize = 8;
+
+/// Number of seconds between the checkpoints saved by a resumable scan
+pub const CHECKPOINT_SECS: u64 = 10;
+
+/// Number of bytes of arguments, including the environment, passed to each
+/// command run by a batched exec. Like xargs, this stays well below the
+/// limit imposed by the operating system
+pub const EXEC_BATCH_BYTES: usize = 128 * 1024;
+
+/// Number of samples per second taken while profiling the scan
+pub const PROFILE_HZ: i32 = 997;
+
+/// Name of the config file discovered at or above the scan root
+pub const CONFIG_FILE: &str = ".amble.toml";
+
+/// Number of seconds between the checks, by amble watch, of whether the
+/// files which match have aged out of the window
+pub const WATCH_INTERVAL_SECS: u64 = 60;
+
+/// Number of milliseconds amble watch waits for a burst of changes to
+/// settle before evaluating them
+pub const WATCH_SETTLE_MILLIS: u64 = 200;
+
+/// Number of seconds amble daemon sleeps at most before checking the clock,
+/// while waiting for the next run
+pub const DAEMON_SLEEP_SECS: u64 = 60;
+
+/// Number of milliseconds between refreshes of the progress indicator shown
+/// during a scan
+pub const PROGRESS_REFRESH_MILLIS: u64 = 100;
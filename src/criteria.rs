@@ -0,0 +1,485 @@
+//! criteria.rs
+//!
+//! Search criteria shared by the sync and async walkers. Given the
+//! metadata for a candidate file, Criteria decides whether or not the
+//! file is of interest, producing a FileMatch if it is.
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, Metadata};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use tracing::{debug, trace};
+
+#[cfg(feature = "mime")]
+use crate::mime::sniff_matches;
+
+use crate::{
+    classify::Classifier,
+    constants::SECS_PER_DAY,
+    duration::{business_cutoff, calendar_cutoff, to_duration},
+    errors::AmbleError,
+    fields::{dir_times_eager, Field, Marker},
+    filematch::FileMatch,
+    hardlinks::{inode, nlink, LinkCount},
+    owner::file_owner,
+    perms::{is_executable, mode_flags},
+    size::{allocated_size, SizeMode},
+};
+
+/// The metadata criteria used to evaluate candidate files.
+#[derive(Debug, Clone)]
+pub(crate) struct Criteria {
+    /// How far back to search
+    pub(crate) window: Duration,
+    /// Per-criterion windows, overriding `window` for those fields
+    pub(crate) field_windows: HashMap<Field, Duration>,
+    /// The instant ages are computed relative to, rather than now
+    pub(crate) as_of: Option<SystemTime>,
+    /// The instant the search started, snapshotted by `compile`, so that
+    /// files found early and late in a long search are aged alike
+    pub(crate) started: Option<SystemTime>,
+    /// When set, the effective time of the file (the latest of its access,
+    /// modification, and creation times) is tested in place of the fields
+    pub(crate) effective: bool,
+    /// When set, a window of N days begins at local midnight N calendar
+    /// days ago, rather than N * 24 hours ago
+    pub(crate) calendar_days: bool,
+    /// When set, a window of N days spans N business days, skipping
+    /// weekends, so it begins N weekdays ago
+    pub(crate) business_days: bool,
+    /// Tolerance for clock drift between hosts, such as NFS clients and
+    /// servers, by which windows are widened
+    pub(crate) skew: Duration,
+    /// Match timestamps later than this, in place of the number of seconds
+    pub(crate) newer_than: Option<SystemTime>,
+    /// Match timestamps earlier than this, in place of the number of seconds
+    pub(crate) older_than: Option<SystemTime>,
+    /// Whether or not to check access time
+    pub(crate) access: bool,
+    /// Devices, by id, whose access times are never updated (noatime), so
+    /// are not tested for files on them
+    pub(crate) noatime_devices: HashSet<u64>,
+    /// Whether or not to check create time (on Linux, where the filesystem records it)
+    pub(crate) create: bool,
+    /// Whether or not to check modification time
+    pub(crate) modify: bool,
+    /// Whether or not to check inode change time (unix only)
+    pub(crate) change: bool,
+    /// When set, every file is classified rather than tested against `days`
+    pub(crate) classifier: Option<Classifier>,
+    /// When set, restrict matches to empty (true) or non-empty (false) files
+    pub(crate) empty: Option<bool>,
+    /// When set, report dangling symbolic links rather than files
+    pub(crate) broken_links: bool,
+    /// When reporting dangling links, only report those whose own change
+    /// time falls within `days`
+    pub(crate) broken_links_aged: bool,
+    /// Shell glob patterns, one of which a file name must match, if any
+    /// are supplied
+    pub(crate) names: Vec<String>,
+    /// The compiled name patterns. See `compile`
+    name_set: Option<GlobSet>,
+    /// The start of each calendar or business day window, by window,
+    /// computed once from local dates. See `compile`
+    cutoffs: HashMap<Duration, SystemTime>,
+    /// MIME type patterns, one of which the sniffed content type of a file
+    /// must match, if any are supplied. Requires the mime feature
+    pub(crate) mime: Vec<String>,
+    /// How file sizes are measured when filtering by size
+    pub(crate) size_mode: SizeMode,
+    /// Minimum size, in bytes, of files of interest
+    pub(crate) min_size: Option<u64>,
+    /// Maximum size, in bytes, of files of interest
+    pub(crate) max_size: Option<u64>,
+    /// When set, restrict matches to files with an execute bit set
+    pub(crate) executable: bool,
+    /// When set, restrict matches to files whose number of hard links
+    /// satisfies the constraint
+    pub(crate) links: Option<LinkCount>,
+    /// When set, restrict matches to files owned by the user id
+    pub(crate) owner: Option<u32>,
+    /// Whether or not to flag security relevant mode bits on matches
+    pub(crate) mode_flags: bool,
+    /// When set, every file which passes the filters is a match,
+    /// regardless of its times
+    pub(crate) every: bool,
+    /// When set, directories whose own modification and change times
+    /// are too old for anything in them to match are not descended into,
+    /// where that can be told (see `prunes_cold_dirs`)
+    pub(crate) cold_dirs: bool,
+}
+
+impl Default for Criteria {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(8 * SECS_PER_DAY),
+            field_windows: HashMap::new(),
+            as_of: None,
+            started: None,
+            calendar_days: false,
+            business_days: false,
+            effective: false,
+            skew: Duration::ZERO,
+            newer_than: None,
+            older_than: None,
+            access: true,
+            noatime_devices: HashSet::new(),
+            create: true,
+            modify: true,
+            change: false,
+            classifier: None,
+            empty: None,
+            broken_links: false,
+            broken_links_aged: false,
+            names: Vec::new(),
+            name_set: None,
+            cutoffs: HashMap::new(),
+            mime: Vec::new(),
+            size_mode: SizeMode::Apparent,
+            min_size: None,
+            max_size: None,
+            executable: false,
+            links: None,
+            owner: None,
+            mode_flags: false,
+            every: false,
+            cold_dirs: false,
+        }
+    }
+}
+
+impl Criteria {
+    /// Is there nothing to search for? This is the case when no metadata
+    /// criteria are turned on, and we are neither classifying, looking for
+    /// broken links, nor matching every file.
+    pub(crate) fn is_empty(&self) -> bool {
+        !(self.classifier.is_some() || self.broken_links || self.every || self.effective
+            || self.access || self.create || self.modify || self.change)
+    }
+
+    /// The instant ages are computed relative to: `as_of`, if supplied,
+    /// otherwise when the search started.
+    pub(crate) fn now(&self) -> SystemTime {
+        self.as_of.or(self.started).unwrap_or_else(SystemTime::now)
+    }
+
+    // Does the timestamp fall within the supplied window, or between the
+    // reference times, if there are any, widened by the skew? When
+    // searching as of some instant, later timestamps had yet to happen, so
+    // do not.
+    fn within(&self, time: SystemTime, window: Duration) -> bool {
+        let early = time.checked_sub(self.skew).unwrap_or(time);
+        let late = time.checked_add(self.skew).unwrap_or(time);
+        if self.newer_than.is_some() || self.older_than.is_some() {
+            return self.newer_than.is_none_or(|newer_than| late > newer_than)
+                   && self.older_than.is_none_or(|older_than| early < older_than);
+        }
+        match self.as_of {
+            Some(as_of) if early > as_of => false,
+            _ if self.calendar_days || self.business_days => {
+                late >= self.cutoffs.get(&window).copied().unwrap_or_else(|| self.cutoff(window))
+            },
+            _ => within_window(late, window, self.now()),
+        }
+    }
+
+    // The start of a calendar or business day window. Days are counted on
+    // local dates, rather than as 24 hours, so that a window spanning a
+    // daylight saving change starts at the same local time of day.
+    fn cutoff(&self, window: Duration) -> SystemTime {
+        if self.business_days {
+            business_cutoff(self.now(), whole_days(window))
+        } else {
+            calendar_cutoff(self.now(), whole_days(window))
+        }
+    }
+
+    // Is the timestamp later than now, by more than the skew?
+    fn is_future(&self, time: SystemTime, now: SystemTime) -> bool {
+        self.as_of.is_none() && time.checked_sub(self.skew).is_some_and(|early| early > now)
+    }
+
+    /// How far back to search for the supplied field.
+    pub(crate) fn window_for(&self, field: Field) -> Duration {
+        self.field_windows.get(&field).copied().unwrap_or(self.window)
+    }
+
+    /// Test the supplied field, with its own window, overriding `window`
+    /// for this field.
+    pub(crate) fn field_window(&mut self, field: Field, window: Duration) {
+        match field {
+            Field::Access => self.access = true,
+            Field::Create => self.create = true,
+            Field::Modify => self.modify = true,
+            Field::Change => self.change = true,
+        }
+        self.field_windows.insert(field, window);
+    }
+
+    /// Return a copy of the criteria, ready to evaluate files, with the
+    /// name patterns compiled, the start of the search snapshotted, and the
+    /// start of any calendar or business day windows computed from it.
+    /// Patterns optionally ignore case.
+    pub(crate) fn compile(&self, case_insensitive: bool) -> Result<Criteria, AmbleError> {
+        if !self.mime.is_empty() && !cfg!(feature = "mime") {
+            return Err(AmbleError::UnexpectedResult(
+                "filtering by MIME type requires amble to be built with the mime feature".to_string()));
+        }
+        let mut compiled = self.clone();
+        compiled.started = Some(SystemTime::now());
+        if self.calendar_days || self.business_days {
+            compiled.cutoffs = self.field_windows.values()
+                .chain(Some(&self.window))
+                .map(|window| (*window, compiled.cutoff(*window)))
+                .collect();
+        }
+        if !self.names.is_empty() {
+            let mut builder = GlobSetBuilder::new();
+            for name in &self.names {
+                builder.add(GlobBuilder::new(name).case_insensitive(case_insensitive).build()?);
+            }
+            compiled.name_set = Some(builder.build()?);
+        }
+        Ok(compiled)
+    }
+
+    /// The metadata fields which have been requested.
+    pub(crate) fn fields(&self) -> Vec<Field> {
+        [(Field::Access, self.access), (Field::Create, self.create), (Field::Modify, self.modify),
+         (Field::Change, self.change)]
+            .iter()
+            .filter(|(_, requested)| *requested)
+            .map(|(field, _)| *field)
+            .collect()
+    }
+
+    // The requested fields, cheapest to test first: those read straight
+    // from the stat buffer, then creation time, which may be unavailable.
+    fn tests(&self) -> impl Iterator<Item = Field> {
+        IntoIterator::into_iter([(Field::Modify, self.modify), (Field::Access, self.access),
+                                 (Field::Change, self.change), (Field::Create, self.create)])
+            .filter(|(_, requested)| *requested)
+            .map(|(field, _)| field)
+    }
+
+    // Is the field tested for the file with the supplied metadata? Access
+    // times are not, on devices mounted noatime.
+    fn tested(&self, field: Field, metadata: &Metadata) -> bool {
+        field != Field::Access || self.noatime_devices.is_empty()
+            || inode(metadata).is_none_or(|(device, _)| !self.noatime_devices.contains(&device))
+    }
+
+    /// Can a regular file at the supplied path be ruled out without reading
+    /// its metadata? This is the case when its name matches none of the
+    /// name patterns, or when reporting broken links, which regular files
+    /// never are. Walkers test this before reading metadata, to save a
+    /// stat call per excluded file.
+    pub(crate) fn excludes(&self, path: &Path) -> bool {
+        let excludes = self.broken_links || !self.matches_name(path);
+        if excludes {
+            trace!(path = %path.display(), "excluded by name");
+        }
+        excludes
+    }
+
+    // Does any requested field fall within its window? Testing stops at
+    // the first which does.
+    fn any_within(&self, metadata: &Metadata) -> bool {
+        if self.effective {
+            let times = [metadata.accessed().ok(), metadata.modified().ok(), Field::Create.timestamp(metadata).ok()];
+            return times.iter().flatten().max().is_some_and(|time| self.within(*time, self.window));
+        }
+        self.tests().filter(|field| self.tested(*field, metadata)).any(|field| {
+            field.timestamp(metadata).is_ok_and(|time| self.within(time, self.window_for(field)))
+        })
+    }
+
+    /// Evaluate a file, given its path and metadata, returning a FileMatch
+    /// if the file is of interest, or None if it is not. Requested fields
+    /// which are unavailable for the file are recorded on the FileMatch
+    /// rather than treated as errors. The cheapest tests come first, and
+    /// the FileMatch is only built once the file is known to match.
+    pub(crate) fn evaluate(&self, path: &Path, metadata: &Metadata) -> Result<Option<FileMatch>, AmbleError> {
+        if self.broken_links || !self.passes_filters(path, metadata) {
+            trace!(path = %path.display(), "rejected by the filters");
+            return Ok(None);
+        }
+        let timed = self.classifier.is_none() && !self.every;
+        if timed && !self.any_within(metadata) {
+            trace!(path = %path.display(), "outside the window");
+            return Ok(None);
+        }
+        trace!(path = %path.display(), "meets the criteria");
+
+        let mut found = FileMatch::from_metadata(path, metadata);
+        if self.mode_flags {
+            found.flags = mode_flags(metadata);
+        }
+
+        if let Some(classifier) = &self.classifier {
+            // classifying as of the skew earlier widens each tier by it
+            let now = self.now();
+            found.class = Some(classifier.classify_as_of(metadata, now.checked_sub(self.skew).unwrap_or(now))?);
+            return self.sniff(found);
+        }
+        if self.every {
+            return self.sniff(found);
+        }
+
+        let now = self.now();
+        if self.effective {
+            let times = [(Field::Access, found.accessed), (Field::Modify, found.modified), (Field::Create, found.created)];
+            found.future.extend(times.iter()
+                                     .filter(|(_, time)| time.is_some_and(|time| self.is_future(time, now)))
+                                     .map(|(field, _)| *field));
+            found.meta.push(Marker::Effective);
+            return self.sniff(found);
+        }
+        // the file is known to match, but every matching field is recorded
+        // for reporting
+        for field in self.fields().into_iter().filter(|field| self.tested(*field, metadata)) {
+            match field.timestamp(metadata) {
+                Ok(time) => {
+                    if self.is_future(time, now) {
+                        found.future.push(field);
+                    }
+                    if self.within(time, self.window_for(field)) {
+                        found.meta.push(field.marker());
+                    }
+                },
+                Err(_) => found.missing.push(field),
+            }
+        }
+        self.sniff(found)
+    }
+
+    /// Can directories be pruned by their own times? A new entry updates
+    /// the modification and change times of its directory, so only scans
+    /// for recently modified or created files, within a window of now,
+    /// may be.
+    pub(crate) fn prunes_cold_dirs(&self) -> bool {
+        self.cold_dirs && (self.modify || self.create) && !(self.access || self.change || self.effective)
+            && self.classifier.is_none() && !self.every && !self.broken_links
+            && self.older_than.is_none() && self.as_of.is_none()
+    }
+
+    /// Should the directory at the supplied path, with the supplied
+    /// metadata, be skipped, along with everything beneath it? This is the
+    /// case when its modification and change times both fall outside the
+    /// widest window, on a filesystem known to keep them up to date. A
+    /// file modified in place, or an entry added beneath a subdirectory,
+    /// does not update them, so such files are missed.
+    pub(crate) fn skips_dir(&self, path: &Path, metadata: &Metadata) -> bool {
+        if !self.prunes_cold_dirs() {
+            return false;
+        }
+        let widest = self.tests().map(|field| self.window_for(field)).max().unwrap_or(self.window);
+        let cold = [Field::Modify, Field::Change].iter()
+            .all(|field| field.timestamp(metadata).is_ok_and(|time| !self.within(time, widest)));
+        let skips = cold && dir_times_eager(path);
+        if skips {
+            debug!(path = %path.display(), reason = "cold", "skipped");
+        }
+        skips
+    }
+
+    /// Evaluate a dangling symbolic link, given its path and the metadata
+    /// of the link itself, returning a FileMatch if the link should be
+    /// reported.
+    pub(crate) fn evaluate_link(&self, path: &Path, link_metadata: &Metadata) -> Result<Option<FileMatch>, AmbleError> {
+        if !self.broken_links || !self.matches_name(path) {
+            return Ok(None);
+        }
+
+        let mut found = FileMatch::from_metadata(path, link_metadata);
+        found.target = Some(fs::read_link(path)?);
+
+        if self.broken_links_aged {
+            match Field::Change.timestamp(link_metadata) {
+                Ok(time) => if self.within(time, self.window_for(Field::Change)) {
+                    found.meta.push(Field::Change.marker());
+                } else {
+                    return Ok(None);
+                },
+                Err(_) => found.missing.push(Field::Change),
+            }
+        }
+        Ok(Some(found))
+    }
+
+    // Content sniffing is comparatively expensive, so it is applied only to
+    // files which have already satisfied the other criteria.
+    #[cfg(feature = "mime")]
+    fn sniff(&self, found: FileMatch) -> Result<Option<FileMatch>, AmbleError> {
+        if self.mime.is_empty() || sniff_matches(&found.path, &self.mime)? {
+            Ok(Some(found))
+        } else {
+            Ok(None)
+        }
+    }
+
+    #[cfg(not(feature = "mime"))]
+    fn sniff(&self, found: FileMatch) -> Result<Option<FileMatch>, AmbleError> {
+        Ok(Some(found))
+    }
+
+    // Does the file pass the filters which apply in addition to the
+    // metadata criteria?
+    fn passes_filters(&self, path: &Path, metadata: &Metadata) -> bool {
+        if !self.matches_name(path) {
+            return false;
+        }
+        if let Some(empty) = self.empty {
+            if (metadata.len() == 0) != empty {
+                return false;
+            }
+        }
+        if self.executable && !is_executable(metadata) {
+            return false;
+        }
+        if self.owner.is_some_and(|uid| file_owner(metadata) != Some(uid)) {
+            return false;
+        }
+        if self.links.is_some_and(|links| !links.matches(nlink(metadata))) {
+            return false;
+        }
+        if self.min_size.is_some() || self.max_size.is_some() {
+            let size = match self.size_mode {
+                SizeMode::Apparent => metadata.len(),
+                SizeMode::Allocated => allocated_size(metadata),
+            };
+            if self.min_size.is_some_and(|min| size < min) || self.max_size.is_some_and(|max| size > max) {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Does the file name match one of the name patterns? If there are no
+    // patterns, every name matches.
+    fn matches_name(&self, path: &Path) -> bool {
+        match (&self.name_set, path.file_name()) {
+            (None, _) => true,
+            (Some(set), Some(name)) => set.is_match(name),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+// The number of whole days in the window, with any fraction rounded up.
+fn whole_days(window: Duration) -> u64 {
+    (window.as_secs_f64() / SECS_PER_DAY as f64).ceil() as u64
+}
+
+/// Does the supplied timestamp fall within `days` # of days before now?
+pub(crate) fn within_days(time: SystemTime, days: f32, now: SystemTime) -> bool {
+    within_window(time, to_duration(days, SECS_PER_DAY), now)
+}
+
+/// Does the supplied timestamp fall within the window before now? Ages
+/// are compared at the full precision of the platform's timestamps.
+/// Timestamps in the future, such as from clock skew, are taken to be now.
+pub(crate) fn within_window(time: SystemTime, window: Duration, now: SystemTime) -> bool {
+    now.duration_since(time).unwrap_or_default() < window
+}
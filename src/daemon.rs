@@ -0,0 +1,264 @@
+//! daemon.rs
+//!
+//! The daemon, which runs the profiles of a config file (see config.rs) on
+//! their schedules, so that deployments need no external scheduler, nor
+//! scripts to collect the results. Each profile giving a schedule is run
+//! by amble itself, as `amble --config FILE --profile NAME --json -- DIRS`,
+//! so that a profile is scanned exactly as it would be by hand, and a scan
+//! which fails does not take the daemon down with it. In a dry run, each
+//! scan is run with --dry-run, so that its actions are only reported.
+//!
+//! The results of each run - the profile, when it started and finished,
+//! its exit status, and the matches it reported - are written to each of
+//! the outputs of the profile:
+//!
+//! - output: a file, which each run is appended to as a line of JSON
+//! - sqlite: a SQLite database, holding a row for each run, and for each
+//!   match. Requires the sqlite feature
+//! - webhook: a URL, which each run is posted to as JSON. Requires the
+//!   webhook feature
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    config::{Config, Settings},
+    constants::DAEMON_SLEEP_SECS,
+    errors::AmbleError,
+    schedule::Schedule,
+    timefmt::Zone,
+};
+
+/// A profile, run on a schedule.
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// The name of the profile
+    pub profile: String,
+    /// The schedule the profile is run on
+    pub schedule: Schedule,
+    /// The directories the profile scans
+    pub dirs: Vec<PathBuf>,
+    /// The file each run is appended to
+    pub output: Option<PathBuf>,
+    /// The SQLite database each run is recorded in
+    pub sqlite: Option<PathBuf>,
+    /// The URL each run is posted to
+    pub webhook: Option<String>,
+}
+
+impl Job {
+    // The job of the named profile, should it give a schedule.
+    fn of(name: &str, settings: Settings) -> Result<Option<Self>, AmbleError> {
+        let schedule = match &settings.schedule {
+            Some(schedule) => schedule.parse::<Schedule>()?,
+            None => return Ok(None),
+        };
+        if settings.dirs.is_empty() {
+            return Err(AmbleError::UnexpectedResult(format!("profile '{}' is scheduled, but gives no dirs", name)));
+        }
+        Ok(Some(Self { profile: name.to_string(), schedule, dirs: settings.dirs, output: settings.output,
+                       sqlite: settings.sqlite, webhook: settings.webhook }))
+    }
+}
+
+/// The results of a run of a profile.
+#[derive(Debug, Clone, Serialize)]
+pub struct Run {
+    /// The name of the profile
+    pub profile: String,
+    /// When the run started, as an RFC 3339 timestamp
+    pub started: String,
+    /// When the run finished, as an RFC 3339 timestamp
+    pub finished: String,
+    /// The exit status of the scan, or None if it was killed
+    pub status: Option<i32>,
+    /// The matches reported, each as a JSON object, or as the line reported
+    /// should it not be one, such as when an action is taken
+    pub matches: Vec<Value>,
+}
+
+impl Run {
+    /// Each match, alongside its path, where it has one.
+    pub fn paths(&self) -> impl Iterator<Item = (Option<&str>, &Value)> {
+        self.matches.iter().map(|found| (found.get("path").and_then(Value::as_str), found))
+    }
+}
+
+/// Runs the scheduled profiles of a config file.
+#[derive(Debug)]
+pub struct Daemon {
+    config: PathBuf,
+    program: PathBuf,
+    jobs: Vec<Job>,
+    dry_run: bool,
+}
+
+impl Daemon {
+    /// New up a Daemon running the scheduled profiles of the supplied
+    /// config file, with the supplied amble program.
+    pub fn new(config: &Path, program: &Path) -> Result<Self, AmbleError> {
+        let loaded = Config::load(config)?;
+        let mut jobs = Vec::new();
+        for name in loaded.settings.profile.keys() {
+            if let Some(job) = Job::of(name, loaded.profile(name)?)? {
+                jobs.push(job);
+            }
+        }
+        if jobs.is_empty() {
+            return Err(AmbleError::UnexpectedResult(format!("no profile in '{}' gives a schedule",
+                                                            config.display())));
+        }
+        Ok(Self { config: config.to_path_buf(), program: program.to_path_buf(), jobs, dry_run: false })
+    }
+
+    /// Set whether the scans are dry runs, whose actions are reported,
+    /// rather than taken.
+    pub fn dry_run(&mut self, dry_run: bool) -> &mut Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// The scheduled profiles.
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    /// Run each scheduled profile once, straight away, returning whether
+    /// every run succeeded.
+    pub fn run_once(&self) -> Result<bool, AmbleError> {
+        let mut succeeded = true;
+        for job in &self.jobs {
+            succeeded &= self.run_job(job)?;
+        }
+        Ok(succeeded)
+    }
+
+    /// Run the scheduled profiles on their schedules, until none will run
+    /// again.
+    pub fn run(&self) -> Result<(), AmbleError> {
+        let now = SystemTime::now();
+        let mut next = self.jobs.iter().map(|job| job.schedule.next_after(now)).collect::<Vec<_>>();
+        loop {
+            let Some((index, at)) = next.iter()
+                                        .enumerate()
+                                        .filter_map(|(index, at)| at.map(|at| (index, at)))
+                                        .min_by_key(|(_, at)| *at) else {
+                return Err(AmbleError::UnexpectedResult("no scheduled profile will run again".to_string()));
+            };
+            let job = &self.jobs[index];
+            eprintln!("# next run: '{}' at {}", job.profile, Zone::Local.rfc3339(at));
+            // slept in steps, so that a change to the clock is noticed
+            while let Ok(remaining) = at.duration_since(SystemTime::now()) {
+                thread::sleep(remaining.min(Duration::from_secs(DAEMON_SLEEP_SECS)));
+            }
+            self.run_job(job)?;
+            next[index] = job.schedule.next_after(at.max(SystemTime::now()));
+        }
+    }
+
+    // Run a profile, writing its results to its outputs, and returning
+    // whether it succeeded. Outputs which fail are reported to stderr.
+    fn run_job(&self, job: &Job) -> Result<bool, AmbleError> {
+        let started = SystemTime::now();
+        let mut command = Command::new(&self.program);
+        if self.dry_run {
+            command.arg("--dry-run");
+        }
+        let output = command
+            .arg("--config").arg(&self.config)
+            .arg("--profile").arg(&job.profile)
+            .arg("--json")
+            .arg("--")
+            .args(&job.dirs)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| AmbleError::IoError(format!("unable to run profile '{}': {}", job.profile, e)))?;
+        let matches = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_str(line).unwrap_or_else(|_| Value::String(line.to_string())))
+            .collect();
+        let run = Run { profile: job.profile.clone(), started: Zone::Local.rfc3339(started),
+                        finished: Zone::Local.rfc3339(SystemTime::now()), status: output.status.code(), matches };
+        eprintln!("# ran '{}': {} matches, {}", job.profile, run.matches.len(), output.status);
+
+        let outputs: [(&str, Option<Result<(), AmbleError>>); 3] = [
+            ("output", job.output.as_deref().map(|path| append(path, &run))),
+            ("sqlite", job.sqlite.as_deref().map(|path| record(path, &run))),
+            ("webhook", job.webhook.as_deref().map(|url| post(url, &run))),
+        ];
+        for (name, written) in IntoIterator::into_iter(outputs) {
+            if let Some(Err(e)) = written {
+                eprintln!("Warning: unable to write the results of '{}' to its {}: {}", job.profile, name, e);
+            }
+        }
+        // as for amble itself, finding nothing is a success
+        Ok(matches!(output.status.code(), Some(0) | Some(1)))
+    }
+}
+
+// Append a run to a file, as a line of JSON.
+fn append(path: &Path, run: &Run) -> Result<(), AmbleError> {
+    let mut line = serde_json::to_vec(run)
+        .map_err(|e| AmbleError::UnexpectedResult(format!("unable to serialize the run: {}", e)))?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(&line)?;
+    Ok(())
+}
+
+// Record a run, and its matches, in a SQLite database, creating its tables
+// should they not exist.
+#[cfg(feature = "sqlite")]
+fn record(path: &Path, run: &Run) -> Result<(), AmbleError> {
+    use rusqlite::{params, Connection};
+
+    let failed = |e: rusqlite::Error| AmbleError::IoError(format!("'{}': {}", path.display(), e));
+    let mut connection = Connection::open(path).map_err(failed)?;
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS runs (id INTEGER PRIMARY KEY, profile TEXT NOT NULL, started TEXT NOT NULL,
+                                          finished TEXT NOT NULL, status INTEGER, matches INTEGER NOT NULL);
+         CREATE TABLE IF NOT EXISTS matches (run INTEGER NOT NULL REFERENCES runs (id), path TEXT,
+                                             record TEXT NOT NULL);").map_err(failed)?;
+    let transaction = connection.transaction().map_err(failed)?;
+    transaction.execute("INSERT INTO runs (profile, started, finished, status, matches) VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![run.profile, run.started, run.finished, run.status, run.matches.len() as i64])
+               .map_err(failed)?;
+    let id = transaction.last_insert_rowid();
+    {
+        let mut insert = transaction.prepare("INSERT INTO matches (run, path, record) VALUES (?1, ?2, ?3)")
+                                    .map_err(failed)?;
+        for (path, found) in run.paths() {
+            insert.execute(params![id, path, found.to_string()]).map_err(failed)?;
+        }
+    }
+    transaction.commit().map_err(failed)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn record(_path: &Path, _run: &Run) -> Result<(), AmbleError> {
+    Err(AmbleError::UnexpectedResult("sqlite requires amble to be built with the sqlite feature".to_string()))
+}
+
+// Post a run to a URL, as JSON.
+#[cfg(feature = "webhook")]
+fn post(url: &str, run: &Run) -> Result<(), AmbleError> {
+    let body = serde_json::to_string(run)
+        .map_err(|e| AmbleError::UnexpectedResult(format!("unable to serialize the run: {}", e)))?;
+    ureq::post(url).set("Content-Type", "application/json")
+                   .send_string(&body)
+                   .map_err(|e| AmbleError::IoError(format!("'{}': {}", url, e)))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "webhook"))]
+fn post(_url: &str, _run: &Run) -> Result<(), AmbleError> {
+    Err(AmbleError::UnexpectedResult("webhooks require amble to be built with the webhook feature".to_string()))
+}
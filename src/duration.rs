@@ -0,0 +1,174 @@
+//! duration.rs
+//!
+//! Parsing of human friendly durations, such as "36h", "2w", or "1y6m",
+//! wherever a number of days is accepted. Fractional days are error prone
+//! (is 0.04 about an hour?), so a duration may instead be given as one or
+//! more numbers, each followed by a unit:
+//!
+//! | unit       | meaning  |
+//! |------------|----------|
+//! | ms         | milliseconds |
+//! | s          | seconds  |
+//! | min        | minutes  |
+//! | h          | hours    |
+//! | d          | days     |
+//! | w          | weeks    |
+//! | m, mo      | months, of 30 days |
+//! | y          | years, of 365 days |
+//!
+//! A plain number, without a unit, is a number of days. Numbers may have
+//! fractions, so that durations are precise to below a second.
+//!
+//! Instants, such as the time ages are computed relative to, are parsed
+//! here too.
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Datelike, Days as CalendarDays, Local, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Weekday};
+
+use crate::{constants::SECS_PER_DAY, errors::AmbleError};
+
+// Units in seconds, with "min", "mo", and "ms" ahead of "m", which they
+// start with
+const UNITS: [(&str, f64); 9] = [
+    ("min", 60.0),
+    ("mo", 30.0 * SECS_PER_DAY as f64),
+    ("ms", 0.001),
+    ("m", 30.0 * SECS_PER_DAY as f64),
+    ("s", 1.0),
+    ("h", 3600.0),
+    ("d", SECS_PER_DAY as f64),
+    ("w", 7.0 * SECS_PER_DAY as f64),
+    ("y", 365.0 * SECS_PER_DAY as f64),
+];
+
+/// Parse a number of days, or a duration such as "36h", "1y6m", or
+/// "1.5s", into a Duration, precise to the nanosecond.
+pub fn parse_duration(s: &str) -> Result<Duration, AmbleError> {
+    let invalid = || AmbleError::ParseError(format!("invalid duration '{}'. Expected DAYS, or eg 36h, 2w, 1y6m", s));
+    let trimmed = s.trim();
+    let secs = match trimmed.parse::<f64>() {
+        Ok(days) => days * SECS_PER_DAY as f64,
+        Err(_) if trimmed.is_empty() => return Err(invalid()),
+        Err(_) => {
+            let mut secs = 0.0;
+            let mut rest = trimmed;
+            while !rest.is_empty() {
+                let end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).ok_or_else(invalid)?;
+                let value = rest[..end].parse::<f64>().map_err(|_| invalid())?;
+                rest = &rest[end..];
+                let (unit, multiplier) = UNITS.iter().find(|(unit, _)| rest.starts_with(unit)).ok_or_else(invalid)?;
+                secs += value * multiplier;
+                rest = &rest[unit.len()..];
+            }
+            secs
+        },
+    };
+    Duration::try_from_secs_f64(secs).map_err(|_| invalid())
+}
+
+/// Parse a number of days, or a duration such as "36h" or "1y6m", into
+/// a number of days.
+pub fn parse_days(s: &str) -> Result<f32, AmbleError> {
+    parse_duration(s).map(|duration| (duration.as_secs_f64() / SECS_PER_DAY as f64) as f32)
+}
+
+/// A number of days, parsed by `parse_duration`, so that it may be given as
+/// a duration.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Days(pub Duration);
+
+impl Days {
+    /// The number of days, including any fraction of a day.
+    pub fn days(&self) -> f64 {
+        self.0.as_secs_f64() / SECS_PER_DAY as f64
+    }
+}
+
+impl FromStr for Days {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_duration(s).map(Days)
+    }
+}
+
+impl fmt::Display for Days {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.days())
+    }
+}
+
+/// The duration of `count` units of `unit_secs` seconds. Negative counts
+/// are empty, and counts too large to represent are saturated.
+pub fn to_duration(count: f32, unit_secs: u64) -> Duration {
+    Duration::try_from_secs_f64((unit_secs as f64 * f64::from(count)).max(0.0)).unwrap_or(Duration::MAX)
+}
+
+/// The instant `pct` percent of the lifetime of a project, which started
+/// at `start`, before `now`, so that 50 is halfway between the two. None
+/// when the project has yet to start.
+pub fn project_cutoff(start: SystemTime, now: SystemTime, pct: f64) -> Option<SystemTime> {
+    let lifetime = now.duration_since(start).ok()?;
+    let age = Duration::try_from_secs_f64(lifetime.as_secs_f64() * pct / 100.0).unwrap_or(Duration::MAX);
+    Some(now.checked_sub(age).unwrap_or(UNIX_EPOCH))
+}
+
+/// Parse an instant, given as an RFC 3339 timestamp (eg
+/// 2024-03-10T00:00:00Z), as a local date and time (eg 2024-03-10 00:00,
+/// with optional seconds), as a local date, meaning its midnight (eg
+/// 2024-03-10), or as @SECONDS since the epoch, which may be fractional.
+pub fn parse_time(s: &str) -> Result<SystemTime, AmbleError> {
+    let invalid = || AmbleError::ParseError(format!(
+        "invalid time '{}'. Expected eg 2024-03-10, 2024-03-10 18:30, 2024-03-10T18:30:00Z, or @1710000000", s));
+    let s = s.trim();
+    if let Some(secs) = s.strip_prefix('@') {
+        let secs = secs.parse::<f64>().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok()).ok_or_else(invalid)?;
+        return Ok(UNIX_EPOCH + secs);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.into());
+    }
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok().and_then(|date| date.and_hms_opt(0, 0, 0)))
+        .ok_or_else(invalid)?;
+    // times skipped by a daylight saving change do not exist locally
+    Local.from_local_datetime(&naive).earliest().map(SystemTime::from).ok_or_else(invalid)
+}
+
+/// Local midnight at the start of the day `days` calendar days before the
+/// day of `now`, so that 0 is the start of today. Where a daylight saving
+/// change skips midnight, the day starts at the first instant after it.
+pub fn calendar_cutoff(now: SystemTime, days: u64) -> SystemTime {
+    let today = DateTime::<Local>::from(now).date_naive();
+    let day = today.checked_sub_days(CalendarDays::new(days)).unwrap_or(NaiveDate::MIN);
+    (0..24)
+        .filter_map(|hour| day.and_hms_opt(hour, 0, 0))
+        .find_map(|start| Local.from_local_datetime(&start).earliest())
+        .map_or(UNIX_EPOCH, SystemTime::from)
+}
+
+/// The same local time of day as `now`, `days` business days earlier, so
+/// that weekends are skipped: one business day before Monday morning is
+/// Friday morning. Where a daylight saving change skips that time of day,
+/// the instant an hour later is used.
+pub fn business_cutoff(now: SystemTime, days: u64) -> SystemTime {
+    let now = DateTime::<Local>::from(now).naive_local();
+    let mut day = now.date();
+    let mut counted = 0;
+    while counted < days {
+        day = match day.pred_opt() {
+            Some(day) => day,
+            None => return UNIX_EPOCH,
+        };
+        if !matches!(day.weekday(), Weekday::Sat | Weekday::Sun) {
+            counted += 1;
+        }
+    }
+    let start = day.and_time(now.time());
+    [start, start + TimeDelta::hours(1)].iter()
+        .find_map(|start| Local.from_local_datetime(start).earliest())
+        .map_or(UNIX_EPOCH, SystemTime::from)
+}
@@ -2,6 +2,7 @@
 //!
 //! Defines AmbleError - the custom error impl for
 //! this crate.
+#![allow(non_local_definitions)]
 use failure::Fail;
 
 #[derive(Debug, Fail)]
@@ -20,6 +21,15 @@ pub enum AmbleError {
 
     #[fail(display = "UnexpectedResult: {}", _0)]
     UnexpectedResult (String),
+
+    #[fail(display = "ParseError: {}", _0)]
+    ParseError (String),
+}
+
+impl From<globset::Error> for AmbleError {
+    fn from(error: globset::Error) -> Self {
+        AmbleError::ParseError(error.to_string())
+    }
 }
 
 impl From<std::io::Error> for AmbleError {
@@ -46,3 +56,8 @@ impl From<ignore::Error> for AmbleError {
     }
 }
 
+impl From<serde_json::Error> for AmbleError {
+    fn from(error: serde_json::Error) -> Self {
+        AmbleError::ParseError(error.to_string())
+    }
+}
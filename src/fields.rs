@@ -0,0 +1,303 @@
+//! fields.rs
+//!
+//! Defines the metadata fields which amble may test, along with
+//! Availability - a map of which of those fields a platform and
+//! filesystem are able to supply.
+use std::fmt::{self, Write as _};
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+/// The timestamp a match was made on, as reported alongside the match.
+/// Each has a distinct code: 'a' for access time (atime), 'm' for
+/// modification time (mtime), 'b' for birth or creation time (btime), 'h'
+/// for inode change time (ctime), and 'e' for the effective time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Marker {
+    Access,
+    Modify,
+    Create,
+    Change,
+    /// The latest of the access, modification, and creation times
+    Effective,
+}
+
+impl Marker {
+    /// All of the markers.
+    pub const ALL: [Marker; 5] = [Marker::Access, Marker::Modify, Marker::Create, Marker::Change, Marker::Effective];
+
+    /// Single character code used to report the marker.
+    pub fn code(self) -> char {
+        match self {
+            Marker::Access => 'a',
+            Marker::Modify => 'm',
+            Marker::Create => 'b',
+            Marker::Change => 'h',
+            Marker::Effective => 'e',
+        }
+    }
+
+    /// The marker with the supplied code, if any.
+    pub fn from_code(code: char) -> Option<Marker> {
+        Marker::ALL.iter().copied().find(|marker| marker.code() == code)
+    }
+
+    /// Name of the timestamp, as reported to the user.
+    pub fn name(self) -> &'static str {
+        match self {
+            Marker::Access => "access",
+            Marker::Modify => "modify",
+            Marker::Create => "create",
+            Marker::Change => "change",
+            Marker::Effective => "effective",
+        }
+    }
+
+    /// The field the marker reports, if it reports a single field.
+    pub fn field(self) -> Option<Field> {
+        match self {
+            Marker::Access => Some(Field::Access),
+            Marker::Modify => Some(Field::Modify),
+            Marker::Create => Some(Field::Create),
+            Marker::Change => Some(Field::Change),
+            Marker::Effective => None,
+        }
+    }
+}
+
+impl fmt::Display for Marker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// The codes of the supplied markers, as reported alongside a match.
+pub fn marker_codes(markers: &[Marker]) -> String {
+    markers.iter().map(|marker| marker.code()).collect()
+}
+
+/// Displays the codes of the supplied markers, as `marker_codes` does,
+/// without allocating.
+pub struct MarkerCodes<'a>(pub &'a [Marker]);
+
+impl fmt::Display for MarkerCodes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|marker| f.write_char(marker.code()))
+    }
+}
+
+/// A metadata timestamp which may be used as search criteria.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Field {
+    Access,
+    Create,
+    Modify,
+    Change,
+}
+
+impl Field {
+    /// Marker used to report a match on the field.
+    pub fn marker(self) -> Marker {
+        match self {
+            Field::Access => Marker::Access,
+            Field::Create => Marker::Create,
+            Field::Modify => Marker::Modify,
+            Field::Change => Marker::Change,
+        }
+    }
+
+    /// Single character code used to report a match on the field.
+    pub fn code(self) -> char {
+        self.marker().code()
+    }
+
+    /// Name of the field, as reported to the user.
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Access => "access",
+            Field::Create => "create",
+            Field::Modify => "modify",
+            Field::Change => "change",
+        }
+    }
+
+    /// Retrieve the field's timestamp from the supplied metadata. An error
+    /// indicates that the field is not available.
+    pub fn timestamp(self, metadata: &Metadata) -> io::Result<SystemTime> {
+        match self {
+            Field::Access => metadata.accessed(),
+            Field::Create => created(metadata),
+            Field::Modify => metadata.modified(),
+            Field::Change => changed(metadata),
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// On linux, std populates Metadata via statx(2) where the kernel supports
+// it, providing the birth time when the filesystem records one (ext4, xfs,
+// btrfs on recent kernels). Otherwise created() returns an error, and the
+// field is reported as unavailable.
+fn created(metadata: &Metadata) -> io::Result<SystemTime> {
+    metadata.created()
+}
+
+// inode change time, from the unix specific metadata
+#[cfg(unix)]
+fn changed(metadata: &Metadata) -> io::Result<SystemTime> {
+    use std::os::unix::fs::MetadataExt;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let nanos = Duration::new(0, metadata.ctime_nsec() as u32);
+    let secs = metadata.ctime();
+    if secs >= 0 {
+        Ok(UNIX_EPOCH + Duration::from_secs(secs as u64) + nanos)
+    } else {
+        Ok(UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + nanos)
+    }
+}
+
+#[cfg(not(unix))]
+fn changed(_metadata: &Metadata) -> io::Result<SystemTime> {
+    Err(io::Error::other("change time is only available on unix"))
+}
+
+/// How the filesystem holding a path updates access times, per its mount
+/// options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtimeMode {
+    /// Access times are updated on every access (strictatime), or the
+    /// mount options are unknown
+    Strict,
+    /// Access times are only updated when older than the modification or
+    /// change time, or a day old (relatime), so they are accurate to a day
+    Relative,
+    /// Access times are never updated (noatime)
+    Never,
+}
+
+// statvfs mount flags, from linux/statfs.h, which libc does not export for
+// every target
+#[cfg(target_os = "linux")]
+const ST_NOATIME: libc::c_ulong = 1024;
+#[cfg(target_os = "linux")]
+const ST_RELATIME: libc::c_ulong = 4096;
+
+/// How the filesystem holding the supplied path updates access times (LINUX
+/// ONLY). Other platforms, and paths which cannot be queried, are reported
+/// as Strict.
+#[cfg(target_os = "linux")]
+pub fn atime_mode(path: &Path) -> AtimeMode {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(c_path) => c_path,
+        Err(_) => return AtimeMode::Strict,
+    };
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return AtimeMode::Strict;
+    }
+    if stat.f_flag & ST_NOATIME != 0 {
+        AtimeMode::Never
+    } else if stat.f_flag & ST_RELATIME != 0 {
+        AtimeMode::Relative
+    } else {
+        AtimeMode::Strict
+    }
+}
+
+/// How the filesystem holding the supplied path updates access times (LINUX
+/// ONLY). Other platforms, and paths which cannot be queried, are reported
+/// as Strict.
+#[cfg(not(target_os = "linux"))]
+pub fn atime_mode(_path: &Path) -> AtimeMode {
+    AtimeMode::Strict
+}
+
+/// Does the filesystem holding the supplied path update the modification
+/// and change times of a directory as soon as entries are added to,
+/// removed from, or renamed within it (LINUX ONLY)? Only local filesystems
+/// known to do so are: ext2/3/4, xfs, btrfs, zfs, and tmpfs. Network
+/// filesystems cache attributes, so a client may see stale times. Other
+/// platforms, and paths which cannot be queried, are reported as not.
+#[cfg(target_os = "linux")]
+pub fn dir_times_eager(path: &Path) -> bool {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    // from linux/magic.h: ext2/3/4, xfs, btrfs, tmpfs, and zfs. Magic numbers
+    // are 32 bits, though the width of f_type varies by architecture
+    const EAGER: [u32; 5] = [0xef53, 0x5846_5342, 0x9123_683e, 0x0102_1994, 0x2fc1_2fc1];
+
+    let c_path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(c_path) => c_path,
+        Err(_) => return false,
+    };
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return false;
+    }
+    EAGER.contains(&(stat.f_type as u32))
+}
+
+/// Does the filesystem holding the supplied path update the modification
+/// and change times of a directory as soon as its entries change (LINUX
+/// ONLY)? Other platforms are reported as not.
+#[cfg(not(target_os = "linux"))]
+pub fn dir_times_eager(_path: &Path) -> bool {
+    false
+}
+
+/// Records whether each of a set of requested fields is available.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Availability {
+    fields: Vec<(Field, bool)>,
+}
+
+impl Availability {
+    /// Probe the supplied metadata for each of the requested fields.
+    pub fn probe(metadata: &Metadata, fields: &[Field]) -> Self {
+        Self {
+            fields: fields.iter()
+                          .map(|field| (*field, field.timestamp(metadata).is_ok()))
+                          .collect(),
+        }
+    }
+
+    /// Is the supplied field available? Fields which were not probed
+    /// are reported as unavailable.
+    pub fn is_available(&self, field: Field) -> bool {
+        self.fields.iter().any(|(f, available)| *f == field && *available)
+    }
+
+    /// Are one or more of the probed fields unavailable?
+    pub fn is_degraded(&self) -> bool {
+        self.fields.iter().any(|(_, available)| !available)
+    }
+
+    /// Iterate over the probed fields and their availability.
+    pub fn iter(&self) -> impl Iterator<Item = &(Field, bool)> {
+        self.fields.iter()
+    }
+}
+
+impl fmt::Display for Availability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fields = self.fields.iter()
+                                .map(|(field, available)| {
+                                    format!("{}={}", field, if *available { "available" } else { "unavailable" })
+                                })
+                                .collect::<Vec<_>>();
+        write!(f, "{}", fields.join(" "))
+    }
+}
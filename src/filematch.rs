@@ -0,0 +1,185 @@
+//! filematch.rs
+//!
+//! Defines FileMatch - the record produced by the walkers for each
+//! file which satisfies the search criteria.
+use std::fmt;
+use std::fs::Metadata;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    classify::Class,
+    fields::{Field, Marker, MarkerCodes},
+    hardlinks::inode,
+    owner::file_owner,
+    perms::ModeFlag,
+    size::{allocated_size, SizeMode},
+};
+
+/// A single file which satisfied the search criteria.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileMatch {
+    /// Path to the file
+    #[serde(with = "crate::spill::path")]
+    pub path: PathBuf,
+    /// Apparent size of the file in bytes
+    pub size: u64,
+    /// Space allocated to the file on disk, in bytes. Smaller than `size`
+    /// for sparse files
+    pub allocated: u64,
+    /// The timestamps which satisfied the metadata criteria. See Marker
+    /// for the code each is reported with
+    pub meta: Vec<Marker>,
+    /// The storage tier of the file, when classifying
+    pub class: Option<Class>,
+    /// Requested metadata fields which were unavailable for the file
+    pub missing: Vec<Field>,
+    /// Requested metadata fields whose timestamps are in the future, such
+    /// as from clock skew between NFS hosts. They are treated as just now
+    pub future: Vec<Field>,
+    /// The (device, inode) pair identifying the file (unix only)
+    pub inode: Option<(u64, u64)>,
+    /// Number of hard links to the file
+    pub nlink: u64,
+    /// Other paths which are hard links to the same file, when reporting
+    /// all the paths of deduplicated hard links
+    #[serde(with = "crate::spill::paths")]
+    pub links: Vec<PathBuf>,
+    /// Target of the file, when it is a broken symbolic link
+    #[serde(with = "crate::spill::optional_path")]
+    pub target: Option<PathBuf>,
+    /// When a directory has been rolled up, the number of matching files
+    /// beneath it
+    pub contents: Option<u64>,
+    /// Security relevant mode bits set on the file, when flagging them
+    pub flags: Vec<ModeFlag>,
+    /// Time the file was last accessed, if available
+    #[serde(with = "crate::spill::optional_time")]
+    pub accessed: Option<SystemTime>,
+    /// Time the file was last modified, if available
+    #[serde(with = "crate::spill::optional_time")]
+    pub modified: Option<SystemTime>,
+    /// Time the file was created, if available
+    #[serde(with = "crate::spill::optional_time")]
+    pub created: Option<SystemTime>,
+    /// User id of the owner of the file (unix only)
+    pub owner: Option<u32>,
+    /// The root of the search beneath which the file was found
+    #[serde(with = "crate::spill::optional_path")]
+    pub root: Option<PathBuf>,
+}
+
+impl FileMatch {
+    /// New up a FileMatch for the supplied path and size, with no
+    /// criteria codes or class.
+    pub fn new(path: impl Into<PathBuf>, size: u64) -> Self {
+        Self {
+            path: path.into(),
+            size,
+            allocated: size,
+            meta: Vec::new(),
+            class: None,
+            missing: Vec::new(),
+            future: Vec::new(),
+            inode: None,
+            nlink: 1,
+            links: Vec::new(),
+            target: None,
+            contents: None,
+            flags: Vec::new(),
+            accessed: None,
+            modified: None,
+            created: None,
+            owner: None,
+            root: None,
+        }
+    }
+
+    /// New up a FileMatch for the supplied path, taking the size and
+    /// inode details from the supplied metadata.
+    pub fn from_metadata(path: impl Into<PathBuf>, metadata: &Metadata) -> Self {
+        let mut found = Self::new(path, metadata.len());
+        found.allocated = allocated_size(metadata);
+        found.inode = inode(metadata);
+        found.accessed = metadata.accessed().ok();
+        found.modified = metadata.modified().ok();
+        found.created = Field::Create.timestamp(metadata).ok();
+        found.owner = file_owner(metadata);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            found.nlink = metadata.nlink();
+        }
+        found
+    }
+
+    /// The effective time of the file: the latest of its access,
+    /// modification, and creation times, where available. A file has been
+    /// untouched in any way since its effective time.
+    pub fn effective_time(&self) -> Option<SystemTime> {
+        self.accessed.max(self.modified).max(self.created)
+    }
+
+    /// The effective age of the file, relative to `now`. Effective times in
+    /// the future are taken to be now.
+    pub fn effective_age(&self, now: SystemTime) -> Option<Duration> {
+        self.effective_time().map(|time| now.duration_since(time).unwrap_or_default())
+    }
+
+    /// Size of the file, measured according to the supplied mode.
+    pub fn size_for(&self, mode: SizeMode) -> u64 {
+        match mode {
+            SizeMode::Apparent => self.size,
+            SizeMode::Allocated => self.allocated,
+        }
+    }
+}
+
+impl fmt::Display for FileMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // written piecewise, rather than collected and joined, as matches
+        // are displayed at a high rate
+        write!(f, "{}", self.path.to_string_lossy())?;
+        match self.class {
+            Some(class) => write!(f, " ({})", class)?,
+            // broken links, and files matched regardless of their times,
+            // are reported without any criteria codes
+            None if self.meta.is_empty() => (),
+            None => write!(f, " ({})", MarkerCodes(&self.meta))?,
+        }
+        if let Some(target) = &self.target {
+            write!(f, " [broken -> {}]", target.to_string_lossy())?;
+        }
+        if !self.missing.is_empty() {
+            write_list(f, "missing", ",", self.missing.iter().map(|field| field.name()))?;
+        }
+        if !self.future.is_empty() {
+            write_list(f, "future", ",", self.future.iter().map(|field| field.name()))?;
+        }
+        if !self.links.is_empty() {
+            write_list(f, "links", ", ", self.links.iter().map(|link| link.to_string_lossy()))?;
+        }
+        if !self.flags.is_empty() {
+            write_list(f, "flags", ",", self.flags.iter().map(|flag| flag.as_str()))?;
+        }
+        if let Some(contents) = self.contents {
+            write!(f, " [directory: {} files]", contents)?;
+        }
+        Ok(())
+    }
+}
+
+// Write a labelled list, as " [label: a,b]".
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter, label: &str, separator: &str, items: impl Iterator<Item = T>)
+-> fmt::Result {
+    write!(f, " [{}: ", label)?;
+    for (index, item) in items.enumerate() {
+        if index > 0 {
+            f.write_str(separator)?;
+        }
+        write!(f, "{}", item)?;
+    }
+    f.write_str("]")
+}
@@ -0,0 +1,223 @@
+//! groups.rs
+//!
+//! Grouping of matches, by their directory, extension, or owner, into a
+//! report of the number of matches and bytes in each group, along with the
+//! oldest and newest effective times within it, so that a report shows the
+//! range of activity in each group rather than just its size. Groups are
+//! spilled to temporary files beyond a limit on the memory they take (see
+//! spill.rs), so that grouping by directory over many millions of
+//! directories runs in bounded memory.
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{constants::BUFFER_BYTES, errors::AmbleError, filematch::FileMatch, owner::user_name, size::SizeMode,
+            spill::{optional_time, Combine, Spill, SpillMap}, timefmt::{TimeFormat, Zone}, traits::Sink};
+
+/// What matches are grouped by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    /// The directory containing the match
+    Dir,
+    /// The extension of the match, ignoring case
+    Ext,
+    /// The owner of the match (unix only)
+    Owner,
+}
+
+impl GroupBy {
+    /// The group the supplied match belongs to.
+    pub fn key(&self, found: &FileMatch) -> String {
+        match self {
+            GroupBy::Dir => found.path.parent().map_or_else(String::new, |dir| dir.display().to_string()),
+            GroupBy::Ext => found.path.extension()
+                                      .map_or_else(|| "(none)".to_string(),
+                                                   |ext| ext.to_string_lossy().to_lowercase()),
+            GroupBy::Owner => match found.owner {
+                Some(uid) => user_name(uid).unwrap_or_else(|| uid.to_string()),
+                None => "(unknown)".to_string(),
+            },
+        }
+    }
+}
+
+impl FromStr for GroupBy {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "dir" | "directory" => Ok(GroupBy::Dir),
+            "ext" | "extension" => Ok(GroupBy::Ext),
+            "owner" | "user" => Ok(GroupBy::Owner),
+            _ => Err(AmbleError::ParseError(format!("invalid grouping '{}'. Expected dir, ext, or owner", s))),
+        }
+    }
+}
+
+impl fmt::Display for GroupBy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            GroupBy::Dir => "dir",
+            GroupBy::Ext => "ext",
+            GroupBy::Owner => "owner",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The matches within a single group.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Group {
+    /// Number of matching files. A rolled up directory counts the files
+    /// beneath it
+    pub files: u64,
+    /// Total size of the matches, in bytes
+    pub bytes: u64,
+    /// The earliest effective time of any match
+    #[serde(with = "optional_time")]
+    pub oldest: Option<SystemTime>,
+    /// The latest effective time of any match
+    #[serde(with = "optional_time")]
+    pub newest: Option<SystemTime>,
+}
+
+impl Group {
+    // A group of the single match, of the supplied size.
+    fn of(found: &FileMatch, size: u64) -> Self {
+        let time = found.effective_time();
+        Self { files: found.contents.unwrap_or(1), bytes: size, oldest: time, newest: time }
+    }
+}
+
+impl Combine for Group {
+    fn combine(&mut self, other: Self) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+        self.oldest = match (self.oldest, other.oldest) {
+            (Some(oldest), Some(other)) => Some(oldest.min(other)),
+            (oldest, other) => oldest.or(other),
+        };
+        self.newest = self.newest.max(other.newest);
+    }
+}
+
+impl Spill for Group {
+    fn footprint(&self) -> usize {
+        mem::size_of::<Self>()
+    }
+}
+
+/// Sink which groups matches, printing a line for each group, ordered by
+/// group, once the search completes. With `json`, each group is printed
+/// as a JSON object, with timestamps in the supplied format. Groups
+/// beyond the buffer limit are spilled to temporary files (see spill.rs).
+#[derive(Debug)]
+pub struct GroupReport {
+    group_by: GroupBy,
+    groups: SpillMap<String, Group>,
+    buffer_limit: u64,
+    // owner names, by user id, as looking them up is comparatively slow
+    names: HashMap<u32, String>,
+    size_mode: SizeMode,
+    json: bool,
+    time_format: TimeFormat,
+    zone: Zone,
+}
+
+impl GroupReport {
+    /// New up an empty GroupReport, grouping matches as supplied.
+    pub fn new(group_by: GroupBy) -> Self {
+        Self {
+            group_by,
+            groups: SpillMap::new(BUFFER_BYTES),
+            buffer_limit: BUFFER_BYTES,
+            names: HashMap::new(),
+            size_mode: SizeMode::Apparent,
+            json: false,
+            time_format: TimeFormat::Rfc3339,
+            zone: Zone::Local,
+        }
+    }
+
+    /// Set how file sizes are measured when totalling bytes.
+    pub fn size_mode(&mut self, size_mode: SizeMode) -> &mut Self {
+        self.size_mode = size_mode;
+        self
+    }
+
+    /// Print each group as a JSON object, with timestamps in the supplied
+    /// format, rather than as text.
+    pub fn json(&mut self, time_format: TimeFormat) -> &mut Self {
+        self.json = true;
+        self.time_format = time_format;
+        self
+    }
+
+    /// Set the number of bytes of groups which may be held in memory,
+    /// beyond which they are spilled to temporary files. The default is
+    /// BUFFER_BYTES.
+    pub fn buffer_limit(&mut self, limit: u64) -> &mut Self {
+        self.groups = SpillMap::new(limit);
+        self.buffer_limit = limit;
+        self
+    }
+
+    /// Render timestamps in the supplied zone, rather than the local
+    /// timezone.
+    pub fn zone(&mut self, zone: Zone) -> &mut Self {
+        self.zone = zone;
+        self
+    }
+
+    /// The group with the supplied key, if any matches fell into it and it
+    /// has not been spilled to disk.
+    pub fn group(&self, key: &str) -> Option<&Group> {
+        self.groups.get(&key.to_string())
+    }
+}
+
+impl Sink for GroupReport {
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        let size = found.size_for(self.size_mode);
+        let key = match (self.group_by, found.owner) {
+            (GroupBy::Owner, Some(uid)) => {
+                self.names.entry(uid).or_insert_with(|| user_name(uid).unwrap_or_else(|| uid.to_string())).clone()
+            },
+            _ => self.group_by.key(&found),
+        };
+        self.groups.add(key, Group::of(&found, size))
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        let groups = mem::replace(&mut self.groups, SpillMap::new(self.buffer_limit)).aggregated()?;
+        if self.json {
+            let time = |time: Option<SystemTime>| time.map(|time| self.time_format.format(time, self.zone));
+            for group in groups {
+                let (key, group) = group?;
+                let object = json!({
+                    "group": key,
+                    "by": self.group_by.to_string(),
+                    "files": group.files,
+                    "bytes": group.bytes,
+                    "oldest": time(group.oldest),
+                    "newest": time(group.newest),
+                });
+                println!("{}", object);
+            }
+            return Ok(());
+        }
+        let time = |time: Option<SystemTime>| time.map_or_else(|| "-".to_string(), |time| self.zone.rfc3339(time));
+        println!("{:<25} {:<25} {:>12} {:>16}  {}", "oldest", "newest", "files", "bytes", self.group_by);
+        for group in groups {
+            let (key, group) = group?;
+            println!("{:<25} {:<25} {:>12} {:>16}  {}", time(group.oldest), time(group.newest), group.files,
+                     group.bytes, key);
+        }
+        Ok(())
+    }
+}
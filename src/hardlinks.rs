@@ -0,0 +1,175 @@
+//! hardlinks.rs
+//!
+//! Deduplication of files with multiple hard links. Without it, a file
+//! with several links is reported once per path, inflating size totals
+//! and risking double handling by downstream actions.
+use std::collections::{HashMap, HashSet};
+use std::fs::Metadata;
+use std::str::FromStr;
+
+use crate::{errors::AmbleError, fields::Availability, filematch::FileMatch, traits::Sink};
+
+/// The (device, inode) pair identifying the file described by the
+/// supplied metadata. Only available on unix.
+pub fn inode(metadata: &Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some((metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// The number of hard links to the file described by the supplied
+/// metadata. On platforms which do not report it, this is 1.
+pub fn nlink(metadata: &Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.nlink()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        1
+    }
+}
+
+/// The metadata of files with multiple hard links, by their (device,
+/// inode) pair, so that the metadata of each is read once, however many
+/// paths lead to it. Files with a single link are only met once, so they
+/// are not cached.
+#[derive(Debug, Default)]
+pub(crate) struct InodeCache {
+    metadata: HashMap<(u64, u64), Metadata>,
+}
+
+impl InodeCache {
+    /// The metadata of the file with the supplied (device, inode) pair, if
+    /// it has been read before.
+    pub(crate) fn get(&self, id: (u64, u64)) -> Option<Metadata> {
+        self.metadata.get(&id).cloned()
+    }
+
+    /// Record the metadata of a file, if it may be met again.
+    pub(crate) fn insert(&mut self, metadata: &Metadata) {
+        if let Some(id) = inode(metadata).filter(|_| nlink(metadata) > 1) {
+            self.metadata.insert(id, metadata.clone());
+        }
+    }
+}
+
+/// A constraint on the number of hard links to a file, parsed as in
+/// find(1): "N" for exactly N links, "+N" for more than N, and "-N" for
+/// fewer than N.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkCount {
+    Exactly(u64),
+    MoreThan(u64),
+    FewerThan(u64),
+}
+
+impl LinkCount {
+    /// Does the supplied number of links satisfy the constraint?
+    pub fn matches(self, nlink: u64) -> bool {
+        match self {
+            LinkCount::Exactly(count) => nlink == count,
+            LinkCount::MoreThan(count) => nlink > count,
+            LinkCount::FewerThan(count) => nlink < count,
+        }
+    }
+}
+
+impl FromStr for LinkCount {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AmbleError::ParseError(format!("invalid link count '{}'. Expected N, +N, or -N", s));
+        let s = s.trim();
+        let (constructor, count): (fn(u64) -> LinkCount, &str) = match s.chars().next() {
+            Some('+') => (LinkCount::MoreThan, &s[1..]),
+            Some('-') => (LinkCount::FewerThan, &s[1..]),
+            _ => (LinkCount::Exactly, s),
+        };
+        count.parse::<u64>().map(constructor).map_err(|_| invalid())
+    }
+}
+
+/// Sink adapter which passes each inode on to the wrapped sink only once,
+/// identifying inodes by their (device, inode) pair.
+///
+/// By default, the first path found for an inode is reported, and the
+/// others are dropped. When `all_paths` is set, matches with multiple
+/// links are held until the search finishes, and reported once with
+/// the other paths found recorded in `FileMatch::links`.
+pub struct DedupLinks<S: Sink> {
+    inner: S,
+    all_paths: bool,
+    seen: HashSet<(u64, u64)>,
+    held: HashMap<(u64, u64), FileMatch>,
+    order: Vec<(u64, u64)>,
+}
+
+impl<S: Sink> DedupLinks<S> {
+    /// New up a DedupLinks wrapping the supplied sink.
+    pub fn new(inner: S, all_paths: bool) -> Self {
+        Self {
+            inner,
+            all_paths,
+            seen: HashSet::new(),
+            held: HashMap::new(),
+            order: Vec::new(),
+        }
+    }
+
+    /// Consume the adapter, returning the wrapped sink.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Sink> Sink for DedupLinks<S> {
+    fn begin(&mut self, availability: &Availability) -> Result<(), AmbleError> {
+        self.inner.begin(availability)
+    }
+
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        let inode = match found.inode {
+            Some(inode) if found.nlink > 1 => inode,
+            _ => return self.inner.accept(found),
+        };
+
+        if self.all_paths {
+            match self.held.get_mut(&inode) {
+                Some(first) => first.links.push(found.path),
+                None => {
+                    self.order.push(inode);
+                    self.held.insert(inode, found);
+                }
+            }
+            return Ok(());
+        }
+
+        if self.seen.insert(inode) {
+            self.inner.accept(found)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        for inode in self.order.drain(..) {
+            if let Some(found) = self.held.remove(&inode) {
+                self.inner.accept(found)?;
+            }
+        }
+        self.inner.finish()
+    }
+
+    fn errors(&self) -> u64 {
+        self.inner.errors()
+    }
+}
@@ -0,0 +1,132 @@
+//! indicator.rs
+//!
+//! The progress indicator shown on stderr during a scan: a spinner reporting
+//! the directories traversed, the files evaluated, and the rate at which
+//! they are, the matches found, and the time elapsed. The counts are read
+//! from a ScanProgress, which the search updates as it runs, by a thread
+//! of the indicator's own, so that the search is not slowed by drawing it.
+//! Warnings printed by the search while it is shown are printed above it,
+//! via eprint_above.
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::{
+    constants::PROGRESS_REFRESH_MILLIS,
+    errors::AmbleError,
+    fields::Availability,
+    filematch::FileMatch,
+    stats::ScanProgress,
+    traits::Sink,
+};
+
+// The indicator being shown, if any, which warnings are printed above.
+static SHOWN: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Print a line to stderr, above the progress indicator, should one be
+/// shown, rather than over it.
+pub(crate) fn eprint_above(line: fmt::Arguments) {
+    match SHOWN.lock().ok().and_then(|shown| shown.clone()) {
+        Some(bar) => bar.suspend(|| eprintln!("{}", line)),
+        None => eprintln!("{}", line),
+    }
+}
+
+/// Shows the progress of a scan on stderr, until it is finished.
+pub struct Indicator {
+    bar: ProgressBar,
+    live: Arc<ScanProgress>,
+    updater: Option<JoinHandle<()>>,
+}
+
+impl Indicator {
+    /// Start showing the progress of a scan on stderr.
+    pub fn start() -> Self {
+        let bar = ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr());
+        bar.set_style(ProgressStyle::with_template("{spinner} [{elapsed_precise}] {msg}")
+                          .unwrap_or_else(|_| ProgressStyle::default_spinner()));
+        let live = Arc::new(ScanProgress::default());
+        let updater = {
+            let (bar, live) = (bar.clone(), Arc::clone(&live));
+            thread::spawn(move || {
+                let refresh = Duration::from_millis(PROGRESS_REFRESH_MILLIS);
+                while !bar.is_finished() {
+                    let secs = bar.elapsed().as_secs_f64();
+                    let rate = if secs > 0.0 { live.files() as f64 / secs } else { 0.0 };
+                    bar.set_message(format!("{} dirs, {} files ({:.0}/s), {} matches",
+                                            live.dirs(), live.files(), rate, live.matches()));
+                    bar.tick();
+                    thread::sleep(refresh);
+                }
+            })
+        };
+        if let Ok(mut shown) = SHOWN.lock() {
+            *shown = Some(bar.clone());
+        }
+        Self { bar, live, updater: Some(updater) }
+    }
+
+    /// The progress which the search should update.
+    pub fn progress(&self) -> Arc<ScanProgress> {
+        Arc::clone(&self.live)
+    }
+
+    /// Wrap a sink, so that what it writes is not drawn over.
+    pub fn around<S: Sink>(&self, sink: S) -> Suspend<S> {
+        Suspend { sink, bar: self.bar.clone() }
+    }
+
+    /// Stop showing the progress, clearing it from stderr.
+    pub fn finish(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Ok(mut shown) = SHOWN.lock() {
+            *shown = None;
+        }
+        self.bar.finish_and_clear();
+        if let Some(updater) = self.updater.take() {
+            // the indicator is cosmetic, so a panicked updater is ignored
+            let _ = updater.join();
+        }
+    }
+}
+
+impl Drop for Indicator {
+    // the indicator is cleared should the scan fail, as well
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Sink which hides the progress indicator while the sink it wraps handles
+/// each match, so that the lines it prints are not mixed up with it.
+pub struct Suspend<S> {
+    sink: S,
+    bar: ProgressBar,
+}
+
+impl<S: Sink> Sink for Suspend<S> {
+    fn begin(&mut self, availability: &Availability) -> Result<(), AmbleError> {
+        let sink = &mut self.sink;
+        self.bar.suspend(|| sink.begin(availability))
+    }
+
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        let sink = &mut self.sink;
+        self.bar.suspend(|| sink.accept(found))
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        let sink = &mut self.sink;
+        self.bar.suspend(|| sink.finish())
+    }
+
+    fn errors(&self) -> u64 {
+        self.sink.errors()
+    }
+}
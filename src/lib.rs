@@ -3,3 +3,50 @@ pub mod syncwalk;
 pub mod asyncwalk;
 pub mod errors;
 pub mod constants;
+pub mod filematch;
+pub mod classify;
+pub mod sinks;
+pub mod fields;
+pub mod skip;
+pub mod hardlinks;
+pub mod symlinks;
+pub mod mime;
+pub mod size;
+pub mod duration;
+pub mod roots;
+pub mod perms;
+pub mod owner;
+pub mod actions;
+pub mod manifest;
+pub mod stats;
+pub mod policy;
+pub mod space;
+pub mod undo;
+pub mod timefmt;
+pub mod activity;
+pub mod schedule;
+pub mod groups;
+pub mod state;
+pub mod backend;
+pub mod priority;
+pub mod spill;
+pub mod resume;
+pub mod config;
+pub mod daemon;
+pub mod indicator;
+#[cfg(feature = "archive")]
+pub mod archive;
+#[cfg(feature = "compress")]
+pub mod compress;
+#[cfg(all(feature = "profile", unix))]
+pub mod profile;
+#[cfg(feature = "watch")]
+pub mod watch;
+mod adaptive;
+mod criteria;
+mod mounts;
+mod prefetch;
+mod rollup;
+mod uring;
+#[cfg(test)]
+mod testing;
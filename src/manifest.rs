@@ -0,0 +1,296 @@
+//! manifest.rs
+//!
+//! Manifests support a two phase cleanup. A search is first planned,
+//! writing a manifest of its matches - with their sizes, modification
+//! times, and content hashes - which may be reviewed. The manifest is
+//! later applied, handing exactly the listed files to an action, but only
+//! if none of them has changed since it was planned.
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::{errors::AmbleError, fields::Availability, filematch::FileMatch, stats::ScanStats, traits::{Finder, Sink}};
+
+/// Version of the manifest format written by Plan
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// The matches of a planned search.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Version of the manifest format
+    pub version: u32,
+    /// The roots which were searched
+    pub roots: Vec<PathBuf>,
+    /// The matches, in the order in which they were found
+    pub entries: Vec<Entry>,
+}
+
+impl Manifest {
+    /// Read a manifest, refusing versions which are not understood.
+    pub fn read(reader: impl Read) -> Result<Self, AmbleError> {
+        let manifest: Manifest = serde_json::from_reader(reader)?;
+        if manifest.version != MANIFEST_VERSION {
+            return Err(AmbleError::ParseError(format!("unsupported manifest version {}", manifest.version)));
+        }
+        Ok(manifest)
+    }
+
+    /// Write the manifest as JSON.
+    pub fn write(&self, writer: impl Write) -> Result<(), AmbleError> {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+}
+
+/// A single match recorded in a manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Path to the match
+    pub path: PathBuf,
+    /// Apparent size in bytes. For a rolled up directory, the total size
+    /// of the files beneath it
+    pub size: u64,
+    /// Modification time, in seconds since the epoch. For a rolled up
+    /// directory, the latest modification time of anything beneath it
+    pub mtime: i64,
+    /// Nanoseconds part of the modification time
+    pub mtime_nsec: u32,
+    /// SHA-256 of the contents of a regular file, in hex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// When a directory has been rolled up, the number of matching files
+    /// beneath it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contents: Option<u64>,
+}
+
+impl Entry {
+    /// Record the current state of the supplied match.
+    pub fn from_match(found: &FileMatch) -> Result<Self, AmbleError> {
+        let metadata = fs::symlink_metadata(&found.path)?;
+        let (size, modified) = state(&found.path, &metadata)?;
+        let (mtime, mtime_nsec) = timestamp(modified);
+        let sha256 = if metadata.is_file() { Some(sha256(&found.path)?) } else { None };
+        Ok(Self { path: found.path.clone(), size, mtime, mtime_nsec, sha256, contents: found.contents })
+    }
+
+    /// The match for the entry, provided that its size, modification time,
+    /// and, where recorded, contents are unchanged since it was planned.
+    pub fn check(&self) -> Result<FileMatch, AmbleError> {
+        let metadata = fs::symlink_metadata(&self.path)?;
+        let (size, modified) = state(&self.path, &metadata)?;
+        if timestamp(modified) != (self.mtime, self.mtime_nsec) {
+            return Err(AmbleError::UnexpectedResult("modified since it was planned".to_string()));
+        }
+        if size != self.size {
+            return Err(AmbleError::UnexpectedResult("size changed since it was planned".to_string()));
+        }
+        // files may be rewritten in place without changing either
+        if let Some(planned) = &self.sha256 {
+            if !metadata.is_file() || sha256(&self.path)? != *planned {
+                return Err(AmbleError::UnexpectedResult("contents changed since it was planned".to_string()));
+            }
+        }
+        let mut found = FileMatch::from_metadata(&self.path, &metadata);
+        found.size = self.size;
+        found.contents = self.contents;
+        Ok(found)
+    }
+}
+
+// The size and modification time of a path. Directories are walked,
+// so that a change to anything beneath them is noticed.
+fn state(path: &Path, metadata: &Metadata) -> Result<(u64, SystemTime), AmbleError> {
+    if !metadata.is_dir() {
+        return Ok((metadata.len(), metadata.modified()?));
+    }
+    let mut size = 0;
+    let mut modified = metadata.modified()?;
+    for entry in WalkDir::new(path).min_depth(1) {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+        modified = modified.max(metadata.modified()?);
+    }
+    Ok((size, modified))
+}
+
+// Seconds and nanoseconds since the epoch, which may be negative.
+fn timestamp(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+        Err(e) => {
+            let before = e.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        },
+    }
+}
+
+/// The SHA-256 of the contents of a file, in hex.
+pub(crate) fn sha256(path: &Path) -> Result<String, AmbleError> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Sink which plans a search, printing each match and writing a manifest
+/// of them once the search finishes. Matches which cannot be recorded are
+/// reported to stderr, and counted as errors.
+#[derive(Debug)]
+pub struct Plan {
+    dest: PathBuf,
+    manifest: Manifest,
+    bytes: u64,
+    errors: u64,
+}
+
+impl Plan {
+    /// New up a Plan writing its manifest to dest, for a search of the
+    /// supplied roots.
+    pub fn new(dest: impl Into<PathBuf>, roots: Vec<PathBuf>) -> Self {
+        Self { dest: dest.into(), manifest: Manifest { version: MANIFEST_VERSION, roots, entries: Vec::new() }, bytes: 0,
+               errors: 0 }
+    }
+}
+
+impl Sink for Plan {
+    fn begin(&mut self, availability: &Availability) -> Result<(), AmbleError> {
+        if availability.is_degraded() {
+            println!("# fields: {}", availability);
+        }
+        Ok(())
+    }
+
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        match Entry::from_match(&found) {
+            Ok(entry) => {
+                println!("{}", found);
+                self.bytes += entry.size;
+                self.manifest.entries.push(entry);
+            },
+            Err(e) => {
+                eprintln!("Error: unable to plan '{}': {}", found.path.display(), e);
+                self.errors += 1;
+            },
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        let file = File::create(&self.dest).map_err(|e| {
+            AmbleError::IoError(format!("unable to write manifest '{}': {}", self.dest.display(), e))
+        })?;
+        let mut writer = BufWriter::new(file);
+        self.manifest.write(&mut writer)?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        println!("# plan: {} files ({} bytes) written to {}",
+                 self.manifest.entries.len(), self.bytes, self.dest.display());
+        Ok(())
+    }
+
+    fn errors(&self) -> u64 {
+        self.errors
+    }
+}
+
+/// Finder which hands the entries of a manifest to a sink, in order.
+/// Entries which have changed since they were planned, or which no longer
+/// exist, are reported to stderr and skipped, and counted as errors.
+#[derive(Debug)]
+pub struct ManifestSearch {
+    manifest: Manifest,
+}
+
+impl ManifestSearch {
+    /// New up a ManifestSearch for the supplied manifest.
+    pub fn new(manifest: Manifest) -> Self {
+        Self { manifest }
+    }
+}
+
+impl Finder for ManifestSearch {
+    type ReturnType = ScanStats;
+
+    fn find_into(&self, sink: &mut dyn Sink) -> Result<ScanStats, AmbleError> {
+        sink.begin(&Availability::default())?;
+        let mut stats = ScanStats::default();
+        for entry in &self.manifest.entries {
+            match entry.check() {
+                Ok(found) => sink.accept(found)?,
+                Err(e) => {
+                    eprintln!("Error: refusing '{}': {}", entry.path.display(), e);
+                    stats.errors += 1;
+                },
+            }
+        }
+        sink.finish()?;
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{actions::{Apply, Delete}, testing::{age, TempTree}};
+
+    // Plan the supplied files, returning the manifest written.
+    fn plan(tree: &TempTree, files: &[&Path]) -> Manifest {
+        let dest = tree.path().join("plan.json");
+        let mut plan = Plan::new(&dest, vec![tree.path().to_path_buf()]);
+        for file in files {
+            plan.accept(FileMatch::new(*file, 0)).unwrap();
+        }
+        plan.finish().unwrap();
+        Manifest::read(File::open(dest).unwrap()).unwrap()
+    }
+
+    // Delete the entries of the manifest, returning the stats of the search.
+    fn apply(manifest: Manifest) -> ScanStats {
+        let mut apply = Apply::new(Delete);
+        apply.dry_run(false);
+        ManifestSearch::new(manifest).find_into(&mut apply).unwrap()
+    }
+
+    #[test]
+    fn apply_deletes_exactly_the_planned_files() {
+        let tree = TempTree::new("manifest-apply");
+        let planned = tree.file("a", b"a");
+        let unplanned = tree.file("b", b"b");
+        let stats = apply(plan(&tree, &[&planned]));
+        assert_eq!(stats.errors, 0);
+        assert!(!planned.exists());
+        assert!(unplanned.exists());
+    }
+
+    #[test]
+    fn apply_refuses_files_modified_since_planned() {
+        let tree = TempTree::new("manifest-modified");
+        let path = tree.file("a", b"a");
+        let manifest = plan(&tree, &[&path]);
+        age(&path, 1);
+        assert_eq!(apply(manifest).errors, 1);
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn apply_refuses_files_rewritten_in_place() {
+        let tree = TempTree::new("manifest-rewritten");
+        let path = tree.file("a", b"a");
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let manifest = plan(&tree, &[&path]);
+        // the same size and modification time, but different contents
+        fs::write(&path, b"b").unwrap();
+        File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        assert_eq!(apply(manifest).errors, 1);
+        assert_eq!(fs::read(&path).unwrap(), b"b");
+    }
+}
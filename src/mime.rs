@@ -0,0 +1,32 @@
+//! mime.rs
+//!
+//! Content sniffing, used to filter matches by MIME type regardless of
+//! their extension. Sniffing requires the `mime` feature, which pulls in
+//! the infer crate to recognize file types from their magic numbers.
+#[cfg(feature = "mime")]
+use std::path::Path;
+
+#[cfg(feature = "mime")]
+use crate::errors::AmbleError;
+
+/// Does the MIME type match the supplied pattern? Patterns are either a
+/// full type, such as "video/mp4", or a wildcard subtype, such as "video/*".
+/// Comparison ignores case.
+pub fn mime_matches(mime: &str, pattern: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(top) => mime.split('/').next().is_some_and(|t| t.eq_ignore_ascii_case(top)),
+        None => mime.eq_ignore_ascii_case(pattern),
+    }
+}
+
+/// Sniff the type of the file at the supplied path, and report whether or
+/// not it matches one of the supplied patterns. Files whose type cannot be
+/// recognized match none of the patterns.
+#[cfg(feature = "mime")]
+pub(crate) fn sniff_matches(path: &Path, patterns: &[String]) -> Result<bool, AmbleError> {
+    let kind = match infer::get_from_path(path)? {
+        Some(kind) => kind,
+        None => return Ok(false),
+    };
+    Ok(patterns.iter().any(|pattern| mime_matches(kind.mime_type(), pattern)))
+}
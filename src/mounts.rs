@@ -0,0 +1,144 @@
+//! mounts.rs
+//!
+//! Per mount concurrency limits. A scan of a root with several mounts
+//! beneath it, such as a project tree spread over NFS filers, may work
+//! with many threads at once while capping the reads of metadata in flight
+//! on any one mount, so that no single filer is overloaded.
+//!
+//! The mounts are read from the mount table once, as the search starts
+//! (Linux only), and each is located beneath the roots, as they were
+//! supplied, so that the mount of an entry is found by comparing its path
+//! with a few prefixes, rather than by resolving it. Entries reached by
+//! following a symlink onto another mount count against the mount of the
+//! path they were reached by. On other platforms, each root is taken to be
+//! a mount of its own.
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+/// Caps the reads in flight at once on each mount. Without a cap, reads
+/// are never held back.
+#[derive(Debug)]
+pub(crate) struct MountLimits {
+    limit: Option<usize>,
+    // the mounts beneath the roots, as prefixes of the paths searched,
+    // with their index in `in_flight`, the deepest first
+    prefixes: Vec<(PathBuf, usize)>,
+    in_flight: Mutex<Vec<usize>>,
+    freed: Condvar,
+}
+
+/// A permit to read on a mount, which is returned when dropped.
+pub(crate) struct MountPermit<'a> {
+    held: Option<(&'a MountLimits, usize)>,
+}
+
+impl Drop for MountPermit<'_> {
+    fn drop(&mut self) {
+        if let Some((limits, mount)) = self.held {
+            limits.lock()[mount] -= 1;
+            limits.freed.notify_all();
+        }
+    }
+}
+
+impl MountLimits {
+    /// New up MountLimits capping the reads in flight on each mount beneath
+    /// the supplied roots, if there is a limit.
+    pub(crate) fn new(roots: &[PathBuf], limit: Option<usize>) -> Self {
+        let limit = limit.map(|limit| limit.max(1));
+        // where mounts are stacked on the same point, the last is in effect
+        let mut seen = HashSet::new();
+        let points = match limit {
+            Some(_) => mount_points().into_iter().rev().filter(|point| seen.insert(point.clone())).collect(),
+            None => Vec::new(),
+        };
+        let mut mounts = points.len();
+        let mut prefixes = Vec::new();
+        for root in roots.iter().filter(|_| limit.is_some()) {
+            let canonical = fs::canonicalize(root).unwrap_or_else(|_| root.clone());
+            // the mount holding the root, then those beneath it
+            let holding = points.iter().enumerate().filter(|(_, point)| canonical.starts_with(point))
+                                .max_by_key(|(_, point)| point.components().count());
+            match holding {
+                Some((mount, _)) => prefixes.push((root.clone(), mount)),
+                None => {
+                    prefixes.push((root.clone(), mounts));
+                    mounts += 1;
+                },
+            }
+            for (mount, point) in points.iter().enumerate() {
+                match point.strip_prefix(&canonical) {
+                    Ok(rest) if !rest.as_os_str().is_empty() => prefixes.push((root.join(rest), mount)),
+                    _ => (),
+                }
+            }
+        }
+        prefixes.sort_by_key(|(prefix, _)| Reverse(prefix.components().count()));
+        Self { limit, prefixes, in_flight: Mutex::new(vec![0; mounts]), freed: Condvar::new() }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<usize>> {
+        self.in_flight.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Wait for a permit to read the entry at the supplied path.
+    pub(crate) fn acquire(&self, path: &Path) -> MountPermit<'_> {
+        let (limit, mount) = match (self.limit, self.prefixes.iter().find(|(prefix, _)| path.starts_with(prefix))) {
+            (Some(limit), Some((_, mount))) => (limit, *mount),
+            _ => return MountPermit { held: None },
+        };
+        let mut in_flight = self.lock();
+        while in_flight[mount] >= limit {
+            in_flight = self.freed.wait(in_flight).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        in_flight[mount] += 1;
+        MountPermit { held: Some((self, mount)) }
+    }
+}
+
+// The mount points of the system, from the mount table.
+#[cfg(target_os = "linux")]
+fn mount_points() -> Vec<PathBuf> {
+    // the fifth field of each line is the mount point, with spaces and the
+    // like escaped in octal
+    fs::read_to_string("/proc/self/mountinfo").unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(unescape)
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mount_points() -> Vec<PathBuf> {
+    Vec::new()
+}
+
+// Undo the octal escapes (eg \040 for a space) of a mount point.
+#[cfg(target_os = "linux")]
+fn unescape(point: &str) -> PathBuf {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    let bytes = point.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let octal = bytes.get(index + 1..index + 4)
+                         .and_then(|digits| std::str::from_utf8(digits).ok())
+                         .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match (bytes[index], octal) {
+            (b'\\', Some(byte)) => {
+                unescaped.push(byte);
+                index += 4;
+            },
+            (byte, _) => {
+                unescaped.push(byte);
+                index += 1;
+            },
+        }
+    }
+    PathBuf::from(OsString::from_vec(unescaped))
+}
@@ -0,0 +1,173 @@
+//! owner.rs
+//!
+//! File ownership. Owners are identified by their numeric user id, which
+//! is only available on unix.
+use std::fmt;
+use std::fs::Metadata;
+use std::str::FromStr;
+
+use crate::errors::AmbleError;
+
+/// The user id of the owner of the file described by the supplied
+/// metadata, if available.
+pub fn file_owner(metadata: &Metadata) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some(metadata.uid())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// The effective user id of the running process, if available.
+pub fn effective_uid() -> Option<u32> {
+    #[cfg(unix)]
+    {
+        // geteuid cannot fail
+        Some(unsafe { libc::geteuid() })
+    }
+    #[cfg(not(unix))]
+    {
+        None
+    }
+}
+
+/// Look up the user id for the supplied user name.
+#[cfg(unix)]
+pub fn user_id(name: &str) -> Option<u32> {
+    use std::ffi::CString;
+    use std::ptr;
+
+    let name = CString::new(name).ok()?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = ptr::null_mut();
+    let rc = unsafe {
+        libc::getpwnam_r(name.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result)
+    };
+    if rc == 0 && !result.is_null() {
+        Some(passwd.pw_uid)
+    } else {
+        None
+    }
+}
+
+/// User names cannot be resolved off unix.
+#[cfg(not(unix))]
+pub fn user_id(_name: &str) -> Option<u32> {
+    None
+}
+
+/// Look up the name of the user with the supplied user id.
+#[cfg(unix)]
+pub fn user_name(uid: u32) -> Option<String> {
+    use std::ffi::CStr;
+    use std::ptr;
+
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = ptr::null_mut();
+    let rc = unsafe { libc::getpwuid_r(uid, &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+    if rc == 0 && !result.is_null() {
+        Some(unsafe { CStr::from_ptr(passwd.pw_name) }.to_string_lossy().into_owned())
+    } else {
+        None
+    }
+}
+
+/// User names cannot be resolved off unix.
+#[cfg(not(unix))]
+pub fn user_name(_uid: u32) -> Option<String> {
+    None
+}
+
+/// Look up the group id for the supplied group name.
+#[cfg(unix)]
+pub fn group_id(name: &str) -> Option<u32> {
+    use std::ffi::CString;
+    use std::ptr;
+
+    let name = CString::new(name).ok()?;
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = ptr::null_mut();
+    let rc = unsafe {
+        libc::getgrnam_r(name.as_ptr(), &mut group, buffer.as_mut_ptr(), buffer.len(), &mut result)
+    };
+    if rc == 0 && !result.is_null() {
+        Some(group.gr_gid)
+    } else {
+        None
+    }
+}
+
+/// Group names cannot be resolved off unix.
+#[cfg(not(unix))]
+pub fn group_id(_name: &str) -> Option<u32> {
+    None
+}
+
+/// The owner of a file, parsed from a user name or numeric user id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Owner(pub u32);
+
+impl FromStr for Owner {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        s.parse::<u32>()
+         .ok()
+         .or_else(|| user_id(s))
+         .map(Owner)
+         .ok_or_else(|| AmbleError::ParseError(format!("unknown user '{}'", s)))
+    }
+}
+
+/// A change of ownership, parsed from `USER`, `USER:GROUP`, or `:GROUP`,
+/// where the user and group are names or numeric ids.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ownership {
+    spec: String,
+    /// The new owner, if it changes
+    pub user: Option<u32>,
+    /// The new group, if it changes
+    pub group: Option<u32>,
+}
+
+impl fmt::Display for Ownership {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.spec)
+    }
+}
+
+impl FromStr for Ownership {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let spec = s.trim();
+        let (user, group) = match spec.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (spec, None),
+        };
+        let user = match user {
+            "" => None,
+            user => Some(user.parse::<Owner>()?.0),
+        };
+        let group = match group {
+            None | Some("") => None,
+            Some(group) => Some(group.parse::<u32>()
+                                      .ok()
+                                      .or_else(|| group_id(group))
+                                      .ok_or_else(|| AmbleError::ParseError(format!("unknown group '{}'", group)))?),
+        };
+        if user.is_none() && group.is_none() {
+            return Err(AmbleError::ParseError(format!("invalid owner '{}'", spec)));
+        }
+        Ok(Ownership { spec: spec.to_string(), user, group })
+    }
+}
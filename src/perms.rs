@@ -0,0 +1,189 @@
+//! perms.rs
+//!
+//! Helpers for examining file permissions, including the security
+//! relevant mode bits which may be flagged on matches.
+use std::fmt;
+use std::fs::Metadata;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::AmbleError;
+
+/// Does the file have any of its execute bits (user, group, or other) set?
+#[cfg(unix)]
+pub fn is_executable(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+/// Execute bits are a unix concept, so no file is executable elsewhere.
+#[cfg(not(unix))]
+pub fn is_executable(_metadata: &Metadata) -> bool {
+    false
+}
+
+/// A security relevant mode bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModeFlag {
+    Setuid,
+    Setgid,
+    Sticky,
+    WorldWritable,
+}
+
+impl ModeFlag {
+    /// Every flag, in the order in which they are reported.
+    pub const ALL: [ModeFlag; 4] = [ModeFlag::Setuid, ModeFlag::Setgid, ModeFlag::Sticky, ModeFlag::WorldWritable];
+
+    /// Name of the flag, as reported to the user.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ModeFlag::Setuid => "setuid",
+            ModeFlag::Setgid => "setgid",
+            ModeFlag::Sticky => "sticky",
+            ModeFlag::WorldWritable => "world-writable",
+        }
+    }
+
+    // the mode bit for the flag
+    fn bit(self) -> u32 {
+        match self {
+            ModeFlag::Setuid => 0o4000,
+            ModeFlag::Setgid => 0o2000,
+            ModeFlag::Sticky => 0o1000,
+            ModeFlag::WorldWritable => 0o002,
+        }
+    }
+}
+
+impl fmt::Display for ModeFlag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The security relevant mode bits set on the file described by the
+/// supplied metadata. Mode bits are a unix concept, so elsewhere there
+/// are none.
+pub fn mode_flags(metadata: &Metadata) -> Vec<ModeFlag> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = metadata.permissions().mode();
+        ModeFlag::ALL.iter().copied().filter(|flag| mode & flag.bit() != 0).collect()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        Vec::new()
+    }
+}
+
+/// A permission change, as accepted by chmod(1): either an octal mode, or
+/// a comma separated list of symbolic clauses such as `go-w` or `a=rX`.
+/// Symbolic clauses without a class apply to all classes, regardless of
+/// the umask.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mode {
+    spec: String,
+    change: ModeChange,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ModeChange {
+    Octal(u32),
+    Symbolic(Vec<Clause>),
+}
+
+// A symbolic clause, such as `go-w`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Clause {
+    // the bits which the classes (u, g, o) may change
+    classes: u32,
+    op: char,
+    perms: String,
+}
+
+impl Mode {
+    /// The mode resulting from applying the change to the supplied mode,
+    /// for a directory if `is_dir` is set.
+    pub fn apply(&self, mode: u32, is_dir: bool) -> u32 {
+        let mut mode = mode & 0o7777;
+        match &self.change {
+            ModeChange::Octal(octal) => *octal,
+            ModeChange::Symbolic(clauses) => {
+                for clause in clauses {
+                    let mut bits = 0;
+                    for perm in clause.perms.chars() {
+                        bits |= match perm {
+                            'r' => 0o444,
+                            'w' => 0o222,
+                            'x' => 0o111,
+                            'X' if is_dir || mode & 0o111 != 0 => 0o111,
+                            's' => 0o6000,
+                            't' => 0o1000,
+                            _ => 0,
+                        };
+                    }
+                    bits &= clause.classes;
+                    mode = match clause.op {
+                        '+' => mode | bits,
+                        '-' => mode & !bits,
+                        _ => (mode & !clause.classes) | bits,
+                    };
+                }
+                mode
+            },
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.spec)
+    }
+}
+
+impl FromStr for Mode {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let spec = s.trim();
+        let invalid = || AmbleError::ParseError(format!("invalid mode '{}'", spec));
+        if !spec.is_empty() && spec.chars().all(|c| c.is_digit(8)) {
+            let octal = u32::from_str_radix(spec, 8).map_err(|_| invalid())?;
+            if octal > 0o7777 {
+                return Err(invalid());
+            }
+            return Ok(Mode { spec: spec.to_string(), change: ModeChange::Octal(octal) });
+        }
+
+        let mut clauses = Vec::new();
+        for clause in spec.split(',') {
+            let op_at = clause.find(|c| "+-=".contains(c)).ok_or_else(invalid)?;
+            let (who, rest) = clause.split_at(op_at);
+            let mut classes = 0;
+            for class in who.chars() {
+                classes |= match class {
+                    'u' => 0o4700,
+                    'g' => 0o2070,
+                    'o' => 0o1007,
+                    'a' => 0o7777,
+                    _ => return Err(invalid()),
+                };
+            }
+            if classes == 0 {
+                classes = 0o7777;
+            }
+            let mut rest = rest.chars();
+            let op = rest.next().ok_or_else(invalid)?;
+            let perms: String = rest.collect();
+            if !perms.chars().all(|c| "rwxXst".contains(c)) {
+                return Err(invalid());
+            }
+            clauses.push(Clause { classes, op, perms });
+        }
+        Ok(Mode { spec: spec.to_string(), change: ModeChange::Symbolic(clauses) })
+    }
+}
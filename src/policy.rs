@@ -0,0 +1,153 @@
+//! policy.rs
+//!
+//! Tiered policies, which apply a different action to files depending
+//! upon how long they have been idle, in a single traversal. A policy
+//! file lists one tier per line, as the number of days a file must have
+//! been idle to fall into the tier (or a duration, such as 6m), an action,
+//! and its argument, if any:
+//!
+//! ```text
+//! # days  action    argument
+//! 30      report
+//! 90      compress  gzip
+//! 1y      move-to   /archive
+//! ```
+//!
+//! A file is idle from its effective time, the latest of its access,
+//! modification, and creation times, and falls into the tier with the
+//! largest number of days it has been idle for. Files which have not been
+//! idle long enough for any tier are left alone.
+use std::io::{BufRead, BufReader, Read};
+use std::time::SystemTime;
+
+use crate::{constants::SECS_PER_DAY, duration::parse_days, errors::AmbleError, fields::Availability, filematch::FileMatch, traits::Sink};
+
+/// The actions which may appear in a policy, along with whether or not
+/// they take an argument: Some(true) if they require one, Some(false) if
+/// it is optional, and None if they take none.
+pub const POLICY_ACTIONS: [(&str, Option<bool>); 10] = [
+    ("report", None),
+    ("delete", None),
+    ("truncate", None),
+    ("trash", None),
+    ("compress", Some(false)),
+    ("move-to", Some(true)),
+    ("stub-to", Some(true)),
+    ("archive", Some(true)),
+    ("chmod", Some(true)),
+    ("chown", Some(true)),
+];
+
+/// A single tier of a policy, as read from a policy file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    /// Number of days a file must have been idle to fall into the tier
+    pub days: f32,
+    /// Name of the action, one of POLICY_ACTIONS
+    pub action: String,
+    /// Argument to the action, such as a destination
+    pub argument: Option<String>,
+}
+
+/// Read the rules of a policy, one per line. Blank lines, and lines
+/// starting with a '#', are ignored.
+pub fn read_policy(reader: impl Read) -> Result<Vec<Rule>, AmbleError> {
+    let mut rules = Vec::new();
+    for (number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason: &str| AmbleError::ParseError(format!("policy line {}: {}", number + 1, reason));
+        let mut fields = line.splitn(3, char::is_whitespace).map(str::trim).filter(|field| !field.is_empty());
+        let days = fields.next()
+                         .and_then(|days| parse_days(days).ok())
+                         .filter(|days| days.is_finite() && *days >= 0.0)
+                         .ok_or_else(|| invalid("expected a number of days"))?;
+        let action = fields.next().ok_or_else(|| invalid("expected an action"))?.to_string();
+        let argument = fields.next().map(str::to_string);
+        match POLICY_ACTIONS.iter().find(|(name, _)| *name == action) {
+            None => return Err(invalid(&format!("unknown action '{}'", action))),
+            Some((_, Some(true))) if argument.is_none() => {
+                return Err(invalid(&format!("{} requires an argument", action)))
+            },
+            Some((_, None)) if argument.is_some() => {
+                return Err(invalid(&format!("{} takes no argument", action)))
+            },
+            _ => (),
+        }
+        if rules.iter().any(|rule: &Rule| rule.days == days) {
+            return Err(invalid(&format!("a tier for {} days already exists", days)));
+        }
+        rules.push(Rule { days, action, argument });
+    }
+    Ok(rules)
+}
+
+/// Sink which hands each file on to the sink for the tier it falls into,
+/// such as an Apply sink for the tier's action. The search feeding it
+/// should match every file (see `every` on the searches).
+pub struct Policy {
+    // (days, sink) for each tier, from the longest idle to the shortest
+    tiers: Vec<(f32, Box<dyn Sink>)>,
+    now: SystemTime,
+}
+
+impl Policy {
+    /// New up a Policy with no tiers.
+    pub fn new() -> Self {
+        Self { tiers: Vec::new(), now: SystemTime::now() }
+    }
+
+    /// Add a tier, for files which have been idle for at least `days`.
+    pub fn tier(&mut self, days: f32, sink: Box<dyn Sink>) -> &mut Self {
+        self.tiers.push((days, sink));
+        self.tiers.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        self
+    }
+
+    /// Compute idle times relative to the supplied instant, rather than
+    /// when the Policy was created.
+    pub fn as_of(&mut self, as_of: SystemTime) -> &mut Self {
+        self.now = as_of;
+        self
+    }
+
+    // Number of days the file has been idle, if its times are available.
+    fn idle_days(&self, found: &FileMatch) -> Option<f64> {
+        let idle = found.effective_age(self.now)?;
+        Some(idle.as_secs_f64() / SECS_PER_DAY as f64)
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sink for Policy {
+    fn begin(&mut self, availability: &Availability) -> Result<(), AmbleError> {
+        self.tiers.iter_mut().try_for_each(|(_, sink)| sink.begin(availability))
+    }
+
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        let idle = match self.idle_days(&found) {
+            Some(idle) => idle,
+            None => return Ok(()),
+        };
+        match self.tiers.iter_mut().find(|(days, _)| idle >= f64::from(*days)) {
+            Some((_, sink)) => sink.accept(found),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        self.tiers.iter_mut().rev().try_for_each(|(_, sink)| sink.finish())
+    }
+
+    fn errors(&self) -> u64 {
+        self.tiers.iter().map(|(_, sink)| sink.errors()).sum()
+    }
+}
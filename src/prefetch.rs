@@ -0,0 +1,127 @@
+//! prefetch.rs
+//!
+//! Metadata prefetching for the sync search. On high latency storage, such
+//! as NFS, the sync search spends most of its time waiting on each stat in
+//! turn. With prefetching, as each directory is read, the metadata of its
+//! entries is read ahead of the search on a small pool of threads, a
+//! bounded number of entries ahead, so that many stats are in flight at
+//! once. The search still evaluates the entries in order, taking each
+//! one's metadata from the pool as it reaches it, so its output is in the
+//! same order as without prefetching.
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crossbeam_channel as channel;
+
+use crate::{constants::PREFETCH_AHEAD, mounts::MountLimits};
+
+// The metadata of an entry to read, and where to send it.
+struct Job {
+    index: usize,
+    path: PathBuf,
+    done: channel::Sender<(usize, io::Result<Metadata>)>,
+}
+
+/// A pool of threads reading metadata ahead of the sync search.
+pub(crate) struct MetadataPool {
+    jobs: Option<channel::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    ahead: usize,
+}
+
+impl MetadataPool {
+    /// New up a pool of the supplied number of threads, whose reads are
+    /// capped on each mount by the supplied limits.
+    pub(crate) fn new(threads: usize, mounts: Arc<MountLimits>) -> Self {
+        let threads = threads.max(1);
+        let (jobs, queue) = channel::unbounded::<Job>();
+        let workers = (0..threads).map(|_| {
+            let queue = queue.clone();
+            let mounts = Arc::clone(&mounts);
+            thread::spawn(move || {
+                for job in queue {
+                    let metadata = {
+                        let _permit = mounts.acquire(&job.path);
+                        fs::symlink_metadata(&job.path)
+                    };
+                    // the directory may have been abandoned
+                    let _ = job.done.send((job.index, metadata));
+                }
+            })
+        }).collect();
+        Self { jobs: Some(jobs), workers, ahead: threads * PREFETCH_AHEAD }
+    }
+
+    /// Start reading the metadata of the entries of a directory, in order.
+    /// Entries without a path are not read.
+    pub(crate) fn lookahead(&self, paths: Vec<Option<PathBuf>>) -> Lookahead {
+        let (done, results) = channel::unbounded();
+        let mut lookahead = Lookahead { jobs: self.jobs.clone(), paths, submitted: 0, done, results,
+                                        ready: HashMap::new(), ahead: self.ahead };
+        lookahead.submit(0);
+        lookahead
+    }
+}
+
+impl Drop for MetadataPool {
+    fn drop(&mut self) {
+        // closing the queue stops the workers, once it is drained
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// The metadata of the entries of a directory, read ahead of the search.
+pub(crate) struct Lookahead {
+    jobs: Option<channel::Sender<Job>>,
+    // the entries to read, which are taken as they are submitted
+    paths: Vec<Option<PathBuf>>,
+    submitted: usize,
+    done: channel::Sender<(usize, io::Result<Metadata>)>,
+    results: channel::Receiver<(usize, io::Result<Metadata>)>,
+    // results which arrived ahead of the entry being waited on
+    ready: HashMap<usize, io::Result<Metadata>>,
+    ahead: usize,
+}
+
+impl Lookahead {
+    // Submit the entries up to the lookahead beyond the supplied index.
+    fn submit(&mut self, index: usize) {
+        let Some(jobs) = &self.jobs else {
+            return;
+        };
+        let until = (index + self.ahead).min(self.paths.len());
+        while self.submitted < until {
+            if let Some(path) = self.paths[self.submitted].take() {
+                let _ = jobs.send(Job { index: self.submitted, path, done: self.done.clone() });
+            }
+            self.submitted += 1;
+        }
+    }
+
+    /// The metadata of the entry at the supplied index, waiting for it to
+    /// be read if need be, or None if it is not being read ahead.
+    pub(crate) fn take(&mut self, index: usize) -> Option<io::Result<Metadata>> {
+        let read = self.paths.get(index).is_some_and(Option::is_some) || index < self.submitted;
+        self.submit(index);
+        if let Some(result) = self.ready.remove(&index) {
+            return Some(result);
+        }
+        if !read {
+            return None;
+        }
+        while let Ok((done, result)) = self.results.recv() {
+            if done == index {
+                return Some(result);
+            }
+            self.ready.insert(done, result);
+        }
+        None
+    }
+}
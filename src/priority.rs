@@ -0,0 +1,57 @@
+//! priority.rs
+//!
+//! Low priority scans, which yield to interactive and render workloads on
+//! the same host. Priorities are set on the calling thread, and inherited
+//! by the threads it goes on to spawn, so they are lowered before the
+//! search starts.
+#[cfg(unix)]
+use std::io;
+
+use crate::errors::AmbleError;
+
+// The nice value of the lowest CPU priority
+#[cfg(unix)]
+const LOWEST_NICE: libc::c_int = 19;
+
+/// Lower the CPU priority of the calling thread, and of the threads it
+/// spawns from now on, to the lowest, and their I/O priority to idle, so
+/// that the disk only serves them when nothing else wants it. The I/O
+/// priority is only lowered on Linux.
+pub fn nice_io() -> Result<(), AmbleError> {
+    lower_cpu_priority()?;
+    idle_io_priority()
+}
+
+#[cfg(unix)]
+fn lower_cpu_priority() -> Result<(), AmbleError> {
+    // on Linux, this applies to the calling thread, rather than every
+    // thread of the process
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, LOWEST_NICE) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn lower_cpu_priority() -> Result<(), AmbleError> {
+    Err(AmbleError::UnexpectedResult("lowering the priority of a scan is only supported on unix".to_string()))
+}
+
+#[cfg(target_os = "linux")]
+fn idle_io_priority() -> Result<(), AmbleError> {
+    // from linux/ioprio.h, which libc does not expose
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+
+    let priority = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+    if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, priority) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn idle_io_priority() -> Result<(), AmbleError> {
+    Ok(())
+}
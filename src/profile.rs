@@ -0,0 +1,76 @@
+//! profile.rs
+//!
+//! Profiling of the scan, so that users may diagnose slow scans on their
+//! own storage. The scan is sampled with pprof, and a flamegraph of where
+//! its CPU time went is written as an SVG once it completes. Allocations
+//! are counted by a global allocator wrapping the system's, which the
+//! binary installs, and reported alongside. Requires the profile feature
+//! (unix only).
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use pprof::{ProfilerGuard, ProfilerGuardBuilder};
+
+use crate::{constants::PROFILE_HZ, errors::AmbleError};
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// The system allocator, counting the allocations made through it.
+pub struct CountingAlloc;
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size.saturating_sub(layout.size()) as u64, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+// The allocations made so far, and the bytes they allocated.
+fn allocations() -> (u64, u64) {
+    (ALLOCATIONS.load(Ordering::Relaxed), ALLOCATED_BYTES.load(Ordering::Relaxed))
+}
+
+/// Profiles the scan, from when it is started until it is finished.
+pub struct Profiler {
+    guard: ProfilerGuard<'static>,
+    out: PathBuf,
+    allocations: (u64, u64),
+}
+
+impl Profiler {
+    /// Start profiling, to write a flamegraph to the supplied path.
+    pub fn start(out: &Path) -> Result<Self, AmbleError> {
+        let guard = ProfilerGuardBuilder::default().frequency(PROFILE_HZ)
+                                                   .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+                                                   .build()
+                                                   .map_err(|e| AmbleError::UnexpectedResult(format!("unable to profile: {}", e)))?;
+        Ok(Self { guard, out: out.to_path_buf(), allocations: allocations() })
+    }
+
+    /// Stop profiling, writing the flamegraph, and reporting the allocations
+    /// made to stderr.
+    pub fn finish(self) -> Result<(), AmbleError> {
+        let (count, bytes) = allocations();
+        let report = self.guard.report().build()
+                         .map_err(|e| AmbleError::UnexpectedResult(format!("unable to profile: {}", e)))?;
+        report.flamegraph(File::create(&self.out)?)
+              .map_err(|e| AmbleError::UnexpectedResult(format!("unable to write '{}': {}", self.out.display(), e)))?;
+        eprintln!("# profiled to {}: {} allocations, {} bytes allocated", self.out.display(),
+                  count - self.allocations.0, bytes - self.allocations.1);
+        Ok(())
+    }
+}
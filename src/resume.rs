@@ -0,0 +1,78 @@
+//! resume.rs
+//!
+//! Resumable scans. As a resumable scan goes, the directories it has yet
+//! to search (its frontier) are saved to a checkpoint file every few
+//! seconds, between directories. When a scan is interrupted, running it
+//! again with the same checkpoint file picks up from the frontier, rather
+//! than from the roots. Matches reported after the last checkpoint are
+//! reported again. The checkpoint is removed once the scan completes.
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::AmbleError;
+
+/// A directory left to search, and its depth beneath its root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pending {
+    /// Path to the directory
+    #[serde(with = "crate::spill::path")]
+    pub path: PathBuf,
+    /// Depth of the directory, the roots being at 0
+    pub depth: usize,
+}
+
+/// The progress of a resumable scan: the roots it searches, and the
+/// directories it has yet to search - its frontier. A depth first scan
+/// takes the next from the back of the frontier, and a breadth first scan
+/// from the front.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// The roots of the scan
+    #[serde(with = "crate::spill::paths")]
+    pub roots: Vec<PathBuf>,
+    /// The directories left to search
+    pub pending: Vec<Pending>,
+}
+
+impl Checkpoint {
+    /// Read the checkpoint saved at the supplied path, if there is one.
+    pub fn load(path: &Path) -> Result<Option<Self>, AmbleError> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let checkpoint = serde_json::from_reader(BufReader::new(file)).map_err(|e| {
+            AmbleError::ParseError(format!("invalid checkpoint '{}': {}", path.display(), e))
+        })?;
+        Ok(Some(checkpoint))
+    }
+
+    /// Save the checkpoint to the supplied path, replacing any saved
+    /// before, so that an interruption while saving leaves the last in
+    /// place.
+    pub fn save(&self, path: &Path) -> Result<(), AmbleError> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temp = path.to_path_buf().into_os_string();
+        temp.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    /// Remove the checkpoint saved at the supplied path, once the scan
+    /// has completed.
+    pub fn remove(path: &Path) -> Result<(), AmbleError> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
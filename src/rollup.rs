@@ -0,0 +1,206 @@
+//! rollup.rs
+//!
+//! Collapsing of matches into their directories. When every entry beneath
+//! a directory matches, the directory is reported as a single match in
+//! place of its contents, turning thousands of lines into one actionable
+//! path.
+//!
+//! Rollup consumes the entries visited by a walker in depth first order,
+//! holding the matches within each open directory until the directory has
+//! been fully visited. The sync walker visits entries in that order as it
+//! goes. The async walker does not, so its entries are gathered and sorted
+//! before being replayed, spilling to disk on large trees (see spill.rs).
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::AmbleError, filematch::FileMatch, spill::{Spill, SpillSort}, traits::Sink};
+
+/// An entry visited during traversal, as far as Rollup is concerned.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum Visit {
+    /// A directory, whose contents are visited next
+    Dir(#[serde(with = "crate::spill::path")] PathBuf),
+    /// A file (or broken link) which matched
+    Match(Box<FileMatch>),
+    /// Any other entry - a file which did not match, a skipped entry, or
+    /// an error. Its directory cannot be rolled up.
+    Other(#[serde(with = "crate::spill::path")] PathBuf),
+}
+
+impl Visit {
+    /// The visit for an entry at the supplied path, which matched if
+    /// `found` is Some.
+    pub(crate) fn evaluated(path: &Path, found: Option<FileMatch>) -> Self {
+        match found {
+            Some(found) => Visit::Match(Box::new(found)),
+            None => Visit::Other(path.to_path_buf()),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            Visit::Dir(path) | Visit::Other(path) => path,
+            Visit::Match(found) => &found.path,
+        }
+    }
+}
+
+impl Spill for Visit {
+    fn footprint(&self) -> usize {
+        match self {
+            Visit::Dir(path) | Visit::Other(path) => mem::size_of::<Self>() + path.as_os_str().len(),
+            Visit::Match(found) => mem::size_of::<Self>() + found.footprint(),
+        }
+    }
+}
+
+// A directory whose contents are being visited
+#[derive(Debug)]
+struct OpenDir {
+    path: PathBuf,
+    // has every entry visited so far matched?
+    complete: bool,
+    // matches beneath the directory, held until it has been fully visited
+    matches: Vec<FileMatch>,
+}
+
+/// Rolls matches up into their directories, handing the results to a
+/// sink. When disabled, matches are handed straight to the sink.
+#[derive(Debug)]
+pub(crate) struct Rollup {
+    enabled: bool,
+    open: Vec<OpenDir>,
+}
+
+impl Rollup {
+    /// New up a Rollup, which only rolls matches up when enabled.
+    pub(crate) fn new(enabled: bool) -> Self {
+        Self { enabled, open: Vec::new() }
+    }
+
+    /// Is rolling up enabled?
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Record a visited entry. Entries must arrive in depth first order,
+    /// with each directory ahead of its contents.
+    pub(crate) fn visit(&mut self, visit: Visit, sink: &mut dyn Sink) -> Result<(), AmbleError> {
+        if !self.enabled {
+            if let Visit::Match(found) = visit {
+                sink.accept(*found)?;
+            }
+            return Ok(());
+        }
+        self.close_outside(visit.path(), sink)?;
+        match visit {
+            Visit::Dir(path) => self.open.push(OpenDir { path, complete: true, matches: Vec::new() }),
+            Visit::Match(found) => match self.open.last_mut() {
+                Some(dir) => dir.matches.push(*found),
+                None => sink.accept(*found)?,
+            },
+            Visit::Other(_) => if let Some(dir) = self.open.last_mut() {
+                dir.complete = false;
+            },
+        }
+        Ok(())
+    }
+
+    /// Record that an entry of the innermost open directory could not be
+    /// read, when even its name is unknown, so that the directory is not
+    /// rolled up.
+    pub(crate) fn unreadable(&mut self) {
+        if let Some(dir) = self.open.last_mut() {
+            dir.complete = false;
+        }
+    }
+
+    /// Record entries which were visited in no particular order, as by the
+    /// async walker. Ordering paths component by component places each
+    /// directory ahead of its contents. Beyond `limit` bytes, the entries
+    /// are spilled to disk while they are sorted.
+    pub(crate) fn replay(&mut self, visits: impl IntoIterator<Item = Visit>, limit: u64, sink: &mut dyn Sink)
+    -> Result<(), AmbleError> {
+        let mut sorted = SpillSort::new(limit, |a: &Visit, b: &Visit| a.path().cmp(b.path()));
+        for visit in visits {
+            sorted.push(visit)?;
+        }
+        for visit in sorted.sorted()? {
+            self.visit(visit?, sink)?;
+        }
+        Ok(())
+    }
+
+    /// Close the directories which are still open. When the traversal was
+    /// cut short, they have not been fully visited, and are not rolled up.
+    pub(crate) fn finish(&mut self, cut_short: bool, sink: &mut dyn Sink) -> Result<(), AmbleError> {
+        if cut_short {
+            self.open.iter_mut().for_each(|dir| dir.complete = false);
+        }
+        while let Some(dir) = self.open.pop() {
+            self.close(dir, sink)?;
+        }
+        Ok(())
+    }
+
+    // close the open directories which do not contain the supplied path
+    fn close_outside(&mut self, path: &Path, sink: &mut dyn Sink) -> Result<(), AmbleError> {
+        while let Some(dir) = self.open.pop() {
+            if path != dir.path && path.starts_with(&dir.path) {
+                self.open.push(dir);
+                break;
+            }
+            self.close(dir, sink)?;
+        }
+        Ok(())
+    }
+
+    // Close a fully visited directory. If all of its contents matched, it
+    // is rolled up into a single match, which is held by its parent.
+    // Otherwise its matches are handed to the sink, and its parent cannot
+    // be rolled up either. Empty directories have no bearing on their parent.
+    fn close(&mut self, dir: OpenDir, sink: &mut dyn Sink) -> Result<(), AmbleError> {
+        if !dir.complete {
+            if let Some(parent) = self.open.last_mut() {
+                parent.complete = false;
+            }
+            for found in dir.matches {
+                sink.accept(found)?;
+            }
+        } else if !dir.matches.is_empty() {
+            let found = roll_up(dir.path, dir.matches);
+            match self.open.last_mut() {
+                Some(parent) => parent.matches.push(found),
+                None => sink.accept(found)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Combine the matches within a directory into a single match for the
+// directory. Sizes are totalled, the criteria codes are those which
+// every match satisfied, and the times are the latest of any match.
+fn roll_up(path: PathBuf, matches: Vec<FileMatch>) -> FileMatch {
+    let mut found = FileMatch::new(path, 0);
+    found.allocated = 0;
+    found.meta = matches[0].meta.clone();
+    found.contents = Some(0);
+    for file in &matches {
+        found.size += file.size;
+        found.allocated += file.allocated;
+        found.meta.retain(|code| file.meta.contains(code));
+        for field in &file.future {
+            if !found.future.contains(field) {
+                found.future.push(*field);
+            }
+        }
+        found.contents = found.contents.map(|count| count + file.contents.unwrap_or(1));
+        found.accessed = found.accessed.max(file.accessed);
+        found.modified = found.modified.max(file.modified);
+        found.created = found.created.max(file.created);
+    }
+    found
+}
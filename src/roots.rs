@@ -0,0 +1,96 @@
+//! roots.rs
+//!
+//! Helpers for choosing what to search: more than one root directory in
+//! a single scan, or a list of candidate paths supplied by another program.
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::{errors::AmbleError, fields::Availability, filematch::FileMatch, traits::Sink};
+
+/// Remove roots which are the same as, or nested within, another of the
+/// supplied roots, so that no directory is searched twice. Roots are
+/// compared by their canonical paths, but are returned as supplied, in
+/// their original order.
+pub fn dedup_roots(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let canonical = roots.iter()
+                         .map(|root| fs::canonicalize(root).unwrap_or_else(|_| root.clone()))
+                         .collect::<Vec<_>>();
+    roots.iter()
+         .enumerate()
+         .filter(|(idx, _)| {
+             !canonical.iter().enumerate().any(|(other_idx, other)| {
+                 other_idx != *idx && canonical[*idx].starts_with(other) &&
+                 (canonical[*idx] != *other || other_idx < *idx)
+             })
+         })
+         .map(|(_, root)| root.clone())
+         .collect()
+}
+
+/// The root, among the supplied roots, beneath which a path was found.
+/// Paths found by a search are joined onto the root they were found
+/// beneath, so compare by prefix.
+pub fn root_of<'a>(roots: &'a [PathBuf], path: &Path) -> Option<&'a PathBuf> {
+    roots.iter().find(|root| path.starts_with(root))
+}
+
+/// Sink which attributes each match to the root it was found beneath,
+/// before handing it on to another sink.
+pub(crate) struct Attribute<'a> {
+    roots: &'a [PathBuf],
+    sink: &'a mut dyn Sink,
+}
+
+impl<'a> Attribute<'a> {
+    pub(crate) fn new(roots: &'a [PathBuf], sink: &'a mut dyn Sink) -> Self {
+        Self { roots, sink }
+    }
+}
+
+impl Sink for Attribute<'_> {
+    fn begin(&mut self, availability: &Availability) -> Result<(), AmbleError> {
+        self.sink.begin(availability)
+    }
+
+    fn accept(&mut self, mut found: FileMatch) -> Result<(), AmbleError> {
+        if found.root.is_none() {
+            found.root = root_of(self.roots, &found.path).cloned();
+        }
+        self.sink.accept(found)
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        self.sink.finish()
+    }
+
+    fn errors(&self) -> u64 {
+        self.sink.errors()
+    }
+}
+
+/// Read a list of paths from the supplied reader. If the input contains a
+/// NUL, paths are NUL delimited (as produced by `find -print0`); otherwise
+/// they are newline delimited. Empty entries are ignored.
+pub fn read_path_list(mut reader: impl Read) -> Result<Vec<PathBuf>, AmbleError> {
+    let mut input = Vec::new();
+    reader.read_to_end(&mut input)?;
+    let delimiter = if input.contains(&0) { 0 } else { b'\n' };
+    Ok(input.split(|byte| *byte == delimiter)
+            .map(|entry| if delimiter == b'\n' { entry.strip_suffix(b"\r").unwrap_or(entry) } else { entry })
+            .filter(|entry| !entry.is_empty())
+            .map(bytes_to_path)
+            .collect())
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
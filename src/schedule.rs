@@ -0,0 +1,297 @@
+//! schedule.rs
+//!
+//! Evaluation of a policy (see policy.rs) against the schedule it is run
+//! on, answering "when will this file be cleaned up?" and "what will the
+//! next run pick up?". A schedule is a cron expression of five fields -
+//! minute, hour, day of the month, month, and day of the week - in local
+//! time:
+//!
+//! ```text
+//! # minute hour day-of-month month day-of-week
+//! 0 2 * * 1-5
+//! ```
+//!
+//! Each field is `*`, a number, a range (`1-5`), or a list of them
+//! (`1,15`), optionally with a step (`*/15`). Months and days of the week
+//! may be given by name (`jan`, `mon`), and Sunday is either 0 or 7. As
+//! with cron, when both the day of the month and the day of the week are
+//! restricted, a day matching either is run. The shorthands @hourly,
+//! @daily, @weekly, @monthly, and @yearly are accepted too.
+//!
+//! Files are assumed to be left alone in the meantime, so that their
+//! effective times do not change.
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Datelike, Days as CalendarDays, Local, TimeDelta, TimeZone, Timelike};
+
+use crate::{constants::SECS_PER_DAY, duration::to_duration, errors::AmbleError, filematch::FileMatch,
+            policy::Rule, timefmt::Zone, traits::Sink};
+
+// How far ahead to look for a run, long enough for a schedule of the 29th
+// of February on a Monday
+const MAX_DAYS_AHEAD: u64 = 366 * 28;
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A cron schedule, on which a policy is run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    expression: String,
+    // each field as a bitmask of the values it matches
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // were the day fields restricted, rather than `*`?
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Schedule {
+    /// The first run of the schedule at or after the supplied time, if
+    /// there is one.
+    pub fn next_at_or_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let local = DateTime::<Local>::from(time).naive_local();
+        let mut start = local.with_second(0)?.with_nanosecond(0)?;
+        if start < local {
+            start += TimeDelta::minutes(1);
+        }
+        for offset in 0..MAX_DAYS_AHEAD {
+            let date = start.date().checked_add_days(CalendarDays::new(offset))?;
+            if !self.runs_on(date.day(), date.month(), date.weekday().num_days_from_sunday()) {
+                continue;
+            }
+            let from = if offset == 0 { start.hour() * 60 + start.minute() } else { 0 };
+            for minute_of_day in from..24 * 60 {
+                let (hour, minute) = (minute_of_day / 60, minute_of_day % 60);
+                if !(bit(self.hours, hour) && bit(self.minutes, minute)) {
+                    continue;
+                }
+                // times skipped by a daylight saving change do not run
+                let run = date.and_hms_opt(hour, minute, 0)
+                              .and_then(|run| Local.from_local_datetime(&run).earliest())
+                              .map(SystemTime::from);
+                if let Some(run) = run.filter(|run| *run >= time) {
+                    return Some(run);
+                }
+            }
+        }
+        None
+    }
+
+    /// The first run of the schedule after the supplied time, if there is
+    /// one.
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        self.next_at_or_after(time + Duration::from_nanos(1))
+    }
+
+    // Does the schedule run on the supplied day? When both day fields are
+    // restricted, either may match.
+    fn runs_on(&self, day: u32, month: u32, weekday: u32) -> bool {
+        if !bit(self.months, month) {
+            return false;
+        }
+        let (day, weekday) = (bit(self.days, day), bit(self.weekdays, weekday));
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+fn bit(mask: u64, value: u32) -> bool {
+    mask & (1 << value) != 0
+}
+
+// Parse a single field of a cron expression into a bitmask of the values
+// it matches, between min and max, with optional names for the values
+// from min.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Option<u64> {
+    let value = |s: &str| -> Option<u32> {
+        let lower = s.to_ascii_lowercase();
+        names.iter()
+             .position(|name| *name == lower)
+             .map(|index| index as u32 + min)
+             .or_else(|| s.parse().ok())
+             .filter(|value| (min..=max).contains(value))
+    };
+    let mut mask = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0)?),
+            None => (item, 1),
+        };
+        let (first, last) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((first, last)) => (value(first)?, value(last)?),
+                None if step > 1 => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if first > last {
+            return None;
+        }
+        mask |= (first..=last).step_by(step as usize).fold(0, |mask, value| mask | 1 << value);
+    }
+    Some(mask)
+}
+
+impl FromStr for Schedule {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AmbleError::ParseError(format!(
+            "invalid schedule '{}'. Expected a cron expression, such as '0 2 * * *', or @daily", s));
+        let expression = match s.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(invalid());
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7, &WEEKDAYS).ok_or_else(invalid)?;
+        // Sunday is both 0 and 7
+        if bit(weekdays, 7) {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            expression: s.trim().to_string(),
+            minutes: parse_field(fields[0], 0, 59, &[]).ok_or_else(invalid)?,
+            hours: parse_field(fields[1], 0, 23, &[]).ok_or_else(invalid)?,
+            days: parse_field(fields[2], 1, 31, &[]).ok_or_else(invalid)?,
+            months: parse_field(fields[3], 1, 12, &MONTHS).ok_or_else(invalid)?,
+            weekdays,
+            days_restricted: !fields[2].starts_with('*'),
+            weekdays_restricted: !fields[4].starts_with('*'),
+        })
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+/// The tier of the policy the file falls into at the supplied instant, if
+/// any: that with the largest number of days the file has been idle for.
+pub fn tier_at<'a>(rules: &'a [Rule], found: &FileMatch, at: SystemTime) -> Option<&'a Rule> {
+    let idle = found.effective_age(at)?.as_secs_f64() / SECS_PER_DAY as f64;
+    rules.iter()
+         .filter(|rule| idle >= f64::from(rule.days))
+         .max_by(|a, b| a.days.total_cmp(&b.days))
+}
+
+/// The tiers the file has yet to reach at the supplied instant, each with
+/// the first run of the schedule at which the file will fall into it (or
+/// a later tier), from the soonest.
+pub fn eligible_runs<'a>(rules: &'a [Rule], schedule: &Schedule, found: &FileMatch, after: SystemTime)
+-> Vec<(&'a Rule, SystemTime)> {
+    let effective = match found.effective_time() {
+        Some(effective) => effective,
+        None => return Vec::new(),
+    };
+    let mut runs: Vec<(&Rule, SystemTime)> = rules.iter()
+        .filter_map(|rule| {
+            let eligible = effective.checked_add(to_duration(rule.days, SECS_PER_DAY))?;
+            if eligible <= after {
+                return None;
+            }
+            Some((rule, schedule.next_at_or_after(eligible)?))
+        })
+        .collect();
+    runs.sort_by(|(a, _), (b, _)| a.days.total_cmp(&b.days));
+    runs
+}
+
+// A tier, as reported to the user
+fn describe(rule: Option<&Rule>) -> String {
+    match rule {
+        Some(Rule { action, argument: Some(argument), .. }) => format!("{} {}", action, argument),
+        Some(Rule { action, .. }) => action.clone(),
+        None => "none".to_string(),
+    }
+}
+
+/// Sink which reports how a policy run on a schedule will treat each file.
+/// By default, only the files which the next run will move into a new tier
+/// are reported. With `timeline`, every file is reported, along with when
+/// it will reach each of the tiers it has yet to.
+#[derive(Debug)]
+pub struct ScheduleCheck {
+    rules: Vec<Rule>,
+    schedule: Schedule,
+    now: SystemTime,
+    next_run: Option<SystemTime>,
+    timeline: bool,
+    zone: Zone,
+}
+
+impl ScheduleCheck {
+    /// New up a ScheduleCheck for the policy with the supplied rules, run on
+    /// the supplied schedule.
+    pub fn new(rules: Vec<Rule>, schedule: Schedule) -> Self {
+        let now = SystemTime::now();
+        let next_run = schedule.next_after(now);
+        Self { rules, schedule, now, next_run, timeline: false, zone: Zone::Local }
+    }
+
+    /// Evaluate the schedule as of the supplied instant, rather than when
+    /// the ScheduleCheck was created.
+    pub fn as_of(&mut self, as_of: SystemTime) -> &mut Self {
+        self.now = as_of;
+        self.next_run = self.schedule.next_after(as_of);
+        self
+    }
+
+    /// Report every file, along with when it will reach each of the tiers
+    /// it has yet to, rather than only the files the next run moves.
+    pub fn timeline(&mut self, timeline: bool) -> &mut Self {
+        self.timeline = timeline;
+        self
+    }
+
+    /// Render times in the supplied zone, rather than the local timezone.
+    pub fn zone(&mut self, zone: Zone) -> &mut Self {
+        self.zone = zone;
+        self
+    }
+
+    /// The next run of the schedule, if there is one.
+    pub fn next_run(&self) -> Option<SystemTime> {
+        self.next_run
+    }
+}
+
+impl Sink for ScheduleCheck {
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        let current = tier_at(&self.rules, &found, self.now);
+        if self.timeline {
+            let mut line = format!("{}: currently {}", found.path.display(), describe(current));
+            for (rule, run) in eligible_runs(&self.rules, &self.schedule, &found, self.now) {
+                line.push_str(&format!("; {} at {}", describe(Some(rule)), self.zone.rfc3339(run)));
+            }
+            println!("{}", line);
+            return Ok(());
+        }
+        let next_run = match self.next_run {
+            Some(next_run) => next_run,
+            None => return Ok(()),
+        };
+        let next = tier_at(&self.rules, &found, next_run);
+        if next.map(|rule| rule.days) != current.map(|rule| rule.days) {
+            println!("{}: {} -> {} at the next run", found.path.display(), describe(current), describe(next));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,456 @@
+//! sinks.rs
+//!
+//! Implementations of the Sink trait, which receive the files found
+//! by a Finder.
+//!
+//! The printing sinks are on the hot path when matches are found at a
+//! high rate, so each renders its matches into a line buffer which is
+//! reused from one match to the next, borrowing from the match rather than
+//! converting its paths and fields into owned strings. Lines are written
+//! to stdout through a buffer, which is flushed once full, when matches
+//! are found slowly, and once the search completes. When stdout is a
+//! terminal, each line is flushed as it is written.
+use std::cmp::Ordering;
+use std::fmt;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Serialize, Serializer};
+
+use crate::{constants::{BUFFER_BYTES, STDOUT_BUFFER_BYTES, STDOUT_FLUSH_MILLIS}, errors::AmbleError, fields::{Availability, Field, Marker, MarkerCodes}, filematch::FileMatch,
+            perms::ModeFlag, size::SizeMode, spill::SpillSort, timefmt::{TimeFormat, Zone}, traits::Sink};
+#[cfg(feature = "watch")]
+use crate::watch::Changes;
+
+// Buffered lines of output to stdout.
+#[derive(Debug)]
+struct Output {
+    writer: BufWriter<io::Stdout>,
+    terminal: bool,
+    flushed: Instant,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        let stdout = io::stdout();
+        Self { terminal: stdout.is_terminal(), writer: BufWriter::with_capacity(STDOUT_BUFFER_BYTES, stdout),
+               flushed: Instant::now() }
+    }
+}
+
+impl Output {
+    // Write a rendered line, flushing it straight away if stdout is a
+    // terminal, or if nothing has been flushed for a while.
+    fn write_line(&mut self, line: &[u8]) -> Result<(), AmbleError> {
+        self.writer.write_all(line)?;
+        if self.terminal || self.flushed.elapsed() >= Duration::from_millis(STDOUT_FLUSH_MILLIS) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), AmbleError> {
+        self.writer.flush()?;
+        self.flushed = Instant::now();
+        Ok(())
+    }
+}
+
+/// Sink which prints each match to stdout. If any of the requested
+/// metadata fields are unavailable, a header reporting the availability
+/// of each field is printed first.
+#[derive(Debug, Default)]
+pub struct PrintSink {
+    line: Vec<u8>,
+    out: Output,
+}
+
+impl Sink for PrintSink {
+    fn begin(&mut self, availability: &Availability) -> Result<(), AmbleError> {
+        if availability.is_degraded() {
+            self.line.clear();
+            writeln!(self.line, "# fields: {}", availability)?;
+            self.out.write_line(&self.line)?;
+        }
+        Ok(())
+    }
+
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        self.line.clear();
+        writeln!(self.line, "{}", found)?;
+        self.out.write_line(&self.line)
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        self.out.flush()
+    }
+}
+
+/// Sink which prints each match to stdout as a JSON object, one per line,
+/// with timestamps in the supplied format. Unavailable timestamps are null.
+/// Paths which are not valid unicode are written lossily.
+#[derive(Debug, Default)]
+pub struct JsonSink {
+    time_format: TimeFormat,
+    zone: Zone,
+    line: Vec<u8>,
+    out: Output,
+}
+
+impl JsonSink {
+    /// New up a JsonSink writing timestamps in the supplied format.
+    pub fn new(time_format: TimeFormat) -> Self {
+        Self { time_format, zone: Zone::Local, line: Vec::new(), out: Output::default() }
+    }
+
+    /// Render RFC 3339 timestamps in the supplied zone, rather than the
+    /// local timezone.
+    pub fn zone(&mut self, zone: Zone) -> &mut Self {
+        self.zone = zone;
+        self
+    }
+
+    fn time(&self, time: Option<SystemTime>) -> Stamp {
+        Stamp { time, format: self.time_format, zone: self.zone }
+    }
+}
+
+impl JsonSink {
+    // Write a match, tagged with the supplied event, if any.
+    fn write(&mut self, found: FileMatch, event: Option<&'static str>) -> Result<(), AmbleError> {
+        let object = JsonMatch {
+            accessed: self.time(found.accessed),
+            allocated: found.allocated,
+            class: found.class.map(|class| class.as_str()),
+            contents: found.contents,
+            created: self.time(found.created),
+            effective: self.time(found.effective_time()),
+            event,
+            flags: &found.flags,
+            future: &found.future,
+            links: &found.links,
+            markers: &found.meta,
+            meta: &found.meta,
+            missing: &found.missing,
+            modified: self.time(found.modified),
+            path: &found.path,
+            root: found.root.as_deref(),
+            size: found.size,
+            target: found.target.as_deref(),
+        };
+        self.line.clear();
+        serde_json::to_writer(&mut self.line, &object)?;
+        self.line.push(b'\n');
+        self.out.write_line(&self.line)
+    }
+}
+
+impl Sink for JsonSink {
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        self.write(found, None)
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        self.out.flush()
+    }
+}
+
+// Changes are tagged with their event, alongside the fields of the match.
+#[cfg(feature = "watch")]
+impl Changes for JsonSink {
+    fn matched(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        self.write(found, Some("matched"))
+    }
+
+    fn unmatched(&mut self, path: &Path) -> Result<(), AmbleError> {
+        self.line.clear();
+        serde_json::to_writer(&mut self.line, &serde_json::json!({"event": "unmatched",
+                                                                  "path": path.to_string_lossy()}))?;
+        self.line.push(b'\n');
+        self.out.write_line(&self.line)
+    }
+
+    fn flush(&mut self) -> Result<(), AmbleError> {
+        self.out.flush()
+    }
+}
+
+// Changes are marked + when the file matches, and - when it no longer does.
+#[cfg(feature = "watch")]
+impl Changes for PrintSink {
+    fn matched(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        self.line.clear();
+        writeln!(self.line, "+ {}", found)?;
+        self.out.write_line(&self.line)
+    }
+
+    fn unmatched(&mut self, path: &Path) -> Result<(), AmbleError> {
+        self.line.clear();
+        writeln!(self.line, "- {}", path.display())?;
+        self.out.write_line(&self.line)
+    }
+
+    fn flush(&mut self) -> Result<(), AmbleError> {
+        self.out.flush()
+    }
+}
+
+// A match as written by JsonSink, borrowed from the FileMatch. The fields
+// are in alphabetical order, as the keys of the objects always have been.
+#[derive(Serialize)]
+struct JsonMatch<'a> {
+    accessed: Stamp,
+    allocated: u64,
+    class: Option<&'static str>,
+    contents: Option<u64>,
+    created: Stamp,
+    effective: Stamp,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<&'static str>,
+    #[serde(serialize_with = "flag_names")]
+    flags: &'a [ModeFlag],
+    #[serde(serialize_with = "field_names")]
+    future: &'a [Field],
+    #[serde(serialize_with = "lossy_paths")]
+    links: &'a [PathBuf],
+    #[serde(serialize_with = "marker_names")]
+    markers: &'a [Marker],
+    #[serde(serialize_with = "codes")]
+    meta: &'a [Marker],
+    #[serde(serialize_with = "field_names")]
+    missing: &'a [Field],
+    modified: Stamp,
+    #[serde(serialize_with = "lossy_path")]
+    path: &'a Path,
+    #[serde(serialize_with = "lossy_optional_path")]
+    root: Option<&'a Path>,
+    size: u64,
+    #[serde(serialize_with = "lossy_optional_path")]
+    target: Option<&'a Path>,
+}
+
+// A timestamp in the format of the JsonSink, or null if unavailable.
+struct Stamp {
+    time: Option<SystemTime>,
+    format: TimeFormat,
+    zone: Zone,
+}
+
+impl Serialize for Stamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.time {
+            Some(time) => self.format.serialize(time, self.zone, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+fn flag_names<S: Serializer>(flags: &&[ModeFlag], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(flags.iter().map(|flag| flag.as_str()))
+}
+
+fn field_names<S: Serializer>(fields: &&[Field], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(fields.iter().map(|field| field.name()))
+}
+
+fn marker_names<S: Serializer>(markers: &&[Marker], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(markers.iter().map(|marker| marker.name()))
+}
+
+fn codes<S: Serializer>(markers: &&[Marker], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&MarkerCodes(markers))
+}
+
+fn lossy_path<S: Serializer>(path: &&Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+fn lossy_optional_path<S: Serializer>(path: &Option<&Path>, serializer: S) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(path) => lossy_path(path, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn lossy_paths<S: Serializer>(paths: &&[PathBuf], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(paths.iter().map(|path| path.to_string_lossy()))
+}
+
+/// Sink which counts the matches handed on to another sink, and their
+/// total size. A rolled up directory counts the files beneath it.
+#[derive(Debug)]
+pub struct Tally<S: Sink> {
+    sink: S,
+    matches: u64,
+    bytes: u64,
+}
+
+impl<S: Sink> Tally<S> {
+    /// New up a Tally handing matches on to the supplied sink.
+    pub fn new(sink: S) -> Self {
+        Self { sink, matches: 0, bytes: 0 }
+    }
+
+    /// Number of matches received so far.
+    pub fn matches(&self) -> u64 {
+        self.matches
+    }
+
+    /// Total apparent size, in bytes, of the matches received so far.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl<S: Sink> Sink for Tally<S> {
+    fn begin(&mut self, availability: &Availability) -> Result<(), AmbleError> {
+        self.sink.begin(availability)
+    }
+
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        self.matches += found.contents.unwrap_or(1);
+        self.bytes += found.size;
+        self.sink.accept(found)
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        self.sink.finish()
+    }
+
+    fn errors(&self) -> u64 {
+        self.sink.errors()
+    }
+}
+
+/// What matches are sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    /// The effective age of the match, youngest first
+    Age,
+    /// The size of the match, smallest first
+    Size,
+    /// The path of the match
+    Path,
+    /// The file name of the match
+    Name,
+}
+
+impl FromStr for SortBy {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "age" => Ok(SortBy::Age),
+            "size" => Ok(SortBy::Size),
+            "path" => Ok(SortBy::Path),
+            "name" => Ok(SortBy::Name),
+            _ => Err(AmbleError::ParseError(format!("invalid sort '{}'. Expected age, size, path, or name", s))),
+        }
+    }
+}
+
+impl fmt::Display for SortBy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SortBy::Age => "age",
+            SortBy::Size => "size",
+            SortBy::Path => "path",
+            SortBy::Name => "name",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Order matches, reversed when REVERSE is set, and by path where they sort
+// equally. The youngest have the latest effective times.
+fn by_age<const REVERSE: bool>(a: &FileMatch, b: &FileMatch) -> Ordering {
+    ordered::<REVERSE>(b.effective_time().cmp(&a.effective_time()), a, b)
+}
+
+fn by_size<const REVERSE: bool>(a: &FileMatch, b: &FileMatch) -> Ordering {
+    ordered::<REVERSE>(a.size.cmp(&b.size), a, b)
+}
+
+fn by_allocated<const REVERSE: bool>(a: &FileMatch, b: &FileMatch) -> Ordering {
+    ordered::<REVERSE>(a.allocated.cmp(&b.allocated), a, b)
+}
+
+fn by_path<const REVERSE: bool>(a: &FileMatch, b: &FileMatch) -> Ordering {
+    ordered::<REVERSE>(Ordering::Equal, a, b)
+}
+
+fn by_name<const REVERSE: bool>(a: &FileMatch, b: &FileMatch) -> Ordering {
+    ordered::<REVERSE>(a.path.file_name().cmp(&b.path.file_name()), a, b)
+}
+
+fn ordered<const REVERSE: bool>(ordering: Ordering, a: &FileMatch, b: &FileMatch) -> Ordering {
+    let ordering = ordering.then_with(|| a.path.cmp(&b.path));
+    if REVERSE { ordering.reverse() } else { ordering }
+}
+
+/// Sink which sorts matches before handing them on to another sink, once
+/// the search completes, so that the order of the output does not depend
+/// on the backend. Matches which sort equally are ordered by path. Beyond
+/// the buffer limit, matches are spilled to temporary files (see
+/// spill.rs).
+#[derive(Debug)]
+pub struct Sorted<S: Sink> {
+    sink: S,
+    compare: fn(&FileMatch, &FileMatch) -> Ordering,
+    matches: SpillSort<FileMatch>,
+    buffer_limit: u64,
+}
+
+impl<S: Sink> Sorted<S> {
+    /// New up a Sorted handing matches on to the supplied sink, sorted as
+    /// supplied, and in reverse if `reverse` is set. Sizes are measured as
+    /// the size mode supplied.
+    pub fn new(sink: S, sort_by: SortBy, reverse: bool, size_mode: SizeMode) -> Self {
+        let compare = match (sort_by, size_mode, reverse) {
+            (SortBy::Age, _, false) => by_age::<false>,
+            (SortBy::Age, _, true) => by_age::<true>,
+            (SortBy::Size, SizeMode::Apparent, false) => by_size::<false>,
+            (SortBy::Size, SizeMode::Apparent, true) => by_size::<true>,
+            (SortBy::Size, SizeMode::Allocated, false) => by_allocated::<false>,
+            (SortBy::Size, SizeMode::Allocated, true) => by_allocated::<true>,
+            (SortBy::Path, _, false) => by_path::<false>,
+            (SortBy::Path, _, true) => by_path::<true>,
+            (SortBy::Name, _, false) => by_name::<false>,
+            (SortBy::Name, _, true) => by_name::<true>,
+        };
+        Self { sink, compare, matches: SpillSort::new(BUFFER_BYTES, compare), buffer_limit: BUFFER_BYTES }
+    }
+
+    /// Set the number of bytes of matches which may be held in memory,
+    /// beyond which they are spilled to temporary files. The default is
+    /// BUFFER_BYTES.
+    pub fn buffer_limit(&mut self, limit: u64) -> &mut Self {
+        self.matches = SpillSort::new(limit, self.compare);
+        self.buffer_limit = limit;
+        self
+    }
+}
+
+impl<S: Sink> Sink for Sorted<S> {
+    fn begin(&mut self, availability: &Availability) -> Result<(), AmbleError> {
+        self.sink.begin(availability)
+    }
+
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        self.matches.push(found)
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        let matches = std::mem::replace(&mut self.matches, SpillSort::new(self.buffer_limit, self.compare));
+        for found in matches.sorted()? {
+            self.sink.accept(found?)?;
+        }
+        self.sink.finish()
+    }
+
+    fn errors(&self) -> u64 {
+        self.sink.errors()
+    }
+}
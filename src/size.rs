@@ -0,0 +1,62 @@
+//! size.rs
+//!
+//! File sizes, and how they are measured. A file's apparent size is its
+//! length in bytes, while its allocated size is the space it occupies on
+//! disk. The two differ for sparse files, where the allocated size is
+//! often far smaller, and is the better estimate of reclaimable space.
+use std::fs::Metadata;
+use std::str::FromStr;
+
+use crate::errors::AmbleError;
+
+/// How the size of a file is measured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SizeMode {
+    /// The length of the file in bytes
+    #[default]
+    Apparent,
+    /// The space allocated to the file on disk (st_blocks * 512)
+    Allocated,
+}
+
+/// The space allocated to the file described by the supplied metadata. On
+/// platforms which do not report allocated blocks, this is the apparent size.
+pub fn allocated_size(metadata: &Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.blocks() * 512
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.len()
+    }
+}
+
+/// A size in bytes, parsed from a number with an optional binary unit
+/// suffix: K, M, G, T (eg "512", "10K", "1.5G"). A trailing "B" or
+/// "iB" is accepted, so "10MB" and "10MiB" are also 10 * 1024 * 1024.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Size(pub u64);
+
+impl FromStr for Size {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AmbleError::ParseError(format!("invalid size '{}'. Expected eg 512, 10K, 1.5G", s));
+        let upper = s.trim().to_ascii_uppercase();
+        let trimmed = upper.trim_end_matches("IB").trim_end_matches('B');
+        let (number, multiplier) = match trimmed.chars().last() {
+            Some('K') => (&trimmed[..trimmed.len() - 1], 1u64 << 10),
+            Some('M') => (&trimmed[..trimmed.len() - 1], 1u64 << 20),
+            Some('G') => (&trimmed[..trimmed.len() - 1], 1u64 << 30),
+            Some('T') => (&trimmed[..trimmed.len() - 1], 1u64 << 40),
+            _ => (trimmed, 1),
+        };
+        let value = number.trim().parse::<f64>().map_err(|_| invalid())?;
+        if !value.is_finite() || value < 0.0 {
+            return Err(invalid());
+        }
+        Ok(Size((value * multiplier as f64).round() as u64))
+    }
+}
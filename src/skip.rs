@@ -0,0 +1,123 @@
+//! skip.rs
+//!
+//! Helpers for working with lists of names to skip during traversal, and
+//! Prune - which decides the entries to leave out of a traversal.
+use std::ffi::OsStr;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{self, Path, PathBuf};
+
+use tracing::debug;
+
+use crate::errors::AmbleError;
+
+/// Read a skip list from the supplied reader, one name per line. Leading
+/// and trailing whitespace is trimmed, and blank lines, as well as lines
+/// starting with '#', are ignored.
+pub fn read_skip_list(reader: impl Read) -> Result<Vec<String>, AmbleError> {
+    let mut skip = Vec::new();
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        let name = line.trim();
+        if name.is_empty() || name.starts_with('#') {
+            continue;
+        }
+        skip.push(name.to_string());
+    }
+    Ok(skip)
+}
+
+/// Predicate to determine if a file name matches one or more of the
+/// names in the supplied list, optionally ignoring case.
+fn matches_list(name: &OsStr, list: &[String], case_insensitive: bool) -> bool {
+    if list.is_empty() {
+        return false;
+    }
+
+    name.to_str()
+        .map(|s| list.iter().any(|item| names_equal(s, item, case_insensitive)))
+        .unwrap_or(false)
+}
+
+/// Is the supplied name that of a hidden file or directory? Hidden names
+/// start with a '.'. The special names "." and ".." are not hidden.
+fn is_hidden(name: &OsStr) -> bool {
+    name.to_str()
+        .map(|s| s.starts_with('.') && s != "." && s != "..")
+        .unwrap_or(false)
+}
+
+// compare two names, optionally ignoring case
+fn names_equal(a: &str, b: &str, case_insensitive: bool) -> bool {
+    if case_insensitive {
+        a.chars().flat_map(char::to_lowercase).eq(b.chars().flat_map(char::to_lowercase))
+    } else {
+        a == b
+    }
+}
+
+/// Decides which entries are left out of a traversal, by name, by path,
+/// or because they are hidden. Shared by the sync and async walkers, so
+/// that both leave out the same entries.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Prune {
+    /// Names of directories and files to skip
+    pub(crate) skip: Vec<String>,
+    /// Whether or not names are matched without regard to case
+    pub(crate) case_insensitive: bool,
+    /// Whether or not to skip hidden directories
+    pub(crate) hidden_dirs: bool,
+    /// Whether or not to skip hidden files
+    pub(crate) hidden_files: bool,
+    /// Absolute path prefixes, beneath which nothing is searched
+    pub(crate) exclude_paths: Vec<PathBuf>,
+}
+
+impl Prune {
+    /// New up a Prune, making the excluded path prefixes absolute.
+    pub(crate) fn new(skip: &[String], case_insensitive: bool, hidden_dirs: bool, hidden_files: bool,
+                      exclude_paths: &[PathBuf]) -> Self {
+        Self {
+            skip: skip.to_vec(),
+            case_insensitive,
+            hidden_dirs,
+            hidden_files,
+            exclude_paths: exclude_paths.iter()
+                                        .map(|prefix| path::absolute(prefix).unwrap_or_else(|_| prefix.clone()))
+                                        .collect(),
+        }
+    }
+
+    /// Should the entry at the supplied path and depth be left out? The
+    /// start directory (depth 0) is never hidden. Skipped entries are
+    /// logged, with the reason.
+    pub(crate) fn skips(&self, path: &Path, is_dir: bool, depth: usize) -> bool {
+        let reason = self.reason(path, is_dir, depth);
+        if let Some(reason) = reason {
+            debug!(path = %path.display(), reason, "skipped");
+        }
+        reason.is_some()
+    }
+
+    /// Why the entry at the supplied path and depth is left out, if it is.
+    pub(crate) fn reason(&self, path: &Path, is_dir: bool, depth: usize) -> Option<&'static str> {
+        let name = path.file_name().unwrap_or_default();
+        let hidden = if is_dir { self.hidden_dirs } else { self.hidden_files };
+        if hidden && depth > 0 && is_hidden(name) {
+            Some("hidden")
+        } else if matches_list(name, &self.skip, self.case_insensitive) {
+            Some("in the skip list")
+        } else if self.excluded(path) {
+            Some("beneath an excluded path")
+        } else {
+            None
+        }
+    }
+
+    // does the path fall beneath one of the excluded path prefixes?
+    fn excluded(&self, path: &Path) -> bool {
+        if self.exclude_paths.is_empty() {
+            return false;
+        }
+        path::absolute(path).is_ok_and(|path| self.exclude_paths.iter().any(|prefix| path.starts_with(prefix)))
+    }
+}
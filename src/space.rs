@@ -0,0 +1,205 @@
+//! space.rs
+//!
+//! Applying an action only until enough space has been freed. Our goal is
+//! usually to bring a volume below some level of use, rather than to remove
+//! everything older than a certain age, so FreeTarget holds the matches
+//! back until the search completes, then hands them on oldest first, until
+//! the free space on their filesystem would reach the target.
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::{
+    constants::BUFFER_BYTES,
+    errors::AmbleError,
+    fields::Availability,
+    filematch::FileMatch,
+    size::Size,
+    spill::SpillSort,
+    traits::Sink,
+};
+
+/// The free space to reach, either as a size, or as a percentage of the
+/// filesystem (eg "500G" or "10%").
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpaceTarget {
+    /// A number of bytes
+    Bytes(u64),
+    /// A percentage of the size of the filesystem
+    Percent(f64),
+}
+
+impl SpaceTarget {
+    /// The number of bytes to reach, on a filesystem of the supplied size.
+    pub fn bytes(&self, total: u64) -> u64 {
+        match self {
+            SpaceTarget::Bytes(bytes) => *bytes,
+            SpaceTarget::Percent(percent) => (total as f64 * percent / 100.0).ceil() as u64,
+        }
+    }
+}
+
+impl FromStr for SpaceTarget {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().strip_suffix('%') {
+            Some(percent) => match percent.trim().parse::<f64>() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(SpaceTarget::Percent(percent)),
+                _ => Err(AmbleError::ParseError(format!("invalid free space target '{}'. Expected eg 500G or 10%", s))),
+            },
+            None => Ok(SpaceTarget::Bytes(s.parse::<Size>()?.0)),
+        }
+    }
+}
+
+impl fmt::Display for SpaceTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpaceTarget::Bytes(bytes) => write!(f, "{} bytes", bytes),
+            SpaceTarget::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+/// The space on a filesystem, as reported by statvfs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsSpace {
+    /// Identifies the filesystem
+    pub id: u64,
+    /// Bytes available to unprivileged users
+    pub available: u64,
+    /// Size of the filesystem in bytes
+    pub total: u64,
+}
+
+/// The space on the filesystem holding the supplied path (UNIX ONLY).
+#[cfg(unix)]
+pub fn fs_space(path: &Path) -> Result<FsSpace, AmbleError> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| AmbleError::IoError(format!("invalid path '{}'", path.display())))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(AmbleError::IoError(format!("unable to stat filesystem of '{}': {}",
+                                               path.display(), std::io::Error::last_os_error())));
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Ok(FsSpace {
+        id: stat.f_fsid as u64,
+        available: stat.f_bavail as u64 * stat.f_frsize as u64,
+        total: stat.f_blocks as u64 * stat.f_frsize as u64,
+    })
+}
+
+/// The space on the filesystem holding the supplied path (UNIX ONLY).
+#[cfg(not(unix))]
+pub fn fs_space(_path: &Path) -> Result<FsSpace, AmbleError> {
+    Err(AmbleError::IoError("free space is only available on unix".to_string()))
+}
+
+// Matches sorted oldest first, holding up to `limit` bytes of them in
+// memory.
+fn oldest_first(limit: u64) -> SpillSort<FileMatch> {
+    SpillSort::new(limit, |a: &FileMatch, b: &FileMatch| a.effective_time().cmp(&b.effective_time()))
+}
+
+/// Holds matches back until the search completes, then hands them to the
+/// wrapped sink, typically one applying an action, oldest first, by their
+/// effective times (see FileMatch::effective_time). Once the matches
+/// handed on from a filesystem would bring its free space to the target,
+/// the rest of its matches are left alone. Each match is assumed to free
+/// its allocated size, so a dry run stops where the real run would. A
+/// match whose filesystem cannot be read, such as one removed since the
+/// search, is left alone too, and counted as an error. Matches held back
+/// beyond a limit on their memory are spilled to temporary files (see
+/// spill.rs).
+#[derive(Debug)]
+pub struct FreeTarget<S: Sink> {
+    sink: S,
+    target: SpaceTarget,
+    matches: SpillSort<FileMatch>,
+    // the projected free space, and the target, by filesystem
+    filesystems: HashMap<u64, (u64, u64)>,
+    left: u64,
+    failed: u64,
+}
+
+impl<S: Sink> FreeTarget<S> {
+    /// New up a FreeTarget handing matches to the supplied sink until the
+    /// target is reached.
+    pub fn new(sink: S, target: SpaceTarget) -> Self {
+        Self { sink, target, matches: oldest_first(BUFFER_BYTES), filesystems: HashMap::new(), left: 0,
+               failed: 0 }
+    }
+
+    /// Set the number of bytes of matches which may be held in memory,
+    /// beyond which they are spilled to temporary files. The default is
+    /// BUFFER_BYTES.
+    pub fn buffer_limit(&mut self, limit: u64) -> &mut Self {
+        self.matches = oldest_first(limit);
+        self
+    }
+
+    /// Number of matches left alone, as the target had been reached.
+    pub fn left(&self) -> u64 {
+        self.left
+    }
+
+    // Has the target been reached on the filesystem of the match? If not,
+    // count the match's space towards it.
+    fn reached(&mut self, found: &FileMatch) -> Result<bool, AmbleError> {
+        // the filesystem is that of the directory holding the match, which
+        // may be a broken link, or no longer exist
+        let parent = match found.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let space = fs_space(parent)?;
+        let target = self.target;
+        let (free, wanted) = self.filesystems.entry(space.id)
+            .or_insert_with(|| (space.available, target.bytes(space.total)));
+        if *free >= *wanted {
+            return Ok(true);
+        }
+        *free += found.allocated;
+        Ok(false)
+    }
+}
+
+impl<S: Sink> Sink for FreeTarget<S> {
+    fn begin(&mut self, availability: &Availability) -> Result<(), AmbleError> {
+        self.sink.begin(availability)
+    }
+
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        self.matches.push(found)
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        let matches = std::mem::replace(&mut self.matches, oldest_first(BUFFER_BYTES));
+        for found in matches.sorted()? {
+            let found = found?;
+            match self.reached(&found) {
+                Ok(true) => self.left += 1,
+                Ok(false) => self.sink.accept(found)?,
+                Err(e) => {
+                    eprintln!("Error: unable to find the free space for '{}': {}", found.path.display(), e);
+                    self.failed += 1;
+                },
+            }
+        }
+        self.sink.finish()?;
+        if self.left > 0 {
+            println!("# free space target of {} reached, leaving {} matches", self.target, self.left);
+        }
+        Ok(())
+    }
+
+    fn errors(&self) -> u64 {
+        self.sink.errors() + self.failed
+    }
+}
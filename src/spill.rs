@@ -0,0 +1,451 @@
+//! spill.rs
+//!
+//! Memory bounded sorting, for sinks which hold every match back until
+//! the search completes. Items are gathered in memory until they would
+//! take more than the limit, when they are sorted and spilled to a
+//! temporary file, one JSON item per line. Once every item has been
+//! pushed, the spilled runs are merged with those still in memory (an
+//! external merge sort), so only the head of each run is held at once.
+//! Items which compare equal keep the order they were pushed in.
+//!
+//! Reports which aggregate matches by key, such as by directory, work the
+//! same way, so that their memory is bounded however many keys there are.
+//! Values are combined by key in memory until they would take more than
+//! the limit, when they are spilled in order of their keys, and the runs
+//! are merged once every value has been added, combining the values of
+//! each key, so the aggregates are reported in order of their keys.
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hash;
+use std::io::{BufReader, BufWriter, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{errors::AmbleError, filematch::FileMatch};
+
+/// An item which may be spilled to disk.
+pub trait Spill: Serialize + DeserializeOwned + 'static {
+    /// Approximate number of bytes of memory taken by the item.
+    fn footprint(&self) -> usize;
+}
+
+impl Spill for FileMatch {
+    fn footprint(&self) -> usize {
+        let paths = self.links.iter().chain(&self.target).chain(&self.root).map(|path| path.as_os_str().len()).sum::<usize>();
+        mem::size_of::<Self>() + self.path.as_os_str().len() + paths
+            + self.links.len() * mem::size_of::<PathBuf>()
+            + self.meta.len() + self.missing.len() + self.future.len() + self.flags.len()
+    }
+}
+
+impl Spill for String {
+    fn footprint(&self) -> usize {
+        mem::size_of::<Self>() + self.len()
+    }
+}
+
+impl<K: Spill, V: Spill> Spill for (K, V) {
+    fn footprint(&self) -> usize {
+        self.0.footprint() + self.1.footprint()
+    }
+}
+
+/// A value which accumulates the others of its key, in a SpillMap.
+pub trait Combine {
+    /// Fold another value of the same key into this one.
+    fn combine(&mut self, other: Self);
+}
+
+// Numbers the temporary files of the process
+static SPILLS: AtomicUsize = AtomicUsize::new(0);
+// Fewest items spilled in a run, however small the limit
+const MIN_RUN: usize = 1024;
+// Most runs open at once. Beyond this, the runs are merged into one
+const MAX_RUNS: usize = 64;
+
+// A run of items spilled to a temporary file, which is removed once the
+// run is dropped.
+#[derive(Debug)]
+struct Run {
+    path: PathBuf,
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Sorts items, spilling them to temporary files beyond a limit on the
+/// memory they take.
+#[derive(Debug)]
+pub struct SpillSort<T: Spill> {
+    items: Vec<T>,
+    bytes: usize,
+    limit: usize,
+    compare: fn(&T, &T) -> Ordering,
+    runs: Vec<Run>,
+}
+
+impl<T: Spill> SpillSort<T> {
+    /// New up a SpillSort ordering items by the supplied comparison, and
+    /// holding up to `limit` bytes of them in memory.
+    pub fn new(limit: u64, compare: fn(&T, &T) -> Ordering) -> Self {
+        Self { items: Vec::new(), bytes: 0, limit: limit as usize, compare, runs: Vec::new() }
+    }
+
+    /// Number of runs spilled to disk so far.
+    pub fn spilled(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Add an item, spilling the items in memory to disk once they take
+    /// more than the limit.
+    pub fn push(&mut self, item: T) -> Result<(), AmbleError> {
+        self.bytes += item.footprint();
+        self.items.push(item);
+        if self.bytes > self.limit && self.items.len() >= MIN_RUN {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    // Sort the items in memory, and write them to a new run, merging the
+    // runs once there are too many to hold open.
+    fn spill(&mut self) -> Result<(), AmbleError> {
+        let compare = self.compare;
+        self.items.sort_by(compare);
+        let run = write_run(self.items.drain(..).map(Ok))?;
+        self.runs.push(run);
+        self.bytes = 0;
+        if self.runs.len() >= MAX_RUNS {
+            let merged = self.merge(Vec::new())?;
+            self.runs = vec![write_run(merged)?];
+        }
+        Ok(())
+    }
+
+    // Merge the spilled runs with the supplied items, which are in order.
+    fn merge(&mut self, items: Vec<T>) -> Result<Sorted<T>, AmbleError> {
+        merge_runs(mem::take(&mut self.runs), items, self.compare)
+    }
+
+    /// The items, in order, merged from the runs spilled to disk and those
+    /// still in memory.
+    pub fn sorted(mut self) -> Result<Sorted<T>, AmbleError> {
+        let mut items = mem::take(&mut self.items);
+        items.sort_by(self.compare);
+        self.merge(items)
+    }
+}
+
+/// Aggregates values by key, spilling them to temporary files, in order of
+/// their keys, beyond a limit on the memory they take.
+#[derive(Debug)]
+pub struct SpillMap<K, V> {
+    entries: HashMap<K, V>,
+    bytes: usize,
+    limit: usize,
+    runs: Vec<Run>,
+}
+
+impl<K: Spill + Ord + Hash, V: Spill + Combine> SpillMap<K, V> {
+    /// New up a SpillMap holding up to `limit` bytes of keys and values in
+    /// memory.
+    pub fn new(limit: u64) -> Self {
+        Self { entries: HashMap::new(), bytes: 0, limit: limit as usize, runs: Vec::new() }
+    }
+
+    /// Number of runs spilled to disk so far.
+    pub fn spilled(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// The value of the supplied key, among those still in memory.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key)
+    }
+
+    /// Combine a value with any other of its key, spilling the values in
+    /// memory to disk once they take more than the limit.
+    pub fn add(&mut self, key: K, value: V) -> Result<(), AmbleError> {
+        if let Some(existing) = self.entries.get_mut(&key) {
+            existing.combine(value);
+            return Ok(());
+        }
+        self.bytes += key.footprint() + value.footprint();
+        self.entries.insert(key, value);
+        if self.bytes > self.limit && self.entries.len() >= MIN_RUN {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    // The values in memory, in order of their keys.
+    fn drain_sorted(&mut self) -> Vec<(K, V)> {
+        let mut entries: Vec<(K, V)> = self.entries.drain().collect();
+        entries.sort_by(by_key);
+        self.bytes = 0;
+        entries
+    }
+
+    // Write the values in memory to a new run, merging the runs once there
+    // are too many to hold open.
+    fn spill(&mut self) -> Result<(), AmbleError> {
+        let entries = self.drain_sorted();
+        self.runs.push(write_run(entries.into_iter().map(Ok))?);
+        if self.runs.len() >= MAX_RUNS {
+            let sorted = merge_runs(mem::take(&mut self.runs), Vec::new(), by_key::<K, V>)?;
+            self.runs = vec![write_run(Aggregated { sorted, next: None })?];
+        }
+        Ok(())
+    }
+
+    /// The values, combined by key, in order of their keys, merged from the
+    /// runs spilled to disk and those still in memory.
+    pub fn aggregated(mut self) -> Result<Aggregated<K, V>, AmbleError> {
+        let entries = self.drain_sorted();
+        Ok(Aggregated { sorted: merge_runs(mem::take(&mut self.runs), entries, by_key)?, next: None })
+    }
+}
+
+// Order entries by their keys.
+fn by_key<K: Ord, V>(a: &(K, V), b: &(K, V)) -> Ordering {
+    a.0.cmp(&b.0)
+}
+
+/// The merged values of a SpillMap, combined by key, in order of their
+/// keys. Reading a spilled value may fail.
+pub struct Aggregated<K, V> {
+    sorted: Sorted<(K, V)>,
+    // the first entry of the next key, read while combining the last
+    next: Option<(K, V)>,
+}
+
+impl<K: Ord, V: Combine> Iterator for Aggregated<K, V> {
+    type Item = Result<(K, V), AmbleError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, mut value) = match self.next.take() {
+            Some(entry) => entry,
+            None => match self.sorted.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            },
+        };
+        for entry in &mut self.sorted {
+            match entry {
+                Ok((other, more)) if other == key => value.combine(more),
+                Ok(entry) => {
+                    self.next = Some(entry);
+                    break;
+                },
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(Ok((key, value)))
+    }
+}
+
+// Merge the supplied runs with the supplied items, which are in order.
+fn merge_runs<T: Spill>(runs: Vec<Run>, items: Vec<T>, compare: fn(&T, &T) -> Ordering)
+-> Result<Sorted<T>, AmbleError> {
+    let mut sources = Vec::with_capacity(runs.len() + 1);
+    for run in runs {
+        let reader = BufReader::new(File::open(&run.path)?);
+        let mut items = Box::new(serde_json::Deserializer::from_reader(reader).into_iter::<T>()
+                                    .map(|item| item.map_err(AmbleError::from)));
+        let head = items.next().transpose()?;
+        sources.push(Source { head, items, _run: Some(run) });
+    }
+    // the items in memory were pushed last, so come last among equals
+    let mut items = Box::new(items.into_iter().map(Ok));
+    let head = items.next().transpose()?;
+    sources.push(Source { head, items, _run: None });
+    Ok(Sorted { sources, compare })
+}
+
+// Write items, which are in order, to a new run.
+fn write_run<T: Spill>(items: impl Iterator<Item = Result<T, AmbleError>>) -> Result<Run, AmbleError> {
+    let path = std::env::temp_dir().join(format!("amble-spill-{}-{}.jsonl", process::id(),
+                                                  SPILLS.fetch_add(1, AtomicOrdering::Relaxed)));
+    let run = Run { path };
+    let file = OpenOptions::new().write(true).create_new(true).open(&run.path)?;
+    let mut writer = BufWriter::new(file);
+    for item in items {
+        serde_json::to_writer(&mut writer, &item?)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(run)
+}
+
+// A sorted run of items being merged, with the next of them.
+struct Source<T> {
+    head: Option<T>,
+    items: Box<dyn Iterator<Item = Result<T, AmbleError>>>,
+    _run: Option<Run>,
+}
+
+/// The merged items of a SpillSort, in order. Reading a spilled item may
+/// fail.
+pub struct Sorted<T> {
+    sources: Vec<Source<T>>,
+    compare: fn(&T, &T) -> Ordering,
+}
+
+impl<T> Iterator for Sorted<T> {
+    type Item = Result<T, AmbleError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // there are few runs, so the least head is found by scanning them,
+        // favoring earlier runs among equals
+        let mut least: Option<usize> = None;
+        for (index, source) in self.sources.iter().enumerate() {
+            if let Some(head) = &source.head {
+                let less = least.and_then(|least| self.sources[least].head.as_ref())
+                                .is_none_or(|least| (self.compare)(head, least) == Ordering::Less);
+                if less {
+                    least = Some(index);
+                }
+            }
+        }
+        let source = &mut self.sources[least?];
+        match source.items.next().transpose() {
+            Ok(next) => Some(Ok(mem::replace(&mut source.head, next)?)),
+            Err(e) => {
+                source.head = None;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+// A path, as text where it is valid unicode, and otherwise as its raw bytes
+// (unix) or UTF-16 code units (windows), so that it survives the round trip.
+#[derive(Serialize, Deserialize)]
+enum EncodedPath {
+    #[serde(rename = "t")]
+    Text(String),
+    #[serde(rename = "r")]
+    Raw(Vec<u8>),
+    #[serde(rename = "w")]
+    Wide(Vec<u16>),
+}
+
+impl From<&Path> for EncodedPath {
+    fn from(path: &Path) -> Self {
+        if let Some(text) = path.to_str() {
+            return EncodedPath::Text(text.to_string());
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            EncodedPath::Raw(path.as_os_str().as_bytes().to_vec())
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::ffi::OsStrExt;
+            EncodedPath::Wide(path.as_os_str().encode_wide().collect())
+        }
+        #[cfg(not(any(unix, windows)))]
+        {
+            EncodedPath::Text(path.to_string_lossy().into_owned())
+        }
+    }
+}
+
+impl From<EncodedPath> for PathBuf {
+    fn from(path: EncodedPath) -> Self {
+        match path {
+            EncodedPath::Text(text) => PathBuf::from(text),
+            #[cfg(unix)]
+            EncodedPath::Raw(bytes) => {
+                use std::os::unix::ffi::OsStringExt;
+                PathBuf::from(std::ffi::OsString::from_vec(bytes))
+            },
+            #[cfg(windows)]
+            EncodedPath::Wide(wide) => {
+                use std::os::windows::ffi::OsStringExt;
+                PathBuf::from(std::ffi::OsString::from_wide(&wide))
+            },
+            #[cfg(not(unix))]
+            EncodedPath::Raw(bytes) => PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()),
+            #[cfg(not(windows))]
+            EncodedPath::Wide(wide) => PathBuf::from(String::from_utf16_lossy(&wide)),
+        }
+    }
+}
+
+/// Serde support for a path which may not be valid unicode.
+pub(crate) mod path {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        EncodedPath::from(path).serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        EncodedPath::deserialize(deserializer).map(PathBuf::from)
+    }
+}
+
+/// Serde support for an optional path which may not be valid unicode.
+pub(crate) mod optional_path {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error> {
+        path.as_deref().map(EncodedPath::from).serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PathBuf>, D::Error> {
+        Option::<EncodedPath>::deserialize(deserializer).map(|path| path.map(PathBuf::from))
+    }
+}
+
+/// Serde support for paths which may not be valid unicode.
+pub(crate) mod paths {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(paths: &[PathBuf], serializer: S) -> Result<S::Ok, S::Error> {
+        paths.iter().map(|path| EncodedPath::from(path.as_path())).collect::<Vec<_>>().serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PathBuf>, D::Error> {
+        Vec::<EncodedPath>::deserialize(deserializer).map(|paths| paths.into_iter().map(PathBuf::from).collect())
+    }
+}
+
+/// Serde support for an optional timestamp, which may precede the epoch,
+/// as whole seconds since the epoch and the nanoseconds past them.
+pub(crate) mod optional_time {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(time: &Option<SystemTime>, serializer: S) -> Result<S::Ok, S::Error> {
+        time.map(|time| match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+            Err(before) => {
+                let before = before.duration();
+                match before.subsec_nanos() {
+                    0 => (-(before.as_secs() as i64), 0),
+                    nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+                }
+            },
+        }).serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<SystemTime>, D::Error> {
+        let time = Option::<(i64, u32)>::deserialize(deserializer)?;
+        Ok(time.map(|(secs, nanos)| {
+            let seconds = Duration::from_secs(secs.unsigned_abs());
+            let since = if secs >= 0 { UNIX_EPOCH + seconds } else { UNIX_EPOCH - seconds };
+            since + Duration::from_nanos(nanos.into())
+        }))
+    }
+}
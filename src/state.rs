@@ -0,0 +1,123 @@
+//! state.rs
+//!
+//! Incremental scans. The matches of each run are recorded in a state
+//! file, so that the next run may report only the entries which newly
+//! match, or which have changed - in modification time or size - since.
+//! Each line of the state file is a JSON object: the modification time of
+//! the match (in nanoseconds since the epoch, or null if unknown), its
+//! size, and its path, kept intact whether or not it is valid unicode. The
+//! file is replaced once the search completes, so an interrupted run
+//! leaves the previous state in place.
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::AmbleError, fields::Availability, filematch::FileMatch, traits::Sink};
+
+/// The modification time (in nanoseconds since the epoch) and size of a
+/// match, as recorded in the state.
+pub type Stamp = (Option<i128>, u64);
+
+// A line of the state file
+#[derive(Debug, Serialize, Deserialize)]
+struct Recorded {
+    modified: Option<i128>,
+    size: u64,
+    #[serde(with = "crate::spill::path")]
+    path: PathBuf,
+}
+
+// Nanoseconds since the epoch, negative for times before it
+fn nanos(time: SystemTime) -> i128 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_nanos() as i128,
+        Err(before) => -(before.duration().as_nanos() as i128),
+    }
+}
+
+/// Read the matches recorded in a state file. A missing file records no
+/// matches, as on the first run.
+pub fn read_state(path: &Path) -> Result<HashMap<PathBuf, Stamp>, AmbleError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut state = HashMap::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let recorded: Recorded = serde_json::from_str(&line).map_err(|e| {
+            AmbleError::ParseError(format!("invalid line {} of state file {}: {}", number + 1, path.display(), e))
+        })?;
+        state.insert(recorded.path, (recorded.modified, recorded.size));
+    }
+    Ok(state)
+}
+
+/// Sink which hands on only the matches which were not recorded in the
+/// previous state, or whose modification time or size has changed since,
+/// and records every match as the new state once the search completes.
+#[derive(Debug)]
+pub struct Incremental<S: Sink> {
+    sink: S,
+    path: PathBuf,
+    previous: HashMap<PathBuf, Stamp>,
+    current: Vec<Recorded>,
+}
+
+impl<S: Sink> Incremental<S> {
+    /// New up an Incremental handing new and changed matches to the
+    /// supplied sink, reading the previous state from, and writing the new
+    /// state to, the supplied path.
+    pub fn new(sink: S, path: impl Into<PathBuf>) -> Result<Self, AmbleError> {
+        let path = path.into();
+        let previous = read_state(&path)?;
+        Ok(Self { sink, path, previous, current: Vec::new() })
+    }
+
+    // Write the new state beside the state file, then move it into place.
+    fn write_state(&self) -> Result<(), AmbleError> {
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        for recorded in &self.current {
+            serde_json::to_writer(&mut writer, recorded)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl<S: Sink> Sink for Incremental<S> {
+    fn begin(&mut self, availability: &Availability) -> Result<(), AmbleError> {
+        self.sink.begin(availability)
+    }
+
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        let stamp = (found.modified.map(nanos), found.size);
+        let unchanged = self.previous.get(&found.path) == Some(&stamp);
+        self.current.push(Recorded { modified: stamp.0, size: stamp.1, path: found.path.clone() });
+        if unchanged {
+            return Ok(());
+        }
+        self.sink.accept(found)
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        self.sink.finish()?;
+        self.write_state()
+    }
+
+    fn errors(&self) -> u64 {
+        self.sink.errors()
+    }
+}
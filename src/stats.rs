@@ -0,0 +1,144 @@
+//! stats.rs
+//!
+//! Statistics gathered by a Finder over the course of a search, and
+//! returned once it completes.
+use std::fs::Metadata;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Statistics for a completed search.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanStats {
+    /// Number of entries which could not be read, and were skipped
+    pub errors: u64,
+    /// The instant the ages of files were computed relative to, which is
+    /// snapshotted once, as the search starts, unless supplied via `as_of`
+    pub reference: Option<SystemTime>,
+    /// Number of files evaluated
+    pub files: u64,
+    /// Number of directories traversed
+    pub dirs: u64,
+    /// Number of times the metadata of an entry was read (stat calls)
+    pub stat_calls: u64,
+    /// How long the search took
+    pub elapsed: Duration,
+    /// CPU time spent by each of the threads traversing the tree, for
+    /// searches which traverse in parallel (unix only)
+    pub threads: Vec<Duration>,
+    /// For searches which adapt their concurrency, the fewest threads which
+    /// were allowed to work at once
+    pub fewest_threads: Option<usize>,
+}
+
+impl ScanStats {
+    /// Bytes of metadata read, one Metadata per stat call.
+    pub fn metadata_bytes(&self) -> u64 {
+        self.stat_calls * mem::size_of::<Metadata>() as u64
+    }
+
+    /// The rate at which the supplied count was processed, per second.
+    pub fn rate(&self, count: u64) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => count as f64 / secs,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Counts of the progress of a search, as it runs, which may be read by
+/// another thread, to report it. See `live_progress`.
+#[derive(Debug, Default)]
+pub struct ScanProgress {
+    files: AtomicU64,
+    dirs: AtomicU64,
+    matches: AtomicU64,
+}
+
+impl ScanProgress {
+    /// Number of files evaluated so far
+    pub fn files(&self) -> u64 {
+        self.files.load(Ordering::Relaxed)
+    }
+
+    /// Number of directories traversed so far
+    pub fn dirs(&self) -> u64 {
+        self.dirs.load(Ordering::Relaxed)
+    }
+
+    /// Number of matches found so far
+    pub fn matches(&self) -> u64 {
+        self.matches.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts of the work done by a search, which may be shared between the
+/// threads doing it.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    files: AtomicU64,
+    dirs: AtomicU64,
+    stat_calls: AtomicU64,
+    // the progress reported as the search runs, if any
+    live: Option<Arc<ScanProgress>>,
+}
+
+impl Counters {
+    /// New up Counters, which also count into the supplied progress.
+    pub(crate) fn reporting(live: Option<Arc<ScanProgress>>) -> Self {
+        Self { live, ..Self::default() }
+    }
+
+    /// Count a file evaluated.
+    pub(crate) fn file(&self) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        if let Some(live) = &self.live {
+            live.files.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a directory traversed.
+    pub(crate) fn dir(&self) {
+        self.dirs.fetch_add(1, Ordering::Relaxed);
+        if let Some(live) = &self.live {
+            live.dirs.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a match found. Only the progress counts matches, as the
+    /// statistics of a search do not.
+    pub(crate) fn matched(&self) {
+        if let Some(live) = &self.live {
+            live.matches.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count reads of metadata.
+    pub(crate) fn stats(&self, calls: u64) {
+        self.stat_calls.fetch_add(calls, Ordering::Relaxed);
+    }
+
+    /// Record the counts in the supplied statistics.
+    pub(crate) fn record(&self, stats: &mut ScanStats) {
+        stats.files = self.files.load(Ordering::Relaxed);
+        stats.dirs = self.dirs.load(Ordering::Relaxed);
+        stats.stat_calls = self.stat_calls.load(Ordering::Relaxed);
+    }
+}
+
+/// CPU time spent by the calling thread so far, if available.
+pub(crate) fn thread_cpu_time() -> Option<Duration> {
+    #[cfg(unix)]
+    {
+        let mut time: libc::timespec = unsafe { mem::zeroed() };
+        if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) } != 0 {
+            return None;
+        }
+        Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+    }
+    #[cfg(not(unix))]
+    {
+        None
+    }
+}
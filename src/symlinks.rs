@@ -0,0 +1,29 @@
+//! symlinks.rs
+//!
+//! Detection of dangling (broken) symbolic links. When following links,
+//! the walkers see a dangling link as an error, as its target cannot be
+//! stat'ed. These helpers allow such errors to be recognized and
+//! reported as broken links instead.
+use std::fs::{self, Metadata};
+use std::path::Path;
+
+/// If the supplied path is a symbolic link whose target does not exist
+/// (or cannot be resolved), return the metadata of the link itself.
+pub fn dangling_link(path: &Path) -> Option<Metadata> {
+    let link_metadata = fs::symlink_metadata(path).ok()?;
+    if link_metadata.file_type().is_symlink() && fs::metadata(path).is_err() {
+        Some(link_metadata)
+    } else {
+        None
+    }
+}
+
+/// Retrieve the path associated with an error from the ignore crate, if any.
+pub(crate) fn ignore_error_path(error: &ignore::Error) -> Option<&Path> {
+    match error {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::WithDepth { err, .. } => ignore_error_path(err),
+        ignore::Error::WithLineNumber { err, .. } => ignore_error_path(err),
+        _ => None,
+    }
+}
@@ -2,37 +2,219 @@
 //!
 //! Single threaded traversal of directory usiing the walkdir crate.
 //! This is a bit slower than asyncwalk, but returns results in order.
-use std::path::PathBuf;
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, Metadata};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use crossbeam_channel as channel;
+use tracing::{debug, info};
 use walkdir::{WalkDir, DirEntry};
-use crate::{ errors::AmbleError, constants::SECS_PER_DAY };
-use super::traits::Finder;
+use crate::{ backend::{Backend, WalkOrder}, classify::Classifier, constants::{CHANNEL_CAPACITY, CHECKPOINT_SECS, SECS_PER_DAY}, criteria::Criteria, duration::to_duration, errors::AmbleError, indicator::eprint_above, fields::{Availability, Field}, filematch::FileMatch, hardlinks::{inode, InodeCache, LinkCount}, mounts::MountLimits, prefetch::MetadataPool, owner::effective_uid, resume::{Checkpoint, Pending}, roots::{dedup_roots, Attribute},
+             rollup::{Rollup, Visit}, size::SizeMode, skip::{read_skip_list, Prune}, stats::{thread_cpu_time, Counters, ScanProgress, ScanStats},
+             symlinks::dangling_link, uring::Prefetcher };
+use super::traits::{Finder, Sink};
 
+// A subdirectory left to search, with its (device, inode) pair when known
+type Subdir = (PathBuf, Option<(u64, u64)>);
+
+// Sink which forwards the matches of a thread searching roots to the thread
+// reporting them.
+struct Forward(channel::Sender<FileMatch>);
+
+impl Sink for Forward {
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        self.0.send(found).map_err(|_| AmbleError::UnexpectedResult("matches are no longer being reported".to_string()))
+    }
+}
+
+// The state of a search shared between the threads searching its roots.
+#[derive(Clone)]
+struct Shared {
+    // (device, inode) pairs of the directories visited so far. As we
+    // follow symlinks, the same directory may be reached more than once,
+    // including from other roots, searched by other threads.
+    visited: Arc<Mutex<HashSet<(u64, u64)>>>,
+    // number of matches found so far, by every thread
+    count: Arc<AtomicUsize>,
+    // caps the reads in flight on each mount
+    mounts: Arc<MountLimits>,
+    // reads metadata ahead of the search, when prefetching
+    pool: Option<Arc<MetadataPool>>,
+    // the progress reported as the search runs, if any
+    live: Option<Arc<ScanProgress>>,
+}
+
+impl Shared {
+    fn new(roots: &[PathBuf], max_per_mount: Option<usize>, prefetch: Option<usize>,
+           live: Option<Arc<ScanProgress>>) -> Self {
+        let mounts = Arc::new(MountLimits::new(roots, max_per_mount));
+        let pool = prefetch.map(|threads| Arc::new(MetadataPool::new(threads, Arc::clone(&mounts))));
+        Self { visited: Arc::default(), count: Arc::default(), mounts, pool, live }
+    }
+}
+
+// The progress of a search, shared between its roots.
+struct Progress {
+    // state shared with the other threads of the search
+    shared: Shared,
+    // number of entries which could not be read
+    errors: u64,
+    // rolls matches up into their directories, when enabled
+    rollup: Rollup,
+    // prefetches the metadata of each directory, with the io_uring backend
+    prefetcher: Option<Prefetcher>,
+    // counts of the files, directories, and stat calls
+    counters: Counters,
+    // the metadata of files with multiple hard links, read so far
+    inodes: InodeCache,
+    // the device of the directory being traversed at each depth
+    dir_devs: Vec<Option<u64>>,
+}
+
+impl Progress {
+    fn new(rollup: bool, shared: Shared) -> Self {
+        let counters = Counters::reporting(shared.live.clone());
+        Self { shared, errors: 0, rollup: Rollup::new(rollup), prefetcher: None, counters,
+               inodes: InodeCache::default(), dir_devs: Vec::new() }
+    }
+
+    // The statistics of the search, which started at `started`, with ages
+    // relative to `reference`.
+    fn stats(&self, reference: SystemTime, started: Instant) -> ScanStats {
+        let mut stats = ScanStats { errors: self.errors, reference: Some(reference), elapsed: started.elapsed(),
+                                    ..ScanStats::default() };
+        self.counters.record(&mut stats);
+        stats
+    }
+
+    // The metadata of the file at the supplied path, with the supplied
+    // (device, inode) pair, if known. Files with multiple hard links are only
+    // read once.
+    fn file_metadata<E>(&mut self, path: &Path, id: Option<(u64, u64)>,
+                        read: impl FnOnce() -> Result<Metadata, E>)
+    -> Result<Metadata, E> {
+        let mounts = Arc::clone(&self.shared.mounts);
+        self.read_metadata(id, || {
+            let _permit = mounts.acquire(path);
+            read()
+        })
+    }
+
+    // As file_metadata, for metadata read under a permit of its own, such
+    // as by the prefetch pool.
+    fn read_metadata<E>(&mut self, id: Option<(u64, u64)>, read: impl FnOnce() -> Result<Metadata, E>)
+    -> Result<Metadata, E> {
+        if let Some(metadata) = id.and_then(|id| self.inodes.get(id)) {
+            return Ok(metadata);
+        }
+        self.counters.stats(1);
+        let metadata = read()?;
+        self.inodes.insert(&metadata);
+        Ok(metadata)
+    }
+
+    // have the maximum number of matches been found?
+    fn limit_reached(&self, max_results: Option<usize>) -> bool {
+        max_results.is_some_and(|max| self.shared.count.load(AtomicOrdering::SeqCst) >= max)
+    }
+
+    // count a match found
+    fn found(&self) {
+        self.shared.count.fetch_add(1, AtomicOrdering::SeqCst);
+        self.counters.matched();
+    }
+
+    // Is this the first time the directory has been visited? Directories are
+    // identified by their (device, inode) pair, which is recorded in
+    // `visited`. If the pair cannot be determined, we assume that it is.
+    fn first_visit(&self, id: Option<(u64, u64)>) -> bool {
+        match id {
+            Some(id) => self.shared.visited.lock().map_or(true, |mut visited| visited.insert(id)),
+            None => true,
+        }
+    }
+}
+
+// The inode number of a directory entry, from the directory listing.
+#[cfg(unix)]
+fn dir_entry_ino(entry: &fs::DirEntry) -> Option<u64> {
+    use std::os::unix::fs::DirEntryExt;
+    Some(entry.ino())
+}
+
+#[cfg(not(unix))]
+fn dir_entry_ino(_entry: &fs::DirEntry) -> Option<u64> {
+    None
+}
+
+// The inode number of a walkdir entry, from the directory listing.
+#[cfg(unix)]
+fn walk_entry_ino(entry: &DirEntry) -> Option<u64> {
+    use walkdir::DirEntryExt;
+    Some(entry.ino())
+}
+
+#[cfg(not(unix))]
+fn walk_entry_ino(_entry: &DirEntry) -> Option<u64> {
+    None
+}
 
 /// Implements the Finder trait to perform syncronous searching of
 /// directory tree for files whose access, create, and/or modify
 /// metadata values are less than or equal to the supplied age in
 /// days, or fraction thereof.
 pub struct SyncSearch {
-    /// The root directory to search
-    start_dir: PathBuf,
-    /// The number of days back to search
-    days: f32,
-    /// Whether or not to check access time
-    access: bool,
-    /// Whether or not to check create time (not available on Linux)
-    create: bool,
-    /// Whether or not to check modification time
-    modify: bool,
-    /// Whether or not to ignore hidden files (files starting with a '.')
-    ignore_hidden: bool,
+    /// The root directories to search
+    roots: Vec<PathBuf>,
+    /// The metadata criteria used to evaluate each file
+    criteria: Criteria,
+    /// Whether or not to skip hidden directories (starting with a '.')
+    hidden_dirs: bool,
+    /// Whether or not to skip hidden files (starting with a '.')
+    hidden_files: bool,
+    /// Whether or not to follow symbolic links
+    follow_links: bool,
     /// A list of zero or more names to skip. These may either be directory names,
     /// in which case we skip any children, or file names, in which case
     /// we skip checking them.
     skip: Vec<String>,
+    /// Whether or not names are matched without regard to case
+    case_insensitive: bool,
+    /// Path prefixes beneath which nothing is searched
+    exclude_paths: Vec<PathBuf>,
+    /// The maximum number of matches to report before stopping
+    max_results: Option<usize>,
+    /// Candidate paths to evaluate in place of traversing the roots
+    candidates: Option<Vec<PathBuf>>,
+    /// Whether or not to report fully matching directories as single matches
+    rollup: bool,
+    /// How directories are traversed
+    backend: Backend,
+    /// The checkpoint of a resumable search
+    resume: Option<PathBuf>,
+    /// The order in which directories are walked
+    order: WalkOrder,
+    /// The number of threads searching roots at once
+    threads: Option<u8>,
+    /// The most reads of metadata in flight at once on any one mount
+    max_per_mount: Option<usize>,
+    /// The number of threads reading metadata ahead of the search
+    prefetch: Option<usize>,
+    /// Counts of the progress of the search, updated as it runs
+    live_progress: Option<Arc<ScanProgress>>,
 }
 
 impl SyncSearch {
 
+    /// The entries to leave out of the search.
+    pub(crate) fn prune(&self) -> Prune {
+        Prune::new(&self.skip, self.case_insensitive, self.hidden_dirs, self.hidden_files, &self.exclude_paths)
+    }
+
     /// New up a SyncSearch instance, supplying a start_dir.
     ///
     /// We default to:
@@ -40,54 +222,232 @@ impl SyncSearch {
     /// - access: true
     /// - create: true
     /// - modify: true
-    /// - ignore_hidden: true
+    /// - change: false
+    /// - hidden_dirs: true
+    /// - hidden_files: true
+    /// - follow_links: true
     /// - skip: []
+    /// - case_insensitive: false
+    /// - exclude_paths: []
+    /// - max_results: None
+    /// - candidates: None
+    /// - rollup: false
+    /// - backend: walkdir
+    /// - resume: None
+    /// - order: depth first
+    /// - threads: None
+    /// - max_per_mount: None
+    /// - prefetch: None
+    /// - live_progress: None
     ///
     pub fn new(start_dir: impl Into<PathBuf>) -> Self {
         Self {
-            start_dir: start_dir.into(),
-            days: 8.0,
-            access: true,
-            create: true,
-            modify: true,
-            ignore_hidden: true,
+            roots: vec![start_dir.into()],
+            criteria: Criteria::default(),
+            hidden_dirs: true,
+            hidden_files: true,
+            follow_links: true,
             skip: Vec::new(),
+            case_insensitive: false,
+            exclude_paths: Vec::new(),
+            max_results: None,
+            candidates: None,
+            rollup: false,
+            backend: Backend::Walkdir,
+            resume: None,
+            order: WalkOrder::DepthFirst,
+            threads: None,
+            max_per_mount: None,
+            prefetch: None,
+            live_progress: None,
         }
     }
 
-    /// reset the start directory for a search.
+    /// reset the start directory for a search, replacing any roots.
     pub fn start_dir(&mut self, start_dir: impl Into<PathBuf>) -> &mut Self {
-        self.start_dir = start_dir.into();
+        self.roots = vec![start_dir.into()];
+        self
+    }
+
+    /// Set the root directories to search. Roots which are nested within,
+    /// or the same as, another root are only searched once.
+    pub fn roots(&mut self, roots: Vec<PathBuf>) -> &mut Self {
+        self.roots = roots;
         self
     }
     /// Set the number of days to search for.
     pub fn days(&mut self, days: f32) -> &mut Self {
-        self.days = days;
+        self.criteria.window = to_duration(days, SECS_PER_DAY);
+        self
+    }
+
+    /// Compute ages relative to the supplied instant, rather than now, so
+    /// that a search may be reproduced, or re-run as of some past time.
+    pub fn as_of(&mut self, as_of: SystemTime) -> &mut Self {
+        self.criteria.as_of = Some(as_of);
+        self
+    }
+
+    /// Match timestamps later than the supplied time, such as the
+    /// modification time of a reference file, in place of days.
+    pub fn newer_than(&mut self, newer_than: Option<SystemTime>) -> &mut Self {
+        self.criteria.newer_than = newer_than;
+        self
+    }
+
+    /// Match timestamps earlier than the supplied time, in place of days.
+    pub fn older_than(&mut self, older_than: Option<SystemTime>) -> &mut Self {
+        self.criteria.older_than = older_than;
+        self
+    }
+
+    /// Widen windows by the supplied tolerance, to absorb clock drift
+    /// between hosts, such as NFS clients and servers. Timestamps are only
+    /// flagged as in the future when later than now by more than it.
+    pub fn skew(&mut self, skew: Duration) -> &mut Self {
+        self.criteria.skew = skew;
+        self
+    }
+
+    /// Test the effective time of each file, the latest of its access,
+    /// modification, and creation times, where available, in place of the
+    /// individual fields, so that a match has been touched in any way
+    /// within the number of days.
+    pub fn effective(&mut self, effective: bool) -> &mut Self {
+        self.criteria.effective = effective;
+        self
+    }
+
+    /// Count days in calendar terms, so that a window of N days begins at
+    /// local midnight N calendar days ago, rather than N * 24 hours ago.
+    /// Fractions of days are rounded up.
+    pub fn calendar_days(&mut self, calendar_days: bool) -> &mut Self {
+        self.criteria.calendar_days = calendar_days;
+        self
+    }
+
+    /// Set how far back to search, in place of days, precise to the
+    /// nanosecond.
+    pub fn window(&mut self, window: Duration) -> &mut Self {
+        self.criteria.window = window;
+        self
+    }
+
+    /// Use the supplied field, with its own window, overriding the window
+    /// set by `days` for this criterion.
+    pub fn field_window(&mut self, field: Field, window: Duration) -> &mut Self {
+        self.criteria.field_window(field, window);
+        self
+    }
+
+    /// Count days as business days, so that a window of N days begins N
+    /// weekdays ago, skipping weekends. Fractions of days are rounded up.
+    pub fn business_days(&mut self, business_days: bool) -> &mut Self {
+        self.criteria.business_days = business_days;
+        self
+    }
+
+    /// Set the number of hours to search for, in place of days.
+    pub fn hours(&mut self, hours: f32) -> &mut Self {
+        self.criteria.window = to_duration(hours, 60 * 60);
+        self
+    }
+
+    /// Set the number of minutes to search for, in place of days.
+    pub fn minutes(&mut self, minutes: f32) -> &mut Self {
+        self.criteria.window = to_duration(minutes, 60);
         self
     }
 
     /// Set whether or not we are interested in access time.
     pub fn access(&mut self, access: bool) -> &mut Self {
-        self.access = access;
+        self.criteria.access = access;
+        self
+    }
+
+    /// Do not test the access times of files on the supplied devices,
+    /// such as those mounted noatime, whose access times are never updated.
+    pub fn noatime_devices(&mut self, devices: HashSet<u64>) -> &mut Self {
+        self.criteria.noatime_devices = devices;
+        self
+    }
+
+    /// Use access time, with its own number of days, overriding the
+    /// number set by `days` for this criterion.
+    pub fn access_days(&mut self, days: f32) -> &mut Self {
+        self.criteria.field_window(Field::Access, to_duration(days, SECS_PER_DAY));
         self
     }
 
     /// Set whether or not we are interested in creation time.
     pub fn create(&mut self, create: bool) -> &mut Self {
-        self.create = create;
+        self.criteria.create = create;
+        self
+    }
+
+    /// Use creation time, with its own number of days, overriding the
+    /// number set by `days` for this criterion.
+    pub fn create_days(&mut self, days: f32) -> &mut Self {
+        self.criteria.field_window(Field::Create, to_duration(days, SECS_PER_DAY));
         self
     }
 
     /// Set whether or not we are interested in modification time.
     pub fn modify(&mut self, modify: bool) -> &mut Self {
-        self.modify = modify;
+        self.criteria.modify = modify;
+        self
+    }
+
+    /// Use modification time, with its own number of days, overriding the
+    /// number set by `days` for this criterion.
+    pub fn modify_days(&mut self, days: f32) -> &mut Self {
+        self.criteria.field_window(Field::Modify, to_duration(days, SECS_PER_DAY));
         self
     }
 
-    /// Set whether or not we should ignore hidden directories by default. Hidden
-    /// directories start with a '.'.
+    /// Set whether or not we are interested in inode change time (ctime).
+    /// Unlike modification time, change time cannot be set by the user.
+    pub fn change(&mut self, change: bool) -> &mut Self {
+        self.criteria.change = change;
+        self
+    }
+
+    /// Use inode change time, with its own number of days, overriding the
+    /// number set by `days` for this criterion.
+    pub fn change_days(&mut self, days: f32) -> &mut Self {
+        self.criteria.field_window(Field::Change, to_duration(days, SECS_PER_DAY));
+        self
+    }
+
+    /// Set whether or not we should ignore hidden directories and hidden
+    /// files. Hidden names start with a '.'. This is shorthand for setting
+    /// both `hidden_dirs` and `hidden_files`.
     pub fn ignore_hidden(&mut self, ignore_hidden: bool) -> &mut Self {
-        self.ignore_hidden = ignore_hidden;
+        self.hidden_dirs = ignore_hidden;
+        self.hidden_files = ignore_hidden;
+        self
+    }
+
+    /// Set whether or not hidden directories are skipped. When they are,
+    /// nothing beneath them is searched. The start directory is never
+    /// considered hidden.
+    pub fn hidden_dirs(&mut self, hidden_dirs: bool) -> &mut Self {
+        self.hidden_dirs = hidden_dirs;
+        self
+    }
+
+    /// Set whether or not hidden files are skipped. This does not affect
+    /// whether hidden directories are searched.
+    pub fn hidden_files(&mut self, hidden_files: bool) -> &mut Self {
+        self.hidden_files = hidden_files;
+        self
+    }
+
+    /// Set whether or not symbolic links are followed. When they are not,
+    /// links to directories are not traversed, and links to files are
+    /// not reported.
+    pub fn follow_links(&mut self, follow_links: bool) -> &mut Self {
+        self.follow_links = follow_links;
         self
     }
 
@@ -97,111 +457,871 @@ impl SyncSearch {
         self
     }
 
-    // Was the entry modified within the last `self.days` # of days?
-    fn report_modified(entry: &walkdir::DirEntry, days: f32) -> Result<bool, AmbleError> {
-        let modified = entry.metadata()?.modified()?;
-        Ok(modified.elapsed()?.as_secs() < ((SECS_PER_DAY as f64 * f64::from(days)).ceil() as u64))
+    /// Set the path prefixes to exclude. Any entry whose absolute path
+    /// starts with one of the prefixes is left out, along with anything
+    /// beneath it. Unlike the skip list, this singles out one instance of
+    /// a name which appears in many places.
+    pub fn exclude_paths(&mut self, exclude_paths: Vec<PathBuf>) -> &mut Self {
+        self.exclude_paths = exclude_paths;
+        self
     }
 
-    // Was the entry accessed iwthint the last `self.days` # of days?
-    fn report_accessed(entry: &walkdir::DirEntry, days: f32) -> Result<bool, AmbleError> {
-        let accessed = entry.metadata()?.accessed()?;
-        Ok(accessed.elapsed()?.as_secs() < ((SECS_PER_DAY as f64 * f64::from(days)).ceil() as u64))
+    /// Restrict matches to files whose names match one of the supplied
+    /// shell glob patterns (eg "*.abc"). Patterns are matched against the
+    /// file name only, not the full path.
+    pub fn names(&mut self, names: Vec<String>) -> &mut Self {
+        self.criteria.names = names;
+        self
     }
 
-    // Was the entry created in the last `self.days` number of days?
-    fn report_created(entry: &walkdir::DirEntry, days: f32) -> Result<bool, AmbleError> {
-        let created = entry.metadata()?.created()?;
-        Ok(created.elapsed()?.as_secs() < ((SECS_PER_DAY as f64 * f64::from(days)).ceil() as u64))
+    /// Set whether or not names and name patterns are matched without
+    /// regard to case. This is useful for trees served to case-insensitive clients, where
+    /// "Cache" and "cache" name the same directory.
+    pub fn case_insensitive(&mut self, case_insensitive: bool) -> &mut Self {
+        self.case_insensitive = case_insensitive;
+        self
     }
 
-    // is the DirEntry hidden? If check is false, we dont bother
-    // actually checking; instead we automatically return false.
-    fn is_hidden(entry: &DirEntry, check: bool) -> bool {
-        if !check { return false; }
-        entry.file_name()
-            .to_str()
-            .map(|s| s.starts_with('.') && s != "./")
-            .unwrap_or(false)
+    /// Extend the skip list with names read from the supplied reader, one
+    /// per line. Blank lines and lines starting with '#' are ignored.
+    pub fn skip_from_reader(&mut self, reader: impl Read) -> Result<&mut Self, AmbleError> {
+        self.skip.extend(read_skip_list(reader)?);
+        Ok(self)
     }
 
-    // predicate to determine if a directory matches one or more
-    // directory names
-    fn matches_list(entry: &DirEntry, list: &[String] ) -> bool {
-        if list.is_empty() {
-            return false;
-        }
+    /// Restrict matches to empty files (Some(true)), or to non-empty files
+    /// (Some(false)). None, the default, matches files of any size.
+    pub fn empty(&mut self, empty: Option<bool>) -> &mut Self {
+        self.criteria.empty = empty;
+        self
+    }
 
-        for item in list {
-            if entry.file_name()
-                .to_str()
-                .map(|s| s == item)
-                .unwrap_or(false) {
-                    return true;
-                }
-        }
+    /// Set whether or not to report dangling symbolic links, rather than
+    /// files. When `aged` is set, only links whose own change time falls
+    /// within the number of days are reported.
+    pub fn broken_links(&mut self, broken_links: bool, aged: bool) -> &mut Self {
+        self.criteria.broken_links = broken_links;
+        self.criteria.broken_links_aged = aged;
+        self
+    }
 
-        false
+    /// Restrict matches to files whose sniffed content type matches one of
+    /// the supplied MIME patterns, such as "image/*" or "video/mp4". The
+    /// content is only examined for files which meet the other criteria.
+    /// Requires the mime feature; searching with patterns set otherwise
+    /// returns an error.
+    pub fn mime(&mut self, mime: Vec<String>) -> &mut Self {
+        self.criteria.mime = mime;
+        self
     }
-}
 
+    /// Set how file sizes are measured when filtering by size. Allocated
+    /// size is the better measure for sparse files.
+    pub fn size_mode(&mut self, size_mode: SizeMode) -> &mut Self {
+        self.criteria.size_mode = size_mode;
+        self
+    }
 
-impl Finder for SyncSearch {
-    type ReturnType = ();
+    /// Restrict matches to files of at least `min_size` bytes.
+    pub fn min_size(&mut self, min_size: Option<u64>) -> &mut Self {
+        self.criteria.min_size = min_size;
+        self
+    }
 
-    fn find_matching(&self) -> Result<Self::ReturnType, AmbleError> {
-        if !(self.access || self.create || self.modify) {
-            println!("No search criteria specified. Must use access, create, or modify");
-            return Ok(());
+    /// Restrict matches to files of at most `max_size` bytes.
+    pub fn max_size(&mut self, max_size: Option<u64>) -> &mut Self {
+        self.criteria.max_size = max_size;
+        self
+    }
+
+    /// Restrict matches to files with any execute bit set. Execute bits
+    /// are unix only, so elsewhere nothing matches.
+    pub fn executable(&mut self, executable: bool) -> &mut Self {
+        self.criteria.executable = executable;
+        self
+    }
+
+    /// Restrict matches to files whose number of hard links satisfies the
+    /// supplied constraint. A file with a single link is the last
+    /// remaining reference to its data.
+    pub fn links(&mut self, links: Option<LinkCount>) -> &mut Self {
+        self.criteria.links = links;
+        self
+    }
+
+    /// Restrict matches to files owned by the supplied user id (unix only).
+    pub fn owner(&mut self, owner: Option<u32>) -> &mut Self {
+        self.criteria.owner = owner;
+        self
+    }
+
+    /// Restrict matches to files owned by the effective user running the
+    /// search (unix only). This is shorthand for `owner`, with the user id
+    /// from geteuid(2). Passing false leaves the owner unchanged.
+    pub fn mine(&mut self, mine: bool) -> &mut Self {
+        if mine {
+            self.criteria.owner = effective_uid();
         }
+        self
+    }
+
+    /// Set whether or not to flag security relevant mode bits (setuid,
+    /// setgid, sticky, and world-writable) on matches, so that an age
+    /// based audit can also pick out risky files.
+    pub fn mode_flags(&mut self, mode_flags: bool) -> &mut Self {
+        self.criteria.mode_flags = mode_flags;
+        self
+    }
 
-        let walker = WalkDir::new(&self.start_dir)
-                .follow_links(true)
+    /// Set whether or not every file which passes the filters is a match,
+    /// regardless of its times. This is how a Policy sees every file.
+    pub fn every(&mut self, every: bool) -> &mut Self {
+        self.criteria.every = every;
+        self
+    }
+
+    /// Set whether or not to skip directories, and everything beneath
+    /// them, whose own modification and change times are older than the
+    /// window. This is a heuristic, which only applies to scans of
+    /// modification or creation times, on local filesystems known to keep
+    /// the times of directories up to date. Files modified in place, and
+    /// entries added beneath subdirectories, do not update those times, so
+    /// such matches are missed.
+    pub fn prune_cold_dirs(&mut self, prune_cold_dirs: bool) -> &mut Self {
+        self.criteria.cold_dirs = prune_cold_dirs;
+        self
+    }
+
+    /// Set the maximum number of matches to report. Once it is reached, the
+    /// search stops. None, the default, reports every match.
+    pub fn max_results(&mut self, max_results: Option<usize>) -> &mut Self {
+        self.max_results = max_results;
+        self
+    }
+
+    /// Evaluate only the supplied candidate paths, rather than traversing
+    /// the roots. Directories are not descended into, and the hidden and
+    /// skip settings do not apply. Pass None to traverse the roots.
+    pub fn candidates(&mut self, candidates: Option<Vec<PathBuf>>) -> &mut Self {
+        self.candidates = candidates;
+        self
+    }
+
+    /// Set whether or not to report a directory as a single match, in place
+    /// of its contents, when every entry beneath it matches. Directories
+    /// containing anything which does not match, or which is skipped, are
+    /// not rolled up. The maximum number of matches counts files.
+    pub fn rollup(&mut self, rollup: bool) -> &mut Self {
+        self.rollup = rollup;
+        self
+    }
+
+    /// Set how directories are traversed. See Backend.
+    pub fn backend(&mut self, backend: Backend) -> &mut Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Make the search resumable, saving the directories it has yet to
+    /// search to the supplied checkpoint file as it goes, and resuming from
+    /// the checkpoint when one was left by an interrupted search. Matches
+    /// found after the last checkpoint are reported again on resuming.
+    /// Resumable searches traverse depth first, whichever the backend.
+    pub fn resume(&mut self, checkpoint: Option<PathBuf>) -> &mut Self {
+        self.resume = checkpoint;
+        self
+    }
+
+    /// Set the order in which directories are walked. See WalkOrder. Breadth
+    /// first walks read directories as the fast backend does, whichever the
+    /// backend, and cannot be rolled up.
+    pub fn order(&mut self, order: WalkOrder) -> &mut Self {
+        self.order = order;
+        self
+    }
+
+    /// Set the number of threads searching roots at once, when there are
+    /// several. By default the roots are searched in turn. Each root is
+    /// searched by a single thread, so matches are in order within each
+    /// root, but those of different roots are interleaved. Resumable searches search one root
+    /// at a time. When the roots are searched one at a time, the threads
+    /// set read metadata ahead of the search instead, as with prefetch,
+    /// unless prefetch is set. Candidates are evaluated by the calling
+    /// thread alone.
+    pub fn threads(&mut self, threads: Option<u8>) -> &mut Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Cap the reads of metadata in flight at once on any one mount beneath
+    /// the roots, when searching several roots at once or prefetching. See
+    /// mounts.rs.
+    pub fn max_per_mount(&mut self, max_per_mount: Option<usize>) -> &mut Self {
+        self.max_per_mount = max_per_mount;
+        self
+    }
+
+    /// Read metadata ahead of the search on a pool of the supplied number
+    /// of threads, keeping matches in order. Directories are read as the
+    /// fast backend reads them, whatever the backend. See prefetch.rs.
+    pub fn prefetch(&mut self, prefetch: Option<usize>) -> &mut Self {
+        self.prefetch = prefetch;
+        self
+    }
+
+    /// Count the files, directories, and matches of the search into the
+    /// supplied progress as it runs, so that another thread may report it.
+    pub fn live_progress(&mut self, live_progress: Option<Arc<ScanProgress>>) -> &mut Self {
+        self.live_progress = live_progress;
+        self
+    }
+
+    /// Classify every file using the supplied Classifier, rather than
+    /// testing files against the number of days. Pass None to turn
+    /// classification off.
+    pub fn classify(&mut self, classifier: Option<Classifier>) -> &mut Self {
+        self.criteria.classifier = classifier;
+        self
+    }
+
+    // Search a single root directory, handing matches to the sink. The
+    // progress of the search is shared between roots.
+    fn search_root(&self, root: &Path, criteria: &Criteria, prune: &Prune, sink: &mut dyn Sink,
+                   progress: &mut Progress)
+    -> Result<(), AmbleError> {
+        let mut entries = WalkDir::new(root)
+                .follow_links(self.follow_links)
                 .into_iter();
 
-        for entry in walker
-        .filter_entry(|e| {
-                !(SyncSearch::is_hidden(e, self.ignore_hidden) ||
-                  SyncSearch::matches_list(e, &self.skip))
+        while let Some(entry) = entries.next() {
+            if progress.limit_reached(self.max_results) {
+                break;
             }
-        ) {
             // filter out errors (like for permissions)
             let entry = match entry {
                 Ok(e) => {
+                    // Entries are filtered here, rather than via filter_entry,
+                    // so that their directories are not rolled up.
+                    if prune.skips(e.path(), e.file_type().is_dir(), e.depth()) {
+                        if e.file_type().is_dir() {
+                            entries.skip_current_dir();
+                        }
+                        progress.rollup.visit(Visit::Other(e.into_path()), sink)?;
+                        continue;
+                    }
+                    if e.file_type().is_dir() {
+                        progress.counters.stats(1);
+                        let metadata = e.metadata().ok();
+                        if e.depth() > 0 && metadata.as_ref().is_some_and(|md| criteria.skips_dir(e.path(), md)) {
+                            entries.skip_current_dir();
+                            progress.rollup.visit(Visit::Other(e.into_path()), sink)?;
+                            continue;
+                        }
+                        let id = metadata.as_ref().and_then(inode);
+                        progress.dir_devs.truncate(e.depth());
+                        progress.dir_devs.push(id.map(|(dev, _)| dev));
+                        if progress.first_visit(id) {
+                            progress.counters.dir();
+                            debug!(path = %e.path().display(), "entering directory");
+                            progress.rollup.visit(Visit::Dir(e.into_path()), sink)?;
+                        } else {
+                            eprint_above(format_args!("Warning: skipping '{}', which has already been visited (symlink loop?)",
+                                                   e.path().display()));
+                            entries.skip_current_dir();
+                            progress.rollup.visit(Visit::Other(e.into_path()), sink)?;
+                        }
+                        continue;
+                    }
+                    if e.path_is_symlink() && !e.file_type().is_file() {
+                        progress.counters.stats(2);
+                        match dangling_link(e.path()) {
+                            Some(md) => SyncSearch::report_link(criteria, e.path(), &md, progress, sink)?,
+                            None => progress.rollup.visit(Visit::Other(e.into_path()), sink)?,
+                        }
+                        continue;
+                    }
                     // need to test to make sure that symlinks
                     // get followed before this test
-                    if !e.file_type().is_file() {continue;}
+                    if !e.file_type().is_file() {
+                        progress.rollup.visit(Visit::Other(e.into_path()), sink)?;
+                        continue;
+                    }
                     e
                 },
-                Err(_) => continue,
+                Err(e) => {
+                    if let Some(ancestor) = e.loop_ancestor() {
+                        eprint_above(format_args!("Warning: skipping '{}', which loops back to '{}'",
+                                               e.path().map(|p| p.display().to_string()).unwrap_or_default(),
+                                               ancestor.display()));
+                    } else if let Some((path, md)) = e.path().and_then(|p| {
+                        progress.counters.stats(2);
+                        dangling_link(p).map(|md| (p, md))
+                    }) {
+                        SyncSearch::report_link(criteria, path, &md, progress, sink)?;
+                        continue
+                    } else {
+                        info!(error = %e, "unable to read entry");
+                        progress.errors += 1;
+                    }
+                    if let Some(path) = e.path() {
+                        progress.rollup.visit(Visit::Other(path.to_path_buf()), sink)?;
+                    }
+                    continue
+                },
+            };
+            progress.counters.file();
+            if criteria.excludes(entry.path()) {
+                progress.rollup.visit(Visit::Other(entry.into_path()), sink)?;
+                continue;
+            }
+            // the inode of a link is not that of the file it leads to
+            let id = match (entry.depth().checked_sub(1), walk_entry_ino(&entry)) {
+                (Some(parent), Some(ino)) if !entry.path_is_symlink() => {
+                    progress.dir_devs.get(parent).copied().flatten().map(|dev| (dev, ino))
+                },
+                _ => None,
             };
-            // doing this roughly in code above.
-            //if !entry.file_type().is_file() { continue; };
-            let mut meta = "".to_string();
-            if self.access && SyncSearch::report_accessed(&entry, self.days )? {
-                    meta.push('a');
+            let metadata = progress.file_metadata(entry.path(), id, || entry.metadata())?;
+            match criteria.evaluate(entry.path(), &metadata)? {
+                Some(found) => {
+                    progress.rollup.visit(Visit::Match(Box::new(found)), sink)?;
+                    progress.found();
+                },
+                None => progress.rollup.visit(Visit::Other(entry.into_path()), sink)?,
+            }
+        }
+        Ok(())
+    }
+
+    // New up the progress of a search, by the calling thread, sharing the
+    // supplied state with the other threads.
+    fn progress(&self, shared: &Shared) -> Result<Progress, AmbleError> {
+        let mut progress = Progress::new(self.rollup, shared.clone());
+        if self.backend == Backend::Uring {
+            progress.prefetcher = Some(Prefetcher::new()?);
+        }
+        Ok(progress)
+    }
 
+    // Search a single root with the backend, and in the walk order, of the
+    // search.
+    fn search_any_root(&self, root: &Path, criteria: &Criteria, prune: &Prune, sink: &mut dyn Sink,
+                       progress: &mut Progress)
+    -> Result<(), AmbleError> {
+        match (self.order, self.backend) {
+            (WalkOrder::BreadthFirst, _) => {
+                self.search_frontier(None, &[root.to_path_buf()], criteria, prune, sink, progress)
+            },
+            // prefetching reads directories as the fast backend does
+            (_, Backend::Walkdir) if progress.shared.pool.is_none() => {
+                self.search_root(root, criteria, prune, sink, progress)
+            },
+            (_, _) => self.search_root_fast(root, criteria, prune, sink, progress),
+        }
+    }
+
+    // Search the roots several at a time, each thread taking the next root
+    // once done with its last, and forwarding its matches to the calling
+    // thread, which hands them to the sink. The threads share the count of
+    // matches, and the directories visited, so that no directory is
+    // searched twice when following links between the roots.
+    fn search_concurrently(&self, roots: &[PathBuf], threads: usize, criteria: &Criteria, prune: &Prune,
+                           sink: &mut dyn Sink, started: Instant)
+    -> Result<ScanStats, AmbleError> {
+        let next = AtomicUsize::new(0);
+        let shared = Shared::new(roots, self.max_per_mount, self.prefetch, self.live_progress.clone());
+        let (tx, rx) = channel::bounded::<FileMatch>(CHANNEL_CAPACITY);
+        thread::scope(|scope| {
+            let workers = (0..threads).map(|_| {
+                let mut forward = Forward(tx.clone());
+                let (next, shared) = (&next, &shared);
+                scope.spawn(move || -> Result<ScanStats, AmbleError> {
+                    let mut progress = self.progress(shared)?;
+                    while let Some(root) = roots.get(next.fetch_add(1, AtomicOrdering::SeqCst)) {
+                        if progress.limit_reached(self.max_results) {
+                            break;
+                        }
+                        self.search_any_root(root, criteria, prune, &mut forward, &mut progress)?;
+                    }
+                    let cut_short = progress.limit_reached(self.max_results);
+                    progress.rollup.finish(cut_short, &mut forward)?;
+                    let mut stats = progress.stats(criteria.now(), started);
+                    stats.threads = thread_cpu_time().into_iter().collect();
+                    Ok(stats)
+                })
+            }).collect::<Vec<_>>();
+            drop(tx);
+
+            // the threads may find a few more files than the maximum between
+            // them, before they see it has been reached
+            let mut reported = 0;
+            let reporting = rx.iter().try_for_each(|found| {
+                if found.contents.is_none() {
+                    if self.max_results.is_some_and(|max| reported >= max) {
+                        return Ok(());
+                    }
+                    reported += 1;
+                }
+                sink.accept(found)
+            });
+            // should the sink fail, the threads stop once they next forward
+            // a match
+            drop(rx);
+
+            let mut stats = ScanStats { reference: Some(criteria.now()), ..ScanStats::default() };
+            let mut searching = Ok(());
+            for worker in workers {
+                match worker.join().unwrap() {
+                    Ok(worker) => {
+                        stats.errors += worker.errors;
+                        stats.files += worker.files;
+                        stats.dirs += worker.dirs;
+                        stats.stat_calls += worker.stat_calls;
+                        stats.threads.extend(worker.threads);
+                    },
+                    Err(e) => searching = searching.and(Err(e)),
+                }
             }
+            reporting?;
+            searching?;
+            stats.elapsed = started.elapsed();
+            Ok(stats)
+        })
+    }
 
-            if self.create {
-                #[cfg(target_os = "macos")] {
-                if SyncSearch::report_created(&entry, self.days)? {
-                    meta.push('c');
-                };
+    // Search a single root directory with the fast backend, handing matches
+    // to the sink. The progress of the search is shared between roots.
+    fn search_root_fast(&self, root: &Path, criteria: &Criteria, prune: &Prune, sink: &mut dyn Sink,
+                        progress: &mut Progress)
+    -> Result<(), AmbleError> {
+        if let Some(id) = SyncSearch::open_root(root, criteria, prune, sink, progress)? {
+            progress.dir_devs = vec![id.map(|(dev, _)| dev)];
+            return self.search_dir_fast(root.to_path_buf(), 0, criteria, prune, sink, progress);
+        }
+        Ok(())
+    }
+
+    // Start on a root with the fast backend. A root which is a file is
+    // evaluated. A root which is a directory to be searched is recorded as
+    // visited, and its (device, inode) pair returned.
+    fn open_root(root: &Path, criteria: &Criteria, prune: &Prune, sink: &mut dyn Sink, progress: &mut Progress)
+    -> Result<Option<Option<(u64, u64)>>, AmbleError> {
+        progress.counters.stats(1);
+        let metadata = match fs::metadata(root) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                info!(path = %root.display(), error = %e, "unable to read root");
+                progress.errors += 1;
+                progress.rollup.visit(Visit::Other(root.to_path_buf()), sink)?;
+                return Ok(None);
+            }
+        };
+        if prune.skips(root, metadata.is_dir(), 0) {
+            progress.rollup.visit(Visit::Other(root.to_path_buf()), sink)?;
+            return Ok(None);
+        }
+        if metadata.is_dir() {
+            progress.first_visit(inode(&metadata));
+            return Ok(Some(inode(&metadata)));
+        }
+        if metadata.is_file() {
+            SyncSearch::report_file(criteria, root, &metadata, progress, sink)?;
+        }
+        Ok(None)
+    }
+
+    // Search the roots with the fast backend, in the walk order of the
+    // search, from a frontier of the directories left to search. When
+    // resumable, the frontier is saved to the checkpoint as the search
+    // goes. A checkpoint left by an interrupted search is resumed from, in
+    // place of the roots, and removed once the search completes (see
+    // resume.rs).
+    fn search_frontier(&self, checkpoint: Option<&Path>, roots: &[PathBuf], criteria: &Criteria, prune: &Prune,
+                       sink: &mut dyn Sink, progress: &mut Progress)
+    -> Result<(), AmbleError> {
+        let depth_first = self.order == WalkOrder::DepthFirst;
+        let saved = match checkpoint {
+            Some(checkpoint) => Checkpoint::load(checkpoint)?,
+            None => None,
+        };
+        let mut pending: VecDeque<(Pending, Option<(u64, u64)>)> = match (checkpoint, saved) {
+            (Some(checkpoint), Some(saved)) if saved.roots != roots => {
+                return Err(AmbleError::UnexpectedResult(format!("checkpoint '{}' is of a scan of other roots",
+                                                                checkpoint.display())));
+            },
+            (Some(checkpoint), Some(saved)) => {
+                eprintln!("# resuming from '{}', with {} directories left", checkpoint.display(), saved.pending.len());
+                saved.pending.into_iter().map(|dir| (dir, None)).collect()
+            },
+            _ => {
+                let mut roots: VecDeque<_> = roots.iter()
+                                                  .map(|root| (Pending { path: root.clone(), depth: 0 }, None))
+                                                  .collect();
+                // the next directory is taken from the back when depth first
+                if depth_first {
+                    roots.make_contiguous().reverse();
                 }
+                roots
+            },
+        };
+        let interval = Duration::from_secs(CHECKPOINT_SECS);
+        let mut saved = Instant::now();
+        loop {
+            let next = if depth_first { pending.pop_back() } else { pending.pop_front() };
+            let Some((Pending { path, depth }, id)) = next else {
+                break;
+            };
+            if progress.limit_reached(self.max_results) {
+                break;
             }
+            let id = if depth == 0 {
+                match SyncSearch::open_root(&path, criteria, prune, sink, progress)? {
+                    Some(id) => id,
+                    None => continue,
+                }
+            } else {
+                // directories resumed from a checkpoint have yet to be read
+                let id = id.or_else(|| {
+                    progress.counters.stats(1);
+                    fs::metadata(&path).ok().and_then(|md| inode(&md))
+                });
+                if !progress.first_visit(id) {
+                    eprint_above(format_args!("Warning: skipping '{}', which has already been visited (symlink loop?)",
+                                           path.display()));
+                    progress.rollup.visit(Visit::Other(path), sink)?;
+                    continue;
+                }
+                id
+            };
+            progress.dir_devs.resize(depth, None);
+            progress.dir_devs.push(id.map(|(dev, _)| dev));
+            let subdirs = self.read_dir_fast(path, depth, criteria, prune, sink, progress)?;
+            let subdirs = subdirs.into_iter().map(|(path, id)| (Pending { path, depth: depth + 1 }, id));
+            if depth_first {
+                pending.extend(subdirs.rev());
+            } else {
+                pending.extend(subdirs);
+            }
+            if let Some(checkpoint) = checkpoint.filter(|_| saved.elapsed() >= interval) {
+                let left = pending.iter().map(|(dir, _)| dir.clone()).collect();
+                Checkpoint { roots: roots.to_vec(), pending: left }.save(checkpoint)?;
+                saved = Instant::now();
+            }
+        }
+        match checkpoint {
+            Some(checkpoint) => Checkpoint::remove(checkpoint),
+            None => Ok(()),
+        }
+    }
 
-            if self.modify && SyncSearch::report_modified(&entry, self.days)? {
-                    meta.push('m');
+    // Search a directory, at the supplied depth, with the fast backend. Its
+    // files are evaluated as it is read, with their metadata read relative
+    // to it, and its subdirectories are searched once it has been read and
+    // closed.
+    fn search_dir_fast(&self, dir: PathBuf, depth: usize, criteria: &Criteria, prune: &Prune, sink: &mut dyn Sink,
+                       progress: &mut Progress)
+    -> Result<(), AmbleError> {
+        let subdirs = self.read_dir_fast(dir, depth, criteria, prune, sink, progress)?;
+        for (subdir, id) in subdirs {
+            if progress.limit_reached(self.max_results) {
+                break;
+            }
+            if !progress.first_visit(id) {
+                eprint_above(format_args!("Warning: skipping '{}', which has already been visited (symlink loop?)",
+                                       subdir.display()));
+                progress.rollup.visit(Visit::Other(subdir), sink)?;
+                continue;
+            }
+            progress.dir_devs.truncate(depth + 1);
+            progress.dir_devs.push(id.map(|(dev, _)| dev));
+            self.search_dir_fast(subdir, depth + 1, criteria, prune, sink, progress)?;
+        }
+        Ok(())
+    }
 
+    // Read a directory, at the supplied depth, with the fast backend,
+    // evaluating its files, and returning its subdirectories, with their
+    // (device, inode) pairs, to be searched in turn.
+    fn read_dir_fast(&self, dir: PathBuf, depth: usize, criteria: &Criteria, prune: &Prune, sink: &mut dyn Sink,
+                     progress: &mut Progress)
+    -> Result<Vec<Subdir>, AmbleError> {
+        let entries: Vec<_> = match fs::read_dir(&dir) {
+            Ok(entries) => entries.collect(),
+            Err(e) => {
+                info!(path = %dir.display(), error = %e, "unable to read directory");
+                progress.errors += 1;
+                progress.rollup.visit(Visit::Other(dir), sink)?;
+                return Ok(Vec::new());
+            }
+        };
+        if let Some(prefetcher) = &mut progress.prefetcher {
+            let names: Vec<_> = entries.iter().flatten().map(|entry| entry.file_name()).collect();
+            // entries which are not prefetched are simply read as usual
+            if let Err(e) = prefetcher.prefetch(&dir, &names) {
+                eprint_above(format_args!("Warning: unable to prefetch '{}': {}", dir.display(), e));
+            }
+        }
+        // the files and directories to be evaluated are read ahead of the
+        // search, when prefetching
+        let mut lookahead = progress.shared.pool.as_ref().map(|pool| {
+            pool.lookahead(entries.iter().map(|entry| {
+                let entry = entry.as_ref().ok()?;
+                let file_type = entry.file_type().ok()?;
+                let path = entry.path();
+                let wanted = (file_type.is_dir() && prune.reason(&path, true, depth + 1).is_none())
+                             || (file_type.is_file() && prune.reason(&path, false, depth + 1).is_none()
+                                 && !criteria.excludes(&path));
+                wanted.then_some(path)
+            }).collect())
+        });
+        progress.counters.dir();
+        debug!(path = %dir.display(), "entering directory");
+        progress.rollup.visit(Visit::Dir(dir.clone()), sink)?;
+        let mut subdirs = Vec::new();
+        for (index, entry) in entries.into_iter().enumerate() {
+            if progress.limit_reached(self.max_results) {
+                break;
+            }
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    info!(path = %dir.display(), error = %e, "unable to read entry");
+                    progress.errors += 1;
+                    progress.rollup.unreadable();
+                    continue;
+                }
+            };
+            let path = entry.path();
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(e) => {
+                    info!(path = %path.display(), error = %e, "unable to read entry");
+                    progress.errors += 1;
+                    progress.rollup.visit(Visit::Other(path), sink)?;
+                    continue;
+                }
+            };
+            // entry types come from the directory listing, so only links
+            // need to be followed to find out what they are
+            let metadata = if file_type.is_symlink() {
+                progress.counters.stats(1);
+                match fs::metadata(&path) {
+                    Ok(metadata) if self.follow_links => Some(metadata),
+                    Ok(metadata) if metadata.is_file() => {
+                        progress.rollup.visit(Visit::Other(path), sink)?;
+                        continue;
+                    },
+                    Ok(_) => None,
+                    Err(e) => {
+                        progress.counters.stats(2);
+                        match dangling_link(&path) {
+                            Some(md) => SyncSearch::report_link(criteria, &path, &md, progress, sink)?,
+                            None => {
+                                info!(path = %path.display(), error = %e, "unable to follow link");
+                                progress.errors += 1;
+                                progress.rollup.visit(Visit::Other(path), sink)?;
+                            },
+                        }
+                        continue;
+                    },
+                }
+            } else {
+                None
+            };
+            let is_dir = metadata.as_ref().map_or(file_type.is_dir(), Metadata::is_dir);
+            let is_file = metadata.as_ref().map_or(file_type.is_file(), Metadata::is_file);
+            if prune.skips(&path, is_dir, depth + 1) || !(is_dir || is_file) {
+                progress.rollup.visit(Visit::Other(path), sink)?;
+                continue;
             }
+            if is_file && criteria.excludes(&path) {
+                progress.counters.file();
+                progress.rollup.visit(Visit::Other(path), sink)?;
+                continue;
+            }
+            // read relative to the open directory, unless already read via
+            // another hard link
+            let metadata = match metadata {
+                Some(metadata) => Ok(metadata),
+                None => {
+                    let dev = progress.dir_devs.get(depth).copied().flatten();
+                    let id = dev.zip(dir_entry_ino(&entry));
+                    match lookahead.as_mut().and_then(|lookahead| lookahead.take(index)) {
+                        Some(prefetched) => progress.read_metadata(id, || prefetched),
+                        None => progress.file_metadata(&path, id, || entry.metadata()),
+                    }
+                },
+            };
+            let metadata = match metadata {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    info!(path = %path.display(), error = %e, "unable to read metadata");
+                    progress.errors += 1;
+                    progress.rollup.visit(Visit::Other(path), sink)?;
+                    continue;
+                }
+            };
+            if is_dir && criteria.skips_dir(&path, &metadata) {
+                progress.rollup.visit(Visit::Other(path), sink)?;
+            } else if is_dir {
+                subdirs.push((path, inode(&metadata)));
+            } else {
+                SyncSearch::report_file(criteria, &path, &metadata, progress, sink)?;
+            }
+        }
+        Ok(subdirs)
+    }
 
-            if !meta.is_empty() {
-                let f_name = entry.path().to_string_lossy();
-                println!("{} ({})", f_name, meta);
+    // Hand a file on, if it meets the criteria.
+    fn report_file(criteria: &Criteria, path: &Path, metadata: &Metadata, progress: &mut Progress,
+                   sink: &mut dyn Sink)
+    -> Result<(), AmbleError> {
+        progress.counters.file();
+        match criteria.evaluate(path, metadata)? {
+            Some(found) => {
+                progress.rollup.visit(Visit::Match(Box::new(found)), sink)?;
+                progress.found();
+            },
+            None => progress.rollup.visit(Visit::Other(path.to_path_buf()), sink)?,
+        }
+        Ok(())
+    }
+
+    // Evaluate each of the candidate paths in turn, without traversal.
+    // Paths which cannot be read are reported to stderr and skipped.
+    fn search_candidates(&self, candidates: &[PathBuf], criteria: &Criteria, sink: &mut dyn Sink, started: Instant)
+    -> Result<ScanStats, AmbleError> {
+        let availability = candidates.first()
+                                     .and_then(|first| fs::metadata(first).ok())
+                                     .map(|md| Availability::probe(&md, &criteria.fields()))
+                                     .unwrap_or_default();
+        sink.begin(&availability)?;
+
+        // there are no directories to roll up
+        let mut progress = Progress::new(false, Shared::new(&[], None, None, self.live_progress.clone()));
+        for path in candidates {
+            if progress.limit_reached(self.max_results) {
+                break;
+            }
+            progress.counters.stats(1);
+            let link_metadata = match fs::symlink_metadata(path) {
+                Ok(md) => md,
+                Err(e) => {
+                    eprint_above(format_args!("Warning: unable to read '{}': {}", path.display(), e));
+                    progress.errors += 1;
+                    continue;
+                }
+            };
+            if link_metadata.file_type().is_symlink() {
+                progress.counters.stats(2);
+                if let Some(md) = dangling_link(path) {
+                    SyncSearch::report_link(criteria, path, &md, &mut progress, sink)?;
+                    continue;
+                }
+                if !self.follow_links { continue; }
+            }
+            if criteria.excludes(path) { continue; }
+            // only links need to be followed to read the metadata of the file
+            let metadata = if link_metadata.file_type().is_symlink() {
+                progress.counters.stats(1);
+                match fs::metadata(path) {
+                    Ok(md) => md,
+                    Err(e) => {
+                        eprint_above(format_args!("Warning: unable to read '{}': {}", path.display(), e));
+                        progress.errors += 1;
+                        continue;
+                    }
+                }
+            } else {
+                link_metadata
+            };
+            if !metadata.is_file() { continue; }
+            progress.counters.file();
+            if let Some(found) = criteria.evaluate(path, &metadata)? {
+                sink.accept(found)?;
+                progress.found();
             }
         }
 
+        sink.finish()?;
+        Ok(progress.stats(criteria.now(), started))
+    }
+
+    // Hand a dangling link on, if it meets the criteria.
+    fn report_link(criteria: &Criteria, path: &Path, link_metadata: &Metadata, progress: &mut Progress,
+                   sink: &mut dyn Sink)
+    -> Result<(), AmbleError> {
+        match criteria.evaluate_link(path, link_metadata)? {
+            Some(found) => {
+                progress.rollup.visit(Visit::Match(Box::new(found)), sink)?;
+                progress.found();
+            },
+            None => progress.rollup.visit(Visit::Other(path.to_path_buf()), sink)?,
+        }
         Ok(())
     }
+
+}
+
+
+impl Finder for SyncSearch {
+    type ReturnType = ScanStats;
+
+    fn find_into(&self, sink: &mut dyn Sink) -> Result<Self::ReturnType, AmbleError> {
+        let started = Instant::now();
+        if self.criteria.is_empty() {
+            println!("No search criteria specified. Must use access, create, modify, change, or broken links");
+            return Ok(ScanStats::default());
+        }
+
+        if self.rollup && self.order == WalkOrder::BreadthFirst {
+            return Err(AmbleError::UnexpectedResult("rolling up requires a depth first walk".to_string()));
+        }
+        let criteria = self.criteria.compile(self.case_insensitive)?;
+        if let Some(candidates) = &self.candidates {
+            return self.search_candidates(candidates, &criteria, sink, started);
+        }
+
+        let roots = dedup_roots(&self.roots);
+        let first = match roots.first() {
+            Some(first) => first,
+            None => {
+                println!("No root directories specified");
+                return Ok(ScanStats::default());
+            }
+        };
+        let availability = Availability::probe(&fs::metadata(first)?,
+                                               &criteria.fields());
+        let mut sink = Attribute::new(&roots, sink);
+        let sink: &mut dyn Sink = &mut sink;
+        sink.begin(&availability)?;
+
+        let prune = self.prune();
+        // roots are only searched at once when threads are asked for, so
+        // that the reference search keeps its matches in order
+        let threads = self.threads.map_or(1, usize::from).min(roots.len());
+        if self.resume.is_none() && threads > 1 {
+            let stats = self.search_concurrently(&roots, threads, &criteria, &prune, sink, started)?;
+            sink.finish()?;
+            return Ok(stats);
+        }
+        // the threads requested, which have no roots to search at once,
+        // read metadata ahead of the search instead
+        let prefetch = self.prefetch.or_else(|| self.threads.map(usize::from).filter(|threads| *threads > 1));
+        let shared = Shared::new(&roots, self.max_per_mount, prefetch, self.live_progress.clone());
+        let mut progress = self.progress(&shared)?;
+        if self.resume.is_some() || self.order == WalkOrder::BreadthFirst {
+            // a breadth first walk takes each depth of every root in turn
+            self.search_frontier(self.resume.as_deref(), &roots, &criteria, &prune, sink, &mut progress)?;
+        } else {
+            for root in &roots {
+                self.search_any_root(root, &criteria, &prune, sink, &mut progress)?;
+            }
+        }
+        let cut_short = progress.limit_reached(self.max_results);
+        progress.rollup.finish(cut_short, sink)?;
+
+        sink.finish()?;
+        Ok(progress.stats(criteria.now(), started))
+    }
 }
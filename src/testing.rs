@@ -0,0 +1,56 @@
+//! testing.rs
+//!
+//! Helpers for the unit tests, which work on trees of files created
+//! beneath the system's temporary directory, and removed once done.
+use std::fs::{self, File, FileTimes};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::time::{Duration, SystemTime};
+
+static TREES: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory beneath the temporary directory, unique to the test, which
+/// is removed along with its contents when dropped.
+#[derive(Debug)]
+pub(crate) struct TempTree {
+    root: PathBuf,
+}
+
+impl TempTree {
+    /// New up an empty TempTree, named for the test.
+    pub(crate) fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("amble-test-{}-{}-{}", name, std::process::id(),
+                                                     TREES.fetch_add(1, AtomicOrdering::Relaxed)));
+        fs::create_dir_all(&root).unwrap();
+        Self { root }
+    }
+
+    /// The root of the tree.
+    pub(crate) fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Write a file at the supplied path relative to the root, creating
+    /// its parents, and return its full path.
+    pub(crate) fn file(&self, relative: &str, contents: &[u8]) -> PathBuf {
+        let path = self.root.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempTree {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Set the access and modification times of the file to the supplied
+/// number of days ago.
+pub(crate) fn age(path: &Path, days: u64) {
+    let then = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+    let file = File::options().write(true).open(path).unwrap();
+    file.set_times(FileTimes::new().set_accessed(then).set_modified(then)).unwrap();
+}
+
@@ -0,0 +1,134 @@
+//! timefmt.rs
+//!
+//! How timestamps appear in structured output, such as the JSON written by
+//! JsonSink. Epoch based formats spare downstream ingestion from parsing
+//! dates at all, while RFC 3339 includes the timezone offset, so that it is
+//! never locale sensitive. RFC 3339 timestamps are rendered in the local
+//! timezone, UTC, or a named timezone, as chosen by the user.
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use serde::Serializer;
+use serde_json::Value;
+
+use crate::errors::AmbleError;
+
+/// The format of timestamps in structured output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// Seconds since the epoch, as an integer
+    Epoch,
+    /// Milliseconds since the epoch, as an integer
+    EpochMillis,
+    /// RFC 3339, with the timezone offset (eg 2024-03-10T18:30:00+01:00)
+    #[default]
+    Rfc3339,
+}
+
+impl TimeFormat {
+    /// The supplied time, in this format, rendered in the supplied zone if
+    /// the format has one. Times before the epoch are negative in the epoch
+    /// based formats.
+    pub fn format(&self, time: SystemTime, zone: Zone) -> Value {
+        match self {
+            TimeFormat::Epoch => Value::from(epoch_millis(time).div_euclid(1000)),
+            TimeFormat::EpochMillis => Value::from(epoch_millis(time)),
+            TimeFormat::Rfc3339 => Value::from(zone.rfc3339(time)),
+        }
+    }
+
+    /// Serialize the supplied time in this format, as `format` renders it,
+    /// without building a Value.
+    pub fn serialize<S: Serializer>(&self, time: SystemTime, zone: Zone, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            TimeFormat::Epoch => serializer.serialize_i64(epoch_millis(time).div_euclid(1000)),
+            TimeFormat::EpochMillis => serializer.serialize_i64(epoch_millis(time)),
+            TimeFormat::Rfc3339 => serializer.serialize_str(&zone.rfc3339(time)),
+        }
+    }
+}
+
+/// The timezone human readable timestamps are rendered in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Zone {
+    /// The local timezone of the machine
+    #[default]
+    Local,
+    /// Coordinated Universal Time
+    Utc,
+    /// A named timezone from the IANA database, such as America/Los_Angeles
+    Named(Tz),
+}
+
+impl Zone {
+    /// The supplied time as an RFC 3339 timestamp, to the second, in this
+    /// zone. UTC is rendered with a Z suffix.
+    pub fn rfc3339(&self, time: SystemTime) -> String {
+        let time = DateTime::<Utc>::from(time);
+        match self {
+            Zone::Local => time.with_timezone(&Local).to_rfc3339_opts(SecondsFormat::Secs, false),
+            Zone::Utc => time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            Zone::Named(tz) => time.with_timezone(tz).to_rfc3339_opts(SecondsFormat::Secs, false),
+        }
+    }
+}
+
+impl FromStr for Zone {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.to_ascii_lowercase().as_str() {
+            "local" => Ok(Zone::Local),
+            "utc" | "z" => Ok(Zone::Utc),
+            _ => s.parse::<Tz>().map(Zone::Named).map_err(|_| {
+                AmbleError::ParseError(format!("invalid timezone '{}'. Expected local, UTC, or a name such as Europe/London", s))
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Zone::Local => write!(f, "local"),
+            Zone::Utc => write!(f, "UTC"),
+            Zone::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+// milliseconds since the epoch, which are negative for earlier times
+fn epoch_millis(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_millis() as i64,
+        Err(e) => -(e.duration().as_millis() as i64),
+    }
+}
+
+impl FromStr for TimeFormat {
+    type Err = AmbleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "epoch" => Ok(TimeFormat::Epoch),
+            "epoch-ms" | "epoch-millis" => Ok(TimeFormat::EpochMillis),
+            "rfc3339" | "iso8601" => Ok(TimeFormat::Rfc3339),
+            _ => Err(AmbleError::ParseError(format!("invalid time format '{}'. Expected epoch, epoch-ms, or rfc3339", s))),
+        }
+    }
+}
+
+impl fmt::Display for TimeFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TimeFormat::Epoch => "epoch",
+            TimeFormat::EpochMillis => "epoch-ms",
+            TimeFormat::Rfc3339 => "rfc3339",
+        };
+        write!(f, "{}", name)
+    }
+}
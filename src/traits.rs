@@ -1,9 +1,10 @@
 //! traits.rs
 //!
 //! Defines the Finder trait, used by syncwalk and asyncwalk
-//! to find the files which match supplied stat metadata
+//! to find the files which match supplied stat metadata, and the
+//! Sink trait, which receives the files found.
 //use std::path::Path;
-use crate::errors::AmbleError;
+use crate::{errors::AmbleError, fields::Availability, filematch::FileMatch, sinks::PrintSink};
 
 /// Finder trait provies the `find_matching` method, which should be used
 /// to find files matching supplied criteria (presumably on the struct or
@@ -11,5 +12,55 @@ use crate::errors::AmbleError;
 pub trait Finder {
     type ReturnType;
 
-    fn find_matching( &self ) -> Result<Self::ReturnType, AmbleError>;
-}
\ No newline at end of file
+    /// Find the files matching the criteria, printing them to stdout.
+    fn find_matching( &self ) -> Result<Self::ReturnType, AmbleError> {
+        self.find_into(&mut PrintSink::default())
+    }
+
+    /// Find the files matching the criteria, handing each one to the
+    /// supplied sink. The sink's `finish` is called once the search
+    /// completes.
+    fn find_into( &self, sink: &mut dyn Sink ) -> Result<Self::ReturnType, AmbleError>;
+}
+
+/// Sink trait receives each FileMatch found by a Finder. Sinks must be
+/// Send, as the async Finder feeds its sink from a separate thread.
+pub trait Sink: Send {
+    /// Called once before the search begins, with the availability of
+    /// the requested metadata fields at the root of the search.
+    fn begin(&mut self, _availability: &Availability) -> Result<(), AmbleError> {
+        Ok(())
+    }
+
+    /// Receive a single match.
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError>;
+
+    /// Called once after the last match has been received.
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        Ok(())
+    }
+
+    /// Number of matches which the sink failed to handle, such as those
+    /// an action could not be applied to.
+    fn errors(&self) -> u64 {
+        0
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for Box<S> {
+    fn begin(&mut self, availability: &Availability) -> Result<(), AmbleError> {
+        (**self).begin(availability)
+    }
+
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        (**self).accept(found)
+    }
+
+    fn finish(&mut self) -> Result<(), AmbleError> {
+        (**self).finish()
+    }
+
+    fn errors(&self) -> u64 {
+        (**self).errors()
+    }
+}
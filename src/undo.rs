@@ -0,0 +1,269 @@
+//! undo.rs
+//!
+//! The undo journal, to which actions which may be reverted, such as
+//! moving files or trashing them, record where each match went. Each line
+//! of the journal is a JSON object: the scan ID of the run, the time (in
+//! seconds since the epoch), the verb, the original path, and the new path
+//! (null for matches in the trash). Paths are absolute, and kept intact
+//! whether or not they are valid unicode. `undo` restores the matches of a
+//! single scan, so that an over-aggressive cleanup can be reverted.
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{actions::relocate, errors::AmbleError};
+
+/// The default location of the journal, $XDG_DATA_HOME/amble/undo.jsonl,
+/// or ~/.local/share/amble/undo.jsonl, if either is known.
+pub fn default_journal() -> Option<PathBuf> {
+    let data = env::var_os("XDG_DATA_HOME")
+        .filter(|data| !data.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))?;
+    Some(data.join("amble").join("undo.jsonl"))
+}
+
+/// A new scan ID, unique to this run: the time, and the process ID.
+pub fn new_scan_id() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default();
+    format!("{}-{}", secs, std::process::id())
+}
+
+/// Appends the matches of a single scan to the undo journal.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    scan_id: String,
+    file: Option<File>,
+}
+
+impl Journal {
+    /// New up a Journal appending to the supplied path, under the
+    /// supplied scan ID. The journal is only created once something is
+    /// recorded.
+    pub fn new(path: impl Into<PathBuf>, scan_id: impl Into<String>) -> Self {
+        Self { path: path.into(), scan_id: scan_id.into(), file: None }
+    }
+
+    /// The ID of the scan being recorded.
+    pub fn scan_id(&self) -> &str {
+        &self.scan_id
+    }
+
+    /// Record that the original path was moved to the new path, or to the
+    /// trash if there is no new path.
+    pub fn record(&mut self, verb: &str, original: &Path, new: Option<&Path>) -> Result<(), AmbleError> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                if let Some(parent) = self.path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                self.file.insert(File::options().append(true).create(true).open(&self.path)?)
+            },
+        };
+        // relative paths would be restored relative to wherever undo is run
+        let entry = Entry {
+            scan_id: self.scan_id.clone(),
+            time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+            verb: verb.to_string(),
+            original: std::path::absolute(original)?,
+            new: new.map(std::path::absolute).transpose()?,
+        };
+        // written at once, so that a line is never interleaved with another
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        file.write_all(&line)?;
+        Ok(())
+    }
+}
+
+// A line of the journal
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    scan_id: String,
+    // only needed to find matches in the trash
+    time: i64,
+    verb: String,
+    #[serde(with = "crate::spill::path")]
+    original: PathBuf,
+    #[serde(with = "crate::spill::optional_path")]
+    new: Option<PathBuf>,
+}
+
+/// The outcome of undoing a scan.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Restored {
+    /// Matches restored to their original paths
+    pub restored: u64,
+    /// Matches which could not be restored
+    pub failed: u64,
+}
+
+/// Restore the matches recorded under the supplied scan ID, latest first,
+/// to their original paths. Matches are never restored over a file which
+/// has since taken their place, although a symlink left in place of a
+/// moved match is replaced. In a dry run, the matches which would be
+/// restored are reported, but left where they are.
+pub fn undo(journal: &Path, scan_id: &str, dry_run: bool) -> Result<Restored, AmbleError> {
+    let file = File::open(journal).map_err(|e| {
+        AmbleError::IoError(format!("unable to read undo journal '{}': {}", journal.display(), e))
+    })?;
+    let mut entries = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // a line which cannot be read may belong to the scan, so it is
+        // reported rather than passed over
+        match serde_json::from_str::<Entry>(&line) {
+            Ok(entry) if entry.scan_id == scan_id => entries.push(entry),
+            Ok(_) => (),
+            Err(e) => eprintln!("Warning: unable to read line {} of undo journal '{}': {}",
+                                number + 1, journal.display(), e),
+        }
+    }
+    if entries.is_empty() {
+        return Err(AmbleError::UnexpectedResult(format!("no entries for scan '{}' in '{}'", scan_id, journal.display())));
+    }
+    let mut restored = Restored::default();
+    let mut trashed = Vec::new();
+    for entry in entries.into_iter().rev() {
+        if dry_run {
+            match &entry.new {
+                Some(new) => println!("[dry run] would restore {} -> {}", new.display(), entry.original.display()),
+                None => println!("[dry run] would restore {} from the trash", entry.original.display()),
+            }
+            restored.restored += 1;
+            continue;
+        }
+        match &entry.new {
+            Some(new) => match restore_moved(&entry.original, new) {
+                Ok(()) => {
+                    println!("restore {} -> {}", new.display(), entry.original.display());
+                    restored.restored += 1;
+                },
+                Err(e) => {
+                    eprintln!("Warning: unable to restore '{}': {}", entry.original.display(), e);
+                    restored.failed += 1;
+                },
+            },
+            None => trashed.push(entry),
+        }
+    }
+    restore_trashed(&trashed, &mut restored);
+    Ok(restored)
+}
+
+// Move a match back to its original path, replacing a symlink left there.
+fn restore_moved(original: &Path, new: &Path) -> Result<(), AmbleError> {
+    if let Ok(metadata) = fs::symlink_metadata(original) {
+        if !metadata.file_type().is_symlink() || fs::read_link(original)? != new {
+            return Err(AmbleError::IoError(format!("'{}' already exists", original.display())));
+        }
+        fs::remove_file(original)?;
+    }
+    if let Some(parent) = original.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    relocate(new, original)
+}
+
+// Restore matches from the trash. Each is the latest item in the trash
+// from its original path, provided it was trashed around when recorded.
+#[cfg(all(feature = "trash", unix, not(target_os = "macos"), not(target_os = "ios"), not(target_os = "android")))]
+fn restore_trashed(entries: &[Entry], restored: &mut Restored) {
+    if entries.is_empty() {
+        return;
+    }
+    let items = match trash::os_limited::list() {
+        Ok(items) => items,
+        Err(e) => {
+            eprintln!("Warning: unable to list the trash: {}", e);
+            restored.failed += entries.len() as u64;
+            return;
+        },
+    };
+    for entry in entries {
+        let item = items.iter()
+            .filter(|item| item.original_path() == entry.original && item.time_deleted >= entry.time - 2)
+            .max_by_key(|item| item.time_deleted);
+        let result = match item {
+            Some(item) => trash::os_limited::restore_all(vec![item.clone()]).map_err(|e| e.to_string()),
+            None => Err("no longer in the trash".to_string()),
+        };
+        match result {
+            Ok(()) => {
+                println!("restore {} from the trash", entry.original.display());
+                restored.restored += 1;
+            },
+            Err(e) => {
+                eprintln!("Warning: unable to restore '{}': {}", entry.original.display(), e);
+                restored.failed += 1;
+            },
+        }
+    }
+}
+
+// Restoring from the trash requires the trash feature, on a platform with a
+// freedesktop trash.
+#[cfg(not(all(feature = "trash", unix, not(target_os = "macos"), not(target_os = "ios"), not(target_os = "android"))))]
+fn restore_trashed(entries: &[Entry], restored: &mut Restored) {
+    for entry in entries {
+        eprintln!("Warning: unable to restore '{}': restoring from the trash is not supported by this build",
+                  entry.original.display());
+        restored.failed += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{actions::{Action, MoveTo}, filematch::FileMatch, testing::TempTree};
+
+    // Move the file beneath dest, journaling it under the scan ID "scan".
+    fn move_to(tree: &TempTree, path: &Path, journal: &Path) {
+        let mut move_to = MoveTo::new(tree.path().join("dest"), vec![tree.path().to_path_buf()]);
+        move_to.journal(Journal::new(journal, "scan"));
+        move_to.apply(&FileMatch::new(path, 0)).unwrap();
+    }
+
+    #[test]
+    fn undo_restores_moved_files() {
+        let tree = TempTree::new("undo");
+        let path = tree.file("src/a", b"a");
+        let journal = tree.path().join("undo.jsonl");
+        move_to(&tree, &path, &journal);
+        assert!(!path.exists());
+        assert_eq!(undo(&journal, "scan", false).unwrap(), Restored { restored: 1, failed: 0 });
+        assert_eq!(fs::read(&path).unwrap(), b"a");
+        assert!(!tree.path().join("dest/src/a").exists());
+    }
+
+    #[test]
+    fn undo_dry_run_changes_nothing() {
+        let tree = TempTree::new("undo-dry-run");
+        let path = tree.file("src/a", b"a");
+        let journal = tree.path().join("undo.jsonl");
+        move_to(&tree, &path, &journal);
+        assert_eq!(undo(&journal, "scan", true).unwrap().restored, 1);
+        assert!(!path.exists());
+        assert!(tree.path().join("dest/src/a").exists());
+    }
+
+    #[test]
+    fn undo_reads_past_unreadable_lines() {
+        let tree = TempTree::new("undo-unreadable");
+        let path = tree.file("src/a", b"a");
+        let journal = tree.path().join("undo.jsonl");
+        fs::write(&journal, "scan\t0\tmove\t/a\t/b\n").unwrap();
+        move_to(&tree, &path, &journal);
+        assert_eq!(undo(&journal, "scan", false).unwrap().restored, 1);
+        assert!(path.exists());
+    }
+}
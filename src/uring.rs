@@ -0,0 +1,78 @@
+//! uring.rs
+//!
+//! The experimental io_uring backend (see backend.rs), which requires
+//! Linux and the io_uring feature. Before the entries of a directory are
+//! evaluated, statx for each of them is submitted to the kernel in a
+//! single batch, so that on a cold cache the inode reads are in flight
+//! together, rather than one at a time. The entries are then evaluated as
+//! by the fast backend, with their metadata served from the warmed cache.
+//! This pays off on large trees on local NVMe, whose queues are deep; on
+//! a warm cache it only adds work.
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+
+/// Submits batches of statx operations through an io_uring.
+pub(crate) struct Prefetcher {
+    #[cfg(all(target_os = "linux", feature = "io_uring"))]
+    ring: io_uring::IoUring,
+}
+
+// Number of statx operations in flight at once
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+const QUEUE_DEPTH: usize = 256;
+
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+impl Prefetcher {
+    /// New up a Prefetcher, setting up its ring. Fails where the kernel
+    /// does not support io_uring, or it has been disabled.
+    pub(crate) fn new() -> io::Result<Self> {
+        Ok(Self { ring: io_uring::IoUring::new(QUEUE_DEPTH as u32)? })
+    }
+
+    /// Read the metadata of the supplied entries of a directory, in
+    /// batches, so that it is cached by the time it is read again.
+    pub(crate) fn prefetch(&mut self, dir: &Path, names: &[OsString]) -> io::Result<()> {
+        use std::ffi::CString;
+        use std::fs::File;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::io::AsRawFd;
+
+        use io_uring::{opcode, types};
+
+        let dir = File::open(dir)?;
+        let names: Vec<CString> = names.iter().filter_map(|name| CString::new(name.as_bytes()).ok()).collect();
+        // the kernel writes into these until each batch completes
+        let mut buffers: Vec<types::statx> = (0..QUEUE_DEPTH).map(|_| unsafe { std::mem::zeroed() }).collect();
+        for batch in names.chunks(QUEUE_DEPTH) {
+            for (name, buffer) in batch.iter().zip(buffers.iter_mut()) {
+                let statx = opcode::Statx::new(types::Fd(dir.as_raw_fd()), name.as_ptr(), buffer)
+                    .flags(libc::AT_SYMLINK_NOFOLLOW)
+                    .mask(libc::STATX_BASIC_STATS)
+                    .build();
+                // the name and buffer outlive the batch, which is waited on
+                // below
+                unsafe { self.ring.submission().push(&statx) }
+                    .map_err(|_| io::Error::other("the io_uring submission queue is full"))?;
+            }
+            self.ring.submit_and_wait(batch.len())?;
+            // failures are met again, and reported, when the entry is read
+            self.ring.completion().for_each(drop);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "io_uring")))]
+impl Prefetcher {
+    /// The io_uring backend is unavailable without Linux and the io_uring
+    /// feature.
+    pub(crate) fn new() -> io::Result<Self> {
+        Err(io::Error::other("the io_uring backend requires linux, and amble to be built with the io_uring feature"))
+    }
+
+    /// Nothing is prefetched without io_uring.
+    pub(crate) fn prefetch(&mut self, _dir: &Path, _names: &[OsString]) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+//! watch.rs
+//!
+//! Watch mode, which keeps a scan up to date as the filesystem changes,
+//! rather than repeating it in full. After an initial scan, the roots are
+//! watched for changes with the notify crate, and each file which changes
+//! is evaluated in place, so that files are reported as they come to match
+//! the criteria, and as they stop matching them.
+//!
+//! The passage of time changes matches too. The files which match are
+//! checked at an interval, to find those which have aged out of the window.
+//! Files which only come to match as time passes, such as those older than
+//! a number of days, are found by rescanning the roots in full, at an
+//! interval of its own. Reads are not changes, so files which come to match
+//! by their access times alone are only found by rescans. Requires the
+//! watch feature.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{self, Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use notify::{event::ModifyKind, Event, EventKind, RecursiveMode, Watcher};
+
+use crate::{
+    constants::{WATCH_INTERVAL_SECS, WATCH_SETTLE_MILLIS},
+    errors::AmbleError,
+    filematch::FileMatch,
+    skip::Prune,
+    syncwalk::SyncSearch,
+    traits::{Finder, Sink},
+};
+
+/// Receives the changes found by a Watch.
+pub trait Changes: Send {
+    /// Receive a file which has come to match the criteria.
+    fn matched(&mut self, found: FileMatch) -> Result<(), AmbleError>;
+    /// Receive the path of a file which no longer matches the criteria, or
+    /// which has gone.
+    fn unmatched(&mut self, path: &Path) -> Result<(), AmbleError>;
+    /// Write out the changes received so far.
+    fn flush(&mut self) -> Result<(), AmbleError>;
+}
+
+// Collects the matches of a search.
+#[derive(Default)]
+struct Found(Vec<FileMatch>);
+
+impl Sink for Found {
+    fn accept(&mut self, found: FileMatch) -> Result<(), AmbleError> {
+        self.0.push(found);
+        Ok(())
+    }
+}
+
+// The paths changed in a burst of events, and whether each was created,
+// renamed, or removed, rather than having its contents or metadata changed.
+#[derive(Default)]
+struct Pending {
+    paths: HashMap<PathBuf, bool>,
+    rescan: bool,
+}
+
+impl Pending {
+    // Add the paths of an event, as they are given by the roots. Accesses
+    // are not changes, and would include the watch's own reads.
+    fn add(&mut self, event: notify::Result<Event>, roots: &[(PathBuf, Vec<PathBuf>)]) {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("Warning: {}", e);
+                // events may have been lost
+                self.rescan = true;
+                return;
+            },
+        };
+        self.rescan |= event.need_rescan();
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        let structural = matches!(event.kind, EventKind::Create(_) | EventKind::Remove(_)
+                                              | EventKind::Modify(ModifyKind::Name(_)));
+        for path in event.paths {
+            let given = roots.iter().find_map(|(root, resolved)| {
+                let relative = resolved.iter().find_map(|resolved| path.strip_prefix(resolved).ok())?;
+                Some(root.join(relative))
+            });
+            if let Some(path) = given {
+                *self.paths.entry(path).or_default() |= structural;
+            }
+        }
+    }
+}
+
+/// Watches the roots of a search, reporting the files which come to match
+/// its criteria, and those which stop matching them.
+pub struct Watch {
+    roots: Vec<PathBuf>,
+    interval: Duration,
+    rescan: Option<Duration>,
+    matched: HashSet<PathBuf>,
+}
+
+impl Watch {
+    /// New up a Watch of the supplied roots. The matches are checked every
+    /// WATCH_INTERVAL_SECS, and the roots are never rescanned.
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self { roots, interval: Duration::from_secs(WATCH_INTERVAL_SECS), rescan: None, matched: HashSet::new() }
+    }
+
+    /// Set the interval at which the files which match are checked, to find
+    /// those which have aged out of the window.
+    pub fn interval(&mut self, interval: Duration) -> &mut Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set the interval at which the roots are rescanned in full, to find
+    /// files which have come to match as time passed. None, the default,
+    /// never rescans them.
+    pub fn rescan(&mut self, rescan: Option<Duration>) -> &mut Self {
+        self.rescan = rescan;
+        self
+    }
+
+    /// Scan the roots with the supplied search, handing each match to the
+    /// supplied changes, then watch the roots, handing on each change,
+    /// until the watch fails.
+    pub fn run(&mut self, search: &mut SyncSearch, changes: &mut dyn Changes) -> Result<(), AmbleError> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)
+            .map_err(|e| AmbleError::UnexpectedResult(format!("unable to watch: {}", e)))?;
+        // watched before the initial scan, so that no change is missed
+        for root in &self.roots {
+            watcher.watch(root, RecursiveMode::Recursive).map_err(|e| {
+                AmbleError::UnexpectedResult(format!("unable to watch '{}': {}", root.display(), e))
+            })?;
+        }
+        // events name paths as they are resolved, which are reported as
+        // they are given by the roots
+        let resolved = self.roots.iter().map(|root| {
+            let resolved = IntoIterator::into_iter([fs::canonicalize(root).ok(), path::absolute(root).ok()]);
+            (root.clone(), resolved.flatten().collect())
+        }).collect::<Vec<_>>();
+        let prune = search.prune();
+        self.rescan_roots(search, changes)?;
+        changes.flush()?;
+
+        let settle = Duration::from_millis(WATCH_SETTLE_MILLIS);
+        let mut checked = Instant::now();
+        let mut rescanned = Instant::now();
+        loop {
+            let checking = checked + self.interval;
+            let due = self.rescan.map_or(checking, |rescan| checking.min(rescanned + rescan));
+            match rx.recv_timeout(due.saturating_duration_since(Instant::now())) {
+                Ok(event) => {
+                    let mut pending = Pending::default();
+                    pending.add(event, &resolved);
+                    // a burst of changes is evaluated at once
+                    thread::sleep(settle);
+                    rx.try_iter().for_each(|event| pending.add(event, &resolved));
+                    self.refresh(search, &prune, pending, changes)?;
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(AmbleError::UnexpectedResult("the watch stopped unexpectedly".to_string()));
+                },
+            }
+            if self.rescan.is_some_and(|rescan| rescanned.elapsed() >= rescan) {
+                self.rescan_roots(search, changes)?;
+                rescanned = Instant::now();
+                checked = rescanned;
+            } else if checked.elapsed() >= self.interval {
+                let matched = self.matched.iter().cloned().collect::<Vec<_>>();
+                let found = evaluate(search, matched)?;
+                self.reconcile(|_| true, found, changes)?;
+                checked = Instant::now();
+            }
+            changes.flush()?;
+        }
+    }
+
+    // Evaluate the paths which changed.
+    fn refresh(&mut self, search: &mut SyncSearch, prune: &Prune, pending: Pending, changes: &mut dyn Changes)
+    -> Result<(), AmbleError> {
+        if pending.rescan {
+            return self.rescan_roots(search, changes);
+        }
+        let mut candidates = Vec::new();
+        for (path, structural) in pending.paths {
+            match fs::symlink_metadata(&path) {
+                // removed, or moved away
+                Err(_) => self.forget(&path, changes)?,
+                // a directory which appears is searched, as its contents
+                // may have arrived with it
+                Ok(metadata) if metadata.is_dir() => if structural && !self.pruned(prune, &path, true) {
+                    let found = scan(search, vec![path.clone()])?;
+                    self.reconcile(|matched| matched.starts_with(&path), found, changes)?;
+                },
+                Ok(_) => if self.pruned(prune, &path, false) {
+                    self.forget(&path, changes)?;
+                } else {
+                    candidates.push(path);
+                },
+            }
+        }
+        if !candidates.is_empty() {
+            let evaluated = candidates.iter().cloned().collect::<HashSet<_>>();
+            let found = evaluate(search, candidates)?;
+            self.reconcile(|path| evaluated.contains(path), found, changes)?;
+        }
+        Ok(())
+    }
+
+    // Scan the roots in full.
+    fn rescan_roots(&mut self, search: &mut SyncSearch, changes: &mut dyn Changes) -> Result<(), AmbleError> {
+        let found = scan(search, self.roots.clone())?;
+        self.reconcile(|_| true, found, changes)
+    }
+
+    // Update the matches within the scope of an evaluation to those found,
+    // handing on those which came to match, and those which stopped.
+    fn reconcile(&mut self, scope: impl Fn(&Path) -> bool, found: Vec<FileMatch>, changes: &mut dyn Changes)
+    -> Result<(), AmbleError> {
+        let now = found.iter().map(|found| found.path.clone()).collect::<HashSet<_>>();
+        let stopped = self.matched.iter()
+                                  .filter(|path| scope(path) && !now.contains(*path))
+                                  .cloned()
+                                  .collect::<Vec<_>>();
+        for path in stopped {
+            self.matched.remove(&path);
+            changes.unmatched(&path)?;
+        }
+        for mut found in found {
+            if self.matched.insert(found.path.clone()) {
+                // files evaluated in place are attributed to their root
+                if found.root.is_none() {
+                    found.root = self.roots.iter().find(|root| found.path.starts_with(root)).cloned();
+                }
+                changes.matched(found)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Stop matching the path, and everything beneath it.
+    fn forget(&mut self, path: &Path, changes: &mut dyn Changes) -> Result<(), AmbleError> {
+        self.reconcile(|matched| matched.starts_with(path), Vec::new(), changes)
+    }
+
+    // Is the path left out of the search, or beneath a directory which is?
+    fn pruned(&self, prune: &Prune, path: &Path, is_dir: bool) -> bool {
+        let Some(root) = self.roots.iter().find(|root| path.starts_with(root)) else {
+            return true;
+        };
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let mut ancestor = root.clone();
+        relative.components().enumerate().any(|(depth, component)| {
+            ancestor.push(component);
+            prune.skips(&ancestor, is_dir || ancestor != path, depth + 1)
+        })
+    }
+}
+
+// The matches beneath the supplied roots.
+fn scan(search: &mut SyncSearch, roots: Vec<PathBuf>) -> Result<Vec<FileMatch>, AmbleError> {
+    let mut found = Found::default();
+    search.candidates(None).roots(roots).find_into(&mut found)?;
+    Ok(found.0)
+}
+
+// The matches amongst the supplied paths, evaluated in place.
+fn evaluate(search: &mut SyncSearch, paths: Vec<PathBuf>) -> Result<Vec<FileMatch>, AmbleError> {
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut found = Found::default();
+    search.candidates(Some(paths)).find_into(&mut found)?;
+    Ok(found.0)
+}